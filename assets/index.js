@@ -1,25 +1,248 @@
-function filterVideos() {
-  var input, filter, table, tr, td, i, txtValue;
-  input = document.getElementById("searchbar");
-  filter = input.value.toUpperCase();
-  table = document.getElementsByTagName("table")[0];
-  tr = table.getElementsByTagName("tr");
-  for (i = 0; i < tr.length; i++) {
-    td = tr[i].getElementsByTagName("td")[1];
-    if (td) {
-      txtValue = td.textContent || td.innerText;
-      if (txtValue.toUpperCase().indexOf(filter) > -1) {
-        tr[i].style.display = "block";
-      } else {
-        tr[i].style.display = "none";
-      }
+// How often (in seconds of playback) the position is reported while playing.
+var PROGRESS_INTERVAL = 5;
+// How long before the end of a video the start of the next one is loaded.
+var WARM_UP = 30;
+// Videos watched beyond this fraction count as finished and start over.
+var FINISHED = 0.95;
+
+function basePath() {
+  return document.body.getAttribute("data-base-path") || "";
+}
+
+// The token requests changing something carry, so other sites can't make them on the viewer's behalf.
+function csrfToken() {
+  var match = document.cookie.match(/(?:^|;\s*)svs_csrf=([^;]*)/);
+  return match ? decodeURIComponent(match[1]) : "";
+}
+
+document.addEventListener("DOMContentLoaded", function () {
+  document.querySelectorAll('input[name="csrf_token"]').forEach(function (input) {
+    input.value = csrfToken();
+  });
+});
+
+function showProgress(id, position, duration) {
+  var bar = document.querySelector('.progress-bar[data-id="' + id + '"]');
+  if (bar && duration) {
+    bar.style.width = Math.min(100, (100 * position) / duration) + "%";
+  }
+}
+
+function saveProgress(video) {
+  var id = video.getAttribute("data-id");
+  var duration = isFinite(video.duration) ? video.duration : null;
+  showProgress(id, video.currentTime, duration);
+  fetch(basePath() + "/api/videos/" + encodeURIComponent(id) + "/progress", {
+    method: "POST",
+    headers: { "Content-Type": "application/json", "X-CSRF-Token": csrfToken() },
+    body: JSON.stringify({ position: video.currentTime, duration: duration }),
+  });
+}
+
+function trackProgress(video, saved) {
+  var lastSaved = 0;
+  if (saved && !(saved.duration && saved.position / saved.duration > FINISHED)) {
+    video.addEventListener("loadedmetadata", function () {
+      video.currentTime = saved.position;
+    }, { once: true });
+  }
+  video.addEventListener("timeupdate", function () {
+    if (Math.abs(video.currentTime - lastSaved) >= PROGRESS_INTERVAL) {
+      lastSaved = video.currentTime;
+      saveProgress(video);
+    }
+  });
+  video.addEventListener("pause", function () { saveProgress(video); });
+  video.addEventListener("ended", function () { saveProgress(video); });
+}
+
+// The saved progress, favorites and ratings of the viewer, kept to set up rows loaded later on.
+var savedProgress = null;
+var savedFavorites = null;
+var savedRatings = null;
+
+function setUpProgress(container) {
+  container.querySelectorAll("video[data-id], audio[data-id]").forEach(function (video) {
+    var id = video.getAttribute("data-id");
+    var saved = savedProgress[id];
+    if (saved) {
+      showProgress(id, saved.position, saved.duration);
+    }
+    trackProgress(video, saved);
+  });
+}
+
+document.addEventListener("DOMContentLoaded", function () {
+  fetch(basePath() + "/api/progress")
+    .then(function (response) { return response.ok ? response.json() : {}; })
+    .catch(function () { return {}; })
+    .then(function (progress) {
+      savedProgress = progress;
+      setUpProgress(document);
+    });
+});
+
+function showFavorite(button, favorite) {
+  button.classList.toggle("starred", favorite);
+  button.innerHTML = favorite ? "&#9733;" : "&#9734;";
+}
+
+function toggleFavorite(button) {
+  var id = button.getAttribute("data-id");
+  fetch(basePath() + "/api/videos/" + encodeURIComponent(id) + "/favorite", {
+    method: "POST",
+    headers: { "X-CSRF-Token": csrfToken() },
+  })
+    .then(function (response) { return response.json(); })
+    .then(function (status) { showFavorite(button, status.favorite); });
+}
+
+function setUpFavorites(container) {
+  savedFavorites.forEach(function (video) {
+    var button = container.querySelector('button.favorite[data-id="' + video.id + '"]');
+    if (button) {
+      showFavorite(button, true);
+    }
+  });
+}
+
+document.addEventListener("DOMContentLoaded", function () {
+  fetch(basePath() + "/api/favorites")
+    .then(function (response) { return response.ok ? response.json() : []; })
+    .catch(function () { return []; })
+    .then(function (favorites) {
+      savedFavorites = favorites;
+      setUpFavorites(document);
+    });
+});
+
+function showRating(widget, stars) {
+  widget.setAttribute("data-rating", stars || 0);
+  widget.querySelectorAll("button").forEach(function (button) {
+    button.classList.toggle("lit", Number(button.getAttribute("data-stars")) <= stars);
+  });
+}
+
+// Rate a video, or take the rating back by clicking the current one again.
+function rate(button) {
+  var widget = button.parentNode;
+  var id = widget.getAttribute("data-id");
+  var stars = Number(button.getAttribute("data-stars"));
+  var rating = Number(widget.getAttribute("data-rating")) === stars ? null : stars;
+  fetch(basePath() + "/api/videos/" + encodeURIComponent(id) + "/rating", {
+    method: "PUT",
+    headers: { "Content-Type": "application/json", "X-CSRF-Token": csrfToken() },
+    body: JSON.stringify({ rating: rating }),
+  }).then(function (response) {
+    if (response.ok) {
+      showRating(widget, rating);
+    }
+  });
+}
+
+function setUpRatings(container) {
+  Object.keys(savedRatings).forEach(function (id) {
+    var widget = container.querySelector('.rating[data-id="' + id + '"]');
+    if (widget) {
+      showRating(widget, savedRatings[id]);
     }
-    td = tr[i].getElementsByTagName("td")[0];
-    if (td) {
-      txtValue = td.textContent || td.innerText;
-      if (txtValue.toUpperCase().indexOf(filter) > -1) {
-        tr[i].style.display = "block";
+  });
+}
+
+document.addEventListener("DOMContentLoaded", function () {
+  fetch(basePath() + "/api/ratings")
+    .then(function (response) { return response.ok ? response.json() : {}; })
+    .catch(function () { return {}; })
+    .then(function (ratings) {
+      savedRatings = ratings;
+      setUpRatings(document);
+    });
+});
+
+// Play the animated preview of a card while hovering it, as long as the video itself wasn't started.
+// Previews that aren't generated yet are asked for again on the next hover.
+function setUpPreviews(container) {
+  container.querySelectorAll("div.card img.preview").forEach(function (preview) {
+    var video = preview.parentNode.querySelector("video");
+    var card = preview.closest("div.card");
+    var hovering = false;
+    var loaded = false;
+    card.addEventListener("mouseenter", function () {
+      hovering = true;
+      if (!video.paused || video.currentTime > 0) {
+        return;
+      }
+      if (loaded) {
+        preview.classList.add("shown");
+      } else if (!preview.getAttribute("src")) {
+        preview.src = preview.getAttribute("data-src");
+      }
+    });
+    card.addEventListener("mouseleave", function () {
+      hovering = false;
+      preview.classList.remove("shown");
+    });
+    video.addEventListener("play", function () { preview.classList.remove("shown"); });
+    preview.addEventListener("load", function () {
+      loaded = true;
+      preview.classList.toggle("shown", hovering && video.paused && video.currentTime === 0);
+    });
+    preview.addEventListener("error", function () { preview.removeAttribute("src"); });
+  });
+}
+
+document.addEventListener("DOMContentLoaded", function () { setUpPreviews(document); });
+
+// Replace the "more" row at the end of the listing with the next chunk of videos, which ends with
+// another "more" row unless it is the last one.
+function loadMore(more, observer) {
+  observer.unobserve(more);
+  fetch(more.getAttribute("data-next"))
+    .then(function (response) { return response.text(); })
+    .then(function (html) {
+      var chunk = document.createElement(more.tagName === "TR" ? "tbody" : "div");
+      chunk.innerHTML = html;
+      if (savedProgress) { setUpProgress(chunk); }
+      if (savedFavorites) { setUpFavorites(chunk); }
+      if (savedRatings) { setUpRatings(chunk); }
+      setUpPreviews(chunk);
+      var rows = Array.prototype.slice.call(chunk.children);
+      rows.forEach(function (row) { more.parentNode.insertBefore(row, more); });
+      more.remove();
+      rows.filter(function (row) { return row.classList.contains("more"); }).forEach(function (row) {
+        observer.observe(row);
+      });
+    });
+}
+
+document.addEventListener("DOMContentLoaded", function () {
+  var observer = new IntersectionObserver(function (entries) {
+    entries.forEach(function (entry) {
+      if (entry.isIntersecting) {
+        loadMore(entry.target, observer);
       }
+    });
+  }, { rootMargin: "800px" });
+  document.querySelectorAll(".more[data-next]").forEach(function (more) { observer.observe(more); });
+});
+
+// On a watch page, load the start of the next video in the folder shortly before this one ends, so it starts
+// right away, and play it once this one has ended.
+function setUpNext(player) {
+  var warmedUp = null;
+  player.addEventListener("timeupdate", function () {
+    if (!warmedUp && player.duration - player.currentTime < WARM_UP) {
+      warmedUp = document.createElement(player.tagName);
+      warmedUp.preload = "auto";
+      warmedUp.muted = true;
+      warmedUp.src = player.getAttribute("data-next-media");
     }
-  }
+  });
+  player.addEventListener("ended", function () {
+    window.location.href = player.getAttribute("data-next-page");
+  });
 }
+
+document.addEventListener("DOMContentLoaded", function () {
+  document.querySelectorAll("[data-next-page]").forEach(setUpNext);
+});
@@ -0,0 +1,43 @@
+//! Cost of handing the index to a request handler for a library of 50k videos:
+//! copying the whole map (as the index page used to) versus sharing the snapshot.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use static_video_server::VideoPlayerState;
+use std::{collections::HashMap, path::PathBuf};
+
+const VIDEOS: usize = 50_000;
+
+fn bench_index_snapshot(c: &mut Criterion) {
+    let mut state = VideoPlayerState::new();
+    for index in 0..VIDEOS {
+        state.load_video(PathBuf::from(format!(
+            "/srv/videos/show-{}/season-{}/episode-{}.mp4",
+            index / 1000,
+            index / 100 % 10,
+            index
+        )), false)
+        .unwrap();
+    }
+
+    let mut group = c.benchmark_group("index_50k");
+    group.bench_function("clone_map", |b| {
+        b.iter(|| {
+            let videos: HashMap<String, PathBuf> = state
+                .videos()
+                .iter()
+                .map(|video| (video.id.clone(), video.path.clone()))
+                .collect();
+            black_box(videos.values().map(|path| path.as_os_str().len()).sum::<usize>())
+        })
+    });
+    group.bench_function("shared_snapshot", |b| {
+        b.iter(|| {
+            let videos = state.videos();
+            black_box(videos.iter().map(|video| video.path.as_os_str().len()).sum::<usize>())
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_index_snapshot);
+criterion_main!(benches);
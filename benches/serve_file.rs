@@ -0,0 +1,51 @@
+//! Throughput of streaming a large video file through `media::serve_file`
+//! for different chunk sizes, with the body drained the way hyper would.
+
+use axum::{
+    body::{Body, HttpBody},
+    http::Request,
+};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use static_video_server::media::{serve_file, DEFAULT_STREAM_BUFFER_SIZE};
+use std::io::Write;
+
+const FILE_SIZE: usize = 64 * 1024 * 1024;
+
+async fn drain(path: &std::path::Path, buffer_size: usize) -> usize {
+    let request = Request::builder().body(Body::empty()).unwrap();
+    let mut body = serve_file(path, request, buffer_size).await.unwrap().into_body();
+    let mut total = 0;
+    while let Some(chunk) = body.data().await {
+        total += chunk.unwrap().len();
+    }
+    total
+}
+
+fn bench_serve_file(c: &mut Criterion) {
+    let path = std::env::temp_dir().join("static-video-server-bench.mp4");
+    let mut file = std::fs::File::create(&path).unwrap();
+    let block = vec![0x5a_u8; 1024 * 1024];
+    for _ in 0..FILE_SIZE / block.len() {
+        file.write_all(&block).unwrap();
+    }
+    drop(file);
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let mut group = c.benchmark_group("serve_file");
+    group.throughput(Throughput::Bytes(FILE_SIZE as u64));
+    group.sample_size(20);
+    for buffer_size in [64 * 1024, DEFAULT_STREAM_BUFFER_SIZE, 1024 * 1024] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(format!("{}KiB", buffer_size / 1024)),
+            &buffer_size,
+            |b, &buffer_size| {
+                b.to_async(&runtime).iter(|| drain(&path, buffer_size));
+            },
+        );
+    }
+    group.finish();
+    std::fs::remove_file(&path).ok();
+}
+
+criterion_group!(benches, bench_serve_file);
+criterion_main!(benches);
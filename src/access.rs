@@ -0,0 +1,61 @@
+//! Restricting which clients are served by their address, e.g. to the LAN even when listening on all interfaces.
+
+use axum::{
+    extract::State,
+    http::{Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use ipnet::IpNet;
+use std::{
+    net::IpAddr,
+    sync::{Arc, RwLock},
+};
+use tracing::debug;
+
+use crate::proxy::ClientIp;
+
+/// The networks clients may and may not connect from.
+#[derive(Debug, Clone, Default)]
+pub struct IpFilter {
+    /// If not empty, only clients in one of these are served.
+    allow: Vec<IpNet>,
+    /// Clients in one of these are never served.
+    deny: Vec<IpNet>,
+}
+
+/// The filter in use, which can be replaced while the server runs.
+pub type SharedIpFilter = Arc<RwLock<IpFilter>>;
+
+impl IpFilter {
+    pub fn new(allow: Vec<IpNet>, deny: Vec<IpNet>) -> Self {
+        Self { allow, deny }
+    }
+
+    /// Whether the filter lets everybody through.
+    pub fn is_open(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        // Listening on `::` reports IPv4 clients as IPv4-mapped IPv6 addresses.
+        let ip = ip.to_canonical();
+        let matches = |network: &IpNet| network.contains(&ip);
+        !self.deny.iter().any(matches) && (self.allow.is_empty() || self.allow.iter().any(matches))
+    }
+}
+
+/// Refuse requests from clients the filter doesn't allow, before they are routed anywhere.
+pub async fn filter_clients<B>(
+    State(filter): State<SharedIpFilter>,
+    client: ClientIp,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let allowed = filter.read().unwrap().is_allowed(client.0);
+    if !allowed {
+        debug!("Refused {} {} from {}", request.method(), request.uri(), client);
+        return (StatusCode::FORBIDDEN, "Your address is not allowed to access this server.").into_response();
+    }
+    next.run(request).await
+}
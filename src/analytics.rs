@@ -0,0 +1,242 @@
+//! What is watched the most: play counts and bytes served per video, recent plays and traffic over time, as JSON at
+//! `/api/stats/top` and as a dashboard at `/stats`, both for admins. Private videos are left out, and so are hidden
+//! ones unless asked for with `?show_hidden=true`.
+
+use axum::{
+    body::{boxed, BoxBody, Bytes, HttpBody},
+    extract::{Query, State},
+    http::{HeaderMap, Response},
+    response::IntoResponse,
+    routing::get,
+    Extension, Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::{
+    store::{SharedIndexStore, VideoStats},
+    visibility::ShowHidden,
+    SharedState, VideoEntry,
+};
+#[cfg(feature = "ui")]
+use crate::{
+    i18n::{Messages, SharedMessages},
+    storage::describe_bytes,
+    store,
+    templates::{RecentPlay, Renderer, StatsTemplate, TopRow, TrafficBar},
+    themes::SharedTheme,
+};
+#[cfg(feature = "ui")]
+use axum::response::Response as AxumResponse;
+#[cfg(feature = "ui")]
+use std::collections::HashMap;
+
+/// The most videos `/api/stats/top` lists.
+const MAX_TOP: usize = 100;
+
+/// The hours of traffic the dashboard shows.
+#[cfg(feature = "ui")]
+const DASHBOARD_HOURS: u64 = 48;
+
+/// Count the bytes of a response body as served of a video once the body is done or dropped.
+pub fn count(response: Response<BoxBody>, store: SharedIndexStore, key: String) -> Response<BoxBody> {
+    if !response.status().is_success() {
+        return response;
+    }
+    response.map(|body| {
+        boxed(Counted {
+            inner: body,
+            store,
+            key,
+            bytes: 0,
+        })
+    })
+}
+
+struct Counted {
+    inner: BoxBody,
+    store: SharedIndexStore,
+    key: String,
+    bytes: u64,
+}
+
+impl HttpBody for Counted {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Bytes, axum::Error>>> {
+        let polled = Pin::new(&mut self.inner).poll_data(cx);
+        if let Poll::Ready(Some(Ok(chunk))) = &polled {
+            self.bytes += chunk.len() as u64;
+        }
+        polled
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, axum::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+}
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        self.store.record_bytes(&self.key, self.bytes);
+    }
+}
+
+/// What is ranked by.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RankBy {
+    #[default]
+    Views,
+    Bytes,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TopQuery {
+    #[serde(default)]
+    pub by: RankBy,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TopVideo {
+    pub id: String,
+    pub title: String,
+    pub views: u64,
+    pub bytes_served: u64,
+}
+
+/// The videos the stats are shown of: like the listings, without private videos and, unless shown, hidden ones.
+fn listed(videos: &[VideoEntry], show_hidden: ShowHidden) -> Vec<VideoEntry> {
+    videos
+        .iter()
+        .filter(|video| !video.private && (!video.hidden || show_hidden.enabled()))
+        .cloned()
+        .collect()
+}
+
+/// The videos watched the most, by plays or by bytes served. Videos never played are left out.
+fn top(videos: &[VideoEntry], stats: &VideoStats, by: RankBy, limit: usize) -> Vec<TopVideo> {
+    let mut top: Vec<TopVideo> = videos
+        .iter()
+        .map(|video| TopVideo {
+            id: video.id.clone(),
+            title: video.title.clone(),
+            views: stats.views(&video.key),
+            bytes_served: stats.bytes_served(&video.key),
+        })
+        .filter(|video| video.views > 0 || video.bytes_served > 0)
+        .collect();
+    match by {
+        RankBy::Views => top.sort_by_key(|video| std::cmp::Reverse((video.views, video.bytes_served))),
+        RankBy::Bytes => top.sort_by_key(|video| std::cmp::Reverse((video.bytes_served, video.views))),
+    }
+    top.truncate(limit);
+    top
+}
+
+/// The most watched videos, e.g. `?by=bytes&limit=20`.
+pub async fn top_videos(
+    State(state): State<SharedState>,
+    Extension(store): Extension<SharedIndexStore>,
+    show_hidden: ShowHidden,
+    Query(query): Query<TopQuery>,
+) -> impl IntoResponse {
+    let videos = listed(&state.lock().unwrap().videos(), show_hidden);
+    let limit = query.limit.unwrap_or(10).min(MAX_TOP);
+    Json(top(&videos, &store.stats(), query.by, limit))
+}
+
+/// How long ago a time was, e.g. `5 min ago`.
+#[cfg(feature = "ui")]
+fn describe_age(time: u64, t: &Messages) -> String {
+    let (message, n) = match store::now().saturating_sub(time) {
+        seconds if seconds < 60 => return t.just_now.clone(),
+        seconds if seconds < 3600 => (&t.minutes_ago, seconds / 60),
+        seconds if seconds < 86_400 => (&t.hours_ago, seconds / 3600),
+        seconds => (&t.days_ago, seconds / 86_400),
+    };
+    Messages::fill(message, &[("n", &n.to_string())])
+}
+
+/// The dashboard: the most watched videos, recent plays and the traffic of the last two days.
+#[cfg(feature = "ui")]
+pub async fn dashboard(
+    State(state): State<SharedState>,
+    Extension(store): Extension<SharedIndexStore>,
+    Extension(renderer): Extension<Renderer>,
+    Extension(t): Extension<SharedMessages>,
+    Extension(theme): Extension<SharedTheme>,
+    show_hidden: ShowHidden,
+) -> AxumResponse {
+    let (videos, base_path) = {
+        let state = state.lock().unwrap();
+        (listed(&state.videos(), show_hidden), state.base_path.clone())
+    };
+    let stats = store.stats();
+    let by_key: HashMap<&str, &VideoEntry> = videos.iter().map(|video| (video.key.as_str(), video)).collect();
+    let recent = store
+        .recent_plays()
+        .iter()
+        .filter_map(|play| {
+            let video = by_key.get(play.key.as_str())?;
+            Some(RecentPlay {
+                id: video.id.clone(),
+                title: video.title.clone(),
+                when: describe_age(play.watched, &t),
+            })
+        })
+        .collect();
+    let traffic = store.traffic(store::now().saturating_sub((DASHBOARD_HOURS - 1) * 3600));
+    let busiest = traffic.iter().map(|(_, traffic)| traffic.bytes).max().unwrap_or(0).max(1);
+    let template = StatsTemplate {
+        t: &t,
+        theme: &theme,
+        base_path,
+        top: top(&videos, &stats, RankBy::Views, 20)
+            .into_iter()
+            .map(|video| TopRow {
+                served: describe_bytes(video.bytes_served),
+                id: video.id,
+                title: video.title,
+                views: video.views,
+            })
+            .collect(),
+        recent,
+        traffic: traffic
+            .iter()
+            .map(|(hour, traffic)| TrafficBar {
+                label: Messages::fill(
+                    &t.traffic_bar,
+                    &[
+                        ("hour", &format!("{:02}:00", hour / 3600 % 24)),
+                        ("plays", &traffic.plays.to_string()),
+                        ("bytes", &describe_bytes(traffic.bytes)),
+                    ],
+                ),
+                percent: traffic.bytes * 100 / busiest,
+            })
+            .collect(),
+        total_served: Messages::fill(
+            &t.total_served,
+            &[("bytes", &describe_bytes(stats.bytes_served.values().sum()))],
+        ),
+    };
+    renderer.page("stats.html", &template)
+}
+
+pub fn analytics_router() -> Router<SharedState> {
+    Router::new()
+        .route("/api/stats/top", get(top_videos))
+}
@@ -0,0 +1,1219 @@
+//! The JSON API.
+
+use axum::{
+    body::Body,
+    extract::{Path, Query, RawQuery, State},
+    http::{header, HeaderMap, HeaderValue, Request, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing::{get, patch, post, put},
+    Extension, Json, Router,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use futures_util::{stream, Stream, StreamExt};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use tokio::sync::broadcast;
+
+#[cfg(feature = "transcoding")]
+use crate::convert;
+use crate::{
+    auth::Editor,
+    cache::SharedFileCache,
+    integrity,
+    jobs::{self, Job, JobKind, JobStatus, JobUpdate, SharedJobQueue},
+    library,
+    library_stats::LibraryStats,
+    media,
+    reload::SharedReloader,
+    scan::{QuarantinedFile, ScanStatus},
+    search::{self, SharedSearchIndex},
+    storage::{FilesystemSpace, StorageUsage},
+    store::{self, Checksum, Progress, SharedIndexStore, VideoStats},
+    streams,
+    urls,
+    users::{self, Role},
+    version,
+    viewer::Viewer,
+    visibility::ShowHidden,
+    RescanSummary, SharedState, VideoEntry,
+};
+
+/// A coarse classification of videos by their duration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DurationBucket {
+    /// Up to 5 minutes.
+    Short,
+    /// 5 to 30 minutes.
+    Medium,
+    /// Over 30 minutes.
+    Long,
+}
+
+impl DurationBucket {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Short => "short",
+            Self::Medium => "medium",
+            Self::Long => "long",
+        }
+    }
+
+    pub fn of(duration: f64) -> Self {
+        if duration <= 300.0 {
+            Self::Short
+        } else if duration <= 1800.0 {
+            Self::Medium
+        } else {
+            Self::Long
+        }
+    }
+}
+
+/// Filters and ordering of the video listing, e.g. `?ext=mkv,mp4&min_height=1080&codec=hevc&sort=-duration`.
+/// Filters on metadata exclude videos that haven't been probed. Private videos are always excluded, and hidden ones
+/// unless an editor asks for them, see [`crate::visibility::ShowHidden`].
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct VideoFilter {
+    /// A comma separated list of kinds of media: `video`, `audio` or `image`.
+    pub kind: Option<String>,
+    /// A comma separated list of extensions.
+    pub ext: Option<String>,
+    /// A comma separated list of codecs.
+    pub codec: Option<String>,
+    pub min_width: Option<u32>,
+    pub max_width: Option<u32>,
+    pub min_height: Option<u32>,
+    pub max_height: Option<u32>,
+    /// The minimum duration in seconds.
+    pub min_duration: Option<f64>,
+    /// The maximum duration in seconds.
+    pub max_duration: Option<f64>,
+    pub bucket: Option<DurationBucket>,
+    /// The minimum average rating, from 1 to 5. Excludes videos nobody rated.
+    pub min_rating: Option<f64>,
+    /// A fuzzy search over titles and paths. Unless sorted otherwise, the best matches are listed first.
+    pub q: Option<String>,
+    /// The key to sort by (`name`, `duration`, `views` or `rating`), prefixed with `-` for descending order,
+    /// e.g. `-views` for the most watched first. Videos are listed in the order of their keys otherwise.
+    pub sort: Option<String>,
+    /// Whether hidden videos are included. Set by handlers from [`crate::visibility::ShowHidden`], not the query.
+    #[serde(skip)]
+    pub show_hidden: bool,
+}
+
+fn in_list(list: &Option<String>, value: Option<&str>) -> bool {
+    match list {
+        None => true,
+        Some(list) => value
+            .map(|value| list.split(',').any(|item| item.trim().eq_ignore_ascii_case(value)))
+            .unwrap_or(false),
+    }
+}
+
+fn in_range<T: PartialOrd>(value: Option<T>, min: Option<T>, max: Option<T>) -> bool {
+    if min.is_none() && max.is_none() {
+        return true;
+    }
+    match value {
+        Some(value) => min.is_none_or(|min| value >= min) && max.is_none_or(|max| value <= max),
+        None => false,
+    }
+}
+
+impl VideoFilter {
+    pub fn matches(&self, video: &VideoEntry, stats: &VideoStats) -> bool {
+        let metadata = video.metadata.as_ref();
+        (!video.hidden || self.show_hidden)
+            && !video.private
+            && in_list(&self.kind, Some(video.kind.as_str()))
+            && in_list(&self.ext, Some(&video.extension))
+            && in_list(&self.codec, metadata.and_then(|metadata| metadata.codec.as_deref()))
+            && in_range(
+                metadata.and_then(|metadata| metadata.width),
+                self.min_width,
+                self.max_width,
+            )
+            && in_range(
+                metadata.and_then(|metadata| metadata.height),
+                self.min_height,
+                self.max_height,
+            )
+            && in_range(video.duration(), self.min_duration, self.max_duration)
+            && self.bucket.is_none_or(|bucket| {
+                video.duration().map(DurationBucket::of) == Some(bucket)
+            })
+            && in_range(stats.rating(&video.key), self.min_rating, None)
+    }
+
+    /// Whether the listing is in the order of the index, i.e. by key, as neither sorted nor searched.
+    pub fn in_key_order(&self) -> bool {
+        let searched = self.q.as_deref().is_some_and(|query| !query.trim().is_empty());
+        // Unknown sort keys leave the order alone, unless descending.
+        let sorted = self.sort.as_deref().is_some_and(|sort| {
+            sort.starts_with('-') || matches!(sort, "name" | "duration" | "views" | "rating")
+        });
+        !searched && !sorted
+    }
+
+    /// Whether the listing depends on play counts or ratings, which change without the index changing.
+    pub fn uses_stats(&self) -> bool {
+        let sort = self.sort.as_deref().map(|sort| sort.trim_start_matches('-'));
+        self.min_rating.is_some() || matches!(sort, Some("views" | "rating"))
+    }
+
+    /// Filter and sort a listing.
+    pub fn apply<'a>(
+        &self,
+        videos: &'a [VideoEntry],
+        stats: &VideoStats,
+        search: &search::SearchIndex,
+    ) -> Vec<&'a VideoEntry> {
+        let mut videos: Vec<&VideoEntry> = videos
+            .iter()
+            .filter(|video| self.matches(video, stats))
+            .collect();
+        if let Some(query) = self.q.as_deref().filter(|query| !query.trim().is_empty()) {
+            videos = search.rank(query, videos);
+        }
+        if let Some(sort) = self.sort.as_deref() {
+            sort_videos(&mut videos, sort, stats);
+        }
+        videos
+    }
+}
+
+/// Sort videos by a key (`name`, `duration`, `views` or `rating`), prefixed with `-` for descending order.
+pub fn sort_videos(videos: &mut [&VideoEntry], sort: &str, stats: &VideoStats) {
+    let (key, descending) = match sort.strip_prefix('-') {
+        Some(key) => (key, true),
+        None => (sort, false),
+    };
+    match key {
+        "name" => videos.sort_by_key(|video| video.name.to_lowercase()),
+        "duration" => videos.sort_by(|a, b| {
+            a.duration()
+                .unwrap_or(0.0)
+                .total_cmp(&b.duration().unwrap_or(0.0))
+        }),
+        "views" => videos.sort_by_key(|video| stats.views(&video.key)),
+        "rating" => videos.sort_by(|a, b| {
+            stats
+                .rating(&a.key)
+                .unwrap_or(0.0)
+                .total_cmp(&stats.rating(&b.key).unwrap_or(0.0))
+        }),
+        _ => {}
+    }
+    if descending {
+        videos.reverse();
+    }
+}
+
+/// The videos right in a folder, in the order of its `sort` setting if it has one.
+pub fn folder_videos<'a>(
+    videos: &'a [VideoEntry],
+    root: &std::path::Path,
+    dir: &std::path::Path,
+    stats: &VideoStats,
+) -> Vec<&'a VideoEntry> {
+    let mut in_folder: Vec<&VideoEntry> = videos.iter().filter(|video| video.path.parent() == Some(dir)).collect();
+    if let Some(sort) = crate::dir_config::DirConfig::load(root, dir).sort {
+        sort_videos(&mut in_folder, &sort, stats);
+    }
+    in_folder
+}
+
+/// The number of videos the index renders at once. The rest is loaded in chunks as the viewer scrolls.
+pub const PAGE_SIZE: usize = 50;
+
+/// The most videos a single chunk may hold.
+pub const MAX_PAGE_SIZE: usize = 500;
+
+/// A chunk of a listing, e.g. `?offset=100&limit=50`.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct Page {
+    #[serde(default)]
+    pub offset: usize,
+    pub limit: Option<usize>,
+}
+
+impl Page {
+    pub fn limit(&self) -> usize {
+        self.limit.unwrap_or(PAGE_SIZE).clamp(1, MAX_PAGE_SIZE)
+    }
+
+    /// Take this chunk out of a listing.
+    /// Returns the chunk along with the offset of the next one, if there is more.
+    pub fn slice<T>(&self, items: Vec<T>) -> (Vec<T>, Option<usize>) {
+        let end = self.offset.saturating_add(self.limit());
+        let next = (end < items.len()).then_some(end);
+        let chunk = items.into_iter().skip(self.offset).take(self.limit()).collect();
+        (chunk, next)
+    }
+
+    /// The query of another chunk of the same listing: the given query with its offset replaced.
+    pub fn query_at(query: &str, offset: usize) -> String {
+        replace_param(query, "offset", &offset.to_string())
+    }
+}
+
+/// A query with a parameter replaced, or added if it wasn't there.
+fn replace_param(query: &str, name: &str, value: &str) -> String {
+    let prefix = format!("{}=", name);
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty() && !pair.starts_with(&prefix))
+        .chain(std::iter::once(format!("{}{}", prefix, value).as_str()))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// A chunk of `/api/videos`, e.g. `?limit=100&cursor=...`, with the cursor of the previous chunk.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct CursorPage {
+    pub cursor: Option<String>,
+    pub limit: Option<usize>,
+}
+
+/// Where a chunk of a listing starts: right after the last video of the previous chunk, found by its key so that
+/// videos added or removed before it don't shift the chunk. Handed out opaque, as base64.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cursor {
+    key: String,
+}
+
+impl Cursor {
+    /// The cursor after a video of a listing.
+    pub fn after(video: &VideoEntry) -> Self {
+        Self { key: video.key.clone() }
+    }
+
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(&self.key)
+    }
+
+    pub fn decode(cursor: &str) -> Option<Self> {
+        let key = String::from_utf8(URL_SAFE_NO_PAD.decode(cursor).ok()?).ok()?;
+        (!key.is_empty()).then_some(Self { key })
+    }
+
+    /// The position in a listing that the chunk after this cursor starts at. If its video is no longer listed, that
+    /// is the first video with a later key in listings in the order of the index, and unknown in sorted ones.
+    pub fn start(&self, videos: &[&VideoEntry], in_key_order: bool) -> Option<usize> {
+        match videos.iter().position(|video| video.key == self.key) {
+            Some(position) => Some(position + 1),
+            None if in_key_order => Some(videos.partition_point(|video| video.key < self.key)),
+            None => None,
+        }
+    }
+}
+
+/// A video in an API listing, along with how often it was played and how it was rated.
+#[derive(Debug, Serialize)]
+pub struct VideoListing<'a> {
+    #[serde(flatten)]
+    pub video: &'a VideoEntry,
+    pub views: u64,
+    /// The average rating, if anybody rated the video.
+    pub rating: Option<f64>,
+}
+
+impl<'a> VideoListing<'a> {
+    pub fn all(videos: Vec<&'a VideoEntry>, stats: &VideoStats) -> Vec<Self> {
+        videos
+            .into_iter()
+            .map(|video| Self {
+                video,
+                views: stats.views(&video.key),
+                rating: stats.rating(&video.key),
+            })
+            .collect()
+    }
+}
+
+lazy_static! {
+    /// When the server started, so ETags handed out before a restart never match.
+    static ref STARTED: u64 = store::now();
+}
+
+/// The ETag of a listing of the index: it changes whenever the index or the play counts and ratings in it do, which
+/// is cheaper to tell than hashing the listing. Listings with hidden videos, which only admins get, are tagged apart
+/// from the others, so an ETag of one never matches the other.
+fn listing_etag(generation: u64, revision: u64, show_hidden: ShowHidden) -> HeaderValue {
+    let hidden = if show_hidden.enabled() { "-h" } else { "" };
+    HeaderValue::from_str(&format!("\"{:x}-{:x}-{:x}{}\"", *STARTED, generation, revision, hidden)).unwrap()
+}
+
+/// Who a listing is for depends on the session cookie or the bearer token, so caches must key it on them.
+const LISTING_VARY: HeaderValue = HeaderValue::from_static("cookie, authorization");
+
+/// Whether the `If-None-Match` of a request lists an ETag, i.e. the copy the client has is still current.
+fn is_unchanged(headers: &HeaderMap, etag: &HeaderValue) -> bool {
+    let Some(if_none_match) = headers.get(header::IF_NONE_MATCH).and_then(|value| value.to_str().ok()) else {
+        return false;
+    };
+    let etag = etag.to_str().unwrap_or_default();
+    if_none_match
+        .split(',')
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == etag)
+}
+
+/// List the indexed videos matching the filters. Tagged with an ETag, so clients polling with `If-None-Match` get a
+/// `304 Not Modified` until something changed.
+///
+/// Given a `limit` or a `cursor`, only lists a chunk of them, linking to the next chunk in `Link: <...>; rel="next"`.
+#[allow(clippy::too_many_arguments)]
+pub async fn list_videos(
+    State(state): State<SharedState>,
+    Extension(store): Extension<SharedIndexStore>,
+    Extension(search): Extension<SharedSearchIndex>,
+    RawQuery(query): RawQuery,
+    Query(mut filter): Query<VideoFilter>,
+    Query(page): Query<CursorPage>,
+    show_hidden: ShowHidden,
+    headers: HeaderMap,
+) -> Response {
+    filter.show_hidden = show_hidden.enabled();
+    let (videos, generation, base_path) = {
+        let state = state.lock().unwrap();
+        (state.videos(), state.generation(), state.base_path.clone())
+    };
+    let etag = listing_etag(generation, store.revision(), show_hidden);
+    if is_unchanged(&headers, &etag) {
+        return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag), (header::VARY, LISTING_VARY)]).into_response();
+    }
+    let stats = store.stats();
+    let mut listed = filter.apply(&videos, &stats, &search);
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert(header::ETAG, etag);
+    response_headers.insert(header::VARY, LISTING_VARY);
+    if page.cursor.is_some() || page.limit.is_some() {
+        let start = match page.cursor.as_deref().map(Cursor::decode) {
+            None => 0,
+            Some(Some(cursor)) => match cursor.start(&listed, filter.in_key_order()) {
+                Some(start) => start,
+                None => {
+                    let message = "The video this cursor follows is gone. List the chunks from the start again.";
+                    return (StatusCode::GONE, message.to_string()).into_response();
+                }
+            },
+            Some(None) => return (StatusCode::BAD_REQUEST, "Invalid cursor".to_string()).into_response(),
+        };
+        let end = start.saturating_add(page.limit.unwrap_or(PAGE_SIZE).clamp(1, MAX_PAGE_SIZE)).min(listed.len());
+        if end < listed.len() {
+            let cursor = Cursor::after(listed[end - 1]).encode();
+            let next = replace_param(&query.unwrap_or_default(), "cursor", &cursor);
+            let link = format!("<{}/api/videos?{}>; rel=\"next\"", base_path, next);
+            if let Ok(link) = HeaderValue::from_str(&link) {
+                response_headers.insert(header::LINK, link);
+            }
+        }
+        listed = listed.into_iter().take(end).skip(start).collect();
+    }
+    (response_headers, Json(VideoListing::all(listed, &stats))).into_response()
+}
+
+/// The playback position reported by the player.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProgressUpdate {
+    pub position: f64,
+    pub duration: Option<f64>,
+}
+
+/// The progress of the viewer in a video.
+pub async fn get_progress(
+    Path(video_id): Path<String>,
+    State(state): State<SharedState>,
+    Extension(store): Extension<SharedIndexStore>,
+    Extension(viewer): Extension<Viewer>,
+) -> impl IntoResponse {
+    let Some(key) = state.lock().unwrap().get(&video_id).map(|video| video.key.clone()) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    Json(store.progress(viewer.as_str(), &key)).into_response()
+}
+
+/// Record how far the viewer got into a video.
+pub async fn set_progress(
+    Path(video_id): Path<String>,
+    State(state): State<SharedState>,
+    Extension(store): Extension<SharedIndexStore>,
+    Extension(viewer): Extension<Viewer>,
+    Json(update): Json<ProgressUpdate>,
+) -> impl IntoResponse {
+    if !update.position.is_finite() || update.position < 0.0 {
+        return (StatusCode::UNPROCESSABLE_ENTITY, "Invalid position").into_response();
+    }
+    let Some(key) = state.lock().unwrap().get(&video_id).map(|video| video.key.clone()) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let progress = Progress {
+        position: update.position,
+        duration: update.duration.filter(|duration| duration.is_finite()),
+        updated: store::now(),
+    };
+    store.set_progress(viewer.as_str(), &key, progress);
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// The progress of the viewer in every indexed video they started, by video id.
+pub async fn list_progress(
+    State(state): State<SharedState>,
+    Extension(store): Extension<SharedIndexStore>,
+    Extension(viewer): Extension<Viewer>,
+) -> impl IntoResponse {
+    let videos = state.lock().unwrap().videos();
+    let mut progress = store.all_progress(viewer.as_str());
+    let by_id: HashMap<&str, Progress> = videos
+        .iter()
+        .filter_map(|video| Some((video.id.as_str(), progress.remove(&video.key)?)))
+        .collect();
+    Json(by_id).into_response()
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FavoriteStatus {
+    pub favorite: bool,
+}
+
+/// Star the video for the viewer, or unstar it if it already was.
+pub async fn toggle_favorite(
+    Path(video_id): Path<String>,
+    State(state): State<SharedState>,
+    Extension(store): Extension<SharedIndexStore>,
+    Extension(viewer): Extension<Viewer>,
+) -> impl IntoResponse {
+    let Some(key) = state.lock().unwrap().get(&video_id).map(|video| video.key.clone()) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let favorite = store.toggle_favorite(viewer.as_str(), &key);
+    Json(FavoriteStatus { favorite }).into_response()
+}
+
+/// The indexed videos the viewer starred, narrowed down by the usual filters.
+pub async fn list_favorites(
+    State(state): State<SharedState>,
+    Extension(store): Extension<SharedIndexStore>,
+    Extension(viewer): Extension<Viewer>,
+    Extension(search): Extension<SharedSearchIndex>,
+    Query(mut filter): Query<VideoFilter>,
+    show_hidden: ShowHidden,
+) -> impl IntoResponse {
+    filter.show_hidden = show_hidden.enabled();
+    let videos = state.lock().unwrap().videos();
+    let favorites = store.favorites(viewer.as_str());
+    let stats = store.stats();
+    let videos: Vec<&VideoEntry> = filter
+        .apply(&videos, &stats, &search)
+        .into_iter()
+        .filter(|video| favorites.contains(&video.key))
+        .collect();
+    Json(VideoListing::all(videos, &stats)).into_response()
+}
+
+/// A video in the watch history, along with when it was last started.
+#[derive(Debug, Serialize)]
+pub struct HistoryListing<'a> {
+    #[serde(flatten)]
+    pub video: &'a VideoEntry,
+    pub watched: u64,
+}
+
+/// The videos the viewer started, most recent first.
+/// Videos that are no longer in the index are left out.
+pub async fn list_history(
+    State(state): State<SharedState>,
+    Extension(store): Extension<SharedIndexStore>,
+    Extension(viewer): Extension<Viewer>,
+) -> impl IntoResponse {
+    let videos = state.lock().unwrap().videos();
+    let by_key: HashMap<&str, &VideoEntry> =
+        videos.iter().map(|video| (video.key.as_str(), video)).collect();
+    let history: Vec<HistoryListing> = store
+        .history(viewer.as_str())
+        .into_iter()
+        .filter_map(|entry| {
+            Some(HistoryListing {
+                video: by_key.get(entry.key.as_str())?,
+                watched: entry.watched,
+            })
+        })
+        .collect();
+    Json(history).into_response()
+}
+
+/// A rating from 1 to 5 stars, or `null` to take it back.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RatingUpdate {
+    pub rating: Option<u8>,
+}
+
+/// Rate a video for the viewer.
+pub async fn set_rating(
+    Path(video_id): Path<String>,
+    State(state): State<SharedState>,
+    Extension(store): Extension<SharedIndexStore>,
+    Extension(viewer): Extension<Viewer>,
+    Json(update): Json<RatingUpdate>,
+) -> impl IntoResponse {
+    if update.rating.is_some_and(|rating| !(1..=5).contains(&rating)) {
+        return (StatusCode::UNPROCESSABLE_ENTITY, "Ratings go from 1 to 5").into_response();
+    }
+    let Some(key) = state.lock().unwrap().get(&video_id).map(|video| video.key.clone()) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    store.set_rating(viewer.as_str(), &key, update.rating);
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// The ratings the viewer gave, by video id.
+pub async fn list_ratings(
+    State(state): State<SharedState>,
+    Extension(store): Extension<SharedIndexStore>,
+    Extension(viewer): Extension<Viewer>,
+) -> impl IntoResponse {
+    let videos = state.lock().unwrap().videos();
+    let ratings = store.ratings_by(viewer.as_str());
+    let by_id: HashMap<&str, u8> = videos
+        .iter()
+        .filter_map(|video| Some((video.id.as_str(), *ratings.get(&video.key)?)))
+        .collect();
+    Json(by_id).into_response()
+}
+
+/// Changes to a video. Fields that are left out stay as they are.
+#[derive(Debug, Clone, Deserialize)]
+pub struct VideoUpdate {
+    /// A new file name, in the same folder and with the same extension.
+    pub name: Option<String>,
+    /// A new title to display instead of the file name. An empty title goes back to the file name.
+    pub title: Option<String>,
+}
+
+/// Rename a video and/or change its title.
+pub async fn update_video(
+    Path(video_id): Path<String>,
+    _editor: Editor,
+    State(state): State<SharedState>,
+    Extension(store): Extension<SharedIndexStore>,
+    Extension(cache): Extension<SharedFileCache>,
+    Json(update): Json<VideoUpdate>,
+) -> Result<Json<VideoEntry>, (StatusCode, String)> {
+    let mut state = state.lock().unwrap();
+    let mut video = state
+        .get(&video_id)
+        .cloned()
+        .ok_or((StatusCode::NOT_FOUND, format!("No video with id {}", video_id)))?;
+    if let Some(name) = &update.name {
+        video = library::rename_video(&mut state, &store, &video_id, name)?;
+        cache.clear();
+    }
+    if let Some(title) = update.title {
+        video = library::retitle_video(&mut state, &store, &video_id, Some(title))?;
+    }
+    Ok(Json(video))
+}
+
+/// Where to move a video: a folder relative to the root.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MoveRequest {
+    pub folder: String,
+}
+
+/// Move a video to another folder under the root.
+pub async fn move_video(
+    Path(video_id): Path<String>,
+    _editor: Editor,
+    State(state): State<SharedState>,
+    Extension(store): Extension<SharedIndexStore>,
+    Extension(cache): Extension<SharedFileCache>,
+    Json(request): Json<MoveRequest>,
+) -> Result<Json<VideoEntry>, (StatusCode, String)> {
+    let mut state = state.lock().unwrap();
+    let video = library::move_video(&mut state, &store, &video_id, &request.folder)?;
+    cache.clear();
+    Ok(Json(video))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RescanQuery {
+    /// The folder to rescan, relative to the root, e.g. `shows/season2`.
+    pub path: String,
+}
+
+/// Rescan a single folder of the library, e.g. `?path=shows/season2`, and tell what changed under it.
+/// Videos elsewhere are left as they are, and videos still there keep their ids.
+pub async fn rescan_folder(
+    _editor: Editor,
+    State(state): State<SharedState>,
+    Extension(reloader): Extension<SharedReloader>,
+    Extension(cache): Extension<SharedFileCache>,
+    Query(query): Query<RescanQuery>,
+) -> Result<Json<RescanSummary>, (StatusCode, String)> {
+    let dir = library::resolve_rescan_dir(&state.lock().unwrap(), &query.path)?;
+    let summary = reloader.rescan_dir(state, dir).await;
+    cache.clear();
+    Ok(Json(summary))
+}
+
+/// Whether the library is being scanned and how far along that is, or else how the last scan went.
+pub async fn scan_status(State(state): State<SharedState>) -> Json<ScanStatus> {
+    let scan_progress = state.lock().unwrap().scan_progress().clone();
+    Json(scan_progress.status())
+}
+
+/// The files the scans came across but couldn't index, e.g. for lack of permission, or because ffprobe couldn't read
+/// them.
+pub async fn list_quarantine(State(state): State<SharedState>) -> Json<Vec<QuarantinedFile>> {
+    Json(state.lock().unwrap().quarantined())
+}
+
+/// Move a video to the trash.
+pub async fn trash_video(
+    Path(video_id): Path<String>,
+    _editor: Editor,
+    State(state): State<SharedState>,
+    Extension(cache): Extension<SharedFileCache>,
+) -> Result<Json<library::TrashedVideo>, (StatusCode, String)> {
+    let mut state = state.lock().unwrap();
+    let trashed = library::trash_video(&mut state, &video_id)?;
+    cache.clear();
+    Ok(Json(trashed))
+}
+
+/// The videos in the trash.
+pub async fn list_trash(
+    _editor: Editor,
+    State(state): State<SharedState>,
+) -> Result<Json<Vec<library::TrashedVideo>>, (StatusCode, String)> {
+    let state = state.lock().unwrap();
+    Ok(Json(library::list_trash(&state)?))
+}
+
+/// Which video to take out of the trash, by the path it had.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RestoreRequest {
+    pub key: String,
+}
+
+/// Move a video from the trash back to where it was.
+pub async fn restore_video(
+    _editor: Editor,
+    State(state): State<SharedState>,
+    Extension(cache): Extension<SharedFileCache>,
+    Json(request): Json<RestoreRequest>,
+) -> Result<Json<VideoEntry>, (StatusCode, String)> {
+    let mut state = state.lock().unwrap();
+    let video = library::restore_video(&mut state, &request.key)?;
+    cache.clear();
+    Ok(Json(video))
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EmptiedTrash {
+    pub deleted: usize,
+}
+
+/// Delete everything in the trash for good.
+pub async fn empty_trash(
+    _editor: Editor,
+    State(state): State<SharedState>,
+) -> Result<Json<EmptiedTrash>, (StatusCode, String)> {
+    let state = state.lock().unwrap();
+    let deleted = library::empty_trash(&state)?;
+    Ok(Json(EmptiedTrash { deleted }))
+}
+
+/// The space taken up by the library, and what's left on its filesystem.
+#[derive(Debug, Serialize)]
+pub struct StorageReport {
+    #[serde(flatten)]
+    pub usage: StorageUsage,
+    /// `None` if the filesystem couldn't be queried.
+    pub filesystem: Option<FilesystemSpace>,
+}
+
+/// Report the size of the library, by folder, and the free space on its filesystem.
+pub async fn storage_stats(State(state): State<SharedState>) -> impl IntoResponse {
+    let (usage, root) = {
+        let state = state.lock().unwrap();
+        (state.storage().clone(), state.root().map(std::path::PathBuf::from))
+    };
+    let filesystem = root.and_then(|root| FilesystemSpace::of(&root).ok());
+    Json(StorageReport { usage, filesystem })
+}
+
+/// Report how many videos there are, how long and how large, by extension, resolution and top-level folder.
+pub async fn library_stats(State(state): State<SharedState>) -> Json<LibraryStats> {
+    Json(state.lock().unwrap().library_stats().clone())
+}
+
+/// The SHA-256 of a video's file. Admins have it hashed first if it wasn't yet or was modified since, anybody else
+/// only gets the one already stored.
+pub async fn video_checksum(
+    Path(video_id): Path<String>,
+    role: Role,
+    State(state): State<SharedState>,
+    Extension(store): Extension<SharedIndexStore>,
+) -> Result<Json<Checksum>, (StatusCode, String)> {
+    let video = state
+        .lock()
+        .unwrap()
+        .get(&video_id)
+        .cloned()
+        .ok_or((StatusCode::NOT_FOUND, format!("No video with id {}", video_id)))?;
+    let failed = |err: std::io::Error| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to hash the video: {}", err));
+    if let Some(stored) = integrity::stored_checksum(&store, &video).map_err(failed)? {
+        return Ok(Json(stored));
+    }
+    if role != Role::Admin {
+        return Err((StatusCode::NOT_FOUND, format!("Video {} wasn't hashed yet", video_id)));
+    }
+    let _permit = integrity::HASHING
+        .acquire()
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+    tokio::task::spawn_blocking(move || integrity::checksum(&store, &video))
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?
+        .map(Json)
+        .map_err(failed)
+}
+
+/// The segment of a video to extract, in seconds.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ClipQuery {
+    pub start: f64,
+    pub end: f64,
+}
+
+/// Extract a segment of a video (without re-encoding it) and offer it as a download. Its bounds are rounded to a
+/// tenth of a second. Clips are kept in the data directory for a while, so asking for the same one again is instant.
+pub async fn extract_clip(
+    Path(video_id): Path<String>,
+    _editor: Editor,
+    State(state): State<SharedState>,
+    Extension(jobs): Extension<SharedJobQueue>,
+    Query(clip): Query<ClipQuery>,
+    slot: streams::StreamSlot,
+    request: Request<Body>,
+) -> Result<Response, (StatusCode, String)> {
+    let (video, buffer_size, limits) = {
+        let state = state.lock().unwrap();
+        let video = state
+            .get(&video_id)
+            .cloned()
+            .ok_or((StatusCode::NOT_FOUND, format!("No video with id {}", video_id)))?;
+        (video, state.stream_buffer_size, state.stream_limits)
+    };
+    let (start, end) = (jobs::round_clip_bound(clip.start), jobs::round_clip_bound(clip.end));
+    if !(start.is_finite() && end.is_finite() && 0.0 <= start && start < end) {
+        return Err((StatusCode::BAD_REQUEST, "A clip needs a start before its end".to_string()));
+    }
+    if video.duration().is_some_and(|duration| start >= duration) {
+        return Err((StatusCode::BAD_REQUEST, "The clip starts after the end of the video".to_string()));
+    }
+    let path = jobs.clip_path(&video.key, start, end);
+    let kind = JobKind::Clip { key: video.key.clone(), start, end };
+    if !path.is_file() && jobs.unfinished_like(&kind) >= jobs::MAX_CLIP_JOBS {
+        return Err((
+            StatusCode::TOO_MANY_REQUESTS,
+            "Too many clips are being extracted, try again in a moment".to_string(),
+        ));
+    }
+    let stem = video.path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = format!("{} ({}-{}).mp4", stem, start, end);
+    let response = serve_job_output(&jobs, kind, &path, &name, request, buffer_size, limits).await?;
+    Ok(streams::hold(response, slot))
+}
+
+/// Serve what a job produces as a download, running the job first unless its output exists already.
+async fn serve_job_output(
+    jobs: &jobs::JobQueue,
+    kind: JobKind,
+    path: &std::path::Path,
+    name: &str,
+    request: Request<Body>,
+    buffer_size: usize,
+    limits: media::StreamLimits,
+) -> Result<Response, (StatusCode, String)> {
+    if !path.is_file() {
+        let job = jobs.wait_for(kind, jobs::Priority::High).await;
+        if job.status != JobStatus::Done {
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Job {} failed: {}", job.id, job.error.unwrap_or_default()),
+            ));
+        }
+    }
+    let mut response = limits.apply(media::serve_file(path, request, buffer_size).await?);
+    if let Ok(value) = HeaderValue::from_str(&urls::content_disposition("attachment", name.as_ref())) {
+        response.headers_mut().insert(header::CONTENT_DISPOSITION, value);
+    }
+    Ok(response)
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AudioQuery {
+    #[serde(default)]
+    pub format: jobs::AudioFormat,
+}
+
+/// Extract the audio track of a video (as MP3 by default) and offer it as a download.
+/// Like clips, extracted tracks are kept in the data directory for a while.
+pub async fn extract_audio(
+    Path(video_id): Path<String>,
+    _editor: Editor,
+    State(state): State<SharedState>,
+    Extension(jobs): Extension<SharedJobQueue>,
+    Query(audio): Query<AudioQuery>,
+    slot: streams::StreamSlot,
+    request: Request<Body>,
+) -> Result<Response, (StatusCode, String)> {
+    let (video, buffer_size, limits) = {
+        let state = state.lock().unwrap();
+        let video = state
+            .get(&video_id)
+            .cloned()
+            .ok_or((StatusCode::NOT_FOUND, format!("No video with id {}", video_id)))?;
+        (video, state.stream_buffer_size, state.stream_limits)
+    };
+    let path = jobs.audio_path(&video.key, audio.format);
+    let kind = JobKind::Audio { key: video.key.clone(), format: audio.format };
+    if !path.is_file() && jobs.unfinished_like(&kind) >= jobs::MAX_AUDIO_JOBS {
+        return Err((
+            StatusCode::TOO_MANY_REQUESTS,
+            "Too many audio tracks are being extracted, try again in a moment".to_string(),
+        ));
+    }
+    let stem = video.path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = format!("{}.{}", stem, audio.format.extension());
+    let response = serve_job_output(&jobs, kind, &path, &name, request, buffer_size, limits).await?;
+    Ok(streams::hold(response, slot))
+}
+
+/// Every job that is pending or running, and the latest finished ones.
+pub async fn list_jobs(_editor: Editor, Extension(jobs): Extension<SharedJobQueue>) -> Json<Vec<Job>> {
+    Json(jobs.list())
+}
+
+pub async fn get_job(
+    Path(job_id): Path<u64>,
+    _editor: Editor,
+    Extension(jobs): Extension<SharedJobQueue>,
+) -> Result<Json<Job>, (StatusCode, String)> {
+    jobs.get(job_id)
+        .map(Json)
+        .ok_or((StatusCode::NOT_FOUND, format!("No job with id {}", job_id)))
+}
+
+/// A job update as a server-sent event: `status` with the job, `progress` with a percentage, or `log` with a line.
+fn job_event(update: JobUpdate) -> Result<Event, serde_json::Error> {
+    match update {
+        JobUpdate::Status(job) => Event::default().event("status").json_data(job),
+        JobUpdate::Progress { progress, .. } => Event::default().event("progress").json_data(json!({ "progress": progress })),
+        JobUpdate::Log { line, .. } => Ok(Event::default().event("log").data(line)),
+    }
+}
+
+/// Stream what happens to a job as server-sent events, starting with its current status, until it is done or failed.
+pub async fn job_events(
+    Path(job_id): Path<u64>,
+    _editor: Editor,
+    Extension(jobs): Extension<SharedJobQueue>,
+) -> Result<Sse<impl Stream<Item = Result<Event, serde_json::Error>>>, (StatusCode, String)> {
+    // Subscribe before looking the job up so nothing happening in between is missed.
+    let updates = jobs.subscribe();
+    let job = jobs
+        .get(job_id)
+        .ok_or((StatusCode::NOT_FOUND, format!("No job with id {}", job_id)))?;
+    let finished = job.status.is_finished();
+    let following = stream::unfold((updates, finished), move |(mut updates, finished)| async move {
+        if finished {
+            return None;
+        }
+        loop {
+            match updates.recv().await {
+                Ok(update) if update.job_id() == job_id => {
+                    let finished = matches!(&update, JobUpdate::Status(job) if job.status.is_finished());
+                    return Some((job_event(update), (updates, finished)));
+                }
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    });
+    let events = stream::once(async move { job_event(JobUpdate::Status(job)) }).chain(following);
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ThumbnailRequest {
+    /// Where to take the frame from, in seconds. A tenth into the video by default.
+    pub at: Option<f64>,
+    #[serde(default)]
+    pub priority: jobs::Priority,
+}
+
+/// Queue (re)generating the thumbnail of a video.
+pub async fn generate_thumbnail(
+    Path(video_id): Path<String>,
+    _editor: Editor,
+    State(state): State<SharedState>,
+    Extension(jobs): Extension<SharedJobQueue>,
+    request: Option<Json<ThumbnailRequest>>,
+) -> Result<(StatusCode, Json<Job>), (StatusCode, String)> {
+    let request = request.map(|Json(request)| request).unwrap_or_default();
+    let video = state
+        .lock()
+        .unwrap()
+        .get(&video_id)
+        .cloned()
+        .ok_or((StatusCode::NOT_FOUND, format!("No video with id {}", video_id)))?;
+    let at = request.at.unwrap_or_else(|| jobs::thumbnail_position(&video));
+    let job = jobs.submit(JobKind::Thumbnail { key: video.key, at }, request.priority);
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+#[cfg(feature = "transcoding")]
+#[derive(Debug, Deserialize)]
+pub struct ConvertRequest {
+    /// The ids of the videos to convert.
+    pub ids: Vec<String>,
+    #[serde(default)]
+    pub container: convert::Container,
+    #[serde(default)]
+    pub codec: convert::VideoCodec,
+    /// Delete the originals once they are converted, instead of keeping both.
+    #[serde(default)]
+    pub replace: bool,
+    /// Burn the configured watermark into the conversions.
+    #[serde(default)]
+    pub watermark: bool,
+    #[serde(default)]
+    pub priority: jobs::Priority,
+}
+
+/// Queue converting videos. Nothing is queued unless every video exists and can be converted.
+#[cfg(feature = "transcoding")]
+pub async fn convert_videos(
+    _editor: Editor,
+    State(state): State<SharedState>,
+    Extension(jobs): Extension<SharedJobQueue>,
+    Json(request): Json<ConvertRequest>,
+) -> Result<(StatusCode, Json<Vec<Job>>), (StatusCode, String)> {
+    if request.watermark {
+        convert::check_watermark(jobs.watermark(), request.codec, request.replace)
+            .map_err(|err| (StatusCode::UNPROCESSABLE_ENTITY, err))?;
+    }
+    let mut kinds = vec![];
+    for id in &request.ids {
+        let video = state
+            .lock()
+            .unwrap()
+            .get(id)
+            .cloned()
+            .ok_or((StatusCode::NOT_FOUND, format!("No video with id {}", id)))?;
+        convert::target(&video.path, request.container, request.codec, request.replace, request.watermark)
+            .map_err(|err| (StatusCode::CONFLICT, err))?;
+        kinds.push(JobKind::Convert {
+            key: video.key,
+            container: request.container,
+            codec: request.codec,
+            replace: request.replace,
+            watermark: request.watermark,
+        });
+    }
+    let submitted = kinds
+        .into_iter()
+        .map(|kind| jobs.submit(kind, request.priority))
+        .collect();
+    Ok((StatusCode::ACCEPTED, Json(submitted)))
+}
+
+pub fn api_router() -> Router<SharedState> {
+    let router = Router::new()
+        .route("/api/videos", get(list_videos))
+        .route("/api/videos/:video_id/checksum", get(video_checksum))
+        .route("/api/scan/status", get(scan_status))
+        .route("/api/quarantine", get(list_quarantine))
+        .route("/api/stats/storage", get(storage_stats))
+        .route("/api/stats/library", get(library_stats))
+        .route("/api/videos/:video_id/progress", get(get_progress).post(set_progress))
+        .route("/api/progress", get(list_progress))
+        .route("/api/videos/:video_id/favorite", post(toggle_favorite))
+        .route("/api/favorites", get(list_favorites))
+        .route("/api/history", get(list_history))
+        .route("/api/videos/:video_id/rating", put(set_rating))
+        .route("/api/ratings", get(list_ratings))
+        .route("/api/videos/:video_id/clip", get(extract_clip))
+        .route("/api/videos/:video_id/audio", get(extract_audio))
+        .route("/api/version", get(version::version));
+    let admin = Router::new()
+        .route("/api/jobs", get(list_jobs))
+        .route("/api/jobs/:job_id", get(get_job))
+        .route("/api/jobs/:job_id/events", get(job_events))
+        .route("/api/videos/:video_id", patch(update_video).delete(trash_video))
+        .route("/api/videos/:video_id/move", post(move_video))
+        .route("/api/rescan", post(rescan_folder))
+        .route("/api/trash", get(list_trash).delete(empty_trash))
+        .route("/api/trash/restore", post(restore_video))
+        .route("/api/videos/:video_id/thumbnail", post(generate_thumbnail));
+    #[cfg(feature = "transcoding")]
+    let admin = admin.route("/api/convert", post(convert_videos));
+    router.merge(users::require(Role::Admin, admin))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{metadata::VideoMetadata, MediaKind};
+    use axum::http::Uri;
+
+    fn video(key: &str, codec: &str, height: u32, duration: f64) -> VideoEntry {
+        let name = key.rsplit('/').next().unwrap().to_string();
+        let extension = name.rsplit('.').next().unwrap().to_string();
+        VideoEntry {
+            id: crate::video_slug(key),
+            path: std::path::PathBuf::from("/videos").join(key),
+            key: key.to_string(),
+            title: name.clone(),
+            name,
+            size: 1024,
+            kind: MediaKind::of_extension(&extension),
+            extension,
+            metadata: Some(VideoMetadata {
+                codec: Some(codec.to_string()),
+                width: Some(height * 16 / 9),
+                height: Some(height),
+                duration: Some(duration),
+            }),
+            hidden: false,
+            private: false,
+        }
+    }
+
+    fn filter(query: &str) -> VideoFilter {
+        let uri: Uri = format!("/api/videos?{}", query).parse().unwrap();
+        Query::<VideoFilter>::try_from_uri(&uri).unwrap().0
+    }
+
+    fn keys(filter: &VideoFilter, videos: &[VideoEntry], stats: &VideoStats) -> Vec<String> {
+        filter
+            .apply(videos, stats, &search::SearchIndex::default())
+            .into_iter()
+            .map(|video| video.key.clone())
+            .collect()
+    }
+
+    fn library() -> Vec<VideoEntry> {
+        vec![
+            video("clip.mp4", "h264", 720, 60.0),
+            video("film.mkv", "hevc", 2160, 7200.0),
+            video("show.mkv", "h264", 1080, 1500.0),
+        ]
+    }
+
+    #[test]
+    fn filters_by_extension_codec_and_resolution() {
+        let videos = library();
+        let stats = VideoStats::default();
+        assert_eq!(keys(&filter("ext=mkv"), &videos, &stats), ["film.mkv", "show.mkv"]);
+        assert_eq!(keys(&filter("ext=MKV,mp4&codec=hevc"), &videos, &stats), ["film.mkv"]);
+        assert_eq!(keys(&filter("min_height=1080&max_height=1080"), &videos, &stats), ["show.mkv"]);
+        assert!(keys(&filter("codec=av1"), &videos, &stats).is_empty());
+    }
+
+    #[test]
+    fn filters_by_duration_and_bucket() {
+        let videos = library();
+        let stats = VideoStats::default();
+        assert_eq!(keys(&filter("min_duration=300&max_duration=1800"), &videos, &stats), ["show.mkv"]);
+        assert_eq!(keys(&filter("bucket=short"), &videos, &stats), ["clip.mp4"]);
+        assert_eq!(keys(&filter("bucket=long"), &videos, &stats), ["film.mkv"]);
+        assert_eq!(DurationBucket::of(300.0), DurationBucket::Short);
+        assert_eq!(DurationBucket::of(300.5), DurationBucket::Medium);
+    }
+
+    #[test]
+    fn leaves_out_unprobed_private_and_hidden_videos() {
+        let mut videos = library();
+        videos[0].metadata = None;
+        videos[1].private = true;
+        videos[2].hidden = true;
+        let stats = VideoStats::default();
+        assert_eq!(keys(&filter(""), &videos, &stats), ["clip.mp4"]);
+        assert!(keys(&filter("min_height=1"), &videos, &stats).is_empty());
+        let mut show_hidden = filter("min_height=1");
+        show_hidden.show_hidden = true;
+        assert_eq!(keys(&show_hidden, &videos, &stats), ["show.mkv"]);
+    }
+
+    #[test]
+    fn sorts_listings() {
+        let videos = library();
+        let mut stats = VideoStats::default();
+        stats.views.insert("show.mkv".to_string(), 3);
+        stats.ratings.insert("clip.mp4".to_string(), 4.5);
+        assert_eq!(keys(&filter("sort=-duration"), &videos, &stats), ["film.mkv", "show.mkv", "clip.mp4"]);
+        assert_eq!(keys(&filter("sort=-views"), &videos, &stats)[0], "show.mkv");
+        assert_eq!(keys(&filter("min_rating=4"), &videos, &stats), ["clip.mp4"]);
+        assert!(filter("sort=-views").uses_stats());
+        assert!(!filter("sort=name").uses_stats());
+    }
+
+    #[test]
+    fn rejects_malformed_filters() {
+        let uri: Uri = "/api/videos?min_height=tall".parse().unwrap();
+        assert!(Query::<VideoFilter>::try_from_uri(&uri).is_err());
+        let uri: Uri = "/api/videos?bucket=huge".parse().unwrap();
+        assert!(Query::<VideoFilter>::try_from_uri(&uri).is_err());
+    }
+
+    #[test]
+    fn encodes_cursors() {
+        let videos = library();
+        let cursor = Cursor::after(&videos[1]);
+        assert_eq!(Cursor::decode(&cursor.encode()), Some(cursor.clone()));
+        assert!(!cursor.encode().contains(['/', '+', '=']));
+    }
+
+    #[test]
+    fn rejects_malformed_cursors() {
+        assert_eq!(Cursor::decode("not base64!"), None);
+        assert_eq!(Cursor::decode(""), None);
+        assert_eq!(Cursor::decode(&URL_SAFE_NO_PAD.encode([0xff, 0xfe])), None);
+    }
+
+    #[test]
+    fn resumes_after_the_cursor() {
+        let videos = library();
+        let listed: Vec<&VideoEntry> = videos.iter().collect();
+        let cursor = Cursor::after(&videos[0]);
+        assert_eq!(cursor.start(&listed, true), Some(1));
+        // Videos added before it don't shift the next chunk.
+        let added = video("a.mp4", "h264", 720, 60.0);
+        let grown: Vec<&VideoEntry> = std::iter::once(&added).chain(videos.iter()).collect();
+        assert_eq!(cursor.start(&grown, true), Some(2));
+    }
+
+    #[test]
+    fn resumes_after_a_removed_video_only_in_key_order() {
+        let videos = library();
+        let cursor = Cursor::after(&videos[1]);
+        let shrunk: Vec<&VideoEntry> = vec![&videos[0], &videos[2]];
+        assert_eq!(cursor.start(&shrunk, true), Some(1));
+        assert_eq!(cursor.start(&shrunk, false), None);
+        assert!(filter("kind=video").in_key_order());
+        assert!(filter("sort=unknown").in_key_order());
+        assert!(!filter("sort=-unknown").in_key_order());
+        assert!(!filter("q=film").in_key_order());
+    }
+}
@@ -0,0 +1,52 @@
+//! The static files of the UI (styles, scripts, icons), embedded into the binary
+//! so it is self-contained and `/assets` can never collide with the media root.
+
+use axum::{
+    extract::Path,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use rust_embed::RustEmbed;
+
+#[derive(RustEmbed)]
+#[folder = "assets/"]
+pub struct UiAssets;
+
+/// Respond with an embedded file, tagged with its content hash so browsers can revalidate cheaply.
+pub fn asset_response(file: &str, request_headers: &HeaderMap) -> Response {
+    let Some(asset) = UiAssets::get(file) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let etag = format!(
+        "\"{}\"",
+        asset
+            .metadata
+            .sha256_hash()
+            .iter()
+            .take(16)
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>()
+    );
+    let etag = HeaderValue::from_str(&etag).unwrap();
+    if request_headers.get(header::IF_NONE_MATCH) == Some(&etag) {
+        return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response();
+    }
+
+    let content_type = mime_guess::from_path(file).first_or_octet_stream();
+    (
+        [
+            (header::CONTENT_TYPE, HeaderValue::from_str(content_type.as_ref()).unwrap()),
+            (header::ETAG, etag),
+        ],
+        asset.data.into_owned(),
+    )
+        .into_response()
+}
+
+pub async fn ui_asset(Path(file): Path<String>, headers: HeaderMap) -> Response {
+    asset_response(file.trim_start_matches('/'), &headers)
+}
+
+pub async fn favicon(headers: HeaderMap) -> Response {
+    asset_response("favicon.ico", &headers)
+}
@@ -0,0 +1,103 @@
+//! Authorization of the API endpoints that change the library (renaming, moving, deleting, ...).
+//!
+//! They are allowed for logged in users and for requests carrying the `--api-token` as a bearer token, or from a
+//! session that logged in with it.
+//! Without either configured they are disabled, since anybody who can reach the server could use them.
+
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header, request::Parts, StatusCode},
+};
+use std::sync::{Arc, RwLock};
+
+use crate::{oidc::UserSession, session::CurrentSession};
+
+/// The configured API token, if any. It can be changed while the server runs.
+#[derive(Debug, Default)]
+pub struct ApiToken(RwLock<Option<String>>);
+
+pub type SharedApiToken = Arc<ApiToken>;
+
+impl ApiToken {
+    pub fn new(token: Option<String>) -> Self {
+        Self(RwLock::new(token))
+    }
+
+    pub fn get(&self) -> Option<String> {
+        self.0.read().unwrap().clone()
+    }
+
+    pub fn set(&self, token: Option<String>) {
+        *self.0.write().unwrap() = token;
+    }
+
+    /// Whether a token is the configured one.
+    pub fn matches(&self, given: &str) -> bool {
+        self.get().is_some_and(|expected| secrets_match(expected.as_bytes(), given.trim().as_bytes()))
+    }
+}
+
+/// Compare two secrets in constant time, so the token can't be guessed byte by byte.
+pub fn secrets_match(expected: &[u8], given: &[u8]) -> bool {
+    expected.len() == given.len()
+        && expected
+            .iter()
+            .zip(given)
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+/// Proof that a request may change the library: either a logged in user or a valid API token.
+#[derive(Debug, Clone)]
+pub enum Editor {
+    User(String),
+    Token,
+}
+
+impl Editor {
+    /// Who is making the change, e.g. to account uploads to: `user:<subject>`, or `token` for the API token.
+    pub fn name(&self) -> String {
+        match self {
+            Self::User(subject) => format!("user:{}", subject),
+            Self::Token => "token".to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Editor
+where
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(user) = parts.extensions.get::<UserSession>() {
+            return Ok(Self::User(user.subject.clone()));
+        }
+        let expected = parts
+            .extensions
+            .get::<SharedApiToken>()
+            .and_then(|token| token.get());
+        let Some(expected) = expected else {
+            return Err((
+                StatusCode::FORBIDDEN,
+                "Changing the library requires a login or an --api-token",
+            ));
+        };
+        let session = parts.extensions.get::<CurrentSession>();
+        if session.is_some_and(|session| session.has_api_token(&expected)) {
+            return Ok(Self::Token);
+        }
+        let given = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        match given {
+            Some(given) if secrets_match(expected.as_bytes(), given.trim().as_bytes()) => Ok(Self::Token),
+            _ => Err((StatusCode::UNAUTHORIZED, "Missing or invalid API token")),
+        }
+    }
+}
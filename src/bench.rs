@@ -0,0 +1,201 @@
+//! The `bench` command: load a running server and report how fast it serves the index, the API and range requests
+//! on videos, so regressions in the serving path show up as numbers.
+
+use clap::Args;
+use reqwest::{header, Client, StatusCode};
+use serde::Deserialize;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing::{error, warn};
+
+#[derive(Debug, Clone, Args)]
+pub struct BenchArgs {
+    /// The server to load, including its base path if it has one.
+    #[clap(long, default_value = "http://127.0.0.1:9092")]
+    pub url: String,
+
+    /// The number of requests in flight at once.
+    #[clap(long, default_value_t = 16)]
+    pub concurrency: usize,
+
+    /// The seconds to load each path for.
+    #[clap(long, default_value_t = 10)]
+    pub duration: u64,
+
+    /// The bytes asked for by each range request.
+    #[clap(long, default_value_t = 1024 * 1024)]
+    pub range_size: u64,
+}
+
+/// The fields of a video in `/api/videos` needed to ask for ranges of it.
+#[derive(Debug, Clone, Deserialize)]
+struct Video {
+    id: String,
+    size: u64,
+}
+
+/// What is requested, over and over.
+#[derive(Debug, Clone)]
+enum Target {
+    Get(String),
+    /// Ranges at random offsets into the videos.
+    Ranges { videos: Arc<Vec<(String, u64)>>, size: u64 },
+}
+
+#[derive(Debug, Default)]
+struct Outcome {
+    /// How long each successful request took, until its whole body was read.
+    latencies: Vec<Duration>,
+    errors: u64,
+    bytes: u64,
+}
+
+/// A xorshift generator, good enough to spread ranges over the videos.
+fn next_random(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+async fn request(client: &Client, target: &Target, random: &mut u64) -> reqwest::Result<(StatusCode, u64)> {
+    let request = match target {
+        Target::Get(url) => client.get(url),
+        Target::Ranges { videos, size } => {
+            let (url, length) = &videos[next_random(random) as usize % videos.len()];
+            let start = next_random(random) % length.saturating_sub(*size).max(1);
+            let end = (start + size).min(*length) - 1;
+            client
+                .get(url)
+                .header(header::RANGE, format!("bytes={}-{}", start, end))
+        }
+    };
+    let response = request.send().await?;
+    let status = response.status();
+    let body = response.bytes().await?;
+    Ok((status, body.len() as u64))
+}
+
+/// Request the target from `concurrency` workers until the time is up.
+async fn load(client: &Client, target: Target, args: &BenchArgs) -> (Outcome, Duration) {
+    let started = Instant::now();
+    let deadline = started + Duration::from_secs(args.duration);
+    let workers: Vec<_> = (0..args.concurrency.max(1))
+        .map(|worker| {
+            let client = client.clone();
+            let target = target.clone();
+            tokio::spawn(async move {
+                let mut outcome = Outcome::default();
+                let mut random = 0x9e37_79b9_7f4a_7c15 ^ (worker as u64 + 1);
+                while Instant::now() < deadline {
+                    let sent = Instant::now();
+                    match request(&client, &target, &mut random).await {
+                        Ok((status, bytes)) if status.is_success() => {
+                            outcome.latencies.push(sent.elapsed());
+                            outcome.bytes += bytes;
+                        }
+                        _ => outcome.errors += 1,
+                    }
+                }
+                outcome
+            })
+        })
+        .collect();
+    let mut total = Outcome::default();
+    for worker in workers {
+        if let Ok(outcome) = worker.await {
+            total.latencies.extend(outcome.latencies);
+            total.errors += outcome.errors;
+            total.bytes += outcome.bytes;
+        }
+    }
+    (total, started.elapsed())
+}
+
+/// The latency below which `percent` of the requests were served.
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    sorted[(sorted.len() * percent / 100).min(sorted.len() - 1)]
+}
+
+fn millis(duration: Duration) -> String {
+    format!("{:.1}ms", duration.as_secs_f64() * 1000.0)
+}
+
+fn report(name: &str, mut outcome: Outcome, elapsed: Duration) {
+    outcome.latencies.sort_unstable();
+    let seconds = elapsed.as_secs_f64().max(f64::EPSILON);
+    let latencies = &outcome.latencies;
+    println!(
+        "{:<6} {:>8} ok {:>6} errors {:>10.1} req/s {:>9.1} MiB/s   p50 {:>8} p90 {:>8} p99 {:>8} max {:>8}",
+        name,
+        latencies.len(),
+        outcome.errors,
+        latencies.len() as f64 / seconds,
+        outcome.bytes as f64 / seconds / (1024.0 * 1024.0),
+        millis(percentile(latencies, 50)),
+        millis(percentile(latencies, 90)),
+        millis(percentile(latencies, 99)),
+        millis(latencies.last().copied().unwrap_or_default()),
+    );
+}
+
+/// Run the `bench` command. Returns the exit code: 0 if every path was served without errors, 1 otherwise.
+pub async fn run(args: &BenchArgs) -> i32 {
+    let base = args.url.trim_end_matches('/');
+    let client = match Client::builder().pool_max_idle_per_host(args.concurrency).build() {
+        Ok(client) => client,
+        Err(err) => {
+            error!("Failed to set up the HTTP client: {}", err);
+            return 1;
+        }
+    };
+    let videos: Vec<Video> = match client.get(format!("{}/api/videos", base)).send().await {
+        Ok(response) => match response.error_for_status() {
+            Ok(response) => response.json().await.unwrap_or_default(),
+            Err(err) => {
+                error!("Failed to list the videos: {}", err);
+                return 1;
+            }
+        },
+        Err(err) => {
+            error!("Can't reach {}: {}", base, err);
+            return 1;
+        }
+    };
+    let videos: Vec<(String, u64)> = videos
+        .into_iter()
+        .filter(|video| video.size > 0)
+        .map(|video| (format!("{}/video/{}", base, video.id), video.size))
+        .collect();
+
+    let mut targets = vec![
+        ("index", Target::Get(format!("{}/", base))),
+        ("api", Target::Get(format!("{}/api/videos", base))),
+    ];
+    if videos.is_empty() {
+        warn!("{} has no videos, skipping range requests", base);
+    } else {
+        let ranges = Target::Ranges {
+            videos: Arc::new(videos),
+            size: args.range_size.max(1),
+        };
+        targets.push(("range", ranges));
+    }
+
+    println!(
+        "Loading {} with {} concurrent requests for {}s per path",
+        base, args.concurrency, args.duration
+    );
+    let mut failed = false;
+    for (name, target) in targets {
+        let (outcome, elapsed) = load(&client, target, args).await;
+        failed |= outcome.errors > 0;
+        report(name, outcome, elapsed);
+    }
+    i32::from(failed)
+}
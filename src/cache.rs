@@ -0,0 +1,189 @@
+//! In-memory caches: a bounded cache for small files like posters and subtitles,
+//! so gallery pages with hundreds of images don't hit the disk on every request,
+//! and a cache of rendered pages so they aren't re-rendered until the index changes.
+//! Also the `Cache-Control` policies telling browsers and proxies what to cache.
+
+use axum::{
+    body::Bytes,
+    http::{header, HeaderValue, Response},
+    Router,
+};
+use lru::LruCache;
+use std::{
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use tower_http::set_header::SetResponseHeaderLayer;
+
+/// A small file held in memory.
+#[derive(Debug, Clone)]
+pub struct CachedFile {
+    pub bytes: Bytes,
+    pub content_type: String,
+}
+
+struct Entries {
+    /// `None` remembers that a file doesn't exist, so missing posters are cheap too.
+    files: LruCache<PathBuf, Option<CachedFile>>,
+    used_bytes: usize,
+}
+
+/// An LRU cache of file contents bounded by the total number of bytes held.
+pub struct FileCache {
+    entries: Mutex<Entries>,
+    capacity_bytes: usize,
+    max_entry_bytes: usize,
+}
+
+pub type SharedFileCache = Arc<FileCache>;
+
+impl FileCache {
+    /// Create a cache holding at most `capacity_bytes`, never caching files above `max_entry_bytes`.
+    pub fn new(capacity_bytes: usize, max_entry_bytes: usize) -> Self {
+        Self {
+            entries: Mutex::new(Entries {
+                files: LruCache::unbounded(),
+                used_bytes: 0,
+            }),
+            capacity_bytes,
+            max_entry_bytes,
+        }
+    }
+
+    /// Get a file from the cache, reading it from disk on a miss.
+    /// Returns `Ok(None)` if the file doesn't exist.
+    pub async fn get(&self, path: &Path) -> std::io::Result<Option<CachedFile>> {
+        if let Some(cached) = self.entries.lock().unwrap().files.get(&path.to_path_buf()) {
+            return Ok(cached.clone());
+        }
+
+        let file = match tokio::fs::read(path).await {
+            Ok(bytes) => Some(CachedFile {
+                bytes: Bytes::from(bytes),
+                content_type: mime_guess::from_path(path)
+                    .first_or_octet_stream()
+                    .to_string(),
+            }),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err),
+        };
+        self.insert(path.to_path_buf(), file.clone());
+        Ok(file)
+    }
+
+    fn insert(&self, path: PathBuf, file: Option<CachedFile>) {
+        let size = file.as_ref().map(|file| file.bytes.len()).unwrap_or(0);
+        if size > self.max_entry_bytes || size > self.capacity_bytes {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(Some(previous)) = entries.files.put(path, file) {
+            entries.used_bytes -= previous.bytes.len();
+        }
+        entries.used_bytes += size;
+        while entries.used_bytes > self.capacity_bytes {
+            match entries.files.pop_lru() {
+                Some((_, Some(evicted))) => entries.used_bytes -= evicted.bytes.len(),
+                Some((_, None)) => {}
+                None => break,
+            }
+        }
+    }
+
+    /// Drop a single file, e.g. after it was (re)generated.
+    pub fn forget(&self, path: &Path) {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(Some(previous)) = entries.files.pop(&path.to_path_buf()) {
+            entries.used_bytes -= previous.bytes.len();
+        }
+    }
+
+    /// Drop everything, e.g. after the library was rescanned.
+    pub fn clear(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.files.clear();
+        entries.used_bytes = 0;
+    }
+}
+
+/// Extensions of poster images looked for next to a video.
+pub const POSTER_EXTENSIONS: [&str; 4] = ["jpg", "jpeg", "png", "webp"];
+
+/// The candidate poster images of a video, e.g. `movie.jpg` and `movie-poster.jpg` for `movie.mp4`.
+pub fn poster_candidates(video: &Path) -> Vec<PathBuf> {
+    let Some(stem) = video.file_stem() else {
+        return vec![];
+    };
+    let with_suffix = |suffix: &str| {
+        let mut name = stem.to_os_string();
+        name.push(suffix);
+        video.with_file_name(name)
+    };
+    POSTER_EXTENSIONS
+        .iter()
+        .flat_map(|extension| [with_suffix(&format!(".{}", extension)), with_suffix(&format!("-poster.{}", extension))])
+        .collect()
+}
+
+/// The candidate WebVTT subtitles of a video, e.g. `movie.vtt` for `movie.mp4`.
+pub fn subtitle_candidates(video: &Path) -> Vec<PathBuf> {
+    vec![video.with_extension("vtt")]
+}
+
+/// Rendered HTML pages keyed by their query (sort, filter, page, ...),
+/// each tagged with the index generation it was rendered from.
+pub struct PageCache {
+    pages: Mutex<LruCache<String, (u64, Bytes)>>,
+}
+
+pub type SharedPageCache = Arc<PageCache>;
+
+impl PageCache {
+    /// Create a cache holding at most `capacity` distinct pages.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            pages: Mutex::new(LruCache::new(
+                NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN),
+            )),
+        }
+    }
+
+    /// Get a page rendered from the given index generation.
+    pub fn get(&self, key: &str, generation: u64) -> Option<Bytes> {
+        match self.pages.lock().unwrap().get(key) {
+            Some((rendered_generation, html)) if *rendered_generation == generation => {
+                Some(html.clone())
+            }
+            _ => None,
+        }
+    }
+
+    pub fn insert(&self, key: String, generation: u64, html: Bytes) {
+        self.pages.lock().unwrap().put(key, (generation, html));
+    }
+}
+
+/// Parse a `Cache-Control` policy such as `public, max-age=86400, immutable`.
+pub fn parse_cache_control(value: &str) -> Result<HeaderValue, String> {
+    HeaderValue::from_str(value.trim()).map_err(|_| format!("{:?} is not a valid header value", value))
+}
+
+/// Send a `Cache-Control` policy with the successful responses of some routes, unless a handler chose its own.
+/// Errors aren't cached, e.g. so a poster that is missing now shows up once it was generated.
+pub fn with_cache_control<S>(router: Router<S>, policy: Option<&HeaderValue>) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let Some(policy) = policy.cloned() else {
+        return router;
+    };
+    router.layer(SetResponseHeaderLayer::if_not_present(
+        header::CACHE_CONTROL,
+        move |response: &Response<_>| {
+            let status = response.status();
+            (status.is_success() || status == axum::http::StatusCode::NOT_MODIFIED).then(|| policy.clone())
+        },
+    ))
+}
@@ -0,0 +1,232 @@
+//! The configuration file given with `--config`, and applying changes to it without a restart.
+//!
+//! The file holds command line options, one per line and without the leading dashes, e.g. `max-stream-rate = 10MiB/s`
+//! or `webdav`. Lines starting with `#` are comments. Options given on the command line take precedence over the
+//! file, which takes precedence over environment variables.
+//!
+//! The file is reloaded when it changes and on `SIGHUP`. Only some settings can change while the server runs; changes
+//! to the others are logged and wait for a restart.
+
+use clap::{error::ErrorKind, ArgMatches, CommandFactory, FromArgMatches};
+use std::{
+    ffi::{OsStr, OsString},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime},
+};
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+use crate::{
+    access::{IpFilter, SharedIpFilter},
+    auth::{ApiToken, SharedApiToken},
+    media::StreamLimits,
+    streams::{SharedStreamLimiter, StreamLimiter},
+    users::{SharedUserStore, UserStore},
+    SharedState, VideoPlayerConfig,
+};
+
+/// How often the file is checked for changes.
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The settings that take effect without a restart, by the id of their argument.
+const RELOADABLE: [&str; 6] = [
+    "api_token",
+    "max_stream_rate",
+    "max_stream_duration",
+    "max_streams_per_client",
+    "allow_ip",
+    "deny_ip",
+];
+
+/// The path given with `--config`, looked up before parsing, since the file adds to the arguments to parse.
+fn config_path(args: &[OsString]) -> Option<PathBuf> {
+    let mut args = args.iter().skip(1).map(|arg| arg.to_string_lossy());
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == "--config" {
+            return args.next().map(|path| PathBuf::from(path.as_ref()));
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(PathBuf::from(path));
+        }
+    }
+    None
+}
+
+/// The options in a configuration file, as command line arguments.
+fn read(path: &Path) -> std::io::Result<Vec<OsString>> {
+    let contents = std::fs::read_to_string(path)?;
+    let args = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let (name, value) = match line.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim())),
+                None => (line, None),
+            };
+            let name = name.trim_start_matches('-');
+            match value {
+                Some(value) => {
+                    let value = value
+                        .strip_prefix('"')
+                        .and_then(|value| value.strip_suffix('"'))
+                        .unwrap_or(value);
+                    format!("--{}={}", name, value).into()
+                }
+                None => format!("--{}", name).into(),
+            }
+        })
+        .collect();
+    Ok(args)
+}
+
+/// Parse the configuration from command line arguments and the configuration file they name, if any.
+pub fn load(args: impl IntoIterator<Item = OsString>) -> Result<(VideoPlayerConfig, ArgMatches), clap::Error> {
+    let mut args: Vec<OsString> = args.into_iter().collect();
+    if let Some(path) = config_path(&args) {
+        let options = read(&path).map_err(|err| {
+            clap::Error::raw(ErrorKind::Io, format!("Can't read {}: {}\n", path.display(), err))
+        })?;
+        // Right after the name of the program, so they come before any subcommand and the command line overrides them.
+        let at = args.len().min(1);
+        args.splice(at..at, options);
+    }
+    let matches = VideoPlayerConfig::command().try_get_matches_from(args)?;
+    let config = VideoPlayerConfig::from_arg_matches(&matches)?;
+    Ok((config, matches))
+}
+
+/// The settings that can change while the server runs, shared with everything that uses them.
+#[derive(Default)]
+pub struct LiveSettings {
+    pub api_token: SharedApiToken,
+    pub stream_limiter: SharedStreamLimiter,
+    pub ip_filter: SharedIpFilter,
+    pub users: SharedUserStore,
+    /// The index of every library, which knows the limits on streams.
+    libraries: Mutex<Vec<SharedState>>,
+}
+
+impl LiveSettings {
+    pub fn new(config: &VideoPlayerConfig) -> Self {
+        Self {
+            api_token: Arc::new(ApiToken::new(config.api_token.clone())),
+            stream_limiter: Arc::new(StreamLimiter::new(config.max_streams_per_client)),
+            ip_filter: Arc::new(RwLock::new(IpFilter::new(config.allow_ip.clone(), config.deny_ip.clone()))),
+            users: Arc::new(UserStore::new(config.users.clone())),
+            libraries: Mutex::default(),
+        }
+    }
+
+    pub fn add_library(&self, state: SharedState) {
+        self.libraries.lock().unwrap().push(state);
+    }
+
+    fn apply(&self, config: &VideoPlayerConfig) {
+        self.api_token.set(config.api_token.clone());
+        self.stream_limiter.set_max(config.max_streams_per_client);
+        *self.ip_filter.write().unwrap() = IpFilter::new(config.allow_ip.clone(), config.deny_ip.clone());
+        self.users.reload();
+        let limits = StreamLimits::from_config(config);
+        for library in self.libraries.lock().unwrap().iter() {
+            library.lock().unwrap().stream_limits = limits;
+        }
+    }
+}
+
+/// The ids of the arguments whose values differ, wherever the values came from.
+fn changed(old: &ArgMatches, new: &ArgMatches) -> Vec<String> {
+    let raw = |matches: &ArgMatches, id: &str| -> Option<Vec<OsString>> {
+        matches
+            .get_raw(id)
+            .map(|values| values.map(OsStr::to_os_string).collect())
+    };
+    VideoPlayerConfig::command()
+        .get_arguments()
+        .map(|arg| arg.get_id().to_string())
+        .filter(|id| raw(old, id) != raw(new, id))
+        .collect()
+}
+
+fn option_names(ids: &[&String]) -> String {
+    ids.iter()
+        .map(|id| format!("--{}", id.replace('_', "-")))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Load the configuration again and apply what changed, keeping the current one if the file is invalid.
+fn reload(live: &LiveSettings, path: &Path, current: &mut ArgMatches) {
+    let (config, matches) = match load(std::env::args_os()) {
+        Ok(loaded) => loaded,
+        Err(err) => {
+            let err = err.to_string();
+            let reason = err.lines().next().unwrap_or_default().trim_start_matches("error: ");
+            warn!("Keeping the current configuration, {} is invalid: {}", path.display(), reason);
+            return;
+        }
+    };
+    let changed = changed(current, &matches);
+    let (reloadable, fixed): (Vec<_>, Vec<_>) = changed.iter().partition(|id| RELOADABLE.contains(&id.as_str()));
+    if changed.is_empty() {
+        info!("Reloaded {}, nothing changed", path.display());
+    }
+    if !reloadable.is_empty() {
+        info!("Reloaded {}, applied {}", path.display(), option_names(&reloadable));
+    }
+    if !fixed.is_empty() {
+        warn!("Changes to {} in {} need a restart to apply", option_names(&fixed), path.display());
+    }
+    live.apply(&config);
+    *current = matches;
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Turn every `SIGHUP` into a reload.
+#[cfg(unix)]
+async fn forward_hangups(reload: Arc<Notify>) {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(err) => {
+            warn!("Can't reload the configuration on SIGHUP: {}", err);
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        reload.notify_one();
+    }
+}
+
+/// Reload the configuration whenever the file changes or on `SIGHUP`.
+/// `current` is what the running server was configured with.
+pub async fn watch(live: Arc<LiveSettings>, path: PathBuf, mut current: ArgMatches) {
+    let requested = Arc::new(Notify::new());
+    #[cfg(unix)]
+    tokio::spawn(forward_hangups(requested.clone()));
+
+    let mut last_modified = modified(&path);
+    let mut interval = tokio::time::interval(WATCH_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let modified = modified(&path);
+                if modified == last_modified {
+                    continue;
+                }
+                last_modified = modified;
+            }
+            _ = requested.notified() => {
+                last_modified = modified(&path);
+            }
+        }
+        reload(&live, &path, &mut current);
+    }
+}
@@ -0,0 +1,275 @@
+//! Converting videos to another container and/or codec in the background, through the job queue,
+//! either from the API or with the `convert` command.
+
+use axum::http::StatusCode;
+use clap::{Args, ValueEnum};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    ffi::OsString,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use tokio::sync::broadcast;
+use tracing::error;
+
+use crate::{
+    cache,
+    jobs::{JobKind, JobQueue, JobStatus, JobUpdate, Priority},
+    library::LibraryResult,
+    store::{IndexStore, SharedIndexStore},
+    watermark::Watermark,
+    SharedState, VideoEntry, VideoPlayerConfig, VideoPlayerState,
+};
+
+/// The containers videos can be converted to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Container {
+    #[default]
+    Mp4,
+    Mkv,
+    Webm,
+    Mov,
+}
+
+impl Container {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Mp4 => "mp4",
+            Self::Mkv => "mkv",
+            Self::Webm => "webm",
+            Self::Mov => "mov",
+        }
+    }
+
+    /// The ffmpeg muxer writing the container.
+    fn format(self) -> &'static str {
+        match self {
+            Self::Mp4 => "mp4",
+            Self::Mkv => "matroska",
+            Self::Webm => "webm",
+            Self::Mov => "mov",
+        }
+    }
+
+    /// How the audio is encoded: WebM only takes Opus (or Vorbis), Matroska takes anything,
+    /// and AAC is the safe choice for MP4 and QuickTime.
+    fn audio_options(self) -> &'static [&'static str] {
+        match self {
+            Self::Webm => &["-c:a", "libopus", "-b:a", "128k"],
+            Self::Mkv => &["-c:a", "copy"],
+            Self::Mp4 | Self::Mov => &["-c:a", "aac", "-b:a", "192k"],
+        }
+    }
+}
+
+/// The video codecs videos can be converted to, or `copy` to only change the container.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum VideoCodec {
+    Copy,
+    #[default]
+    H264,
+    Hevc,
+    Vp9,
+    Av1,
+}
+
+impl VideoCodec {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Copy => "copy",
+            Self::H264 => "h264",
+            Self::Hevc => "hevc",
+            Self::Vp9 => "vp9",
+            Self::Av1 => "av1",
+        }
+    }
+
+    fn options(self) -> &'static [&'static str] {
+        match self {
+            Self::Copy => &["-c:v", "copy"],
+            Self::H264 => &["-c:v", "libx264", "-crf", "23", "-preset", "medium"],
+            Self::Hevc => &["-c:v", "libx265", "-crf", "28", "-preset", "medium"],
+            Self::Vp9 => &["-c:v", "libvpx-vp9", "-crf", "32", "-b:v", "0"],
+            Self::Av1 => &["-c:v", "libsvtav1", "-crf", "35"],
+        }
+    }
+}
+
+/// The ffmpeg options converting a video, burning in a watermark if given.
+pub fn options(container: Container, codec: VideoCodec, watermark: Option<&Watermark>) -> Vec<String> {
+    let mut options: Vec<String> = vec![];
+    match watermark {
+        Some(watermark) => {
+            options.extend(watermark.options());
+            options.extend(["-map".into(), "[v]".into()]);
+        }
+        None => options.extend(["-map".into(), "0:v:0".into()]),
+    }
+    let mut rest = vec!["-map", "0:a?", "-sn"];
+    rest.extend(codec.options());
+    rest.extend(container.audio_options());
+    if matches!(container, Container::Mp4 | Container::Mov) {
+        rest.extend(["-movflags", "+faststart"]);
+    }
+    rest.extend(["-f", container.format()]);
+    options.extend(rest.into_iter().map(String::from));
+    options
+}
+
+/// Check that a conversion can be watermarked: a watermark is configured, the video is re-encoded (filters can't be
+/// applied to copied streams), and the clean original is kept.
+pub fn check_watermark(watermark: Option<&Watermark>, codec: VideoCodec, replace: bool) -> Result<(), String> {
+    if watermark.is_none() {
+        Err("No watermark is configured, see --watermark and --watermark-text".to_string())
+    } else if codec == VideoCodec::Copy {
+        Err("A watermark can't be burned into copied streams, pick a codec".to_string())
+    } else if replace {
+        Err("A watermarked conversion can't replace the original".to_string())
+    } else {
+        Ok(())
+    }
+}
+
+/// Where the conversion of a video is written to: next to it with the extension of the container, or with the codec
+/// added to the name if that is taken (by the video itself, unless it is to be replaced).
+/// Watermarked conversions are always marked as such in their name.
+pub fn target(
+    video: &Path,
+    container: Container,
+    codec: VideoCodec,
+    replace: bool,
+    watermark: bool,
+) -> Result<PathBuf, String> {
+    let converted = video.with_extension(container.extension());
+    if !watermark && ((replace && converted == video) || !converted.exists()) {
+        return Ok(converted);
+    }
+    let mut name: OsString = video.file_stem().unwrap_or_default().to_owned();
+    let mark = if watermark { "watermarked" } else { codec.name() };
+    name.push(format!(" ({}).{}", mark, container.extension()));
+    let converted = video.with_file_name(name);
+    if converted.exists() {
+        return Err(format!("{} exists already", converted.display()));
+    }
+    Ok(converted)
+}
+
+/// Index a finished conversion. If it replaces the original, the original is deleted
+/// and its progress, favorites, ... carry over to the conversion.
+pub fn add_conversion(
+    state: &mut VideoPlayerState,
+    store: &IndexStore,
+    original: &VideoEntry,
+    converted: PathBuf,
+    replace: bool,
+) -> LibraryResult<VideoEntry> {
+    if replace {
+        if converted != original.path {
+            std::fs::remove_file(&original.path).map_err(|err| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Failed to delete {}: {}", original.key, err),
+                )
+            })?;
+        }
+        state.remove_video(&original.id);
+        store.rename_key(&original.key, &state.relative_key(&converted));
+    }
+    Ok(state.add_video(converted)?.clone())
+}
+
+/// Convert videos given by their path relative to the assets root (or on disk), then exit with the outcome.
+#[derive(Debug, Clone, Args)]
+pub struct ConvertArgs {
+    /// The videos to convert.
+    #[clap(required = true)]
+    pub videos: Vec<PathBuf>,
+
+    #[clap(long, value_enum, default_value_t = Container::Mp4)]
+    pub container: Container,
+
+    #[clap(long, value_enum, default_value_t = VideoCodec::H264)]
+    pub codec: VideoCodec,
+
+    /// Delete the originals once they are converted, instead of keeping both.
+    #[clap(long)]
+    pub replace: bool,
+
+    /// Burn the configured watermark into the conversions.
+    #[clap(long)]
+    pub watermarked: bool,
+}
+
+/// Run the `convert` command. Returns the exit code: 0 if every video was converted, 1 otherwise.
+///
+/// Jobs aren't persisted, so a server running on the same data directory keeps its queue to itself.
+pub async fn run(config: &VideoPlayerConfig, args: &ConvertArgs) -> i32 {
+    let store: SharedIndexStore = match IndexStore::open(&config.data_dir) {
+        Ok(store) => Arc::new(store),
+        Err(err) => {
+            error!("Failed to open the store in {}: {}", config.data_dir.display(), err);
+            return 1;
+        }
+    };
+    let state: SharedState = match VideoPlayerState::build(config, store.clone()) {
+        Ok(state) => Arc::new(Mutex::new(state)),
+        Err(err) => {
+            error!("Failed to index {}: {}", config.assets_root, err);
+            return 1;
+        }
+    };
+    let watermark = Watermark::from_config(config);
+    if args.watermarked {
+        if let Err(err) = check_watermark(watermark.as_ref(), args.codec, args.replace) {
+            error!("{}", err);
+            return 1;
+        }
+    }
+    let queue = Arc::new(JobQueue::in_memory(&config.data_dir, &config.ffmpeg).with_watermark(watermark));
+    let mut updates = queue.subscribe();
+    queue.start(config.job_workers, state.clone(), store, Arc::new(cache::FileCache::new(0, 0)));
+
+    let root = PathBuf::from(&config.assets_root);
+    let mut pending = HashSet::new();
+    let mut failed = 0;
+    for path in &args.videos {
+        let key = {
+            let state = state.lock().unwrap();
+            state.relative_key(&root.join(path))
+        };
+        let exists = state.lock().unwrap().videos().iter().any(|video| video.key == key);
+        if !exists {
+            error!("{} is not a video in {}", path.display(), config.assets_root);
+            failed += 1;
+            continue;
+        }
+        let kind = JobKind::Convert {
+            key,
+            container: args.container,
+            codec: args.codec,
+            replace: args.replace,
+            watermark: args.watermarked,
+        };
+        pending.insert(queue.submit(kind, Priority::Normal).id);
+    }
+
+    while !pending.is_empty() {
+        let job = match updates.recv().await {
+            Ok(JobUpdate::Status(job)) if job.status.is_finished() && pending.contains(&job.id) => job,
+            Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        pending.remove(&job.id);
+        let JobKind::Convert { key, .. } = &job.kind else {
+            continue;
+        };
+        if job.status == JobStatus::Failed {
+            error!("Failed to convert {}: {}", key, job.error.unwrap_or_default());
+            failed += 1;
+        }
+    }
+    i32::from(failed > 0)
+}
@@ -0,0 +1,94 @@
+//! `OPTIONS` requests and CORS: which methods a path supports, and which other sites' pages may use them, as given
+//! with `--cors-origin`.
+
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, HeaderValue, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::sync::Arc;
+use tracing::warn;
+
+/// The request headers pages of other origins may send.
+const ALLOW_HEADERS: &str = "authorization, content-type, range";
+
+/// The response headers pages of other origins may read, besides the basic ones.
+const EXPOSE_HEADERS: &str = "accept-ranges, content-length, content-range, content-disposition, etag, link";
+
+/// How long browsers may cache the answer to a preflight, in seconds.
+const MAX_AGE: &str = "3600";
+
+/// The origins allowed to make cross-origin requests.
+#[derive(Debug, Clone, Default)]
+pub struct CorsPolicy {
+    any: bool,
+    origins: Vec<HeaderValue>,
+}
+
+pub type SharedCorsPolicy = Arc<CorsPolicy>;
+
+impl CorsPolicy {
+    /// A policy allowing the given origins, `*` allowing any.
+    pub fn new(origins: &[String]) -> Self {
+        Self {
+            any: origins.iter().any(|origin| origin == "*"),
+            origins: origins
+                .iter()
+                .filter(|origin| *origin != "*")
+                .filter_map(|origin| match HeaderValue::from_str(origin.trim_end_matches('/')) {
+                    Ok(origin) => Some(origin),
+                    Err(_) => {
+                        warn!("Ignoring the invalid CORS origin {}", origin);
+                        None
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// The `Access-Control-Allow-Origin` to answer a request with, if its origin is allowed.
+    fn allow_origin(&self, headers: &HeaderMap) -> Option<HeaderValue> {
+        let origin = headers.get(header::ORIGIN)?;
+        if self.any {
+            return Some(HeaderValue::from_static("*"));
+        }
+        self.origins.contains(origin).then(|| origin.clone())
+    }
+}
+
+/// Answer `OPTIONS` requests for paths that don't handle them themselves with `204 No Content` and the methods the
+/// path supports in `Allow`, rather than `405 Method Not Allowed`, and add the CORS headers for allowed origins.
+/// Preflights are answered the same way, allowing the methods of the path.
+pub async fn handle_cors<B>(State(policy): State<SharedCorsPolicy>, request: Request<B>, next: Next<B>) -> Response {
+    let options = request.method() == Method::OPTIONS;
+    let preflight = options && request.headers().contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+    let allow_origin = policy.allow_origin(request.headers());
+    let mut response = next.run(request).await;
+    let mut allow = None;
+    if options && response.status() == StatusCode::METHOD_NOT_ALLOWED {
+        if let Some(methods) = response.headers().get(header::ALLOW).and_then(|allow| allow.to_str().ok()) {
+            let methods = format!("{},OPTIONS", methods);
+            response = (StatusCode::NO_CONTENT, [(header::ALLOW, methods.clone())]).into_response();
+            allow = Some(methods);
+        }
+    }
+    let headers = response.headers_mut();
+    if !policy.any && !policy.origins.is_empty() {
+        headers.append(header::VARY, HeaderValue::from_static("origin"));
+    }
+    let Some(allow_origin) = allow_origin else {
+        return response;
+    };
+    headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+    if preflight {
+        if let Some(methods) = allow.and_then(|allow| HeaderValue::from_str(&allow).ok()) {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_METHODS, methods);
+            headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, HeaderValue::from_static(ALLOW_HEADERS));
+            headers.insert(header::ACCESS_CONTROL_MAX_AGE, HeaderValue::from_static(MAX_AGE));
+        }
+    } else {
+        headers.insert(header::ACCESS_CONTROL_EXPOSE_HEADERS, HeaderValue::from_static(EXPOSE_HEADERS));
+    }
+    response
+}
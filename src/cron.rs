@@ -0,0 +1,214 @@
+//! Cron expressions, for `--rescan-schedule`: minute, hour, day of the month, month and day of the week, in UTC.
+//!
+//! Fields are `*`, a number, a range like `1-5`, any of those with a step like `*/15` or `0-30/10`, or a comma
+//! separated list of them. Days of the week count from 0 (or 7) for Sunday. As in cron, a day matches when either
+//! of the day fields does, if both are restricted. `@hourly`, `@daily`, `@weekly` and `@monthly` are shortcuts.
+
+/// A parsed cron expression. Every field is a bit mask of the values it matches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+const MINUTE: u64 = 60;
+const HOUR: u64 = 60 * MINUTE;
+const DAY: u64 = 24 * HOUR;
+
+/// How far ahead to look for a time matching, since some expressions never match, like `0 0 30 2 *`.
+const HORIZON: u64 = 5 * 366 * DAY;
+
+/// Parse a field into the mask of the values from `min` to `max` it matches.
+fn parse_field(field: &str, min: u64, max: u64) -> Result<u64, String> {
+    let mut mask = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u64>().map_err(|_| format!("Invalid step in {}", part))?),
+            None => (part, 1),
+        };
+        let (from, to) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((from, to)) => (parse_value(from, part)?, parse_value(to, part)?),
+                // `5/10` means from 5 on.
+                None if part.contains('/') => (parse_value(range, part)?, max),
+                None => {
+                    let value = parse_value(range, part)?;
+                    (value, value)
+                }
+            },
+        };
+        if step == 0 {
+            return Err(format!("Invalid step in {}", part));
+        }
+        if from < min || to > max || from > to {
+            return Err(format!("{} is out of range {}-{}", part, min, max));
+        }
+        for value in (from..=to).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+    Ok(mask)
+}
+
+fn parse_value(value: &str, part: &str) -> Result<u64, String> {
+    value.parse().map_err(|_| format!("Invalid value in {}", part))
+}
+
+/// The year, month (1 to 12) and day of the month of a day since the Unix epoch.
+pub fn civil_date(days: u64) -> (u64, u64, u64) {
+    // See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+    (year, month, day)
+}
+
+impl Schedule {
+    pub fn parse(expression: &str) -> Result<Self, String> {
+        let expression = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            expression => expression,
+        };
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(format!(
+                "{} should have five fields: minute, hour, day of month, month and day of week",
+                expression
+            ));
+        };
+        let weekday_mask = parse_field(weekdays, 0, 7)?;
+        Ok(Self {
+            minutes: parse_field(minutes, 0, 59)?,
+            hours: parse_field(hours, 0, 23)?,
+            days: parse_field(days, 1, 31)?,
+            months: parse_field(months, 1, 12)?,
+            // Sunday is both 0 and 7.
+            weekdays: (weekday_mask | weekday_mask >> 7) & 0x7f,
+            any_day: days.starts_with('*'),
+            any_weekday: weekdays.starts_with('*'),
+        })
+    }
+
+    fn matches_day(&self, days: u64) -> bool {
+        let (_, month, day) = civil_date(days);
+        if self.months & 1 << month == 0 {
+            return false;
+        }
+        // The Unix epoch was a Thursday.
+        let weekday = (days + 4) % 7;
+        let day_matches = self.days & 1 << day != 0;
+        let weekday_matches = self.weekdays & 1 << weekday != 0;
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day_matches || weekday_matches,
+            _ => day_matches && weekday_matches,
+        }
+    }
+
+    /// The first time after the given one that matches, in seconds since the Unix epoch.
+    /// `None` if it never matches, like on the 30th of February.
+    pub fn next_after(&self, time: u64) -> Option<u64> {
+        let mut next = (time / MINUTE + 1) * MINUTE;
+        while next < time + HORIZON {
+            if !self.matches_day(next / DAY) {
+                next = (next / DAY + 1) * DAY;
+            } else if self.hours & 1 << (next % DAY / HOUR) == 0 {
+                next = (next / HOUR + 1) * HOUR;
+            } else if self.minutes & 1 << (next % HOUR / MINUTE) == 0 {
+                next += MINUTE;
+            } else {
+                return Some(next);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Monday, 2024-01-01 00:00 UTC.
+    const NEW_YEAR: u64 = 1_704_067_200;
+
+    fn next(expression: &str) -> Option<u64> {
+        Schedule::parse(expression).unwrap().next_after(NEW_YEAR)
+    }
+
+    #[test]
+    fn finds_the_next_time() {
+        assert_eq!(next("*/15 * * * *"), Some(NEW_YEAR + 15 * MINUTE));
+        assert_eq!(next("0 9 * * 1-5"), Some(NEW_YEAR + 9 * HOUR));
+        assert_eq!(next("30 2,4 * * *"), Some(NEW_YEAR + 2 * HOUR + 30 * MINUTE));
+        assert_eq!(next("0 0 29 2 *"), Some(1_709_164_800));
+    }
+
+    #[test]
+    fn expands_shortcuts() {
+        assert_eq!(next("@hourly"), Some(NEW_YEAR + HOUR));
+        assert_eq!(next("@daily"), Some(NEW_YEAR + DAY));
+        assert_eq!(next("@weekly"), Some(NEW_YEAR + 6 * DAY));
+        assert_eq!(next("@monthly"), Some(1_706_745_600));
+    }
+
+    #[test]
+    fn matches_either_day_field_when_both_are_restricted() {
+        // The 13th, or any Monday: the next Monday comes first.
+        assert_eq!(next("0 0 13 * 1"), Some(NEW_YEAR + 7 * DAY));
+        // With the day of the week left open, only the 13th.
+        assert_eq!(next("0 0 13 * *"), Some(NEW_YEAR + 12 * DAY));
+    }
+
+    #[test]
+    fn counts_sunday_as_0_and_7() {
+        assert_eq!(Schedule::parse("0 0 * * 0"), Schedule::parse("0 0 * * 7"));
+        assert_eq!(next("0 0 * * 7"), Some(NEW_YEAR + 6 * DAY));
+    }
+
+    #[test]
+    fn never_matches_impossible_days() {
+        assert_eq!(next("0 0 30 2 *"), None);
+    }
+
+    #[test]
+    fn rejects_invalid_expressions() {
+        for expression in [
+            "",
+            "* * * *",
+            "* * * * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * * 13 *",
+            "* * * * 8",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "a * * * *",
+            "1-x * * * *",
+            "@yearly",
+        ] {
+            assert!(Schedule::parse(expression).is_err(), "{:?}", expression);
+        }
+    }
+
+    #[test]
+    fn converts_days_to_dates() {
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        assert_eq!(civil_date(NEW_YEAR / DAY), (2024, 1, 1));
+        assert_eq!(civil_date(1_709_164_800 / DAY), (2024, 2, 29));
+    }
+}
@@ -0,0 +1,147 @@
+//! Protection against cross-site request forgery: a page of another site making the browser of a visitor send a
+//! request that changes something, e.g. `POST /reload`, along with the session cookie of the visitor.
+//!
+//! State-changing requests (anything but `GET`, `HEAD`, `OPTIONS` and `TRACE`) that carry a session cookie must also
+//! carry the CSRF token of the session, which other sites can't read: as an `X-CSRF-Token` header, or as the
+//! `csrf_token` field of a form. The pages get it from the `svs_csrf` cookie. Requests carrying the `--api-token` as a
+//! bearer token are let through, since browsers never add one on their own, and so are those without a session cookie,
+//! which carry no credentials to forge. Any other `Authorization` header doesn't count: the session cookie would still
+//! be what lets the request in.
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{FromRequest, State},
+    http::{header, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use axum_extra::extract::cookie::CookieJar;
+use percent_encoding::percent_decode;
+
+use crate::{
+    auth::{secrets_match, SharedApiToken},
+    session::{CurrentSession, SESSION_COOKIE},
+};
+
+/// The header that carries the token.
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+/// The `csrf_token` field of a form.
+fn form_token(form: &[u8]) -> Option<String> {
+    form.split(|&byte| byte == b'&').find_map(|pair| {
+        let value = pair.strip_prefix(b"csrf_token=")?;
+        let value: Vec<u8> = value.iter().map(|&byte| if byte == b'+' { b' ' } else { byte }).collect();
+        Some(percent_decode(&value).decode_utf8_lossy().to_string())
+    })
+}
+
+fn is_form(request: &Request<Body>) -> bool {
+    request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/x-www-form-urlencoded"))
+}
+
+fn rejection() -> Response {
+    (StatusCode::FORBIDDEN, "Missing or invalid CSRF token. Reload the page and try again.").into_response()
+}
+
+/// Whether a request carries the API token as a bearer token.
+fn has_api_token(request: &Request<Body>, token: &SharedApiToken) -> bool {
+    request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|given| token.matches(given))
+}
+
+/// Reject state-changing requests of a session that don't carry its CSRF token.
+/// Comes after [`crate::session::attach_session`].
+pub async fn verify_csrf(State(token): State<SharedApiToken>, request: Request<Body>, next: Next<Body>) -> Response {
+    if request.method().is_safe() || has_api_token(&request, &token) {
+        return next.run(request).await;
+    }
+    if CookieJar::from_headers(request.headers()).get(SESSION_COOKIE).is_none() {
+        return next.run(request).await;
+    }
+    let Some(expected) = request.extensions().get::<CurrentSession>().map(|session| session.csrf_token()) else {
+        return next.run(request).await;
+    };
+    let given = request
+        .headers()
+        .get(CSRF_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    if let Some(given) = given {
+        if !secrets_match(expected.as_bytes(), given.as_bytes()) {
+            return rejection();
+        }
+        return next.run(request).await;
+    }
+    if !is_form(&request) {
+        return rejection();
+    }
+    // The form is read to find the token, and handed on as it was.
+    let (parts, body) = request.into_parts();
+    let Ok(bytes) = Bytes::from_request(Request::new(body), &()).await else {
+        return (StatusCode::PAYLOAD_TOO_LARGE, "The form is too large.").into_response();
+    };
+    match form_token(&bytes) {
+        Some(given) if secrets_match(expected.as_bytes(), given.as_bytes()) => {
+            next.run(Request::from_parts(parts, Body::from(bytes))).await
+        }
+        _ => rejection(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::ApiToken;
+    use std::sync::Arc;
+
+    fn request(headers: &[(&str, &str)]) -> Request<Body> {
+        let mut request = Request::post("/reload");
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        request.body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn finds_the_token_of_a_form() {
+        assert_eq!(form_token(b"csrf_token=abc"), Some("abc".to_string()));
+        assert_eq!(form_token(b"path=shows&csrf_token=a%2Bb%3D&x=1"), Some("a+b=".to_string()));
+        assert_eq!(form_token(b"csrf_token=a+b"), Some("a b".to_string()));
+        assert_eq!(form_token(b"csrf_token="), Some(String::new()));
+    }
+
+    #[test]
+    fn finds_no_token_in_other_forms() {
+        assert_eq!(form_token(b""), None);
+        assert_eq!(form_token(b"path=shows"), None);
+        assert_eq!(form_token(b"xcsrf_token=abc"), None);
+        assert_eq!(form_token(b"csrf_tokens=abc"), None);
+    }
+
+    #[test]
+    fn tells_forms_apart() {
+        assert!(is_form(&request(&[("content-type", "application/x-www-form-urlencoded")])));
+        assert!(is_form(&request(&[("content-type", "application/x-www-form-urlencoded; charset=utf-8")])));
+        assert!(!is_form(&request(&[("content-type", "application/json")])));
+        assert!(!is_form(&request(&[])));
+    }
+
+    #[test]
+    fn only_lets_the_api_token_through() {
+        let token = Arc::new(ApiToken::new(Some("sekrit".to_string())));
+        assert!(has_api_token(&request(&[("authorization", "Bearer sekrit")]), &token));
+        assert!(!has_api_token(&request(&[("authorization", "Bearer nope")]), &token));
+        assert!(!has_api_token(&request(&[("authorization", "Basic c2Vrcml0")]), &token));
+        assert!(!has_api_token(&request(&[]), &token));
+        let no_token = Arc::new(ApiToken::new(None));
+        assert!(!has_api_token(&request(&[("authorization", "Bearer ")]), &no_token));
+    }
+}
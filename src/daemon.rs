@@ -0,0 +1,52 @@
+//! Running in the background for classic init scripts: detaching from the terminal, and keeping a PID file.
+
+use fs2::FileExt;
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+};
+
+/// Fork into the background, detached from the terminal, with the output going to `log_file`.
+/// Has to happen before any threads are started (i.e. the runtime), since only the calling thread survives a fork.
+#[cfg(unix)]
+pub fn daemonize(log_file: &Path) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let log = OpenOptions::new().create(true).append(true).open(log_file)?;
+    let null = File::open("/dev/null")?;
+    let check = |result: libc::c_int| if result == -1 { Err(io::Error::last_os_error()) } else { Ok(result) };
+    // SAFETY: no other threads are running yet, and the parents exit right away without running any destructors.
+    unsafe {
+        // Fork twice, so the daemon is not a session leader and can never reacquire a terminal.
+        if check(libc::fork())? > 0 {
+            libc::_exit(0);
+        }
+        check(libc::setsid())?;
+        if check(libc::fork())? > 0 {
+            libc::_exit(0);
+        }
+        libc::umask(0o027);
+        check(libc::dup2(null.as_raw_fd(), libc::STDIN_FILENO))?;
+        check(libc::dup2(log.as_raw_fd(), libc::STDOUT_FILENO))?;
+        check(libc::dup2(log.as_raw_fd(), libc::STDERR_FILENO))?;
+    }
+    Ok(())
+}
+
+/// Write the id of this process to `path`, locked for as long as the returned file is kept open,
+/// so a second instance started with the same PID file refuses to run.
+pub fn write_pid_file(path: &Path) -> io::Result<File> {
+    let mut file = OpenOptions::new().create(true).truncate(false).write(true).open(path)?;
+    if file.try_lock_exclusive().is_err() {
+        let running = std::fs::read_to_string(path).unwrap_or_default();
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("another instance is running with pid {}", running.trim()),
+        ));
+    }
+    file.set_len(0)?;
+    writeln!(file, "{}", std::process::id())?;
+    file.flush()?;
+    Ok(file)
+}
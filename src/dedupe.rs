@@ -0,0 +1,153 @@
+//! The `dedupe` command: finds the videos of the library that are identical byte for byte, and tells how much space
+//! the copies waste. It can write a shell script that deletes the copies, or replaces them with hard links, to review
+//! before running it.
+
+use clap::Args;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Write as _,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tracing::{error, warn};
+
+use crate::{integrity::hash_file, storage::describe_bytes, store::IndexStore, VideoEntry, VideoPlayerConfig, VideoPlayerState};
+
+#[derive(Debug, Clone, Args)]
+pub struct DedupeArgs {
+    /// Print every group of identical videos and the space the copies waste. The default, unless writing a script.
+    #[clap(long)]
+    pub report: bool,
+
+    /// Write a shell script to this path that keeps the first video of every group, by path, and deletes the others.
+    #[clap(long)]
+    pub script: Option<PathBuf>,
+
+    /// Have the script replace the copies with hard links to the video kept, rather than delete them.
+    #[clap(long, requires = "script")]
+    pub hardlink: bool,
+}
+
+/// Videos with identical contents, sorted by path.
+#[derive(Debug)]
+struct Duplicates {
+    size: u64,
+    videos: Vec<(String, PathBuf)>,
+}
+
+impl Duplicates {
+    /// The space taken up by all but one of the videos.
+    fn wasted(&self) -> u64 {
+        self.size * (self.videos.len() as u64).saturating_sub(1)
+    }
+}
+
+/// What identifies a file on disk, so hard links to one file, which take up its space only once, aren't taken for
+/// copies of it.
+#[cfg(unix)]
+fn file_id(path: &Path) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_path: &Path) -> Option<(u64, u64)> {
+    None
+}
+
+/// The groups of identical videos, those wasting the most space first.
+/// Only videos of the same size are hashed, since others can't be identical.
+fn find_duplicates(videos: &[VideoEntry]) -> Vec<Duplicates> {
+    let mut by_size: BTreeMap<u64, Vec<&VideoEntry>> = BTreeMap::new();
+    for video in videos.iter().filter(|video| video.size > 0) {
+        by_size.entry(video.size).or_default().push(video);
+    }
+    let mut groups = vec![];
+    for (size, candidates) in by_size.into_iter().filter(|(_, candidates)| candidates.len() > 1) {
+        let mut seen = HashSet::new();
+        let mut by_hash: HashMap<String, Vec<(String, PathBuf)>> = HashMap::new();
+        for video in candidates {
+            if file_id(&video.path).is_some_and(|id| !seen.insert(id)) {
+                continue;
+            }
+            match hash_file(&video.path) {
+                Ok(hash) => by_hash.entry(hash).or_default().push((video.key.clone(), video.path.clone())),
+                Err(err) => warn!("Skipping {}: {}", video.path.display(), err),
+            }
+        }
+        for mut videos in by_hash.into_values().filter(|videos| videos.len() > 1) {
+            videos.sort();
+            groups.push(Duplicates { size, videos });
+        }
+    }
+    groups.sort_by(|a, b| b.wasted().cmp(&a.wasted()).then_with(|| a.videos.cmp(&b.videos)));
+    groups
+}
+
+/// A path quoted for the shell.
+fn quote(path: &Path) -> String {
+    format!("'{}'", path.to_string_lossy().replace('\'', r"'\''"))
+}
+
+/// A script that keeps the first video of every group and deletes or hard links the others.
+fn script(groups: &[Duplicates], hardlink: bool) -> String {
+    let mut script = String::from("#!/bin/sh\n# Written by `static-video-server dedupe`. Review before running.\nset -e\n");
+    for group in groups {
+        let (kept_key, kept) = &group.videos[0];
+        let _ = writeln!(script, "\n# Keeping {} ({})", kept_key, describe_bytes(group.size));
+        for (_, copy) in &group.videos[1..] {
+            let _ = if hardlink {
+                writeln!(script, "ln -f -- {} {}", quote(kept), quote(copy))
+            } else {
+                writeln!(script, "rm -- {}", quote(copy))
+            };
+        }
+    }
+    script
+}
+
+/// Run the `dedupe` command. Returns the exit code.
+pub async fn run(config: &VideoPlayerConfig, args: &DedupeArgs) -> i32 {
+    let state = match VideoPlayerState::build(config, Arc::new(IndexStore::in_memory())) {
+        Ok(state) => state,
+        Err(err) => {
+            error!("Failed to index {}: {}", config.assets_root, err);
+            return 1;
+        }
+    };
+    let videos = state.videos();
+    let groups = match tokio::task::spawn_blocking(move || find_duplicates(&videos)).await {
+        Ok(groups) => groups,
+        Err(err) => {
+            error!("Failed to look for duplicates: {}", err);
+            return 1;
+        }
+    };
+    if args.report || args.script.is_none() {
+        for group in &groups {
+            println!(
+                "{} identical videos of {}, wasting {}:",
+                group.videos.len(),
+                describe_bytes(group.size),
+                describe_bytes(group.wasted())
+            );
+            for (key, _) in &group.videos {
+                println!("  {}", key);
+            }
+        }
+        println!(
+            "{} groups of identical videos, wasting {} in total.",
+            groups.len(),
+            describe_bytes(groups.iter().map(Duplicates::wasted).sum())
+        );
+    }
+    if let Some(path) = &args.script {
+        if let Err(err) = std::fs::write(path, script(&groups, args.hardlink)) {
+            error!("Failed to write {}: {}", path.display(), err);
+            return 1;
+        }
+        println!("Wrote {}.", path.display());
+    }
+    0
+}
@@ -0,0 +1,98 @@
+//! `.svs.toml` files, overriding how the folder they are in and everything under it is indexed and shown, e.g.
+//!
+//! ```toml
+//! title = "Holidays"
+//! sort = "-name"
+//! hidden = true
+//! hidden_videos = ["bloopers.mp4"]
+//! extensions = ["mp4", "mkv"]
+//! ```
+//!
+//! Settings are inherited by subfolders, whose own `.svs.toml` can override them, except for the title and the
+//! hidden videos, which only concern the folder they are in.
+
+use serde::Deserialize;
+use std::{collections::HashSet, path::Path};
+use tracing::warn;
+
+pub const FILE_NAME: &str = ".svs.toml";
+
+/// The contents of a single `.svs.toml`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct DirConfigFile {
+    title: Option<String>,
+    sort: Option<String>,
+    hidden: Option<bool>,
+    hidden_videos: Option<Vec<String>>,
+    extensions: Option<Vec<String>>,
+}
+
+impl DirConfigFile {
+    fn read(path: &Path) -> Option<Self> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return None,
+            Err(err) => {
+                warn!("Can't read {}: {}", path.display(), err);
+                return None;
+            }
+        };
+        toml::from_str(&contents)
+            .map_err(|err| warn!("Ignoring {}: {}", path.display(), err))
+            .ok()
+    }
+}
+
+/// The settings of a folder, merged from the `.svs.toml` files of the folder and its parents.
+#[derive(Debug, Clone, Default)]
+pub struct DirConfig {
+    /// The name the folder is shown with instead of its own.
+    pub title: Option<String>,
+    /// How the videos of the folder are sorted when browsing it, like the `sort` of listings, e.g. `-name`.
+    pub sort: Option<String>,
+    /// Whether the videos are left out of the listings and the API, and the folder out of `/browse/`.
+    /// They can still be played and browsed by link.
+    pub hidden: bool,
+    /// The file names of the videos of the folder that are hidden like that, although the folder isn't.
+    pub hidden_videos: HashSet<String>,
+    /// The only (lowercase) extensions indexed, instead of those of the server.
+    pub extensions: Option<HashSet<String>>,
+}
+
+impl DirConfig {
+    /// The settings of a folder under the root.
+    pub fn load(root: &Path, dir: &Path) -> Self {
+        let relative = dir.strip_prefix(root).unwrap_or(Path::new(""));
+        let mut folder = root.to_path_buf();
+        let mut config = Self::default();
+        for component in std::iter::once(None).chain(relative.components().map(Some)) {
+            if let Some(component) = component {
+                folder.push(component);
+            }
+            config.title = None;
+            config.hidden_videos.clear();
+            let Some(file) = DirConfigFile::read(&folder.join(FILE_NAME)) else {
+                continue;
+            };
+            config.title = file.title;
+            config.sort = file.sort.or(config.sort);
+            config.hidden = file.hidden.unwrap_or(config.hidden);
+            config.hidden_videos = file.hidden_videos.unwrap_or_default().into_iter().collect();
+            if let Some(extensions) = file.extensions {
+                config.extensions = Some(
+                    extensions
+                        .iter()
+                        .map(|extension| extension.trim_start_matches('.').to_ascii_lowercase())
+                        .collect(),
+                );
+            }
+        }
+        config
+    }
+
+    /// Whether a video of the folder, by file name, is hidden.
+    pub fn hides(&self, name: &str) -> bool {
+        self.hidden || self.hidden_videos.contains(name)
+    }
+}
@@ -0,0 +1,60 @@
+//! The errors of the library, and how they are turned into responses.
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use std::path::PathBuf;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("No video with id {0}")]
+    VideoNotFound(String),
+    #[error("No library root is configured")]
+    NoRoot,
+    #[error("{} has no file extension", .0.display())]
+    MissingExtension(PathBuf),
+    #[error("Failed to index {}: {source}", path.display())]
+    Scan {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// A file that can be read, but not as a video.
+    #[error("{} is broken: {reason}", path.display())]
+    Broken {
+        path: PathBuf,
+        reason: String,
+    },
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::VideoNotFound(_) | Self::NoRoot => StatusCode::NOT_FOUND,
+            Self::Scan { source: err, .. } | Self::Io(err) => match err.kind() {
+                std::io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+                std::io::ErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            },
+            Self::MissingExtension(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::Broken { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        (self.status(), self.to_string()).into_response()
+    }
+}
+
+/// So the error can be returned from handlers that use [`crate::library::LibraryResult`].
+impl From<Error> for (StatusCode, String) {
+    fn from(err: Error) -> Self {
+        (err.status(), err.to_string())
+    }
+}
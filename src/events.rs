@@ -0,0 +1,147 @@
+//! Library events (videos added or removed, scans finishing, ...) and the webhooks they are posted to.
+
+use serde::Serialize;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{debug, warn};
+
+use crate::{store, VideoEntry};
+
+/// How many events may queue up for a slow subscriber before it starts missing some.
+const EVENT_BUFFER: usize = 1024;
+
+/// How often a webhook delivery is attempted before giving up on the event.
+const WEBHOOK_ATTEMPTS: u32 = 3;
+
+/// A video as described in events.
+#[derive(Debug, Clone, Serialize)]
+pub struct EventVideo {
+    pub id: String,
+    /// The path relative to the root.
+    pub key: String,
+    pub name: String,
+    pub title: String,
+    pub size: u64,
+}
+
+impl From<&VideoEntry> for EventVideo {
+    fn from(video: &VideoEntry) -> Self {
+        Self {
+            id: video.id.clone(),
+            key: video.key.clone(),
+            name: video.name.clone(),
+            title: video.title.clone(),
+            size: video.size,
+        }
+    }
+}
+
+/// Something that happened to the library.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum LibraryEvent {
+    VideoAdded { video: EventVideo },
+    VideoRemoved { video: EventVideo },
+    VideoMoved { from: String, video: EventVideo },
+    ScanCompleted { videos: usize },
+    PlaybackStarted { video: EventVideo },
+    /// The video got a new title.
+    VideoUpdated { video: EventVideo },
+}
+
+impl LibraryEvent {
+    /// The name of the event, as in the `event` field of its JSON.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::VideoAdded { .. } => "video_added",
+            Self::VideoRemoved { .. } => "video_removed",
+            Self::VideoMoved { .. } => "video_moved",
+            Self::ScanCompleted { .. } => "scan_completed",
+            Self::PlaybackStarted { .. } => "playback_started",
+            Self::VideoUpdated { .. } => "video_updated",
+        }
+    }
+}
+
+/// An event along with where and when it happened.
+#[derive(Debug, Clone, Serialize)]
+pub struct EventEnvelope {
+    #[serde(flatten)]
+    pub event: LibraryEvent,
+    /// The assets root of the library the event is about.
+    pub root: String,
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+/// Hands library events to everybody who subscribed to them.
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<EventEnvelope>,
+    root: String,
+}
+
+impl EventBus {
+    pub fn new(root: &str) -> Self {
+        Self {
+            sender: broadcast::channel(EVENT_BUFFER).0,
+            root: root.to_string(),
+        }
+    }
+
+    /// Publish an event. It is dropped if nobody subscribed.
+    pub fn emit(&self, event: LibraryEvent) {
+        let _ = self.sender.send(EventEnvelope {
+            event,
+            root: self.root.clone(),
+            timestamp: store::now(),
+        });
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<EventEnvelope> {
+        self.sender.subscribe()
+    }
+}
+
+/// POST every event as JSON to the given urls, retrying failed deliveries a few times.
+pub async fn deliver_webhooks(mut events: broadcast::Receiver<EventEnvelope>, urls: Vec<String>) {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .unwrap_or_default();
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                warn!("Webhooks fell behind and missed {} events.", missed);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        for url in &urls {
+            post_with_retries(&client, url, &event).await;
+        }
+    }
+}
+
+async fn post_with_retries(client: &reqwest::Client, url: &str, event: &EventEnvelope) {
+    for attempt in 1..=WEBHOOK_ATTEMPTS {
+        let result = client
+            .post(url)
+            .json(event)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        match result {
+            Ok(_) => {
+                debug!("Delivered event to {}", url);
+                return;
+            }
+            Err(err) if attempt < WEBHOOK_ATTEMPTS => {
+                debug!("Failed to deliver event to {} (attempt {}): {}", url, attempt, err);
+                tokio::time::sleep(Duration::from_secs(1 << attempt)).await;
+            }
+            Err(err) => warn!("Giving up delivering event to {}: {}", url, err),
+        }
+    }
+}
@@ -0,0 +1,216 @@
+//! An optional tantivy index over titles, paths, probed metadata and subtitles, for searching very
+//! large libraries. It is persisted next to the store and kept up to date from the library events.
+
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+use tantivy::{
+    collector::{DocSetCollector, TopDocs},
+    directory::MmapDirectory,
+    query::{AllQuery, QueryParser},
+    schema::{Field, Schema, Value, STORED, STRING, TEXT},
+    Index, IndexReader, IndexWriter, ReloadPolicy, TantivyDocument, Term,
+};
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+use crate::{
+    cache,
+    events::{EventEnvelope, LibraryEvent},
+    SharedState, VideoEntry,
+};
+
+/// The memory the index writer may buffer documents in before flushing them to disk.
+const WRITER_MEMORY: usize = 50 * 1024 * 1024;
+
+/// Subtitles larger than this are not indexed.
+const MAX_SUBTITLES_SIZE: u64 = 1024 * 1024;
+
+pub struct FullTextIndex {
+    index: Index,
+    reader: IndexReader,
+    writer: Mutex<IndexWriter>,
+    key: Field,
+    title: Field,
+    path: Field,
+    metadata: Field,
+    subtitles: Field,
+}
+
+impl FullTextIndex {
+    /// Open the index in a directory, creating it if needed.
+    pub fn open(dir: &Path) -> tantivy::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let mut schema = Schema::builder();
+        let key = schema.add_text_field("key", STRING | STORED);
+        let title = schema.add_text_field("title", TEXT | STORED);
+        let path = schema.add_text_field("path", TEXT);
+        let metadata = schema.add_text_field("metadata", TEXT);
+        let subtitles = schema.add_text_field("subtitles", TEXT);
+        let index = Index::open_or_create(MmapDirectory::open(dir)?, schema.build())?;
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::Manual)
+            .try_into()?;
+        let writer = Mutex::new(index.writer(WRITER_MEMORY)?);
+        Ok(Self {
+            index,
+            reader,
+            writer,
+            key,
+            title,
+            path,
+            metadata,
+            subtitles,
+        })
+    }
+
+    fn document(&self, video: &VideoEntry) -> TantivyDocument {
+        let mut document = TantivyDocument::default();
+        document.add_text(self.key, &video.key);
+        document.add_text(self.title, &video.title);
+        document.add_text(self.path, &video.key);
+        document.add_text(self.metadata, &video.extension);
+        if let Some(codec) = video.metadata.as_ref().and_then(|metadata| metadata.codec.as_deref()) {
+            document.add_text(self.metadata, codec);
+        }
+        for subtitles in cache::subtitle_candidates(&video.path) {
+            if !std::fs::metadata(&subtitles).is_ok_and(|metadata| metadata.len() <= MAX_SUBTITLES_SIZE) {
+                continue;
+            }
+            if let Ok(text) = std::fs::read_to_string(&subtitles) {
+                document.add_text(self.subtitles, &text);
+            }
+        }
+        document
+    }
+
+    /// Add or replace videos and remove others, by key, then make the changes searchable.
+    fn update(&self, upserts: &[VideoEntry], removals: &[String]) -> tantivy::Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        for key in removals {
+            writer.delete_term(Term::from_field_text(self.key, key));
+        }
+        for video in upserts {
+            writer.delete_term(Term::from_field_text(self.key, &video.key));
+            writer.add_document(self.document(video))?;
+        }
+        writer.commit()?;
+        self.reader.reload()
+    }
+
+    /// The key and title of every indexed video.
+    fn indexed(&self) -> tantivy::Result<HashMap<String, String>> {
+        let searcher = self.reader.searcher();
+        let mut indexed = HashMap::new();
+        for address in searcher.search(&AllQuery, &DocSetCollector)? {
+            let document: TantivyDocument = searcher.doc(address)?;
+            let field = |field| {
+                document
+                    .get_first(field)
+                    .and_then(|value| value.as_str())
+                    .unwrap_or_default()
+                    .to_string()
+            };
+            indexed.insert(field(self.key), field(self.title));
+        }
+        Ok(indexed)
+    }
+
+    /// Bring the index in line with the videos of the library, only touching the ones that changed.
+    pub fn sync(&self, videos: &[VideoEntry]) -> tantivy::Result<()> {
+        let mut indexed = self.indexed()?;
+        let upserts: Vec<VideoEntry> = videos
+            .iter()
+            .filter(|video| indexed.remove(&video.key).as_ref() != Some(&video.title))
+            .cloned()
+            .collect();
+        let removals: Vec<String> = indexed.into_keys().collect();
+        if !upserts.is_empty() || !removals.is_empty() {
+            info!(
+                "Updating the full-text index: {} added or changed, {} removed",
+                upserts.len(),
+                removals.len()
+            );
+            self.update(&upserts, &removals)?;
+        }
+        Ok(())
+    }
+
+    /// The keys of the videos matching a query, best match first, with their scores.
+    /// Typos of one letter in titles and paths are forgiven.
+    pub fn search(&self, query: &str, limit: usize) -> tantivy::Result<Vec<(String, f32)>> {
+        let searcher = self.reader.searcher();
+        let mut parser = QueryParser::for_index(&self.index, vec![self.title, self.path, self.metadata, self.subtitles]);
+        parser.set_field_boost(self.title, 2.0);
+        parser.set_field_fuzzy(self.title, false, 1, true);
+        parser.set_field_fuzzy(self.path, false, 1, true);
+        let (query, _) = parser.parse_query_lenient(query);
+        let mut keys = vec![];
+        for (score, address) in searcher.search(&query, &TopDocs::with_limit(limit.max(1)))? {
+            let document: TantivyDocument = searcher.doc(address)?;
+            if let Some(key) = document.get_first(self.key).and_then(|value| value.as_str()) {
+                keys.push((key.to_string(), score));
+            }
+        }
+        Ok(keys)
+    }
+}
+
+/// Apply library events to the index as they happen, and resync it after every scan
+/// (which also covers folders indexed lazily without events).
+/// Events that queue up meanwhile, e.g. during a rescan, are applied in one go.
+pub async fn keep_updated(
+    index: Arc<FullTextIndex>,
+    state: SharedState,
+    mut events: broadcast::Receiver<EventEnvelope>,
+) {
+    loop {
+        let mut batch = vec![];
+        let mut resync = false;
+        match events.recv().await {
+            Ok(event) => batch.push(event.event),
+            Err(broadcast::error::RecvError::Lagged(_)) => resync = true,
+            Err(broadcast::error::RecvError::Closed) => return,
+        }
+        loop {
+            match events.try_recv() {
+                Ok(event) => batch.push(event.event),
+                Err(broadcast::error::TryRecvError::Lagged(_)) => resync = true,
+                Err(_) => break,
+            }
+        }
+
+        let mut upserts = vec![];
+        let mut removals = vec![];
+        for event in batch {
+            match event {
+                LibraryEvent::ScanCompleted { .. } => resync = true,
+                LibraryEvent::VideoRemoved { video } => removals.push(video.key),
+                LibraryEvent::VideoAdded { video } | LibraryEvent::VideoUpdated { video } => upserts.push(video.id),
+                LibraryEvent::VideoMoved { from, video } => {
+                    removals.push(from);
+                    upserts.push(video.id);
+                }
+                LibraryEvent::PlaybackStarted { .. } => {}
+            }
+        }
+        let result = if resync {
+            let videos = state.lock().unwrap().videos();
+            index.sync(&videos)
+        } else if upserts.is_empty() && removals.is_empty() {
+            Ok(())
+        } else {
+            let upserts: Vec<VideoEntry> = {
+                let state = state.lock().unwrap();
+                upserts.iter().filter_map(|id| state.get(id).cloned()).collect()
+            };
+            index.update(&upserts, &removals)
+        };
+        if let Err(err) = result {
+            warn!("Failed to update the full-text index: {}", err);
+        }
+    }
+}
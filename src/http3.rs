@@ -0,0 +1,104 @@
+//! An experimental HTTP/3 listener that serves the same router over QUIC.
+
+use axum::{
+    body::{Body, HttpBody},
+    extract::ConnectInfo,
+    http::{Request, Response},
+    Router,
+};
+use bytes::{Buf, Bytes};
+use h3::server::RequestStream;
+use std::{error::Error, net::SocketAddr, sync::Arc};
+use tower::ServiceExt;
+use tracing::{debug, info, warn};
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+/// Accept QUIC connections on the given address and serve every request with the router.
+///
+/// The TLS config is the same one used by the TCP listener (including client authentication),
+/// only advertising `h3` instead.
+pub async fn serve(
+    addr: SocketAddr,
+    mut tls_config: rustls::ServerConfig,
+    app: Router,
+) -> Result<(), BoxError> {
+    tls_config.alpn_protocols = vec![b"h3".to_vec()];
+    let server_config = quinn::ServerConfig::with_crypto(Arc::new(tls_config));
+    let endpoint = quinn::Endpoint::server(server_config, addr)?;
+    info!("Starting HTTP/3 listener on udp://{}", addr);
+
+    while let Some(connecting) = endpoint.accept().await {
+        let app = app.clone();
+        tokio::spawn(async move {
+            if let Err(err) = serve_connection(connecting, app).await {
+                debug!("HTTP/3 connection closed: {}", err);
+            }
+        });
+    }
+    Ok(())
+}
+
+async fn serve_connection(connecting: quinn::Connecting, app: Router) -> Result<(), BoxError> {
+    let connection = connecting.await?;
+    let remote = connection.remote_address();
+    let mut connection: h3::server::Connection<_, Bytes> =
+        h3::server::Connection::new(h3_quinn::Connection::new(connection)).await?;
+
+    while let Some((request, stream)) = connection.accept().await? {
+        let app = app.clone();
+        tokio::spawn(async move {
+            if let Err(err) = serve_request(request, stream, app, remote).await {
+                warn!("Failed to serve HTTP/3 request: {}", err);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Serve one request. Its body is handed to the router as it arrives, so uploads are neither buffered in memory nor
+/// limited in size beyond what the handlers themselves accept.
+async fn serve_request<S>(
+    request: Request<()>,
+    stream: RequestStream<S, Bytes>,
+    app: Router,
+    remote: SocketAddr,
+) -> Result<(), BoxError>
+where
+    S: h3::quic::BidiStream<Bytes>,
+    S::RecvStream: Send + 'static,
+{
+    let (mut stream, mut incoming) = stream.split();
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        loop {
+            match incoming.recv_data().await {
+                Ok(Some(mut chunk)) => {
+                    // Waits for the handler to take the previous chunk, and stops once it drops the body.
+                    if sender.send_data(chunk.copy_to_bytes(chunk.remaining())).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(None) => break,
+                Err(err) => {
+                    debug!("Failed to receive HTTP/3 request body: {}", err);
+                    sender.abort();
+                    break;
+                }
+            }
+        }
+    });
+
+    let (parts, ()) = request.into_parts();
+    let mut request = Request::from_parts(parts, body);
+    request.extensions_mut().insert(ConnectInfo(remote));
+
+    let response = app.oneshot(request).await?;
+    let (parts, mut body) = response.into_parts();
+    stream.send_response(Response::from_parts(parts, ())).await?;
+    while let Some(chunk) = body.data().await {
+        stream.send_data(chunk?).await?;
+    }
+    stream.finish().await?;
+    Ok(())
+}
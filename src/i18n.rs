@@ -0,0 +1,188 @@
+//! Translations of the UI. Every string of the pages is looked up in the messages of the language picked for the
+//! request: the first one of its `Accept-Language` that there is a translation for, or else the one of `--lang`.
+//!
+//! Translations are kept in `locales/<lang>.toml`. Strings a translation leaves out fall back to English.
+
+use axum::{
+    extract::State,
+    http::{header, HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// The languages there are translations for, English first.
+const LOCALES: [(&str, &str); 4] = [
+    ("en", include_str!("../locales/en.toml")),
+    ("de", include_str!("../locales/de.toml")),
+    ("es", include_str!("../locales/es.toml")),
+    ("fr", include_str!("../locales/fr.toml")),
+];
+
+/// The strings of the UI in one language. `{name}` in a string is replaced with a value, see [`Messages::fill`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Messages {
+    /// The language code, e.g. `de`.
+    #[serde(default)]
+    pub lang: String,
+    pub app_name: String,
+    pub all_videos: String,
+    pub library: String,
+    pub download: String,
+    pub share: String,
+    pub favorite: String,
+    pub index_title: String,
+    pub reload_index: String,
+    pub favorites: String,
+    pub history: String,
+    pub top_rated: String,
+    pub all: String,
+    pub short: String,
+    pub medium: String,
+    pub long: String,
+    pub everything: String,
+    pub videos: String,
+    pub music: String,
+    pub images: String,
+    pub list: String,
+    pub grid: String,
+    pub dark: String,
+    pub light: String,
+    pub auto: String,
+    pub search_placeholder: String,
+    pub loading_more: String,
+    pub show_hidden: String,
+    pub back_to_all_videos: String,
+    pub share_title: String,
+    pub next: String,
+    pub watch: String,
+    pub share_signed: String,
+    pub new_link_valid_for: String,
+    pub one_hour: String,
+    pub one_day: String,
+    pub one_week: String,
+    pub thirty_days: String,
+    pub days: String,
+    pub hours: String,
+    pub minutes: String,
+    pub stats: String,
+    pub total_served: String,
+    pub traffic: String,
+    pub traffic_bar: String,
+    pub most_watched: String,
+    pub video: String,
+    pub plays: String,
+    pub served: String,
+    pub recently_played: String,
+    pub just_now: String,
+    pub minutes_ago: String,
+    pub hours_ago: String,
+    pub days_ago: String,
+}
+
+pub type SharedMessages = Arc<Messages>;
+
+impl Messages {
+    /// A string with its `{name}`s replaced by their values.
+    pub fn fill(message: &str, values: &[(&str, &str)]) -> String {
+        values.iter().fold(message.to_string(), |message, (name, value)| {
+            message.replace(&format!("{{{}}}", name), value)
+        })
+    }
+}
+
+/// The messages of every language there is a translation for.
+#[derive(Debug, Clone)]
+pub struct Translations {
+    languages: Vec<SharedMessages>,
+    /// The position of the language used when the browser asks for none of them.
+    default: usize,
+}
+
+pub type SharedTranslations = Arc<Translations>;
+
+impl Translations {
+    /// The translations, falling back to the given language.
+    pub fn new(default: &str) -> Result<Self, String> {
+        let english: toml::Table = toml::from_str(LOCALES[0].1).map_err(|err| format!("locales/en.toml: {}", err))?;
+        let mut languages = vec![];
+        for (lang, source) in LOCALES {
+            let mut table = english.clone();
+            let translated: toml::Table =
+                toml::from_str(source).map_err(|err| format!("locales/{}.toml: {}", lang, err))?;
+            table.extend(translated);
+            table.insert("lang".into(), toml::Value::String(lang.into()));
+            let messages: Messages = toml::Value::Table(table)
+                .try_into()
+                .map_err(|err| format!("locales/{}.toml: {}", lang, err))?;
+            languages.push(Arc::new(messages));
+        }
+        let default = languages
+            .iter()
+            .position(|messages| messages.lang.eq_ignore_ascii_case(default))
+            .ok_or_else(|| {
+                let known: Vec<&str> = LOCALES.iter().map(|(lang, _)| *lang).collect();
+                format!("There is no translation for {}, only for {}", default, known.join(", "))
+            })?;
+        Ok(Self { languages, default })
+    }
+
+    /// The messages of the language a browser prefers out of those there are, given its `Accept-Language`.
+    pub fn negotiate(&self, accept_language: Option<&str>) -> SharedMessages {
+        let mut wanted: Vec<(&str, f32)> = accept_language
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';');
+                let tag = parts.next()?.trim();
+                let quality = parts
+                    .find_map(|param| param.trim().strip_prefix("q="))
+                    .map_or(Some(1.0), |quality| quality.trim().parse().ok())?;
+                (!tag.is_empty() && tag != "*" && quality > 0.0).then_some((tag, quality))
+            })
+            .collect();
+        // Stable, so equally preferred languages keep their order.
+        wanted.sort_by(|a, b| b.1.total_cmp(&a.1));
+        wanted
+            .iter()
+            .find_map(|(tag, _)| {
+                let primary = tag.split('-').next().unwrap_or(tag);
+                self.languages
+                    .iter()
+                    .find(|messages| messages.lang.eq_ignore_ascii_case(primary))
+            })
+            .unwrap_or(&self.languages[self.default])
+            .clone()
+    }
+}
+
+/// Attach the [`Messages`] of the language negotiated to every request, and tell caches that pages vary by it.
+pub async fn choose_language<B>(
+    State(translations): State<SharedTranslations>,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let accept_language = request
+        .headers()
+        .get(header::ACCEPT_LANGUAGE)
+        .and_then(|accept_language| accept_language.to_str().ok());
+    let messages = translations.negotiate(accept_language);
+    let lang = HeaderValue::from_str(&messages.lang).ok();
+    request.extensions_mut().insert(messages);
+    let mut response = next.run(request).await;
+    let is_page = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("text/html"));
+    if !is_page {
+        return response;
+    }
+    let headers = response.headers_mut();
+    headers.append(header::VARY, HeaderValue::from_static("accept-language"));
+    if let Some(lang) = lang {
+        headers.insert(header::CONTENT_LANGUAGE, lang);
+    }
+    response
+}
@@ -0,0 +1,251 @@
+//! Importing the videos dropped into `--incoming`, e.g. when offloading a camera: once they settle, they are checked
+//! with ffprobe, moved into the library under a path made from `--incoming-template`, and indexed.
+
+use std::{
+    io::ErrorKind,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+    time::{Duration, UNIX_EPOCH},
+};
+use tracing::{info, warn};
+
+use crate::{cron, metadata, scan, SharedState, VideoPlayerConfig};
+
+/// The folder, inside the incoming folder, that files ffprobe can't read are moved to.
+pub const REJECTED_DIR: &str = "rejected";
+
+/// How often the incoming folder is checked for new files.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+const PLACEHOLDERS: [&str; 7] = ["name", "title", "year", "month", "day", "date", "time"];
+
+/// Where imported videos go, relative to the root and without the extension, e.g. `Imported/{date}/{name}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template(String);
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            let Some(end) = rest[start..].find('}') else {
+                return Err(format!("Unclosed {{ in {}", template));
+            };
+            let placeholder = &rest[start + 1..start + end];
+            if !PLACEHOLDERS.contains(&placeholder) {
+                return Err(format!(
+                    "Unknown placeholder {{{}}} in {}, expected one of {{{}}}",
+                    placeholder,
+                    template,
+                    PLACEHOLDERS.join("}, {")
+                ));
+            }
+            rest = &rest[start + end + 1..];
+        }
+        let escapes = Path::new(template)
+            .components()
+            .any(|component| !matches!(component, Component::Normal(_)));
+        if template.is_empty() || escapes {
+            return Err(format!("{} should be a relative path inside the root", template));
+        }
+        Ok(Self(template.to_string()))
+    }
+
+    /// The path of a file under the root, given its name without the extension and when it was last modified.
+    fn render(&self, name: &str, modified: u64) -> PathBuf {
+        let (year, month, day) = cron::civil_date(modified / 86_400);
+        let seconds = modified % 86_400;
+        let title = name
+            .split(|c: char| c == '_' || c == '.' || c.is_whitespace())
+            .filter(|word| !word.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let path = self
+            .0
+            .replace("{name}", name)
+            .replace("{title}", &title)
+            .replace("{year}", &format!("{:04}", year))
+            .replace("{month}", &format!("{:02}", month))
+            .replace("{day}", &format!("{:02}", day))
+            .replace("{date}", &format!("{:04}-{:02}-{:02}", year, month, day))
+            .replace("{time}", &format!("{:02}-{:02}-{:02}", seconds / 3600, seconds % 3600 / 60, seconds % 60));
+        PathBuf::from(path)
+    }
+}
+
+/// Moves the videos dropped into the incoming folder into the library.
+pub struct Importer {
+    incoming: PathBuf,
+    root: PathBuf,
+    template: Template,
+    settle: Duration,
+    ffprobe: String,
+}
+
+impl Importer {
+    /// `None` unless `--incoming` is given.
+    pub fn new(config: &VideoPlayerConfig) -> Option<Self> {
+        Some(Self {
+            incoming: config.incoming.clone()?,
+            root: PathBuf::from(&config.assets_root),
+            template: config.incoming_template.clone(),
+            settle: Duration::from_secs(config.settle_time),
+            ffprobe: config.ffprobe.clone(),
+        })
+    }
+
+    /// Import what is dropped into the incoming folder in the background, for as long as the server runs.
+    pub fn start(self, state: SharedState) {
+        info!("Importing videos dropped into {}.", self.incoming.display());
+        let importer = Arc::new(self);
+        tokio::spawn(async move {
+            loop {
+                let (importer, state) = (importer.clone(), state.clone());
+                let _ = tokio::task::spawn_blocking(move || importer.import_all(&state)).await;
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+        });
+    }
+
+    /// The files in the incoming folder and its subfolders, except the rejected ones.
+    fn files(&self) -> Vec<PathBuf> {
+        let mut files = vec![];
+        let mut pending = vec![self.incoming.clone()];
+        while let Some(dir) = pending.pop() {
+            let Ok(entries) = std::fs::read_dir(&dir) else {
+                continue;
+            };
+            for path in entries.flatten().map(|entry| entry.path()) {
+                if path.is_dir() {
+                    if path != self.incoming.join(REJECTED_DIR) {
+                        pending.push(path);
+                    }
+                } else {
+                    files.push(path);
+                }
+            }
+        }
+        files.sort();
+        files
+    }
+
+    fn import_all(&self, state: &SharedState) {
+        for path in self.files() {
+            // Files that aren't media, e.g. the metadata some cameras keep next to videos, are left as they are.
+            if !scan::is_settled(&path, self.settle) || !state.lock().unwrap().is_video_file(&path) {
+                continue;
+            }
+            if let Err(reason) = metadata::probe(&self.ffprobe, &path) {
+                self.reject(&path, &reason);
+                continue;
+            }
+            if let Err(err) = self.import(&path, state) {
+                warn!("Failed to import {}: {}", path.display(), err);
+            }
+        }
+    }
+
+    /// Move a file into the library, under the template, and index it.
+    fn import(&self, path: &Path, state: &SharedState) -> std::io::Result<()> {
+        let modified = std::fs::metadata(path)?
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|modified| modified.as_secs())
+            .unwrap_or_default();
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        let relative = self.template.render(&name, modified);
+        // A file named `...mp4` has `..` for a name.
+        if relative.components().any(|component| !matches!(component, Component::Normal(_))) {
+            return Err(std::io::Error::new(ErrorKind::InvalidInput, "its name makes a path outside the root"));
+        }
+        let mut target = self.root.join(relative);
+        if let Some(extension) = path.extension() {
+            let mut file_name = target.file_name().unwrap_or_default().to_os_string();
+            file_name.push(".");
+            file_name.push(extension);
+            target.set_file_name(file_name);
+        }
+        let target = available_path(target);
+        move_file(path, &target)?;
+        let mut state = state.lock().unwrap();
+        let key = state.relative_key(&target);
+        match state.add_video(target) {
+            Ok(video) => info!("Imported {} as {}.", path.display(), video.key),
+            Err(err) => warn!("Imported {} as {}, but failed to index it: {}", path.display(), key, err),
+        }
+        Ok(())
+    }
+
+    /// Move a file ffprobe can't read out of the way, keeping its path in the incoming folder.
+    fn reject(&self, path: &Path, reason: &str) {
+        let relative = path.strip_prefix(&self.incoming).unwrap_or(path);
+        let target = available_path(self.incoming.join(REJECTED_DIR).join(relative));
+        warn!("Rejecting {}: {}", path.display(), reason);
+        if let Err(err) = move_file(path, &target) {
+            warn!("Failed to move {} to {}: {}", path.display(), target.display(), err);
+        }
+    }
+}
+
+/// The path, or if it is taken, the first of `name (2).ext`, `name (3).ext`, ... that isn't.
+fn available_path(path: PathBuf) -> PathBuf {
+    if !path.exists() {
+        return path;
+    }
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+    let extension = path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    (2..)
+        .map(|n| path.with_file_name(format!("{} ({}){}", stem, n, extension)))
+        .find(|candidate| !candidate.exists())
+        .expect("there are always more names")
+}
+
+/// Move a file, creating the folder it goes in. Incoming folders are often on another filesystem, like a memory
+/// card, in which case it is copied and deleted.
+fn move_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if std::fs::rename(from, to).is_err() {
+        std::fs::copy(from, to)?;
+        std::fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2024-01-01 09:30:15 UTC.
+    const MODIFIED: u64 = 1_704_101_415;
+
+    fn render(template: &str, name: &str) -> PathBuf {
+        Template::parse(template).unwrap().render(name, MODIFIED)
+    }
+
+    #[test]
+    fn renders_placeholders() {
+        assert_eq!(render("Imported/{date}/{name}", "GX010042"), PathBuf::from("Imported/2024-01-01/GX010042"));
+        assert_eq!(render("{year}/{month}/{day}/{time}", "clip"), PathBuf::from("2024/01/01/09-30-15"));
+        assert_eq!(render("Films/{title}", "The_Big.Trip  2023"), PathBuf::from("Films/The Big Trip 2023"));
+        assert_eq!(render("Camera/{name}-{name}", "a"), PathBuf::from("Camera/a-a"));
+    }
+
+    #[test]
+    fn rejects_unknown_and_unclosed_placeholders() {
+        assert!(Template::parse("Imported/{camera}/{name}").is_err());
+        assert!(Template::parse("Imported/{name").is_err());
+        assert!(Template::parse("Imported/{}").is_err());
+    }
+
+    #[test]
+    fn rejects_paths_outside_the_root() {
+        for template in ["", "/srv/{name}", "../{name}", "Imported/../../{name}", "./{name}"] {
+            assert!(Template::parse(template).is_err(), "{:?}", template);
+        }
+        assert!(Template::parse("Imported/{date}/{name}").is_ok());
+    }
+}
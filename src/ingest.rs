@@ -0,0 +1,278 @@
+//! Recording RTSP streams, e.g. of IP cameras, into the library with ffmpeg, for using the server as a simple NVR.
+//!
+//! Every stream given with `--record` is recorded continuously, or daily within a window of time, into segments of
+//! `--record-segment` seconds. Segments are written to the data directory and moved into a folder of their own under
+//! `--record-dir` once done, where they are indexed right away. With `--record-retention`, segments older than that
+//! are deleted again.
+
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    process::Command,
+};
+use tracing::{info, warn};
+
+use crate::{store, SharedState, VideoPlayerConfig};
+
+/// The name of the folder segments are recorded into, inside the data directory.
+pub const RECORDING_DIR: &str = "recording";
+
+/// How long to wait before reconnecting to a stream that failed or ended.
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+
+/// A stream to record, given as `name=url`, or as `name@22:00-06:00=url` to only record within that window.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Camera {
+    /// The name of the folder its recordings are kept in.
+    pub name: String,
+    pub url: String,
+    pub window: Option<Window>,
+}
+
+/// A daily window of time, in seconds since midnight UTC. It may span midnight, e.g. `22:00-06:00`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Window {
+    start: u64,
+    end: u64,
+}
+
+const DAY: u64 = 24 * 60 * 60;
+
+impl Window {
+    fn parse(value: &str) -> Result<Self, String> {
+        let time = |time: &str| -> Option<u64> {
+            let (hours, minutes) = time.split_once(':')?;
+            let (hours, minutes): (u64, u64) = (hours.parse().ok()?, minutes.parse().ok()?);
+            (hours < 24 && minutes < 60).then_some(hours * 3600 + minutes * 60)
+        };
+        let (start, end) = value
+            .split_once('-')
+            .and_then(|(start, end)| Some((time(start)?, time(end)?)))
+            .filter(|(start, end)| start != end)
+            .ok_or_else(|| format!("Invalid window {}, expected e.g. 22:00-06:00", value))?;
+        Ok(Self { start, end })
+    }
+
+    /// How long until the window opens, and how long it stays open then, at a time of day.
+    fn next(&self, time_of_day: u64) -> (u64, u64) {
+        let length = (self.end + DAY - self.start) % DAY;
+        let since_start = (time_of_day + DAY - self.start) % DAY;
+        if since_start < length {
+            (0, length - since_start)
+        } else {
+            (DAY - since_start, length)
+        }
+    }
+}
+
+/// Parse a `--record` value.
+pub fn parse_camera(value: &str) -> Result<Camera, String> {
+    let (name, url) = value
+        .split_once('=')
+        .ok_or_else(|| format!("Invalid stream {}, expected name=rtsp://...", value))?;
+    let (name, window) = match name.split_once('@') {
+        Some((name, window)) => (name, Some(Window::parse(window)?)),
+        None => (name, None),
+    };
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(format!("Invalid stream name {}: use letters, digits, dashes and underscores", name));
+    }
+    Ok(Camera {
+        name: name.to_string(),
+        url: url.to_string(),
+        window,
+    })
+}
+
+/// Records the streams of `--record` into the library.
+pub struct Recorder {
+    ffmpeg: String,
+    /// Where segments are written to while they are recorded.
+    recording_dir: PathBuf,
+    /// Where finished segments are moved to, in a folder per stream.
+    library_dir: PathBuf,
+    segment: u64,
+    retention: Option<Duration>,
+}
+
+impl Recorder {
+    pub fn new(config: &VideoPlayerConfig) -> Self {
+        Self {
+            ffmpeg: config.ffmpeg.clone(),
+            recording_dir: config.data_dir.join(RECORDING_DIR),
+            library_dir: Path::new(&config.assets_root).join(&config.record_dir),
+            segment: config.record_segment.max(1),
+            retention: config.record_retention.map(|hours| Duration::from_secs(hours * 3600)),
+        }
+    }
+
+    /// Record every stream in the background, for as long as the server runs.
+    pub fn start(self, cameras: &[Camera], state: SharedState) {
+        let recorder = Arc::new(self);
+        for camera in cameras {
+            tokio::spawn(recorder.clone().record(camera.clone(), state.clone()));
+        }
+    }
+
+    async fn record(self: Arc<Self>, camera: Camera, state: SharedState) {
+        let recording_dir = self.recording_dir.join(&camera.name);
+        if let Err(err) = tokio::fs::create_dir_all(&recording_dir).await {
+            warn!("Not recording {}: can't create {}: {}", camera.name, recording_dir.display(), err);
+            return;
+        }
+        // Segments left over from before a restart. Being fragmented, they play up to where they were cut off.
+        if let Ok(mut leftovers) = tokio::fs::read_dir(&recording_dir).await {
+            while let Ok(Some(leftover)) = leftovers.next_entry().await {
+                self.file(&camera, &leftover.path(), &state).await;
+            }
+        }
+        loop {
+            let length = match camera.window {
+                None => None,
+                Some(window) => {
+                    let (wait, length) = window.next(store::now() % DAY);
+                    if wait > 0 {
+                        info!("Recording {} again in {} minutes.", camera.name, wait / 60);
+                        tokio::time::sleep(Duration::from_secs(wait)).await;
+                    }
+                    Some(length)
+                }
+            };
+            info!("Recording {}.", camera.name);
+            let recorded = self.run_ffmpeg(&camera, &recording_dir, length, &state).await;
+            let closed = camera.window.is_some_and(|window| window.next(store::now() % DAY).0 > 0);
+            match recorded {
+                Ok(()) if closed => info!("Stopped recording {} for the day.", camera.name),
+                Ok(()) => warn!("The stream of {} ended. Reconnecting.", camera.name),
+                Err(err) => warn!("Recording {} failed: {}. Reconnecting.", camera.name, err),
+            }
+            if !closed {
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        }
+    }
+
+    /// Record a stream for a while, or until it ends, filing every segment once it is done.
+    async fn run_ffmpeg(
+        &self,
+        camera: &Camera,
+        recording_dir: &Path,
+        length: Option<u64>,
+        state: &SharedState,
+    ) -> Result<(), String> {
+        let mut command = Command::new(&self.ffmpeg);
+        command.args(["-nostdin", "-hide_banner", "-loglevel", "error"]);
+        if camera.url.starts_with("rtsp") {
+            command.args(["-rtsp_transport", "tcp"]);
+        }
+        command.arg("-i").arg(&camera.url);
+        if let Some(length) = length {
+            command.arg("-t").arg(length.to_string());
+        }
+        command
+            .args(["-map", "0:v?", "-map", "0:a?", "-c", "copy", "-f", "segment", "-segment_format", "mp4"])
+            .args(["-segment_format_options", "movflags=+frag_keyframe+empty_moov+default_base_moof"])
+            .arg("-segment_time")
+            .arg(self.segment.to_string())
+            .args(["-reset_timestamps", "1", "-strftime", "1"])
+            // Every segment is listed on stdout once it is done.
+            .args(["-segment_list", "pipe:1", "-segment_list_type", "flat"])
+            .arg(recording_dir.join(format!("{}-%Y-%m-%d_%H-%M-%S.mp4", camera.name)))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        let mut child = command
+            .spawn()
+            .map_err(|err| format!("Failed to run {}: {}", self.ffmpeg, err))?;
+        if let Some(stderr) = child.stderr.take() {
+            let name = camera.name.clone();
+            tokio::spawn(async move {
+                let mut lines = BufReader::new(stderr).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    warn!("ffmpeg recording {}: {}", name, line);
+                }
+            });
+        }
+        if let Some(stdout) = child.stdout.take() {
+            let mut segments = BufReader::new(stdout).lines();
+            while let Ok(Some(segment)) = segments.next_line().await {
+                let Some(file_name) = Path::new(segment.trim()).file_name() else {
+                    continue;
+                };
+                self.file(camera, &recording_dir.join(file_name), state).await;
+            }
+        }
+        let status = child.wait().await.map_err(|err| err.to_string())?;
+        if !status.success() {
+            return Err(format!("ffmpeg exited with {}", status));
+        }
+        Ok(())
+    }
+
+    /// Move a finished segment into the library, index it, and delete the segments past the retention.
+    async fn file(&self, camera: &Camera, segment: &Path, state: &SharedState) {
+        let Some(file_name) = segment.file_name() else {
+            return;
+        };
+        let dir = self.library_dir.join(&camera.name);
+        let target = dir.join(file_name);
+        if let Err(err) = tokio::fs::create_dir_all(&dir).await {
+            warn!("Failed to create {}: {}", dir.display(), err);
+            return;
+        }
+        // The data directory may be on another filesystem than the library.
+        if tokio::fs::rename(segment, &target).await.is_err() {
+            if let Err(err) = tokio::fs::copy(segment, &target).await {
+                warn!("Failed to move {} into the library: {}", segment.display(), err);
+                return;
+            }
+            let _ = tokio::fs::remove_file(segment).await;
+        }
+        {
+            let mut state = state.lock().unwrap();
+            // Unless a rescan got to it first.
+            if !state.videos().iter().any(|video| video.path == target) {
+                if let Err(err) = state.add_video(target) {
+                    warn!("Failed to index a recording of {}: {}", camera.name, err);
+                }
+            }
+        }
+        if let Some(retention) = self.retention {
+            self.expire(&dir, retention, state).await;
+        }
+    }
+
+    /// Delete the recordings in a folder older than the retention, and drop them from the index.
+    async fn expire(&self, dir: &Path, retention: Duration, state: &SharedState) {
+        let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+            return;
+        };
+        let cutoff = SystemTime::now() - retention;
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let expired = entry
+                .metadata()
+                .await
+                .and_then(|metadata| metadata.modified())
+                .is_ok_and(|modified| modified < cutoff);
+            if !expired {
+                continue;
+            }
+            let path = entry.path();
+            if let Err(err) = tokio::fs::remove_file(&path).await {
+                warn!("Failed to delete the expired recording {}: {}", path.display(), err);
+                continue;
+            }
+            let mut state = state.lock().unwrap();
+            let id = state.videos().iter().find(|video| video.path == path).map(|video| video.id.clone());
+            if let Some(id) = id {
+                state.remove_video(&id);
+            }
+        }
+    }
+}
@@ -0,0 +1,169 @@
+//! Checksums of the videos, kept in the store, to tell when a file got corrupted, e.g. by bit rot on an aging disk.
+//! Served at `/api/videos/:id/checksum`, and verified for the whole library with the `verify` command.
+//! Only admins have videos hashed on demand, a few at a time, since that reads whole files.
+
+use clap::Args;
+use lazy_static::lazy_static;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{path::Path, sync::Arc, time::UNIX_EPOCH};
+use tokio::sync::Semaphore;
+use tracing::error;
+
+use crate::{
+    store::{self, Checksum, IndexStore},
+    VideoEntry, VideoPlayerConfig, VideoPlayerState,
+};
+
+/// How many videos may be hashed on demand at once.
+const MAX_HASHING: usize = 2;
+
+lazy_static! {
+    /// Held while hashing a video on demand.
+    pub static ref HASHING: Semaphore = Semaphore::new(MAX_HASHING);
+}
+
+/// The SHA-256 of a file, in hex.
+pub fn hash_file(path: &Path) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// The size of a file and when it was last modified, in seconds since the Unix epoch.
+fn stamp(path: &Path) -> std::io::Result<(u64, u64)> {
+    let metadata = std::fs::metadata(path)?;
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|modified| modified.as_secs())
+        .unwrap_or_default();
+    Ok((metadata.len(), modified))
+}
+
+/// How a file compares to its stored checksum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verdict {
+    /// It wasn't hashed before.
+    New,
+    Intact,
+    /// It was modified since it was hashed, so its checksum was replaced.
+    Changed,
+    /// Its contents changed although its size and modification time didn't.
+    Corrupted,
+}
+
+/// Hash a video and compare it with its stored checksum. The new checksum is stored, unless the file is corrupted,
+/// so it keeps being reported as such.
+pub fn verify(store: &IndexStore, video: &VideoEntry) -> std::io::Result<(Verdict, Checksum)> {
+    let (size, modified) = stamp(&video.path)?;
+    let checksum = Checksum {
+        sha256: hash_file(&video.path)?,
+        size,
+        modified,
+        verified: store::now(),
+    };
+    let verdict = match store.checksum(&video.key) {
+        None => Verdict::New,
+        Some(stored) if stored.sha256 == checksum.sha256 => Verdict::Intact,
+        Some(stored) if stored.size != size || stored.modified != modified => Verdict::Changed,
+        Some(_) => Verdict::Corrupted,
+    };
+    if verdict != Verdict::Corrupted {
+        store.set_checksum(&video.key, checksum.clone());
+    }
+    Ok((verdict, checksum))
+}
+
+/// The stored checksum of a video, unless it has none yet or the file was modified since.
+pub fn stored_checksum(store: &IndexStore, video: &VideoEntry) -> std::io::Result<Option<Checksum>> {
+    let Some(stored) = store.checksum(&video.key) else {
+        return Ok(None);
+    };
+    Ok((stamp(&video.path)? == (stored.size, stored.modified)).then_some(stored))
+}
+
+/// The stored checksum of a video, hashing it first if it has none yet or the file was modified since.
+pub fn checksum(store: &IndexStore, video: &VideoEntry) -> std::io::Result<Checksum> {
+    match stored_checksum(store, video)? {
+        Some(stored) => Ok(stored),
+        None => verify(store, video).map(|(_, checksum)| checksum),
+    }
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct VerifyArgs {
+    /// Only verify the videos in these folders, relative to the root. Everything by default.
+    pub folders: Vec<String>,
+}
+
+/// Run the `verify` command: hash every video and report those whose contents changed although their files weren't
+/// modified. Returns the exit code: 0 if none is corrupted or unreadable, 1 otherwise.
+pub async fn run(config: &VideoPlayerConfig, args: &VerifyArgs) -> i32 {
+    let store = match IndexStore::open(&config.data_dir) {
+        Ok(store) => Arc::new(store),
+        Err(err) => {
+            error!("Failed to open the store in {}: {}", config.data_dir.display(), err);
+            return 1;
+        }
+    };
+    let state = match VideoPlayerState::build(config, store.clone()) {
+        Ok(state) => state,
+        Err(err) => {
+            error!("Failed to index {}: {}", config.assets_root, err);
+            return 1;
+        }
+    };
+    let videos = state.videos();
+    let folders: Vec<String> = args
+        .folders
+        .iter()
+        .map(|folder| format!("{}/", folder.trim_matches('/')))
+        .collect();
+    let verified = tokio::task::spawn_blocking(move || {
+        let mut counts = [0; 4];
+        let mut unreadable = 0;
+        for video in videos
+            .iter()
+            .filter(|video| folders.is_empty() || folders.iter().any(|folder| video.key.starts_with(folder)))
+        {
+            match verify(&store, video) {
+                Ok((verdict, checksum)) => {
+                    counts[verdict as usize] += 1;
+                    match verdict {
+                        Verdict::Corrupted => println!(
+                            "CORRUPTED {}: its SHA-256 is {}, not {}",
+                            video.key,
+                            checksum.sha256,
+                            store.checksum(&video.key).map(|stored| stored.sha256).unwrap_or_default()
+                        ),
+                        Verdict::Changed => println!("changed   {}", video.key),
+                        Verdict::New | Verdict::Intact => {}
+                    }
+                }
+                Err(err) => {
+                    unreadable += 1;
+                    println!("UNREADABLE {}: {}", video.key, err);
+                }
+            }
+        }
+        store.flush();
+        (counts, unreadable)
+    })
+    .await;
+    let Ok(([new, intact, changed, corrupted], unreadable)) = verified else {
+        error!("Failed to verify the library.");
+        return 1;
+    };
+    println!(
+        "Verified {} videos: {} intact, {} hashed for the first time, {} changed, {} corrupted, {} unreadable.",
+        new + intact + changed + corrupted + unreadable,
+        intact,
+        new,
+        changed,
+        corrupted,
+        unreadable
+    );
+    i32::from(corrupted + unreadable > 0)
+}
@@ -0,0 +1,831 @@
+//! Background jobs for the work that runs ffmpeg (generating thumbnails, ...), worked through by a bounded pool of
+//! workers in order of priority. Failed jobs are retried a few times, and unfinished jobs are persisted in the data
+//! directory so a restart picks them up again. Progress and the log of ffmpeg are streamed to whoever follows a job.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    process::Command,
+    sync::{broadcast, Notify},
+};
+use tracing::{debug, error, info, info_span, warn, Instrument};
+
+use crate::{cache, events::LibraryEvent, store, SharedState};
+#[cfg(feature = "transcoding")]
+use crate::{
+    convert::{self, Container, VideoCodec},
+    watermark::Watermark,
+};
+
+/// The name of the file unfinished jobs are persisted to, inside the data directory.
+pub const JOBS_FILE: &str = "jobs.json";
+
+/// The name of the folder generated thumbnails are kept in, inside the data directory.
+pub const THUMBNAILS_DIR: &str = "thumbnails";
+
+/// The name of the folder extracted clips are kept in, inside the data directory.
+pub const CLIPS_DIR: &str = "clips";
+
+/// How many clips may be waiting to be extracted at once. Asking for more is refused until some are done.
+pub const MAX_CLIP_JOBS: usize = 4;
+
+/// How long extracted clips are kept.
+const CLIP_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// How much space extracted clips may take up together. The oldest are removed first.
+const MAX_CLIPS_BYTES: u64 = 2 * 1024 * 1024 * 1024;
+
+/// The name of the folder extracted audio tracks are kept in, inside the data directory.
+pub const AUDIO_DIR: &str = "audio";
+
+/// How many audio tracks may be waiting to be extracted at once. Asking for more is refused until some are done.
+pub const MAX_AUDIO_JOBS: usize = 4;
+
+/// How long extracted audio tracks are kept.
+const AUDIO_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// How much space extracted audio tracks may take up together. The oldest are removed first.
+const MAX_AUDIO_BYTES: u64 = 2 * 1024 * 1024 * 1024;
+
+/// The name of the folder animated previews are kept in, inside the data directory.
+pub const PREVIEWS_DIR: &str = "previews";
+
+/// How many frames an animated preview shows, sampled evenly across the video.
+pub const PREVIEW_FRAMES: u32 = 6;
+
+/// How many frames of an animated preview are shown per second.
+const PREVIEW_RATE: u32 = 2;
+
+/// How often a job is attempted before it is marked as failed.
+pub const MAX_ATTEMPTS: u32 = 3;
+
+/// How many finished (done or failed) jobs are remembered for `GET /api/jobs`.
+pub const FINISHED_JOBS: usize = 200;
+
+/// How long to wait before retrying a failed job, multiplied by the number of attempts so far.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// How many updates may queue up for a slow follower of a job before it starts missing some.
+const UPDATE_BUFFER: usize = 256;
+
+/// Jobs of higher priority are started first, in the order they were submitted otherwise.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    /// Work nobody is waiting for, like thumbnails generated after a scan.
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+/// The formats audio tracks can be extracted to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioFormat {
+    #[default]
+    Mp3,
+    /// AAC in an MPEG-4 container.
+    M4a,
+}
+
+impl AudioFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Mp3 => "mp3",
+            Self::M4a => "m4a",
+        }
+    }
+
+    /// The ffmpeg options to encode to the format.
+    fn options(self) -> &'static [&'static str] {
+        match self {
+            Self::Mp3 => &["-c:a", "libmp3lame", "-q:a", "2", "-f", "mp3"],
+            Self::M4a => &["-c:a", "aac", "-b:a", "192k", "-movflags", "+faststart", "-f", "ipod"],
+        }
+    }
+}
+
+/// What a job does.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JobKind {
+    /// Extract a frame of a video as its thumbnail, shown as the poster if there is none next to the video.
+    Thumbnail {
+        /// The video, by its path relative to the root.
+        key: String,
+        /// Where to take the frame from, in seconds.
+        at: f64,
+    },
+    /// Sample frames across a video into an animated WebP, shown when hovering it in the grid.
+    Preview { key: String },
+    /// Extract the audio track of a video, e.g. to listen to a lecture on the go.
+    Audio { key: String, format: AudioFormat },
+    /// Copy a segment of a video into an MP4 of its own, without re-encoding it.
+    Clip {
+        key: String,
+        /// Where the segment starts and ends, in seconds.
+        start: f64,
+        end: f64,
+    },
+    /// Convert a video to another container and/or codec, next to it or in place of it.
+    #[cfg(feature = "transcoding")]
+    Convert {
+        key: String,
+        container: Container,
+        codec: VideoCodec,
+        /// Delete the original once it is converted.
+        replace: bool,
+        /// Burn the configured watermark into the conversion.
+        #[serde(default)]
+        watermark: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Done,
+    Failed,
+}
+
+impl JobStatus {
+    pub fn is_finished(self) -> bool {
+        matches!(self, Self::Done | Self::Failed)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    pub id: u64,
+    #[serde(flatten)]
+    pub kind: JobKind,
+    pub priority: Priority,
+    pub status: JobStatus,
+    /// How often the job was started so far.
+    pub attempts: u32,
+    /// How far the running (or last) attempt got, in percent, as reported by ffmpeg.
+    #[serde(default)]
+    pub progress: Option<f64>,
+    /// Why the last attempt failed.
+    pub error: Option<String>,
+    /// When the job was submitted, in seconds since the Unix epoch.
+    pub created: u64,
+    /// When the status last changed, in seconds since the Unix epoch.
+    pub updated: u64,
+    /// A failed job isn't retried before this.
+    #[serde(skip)]
+    retry_at: Option<Instant>,
+}
+
+/// Something that happened to a job, as streamed from `/api/jobs/:id/events`.
+#[derive(Debug, Clone)]
+pub enum JobUpdate {
+    /// The job was submitted, started, retried or finished.
+    Status(Job),
+    Progress { id: u64, progress: f64 },
+    /// A line ffmpeg logged while running the job.
+    Log { id: u64, line: String },
+}
+
+impl JobUpdate {
+    pub fn job_id(&self) -> u64 {
+        match self {
+            Self::Status(job) => job.id,
+            Self::Progress { id, .. } | Self::Log { id, .. } => *id,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Jobs {
+    next_id: u64,
+    /// Every job by id, including the latest finished ones.
+    jobs: BTreeMap<u64, Job>,
+}
+
+impl Jobs {
+    /// The unfinished jobs, which are what gets persisted.
+    fn unfinished(&self) -> Self {
+        Self {
+            next_id: self.next_id,
+            jobs: self
+                .jobs
+                .iter()
+                .filter(|(_, job)| !job.status.is_finished())
+                .map(|(id, job)| (*id, job.clone()))
+                .collect(),
+        }
+    }
+}
+
+/// The job queue of a library.
+pub struct JobQueue {
+    /// Where unfinished jobs are persisted, or `None` to only keep them in memory.
+    path: Option<PathBuf>,
+    data_dir: PathBuf,
+    ffmpeg: String,
+    /// The watermark burned into conversions that ask for it.
+    #[cfg(feature = "transcoding")]
+    watermark: Option<Watermark>,
+    jobs: Mutex<Jobs>,
+    /// Wakes up an idle worker when there is something to do.
+    wakeup: Notify,
+    updates: broadcast::Sender<JobUpdate>,
+}
+
+pub type SharedJobQueue = Arc<JobQueue>;
+
+impl JobQueue {
+    /// Open the queue of a data directory, picking up the jobs that didn't finish before the last shutdown.
+    pub fn open(data_dir: &Path, ffmpeg: &str) -> std::io::Result<Self> {
+        let path = data_dir.join(JOBS_FILE);
+        let mut jobs: Jobs = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Jobs::default(),
+            Err(err) => return Err(err),
+        };
+        // Jobs that were running when the server stopped start over.
+        for job in jobs.jobs.values_mut() {
+            job.status = JobStatus::Pending;
+        }
+        if !jobs.jobs.is_empty() {
+            info!("Resuming {} unfinished jobs.", jobs.jobs.len());
+        }
+        Ok(Self {
+            path: Some(path),
+            data_dir: data_dir.to_path_buf(),
+            ffmpeg: ffmpeg.to_string(),
+            #[cfg(feature = "transcoding")]
+            watermark: None,
+            jobs: Mutex::new(jobs),
+            wakeup: Notify::new(),
+            updates: broadcast::channel(UPDATE_BUFFER).0,
+        })
+    }
+
+    /// A queue that is only kept in memory, e.g. for the jobs of a command that exits once they are done.
+    pub fn in_memory(data_dir: &Path, ffmpeg: &str) -> Self {
+        Self {
+            path: None,
+            data_dir: data_dir.to_path_buf(),
+            ffmpeg: ffmpeg.to_string(),
+            #[cfg(feature = "transcoding")]
+            watermark: None,
+            jobs: Mutex::new(Jobs::default()),
+            wakeup: Notify::new(),
+            updates: broadcast::channel(UPDATE_BUFFER).0,
+        }
+    }
+
+    /// Burn a watermark into the conversions that ask for it.
+    #[cfg(feature = "transcoding")]
+    pub fn with_watermark(mut self, watermark: Option<Watermark>) -> Self {
+        self.watermark = watermark;
+        self
+    }
+
+    /// The watermark burned into the conversions that ask for it, if one is configured.
+    #[cfg(feature = "transcoding")]
+    pub fn watermark(&self) -> Option<&Watermark> {
+        self.watermark.as_ref()
+    }
+
+    /// Change the jobs and persist the unfinished ones.
+    fn update<R>(&self, change: impl FnOnce(&mut Jobs) -> R) -> R {
+        let mut jobs = self.jobs.lock().unwrap();
+        let result = change(&mut jobs);
+        if let Some(path) = &self.path {
+            if let Err(err) = store::persist(path, &jobs.unfinished()) {
+                error!("Failed to persist {}: {}", path.display(), err);
+            }
+        }
+        result
+    }
+
+    /// Queue a job. If the same job is already pending or running, that one is returned instead.
+    pub fn submit(&self, kind: JobKind, priority: Priority) -> Job {
+        let job = self.update(|jobs| {
+            let existing = jobs
+                .jobs
+                .values_mut()
+                .find(|job| !job.status.is_finished() && job.kind == kind);
+            if let Some(job) = existing {
+                job.priority = job.priority.max(priority);
+                return job.clone();
+            }
+            let now = store::now();
+            let job = Job {
+                id: jobs.next_id,
+                kind,
+                priority,
+                status: JobStatus::Pending,
+                attempts: 0,
+                progress: None,
+                error: None,
+                created: now,
+                updated: now,
+                retry_at: None,
+            };
+            jobs.next_id += 1;
+            jobs.jobs.insert(job.id, job.clone());
+            job
+        });
+        self.publish(JobUpdate::Status(job.clone()));
+        self.wakeup.notify_one();
+        job
+    }
+
+    /// Tell the followers of a job what happened to it. Dropped if nobody follows.
+    fn publish(&self, update: JobUpdate) {
+        let _ = self.updates.send(update);
+    }
+
+    /// Follow what happens to all jobs from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<JobUpdate> {
+        self.updates.subscribe()
+    }
+
+    /// Queue a job and wait until it is done or failed for good, e.g. to respond with what it produced.
+    pub async fn wait_for(&self, kind: JobKind, priority: Priority) -> Job {
+        // Subscribe before submitting so the job can't finish unnoticed in between.
+        let mut updates = self.subscribe();
+        let mut job = self.submit(kind, priority);
+        while !job.status.is_finished() {
+            match updates.recv().await {
+                Ok(JobUpdate::Status(update)) if update.id == job.id => job = update,
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(_)) => match self.get(job.id) {
+                    Some(current) => job = current,
+                    None => break,
+                },
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+        job
+    }
+
+    /// Whether the same job failed for good before, so it isn't worth trying again.
+    pub fn has_failed(&self, kind: &JobKind) -> bool {
+        self.jobs
+            .lock()
+            .unwrap()
+            .jobs
+            .values()
+            .any(|job| job.status == JobStatus::Failed && &job.kind == kind)
+    }
+
+    /// All known jobs, the most recently submitted first.
+    pub fn list(&self) -> Vec<Job> {
+        self.jobs.lock().unwrap().jobs.values().rev().cloned().collect()
+    }
+
+    pub fn get(&self, id: u64) -> Option<Job> {
+        self.jobs.lock().unwrap().jobs.get(&id).cloned()
+    }
+
+    /// Where the thumbnail of a video is generated to.
+    pub fn thumbnail_path(&self, key: &str) -> PathBuf {
+        self.data_dir.join(THUMBNAILS_DIR).join(format!("{}.jpg", key))
+    }
+
+    /// Where the animated preview of a video is generated to.
+    pub fn preview_path(&self, key: &str) -> PathBuf {
+        self.data_dir.join(PREVIEWS_DIR).join(format!("{}.webp", key))
+    }
+
+    /// Where the audio track of a video is extracted to.
+    pub fn audio_path(&self, key: &str, format: AudioFormat) -> PathBuf {
+        self.data_dir.join(AUDIO_DIR).join(format!("{}.{}", key, format.extension()))
+    }
+
+    /// How many jobs of the same kind as this one (clips, audio tracks, ...) are pending or running.
+    pub fn unfinished_like(&self, kind: &JobKind) -> usize {
+        let kind = std::mem::discriminant(kind);
+        self.jobs
+            .lock()
+            .unwrap()
+            .jobs
+            .values()
+            .filter(|job| !job.status.is_finished() && std::mem::discriminant(&job.kind) == kind)
+            .count()
+    }
+
+    /// Where a clip of a video is extracted to.
+    pub fn clip_path(&self, key: &str, start: f64, end: f64) -> PathBuf {
+        self.data_dir.join(CLIPS_DIR).join(key).join(format!("{}-{}.mp4", start, end))
+    }
+
+    /// Take the pending job to work on next, if any is due.
+    fn next(&self) -> Option<Job> {
+        let job = self.update(|jobs| {
+            let now = Instant::now();
+            let job = jobs
+                .jobs
+                .values_mut()
+                .filter(|job| job.status == JobStatus::Pending)
+                .filter(|job| job.retry_at.is_none_or(|retry_at| retry_at <= now))
+                .max_by_key(|job| (job.priority, std::cmp::Reverse(job.id)))?;
+            job.status = JobStatus::Running;
+            job.attempts += 1;
+            job.progress = None;
+            job.updated = store::now();
+            Some(job.clone())
+        })?;
+        self.publish(JobUpdate::Status(job.clone()));
+        Some(job)
+    }
+
+    /// Record the outcome of a job, scheduling a retry if it failed and has attempts left.
+    fn finish(self: &Arc<Self>, id: u64, outcome: Result<(), String>) {
+        let updated = self.update(|jobs| {
+            let job = jobs.jobs.get_mut(&id)?;
+            job.updated = store::now();
+            let retry = match outcome {
+                Ok(()) => {
+                    job.status = JobStatus::Done;
+                    job.error = None;
+                    None
+                }
+                Err(err) if job.attempts < MAX_ATTEMPTS => {
+                    warn!("Job {} failed (attempt {}), retrying: {}", id, job.attempts, err);
+                    let delay = RETRY_DELAY * job.attempts;
+                    job.status = JobStatus::Pending;
+                    job.error = Some(err);
+                    job.retry_at = Some(Instant::now() + delay);
+                    Some(delay)
+                }
+                Err(err) => {
+                    warn!("Job {} failed for good: {}", id, err);
+                    job.status = JobStatus::Failed;
+                    job.error = Some(err);
+                    None
+                }
+            };
+            let job = job.clone();
+            let finished: Vec<u64> = jobs
+                .jobs
+                .values()
+                .filter(|job| job.status.is_finished())
+                .map(|job| job.id)
+                .collect();
+            for id in finished.iter().take(finished.len().saturating_sub(FINISHED_JOBS)) {
+                jobs.jobs.remove(id);
+            }
+            Some((job, retry))
+        });
+        let Some((job, retry)) = updated else {
+            return;
+        };
+        self.publish(JobUpdate::Status(job));
+        if let Some(delay) = retry {
+            let queue = self.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(delay).await;
+                queue.wakeup.notify_one();
+            });
+        }
+    }
+
+    /// Start `workers` workers running the jobs of a library.
+    pub fn start(
+        self: &Arc<Self>,
+        workers: usize,
+        state: SharedState,
+        store: store::SharedIndexStore,
+        cache: cache::SharedFileCache,
+    ) {
+        for _ in 0..workers.max(1) {
+            let queue = self.clone();
+            let state = state.clone();
+            let store = store.clone();
+            let cache = cache.clone();
+            tokio::spawn(async move {
+                loop {
+                    match queue.next() {
+                        Some(job) => {
+                            debug!("Running job {}: {:?}", job.id, job.kind);
+                            let span = info_span!("job", id = job.id, kind = ?job.kind, attempt = job.attempts);
+                            let outcome = queue.run(&job, &state, &store, &cache).instrument(span).await;
+                            queue.finish(job.id, outcome);
+                        }
+                        None => queue.wakeup.notified().await,
+                    }
+                }
+            });
+        }
+    }
+
+    #[cfg_attr(not(feature = "transcoding"), allow(unused_variables))]
+    async fn run(
+        &self,
+        job: &Job,
+        state: &SharedState,
+        store: &store::IndexStore,
+        cache: &cache::FileCache,
+    ) -> Result<(), String> {
+        match &job.kind {
+            JobKind::Thumbnail { key, at } => {
+                let video = find_video(state, key)?;
+                let thumbnail = self.thumbnail_path(key);
+                let options = ["-frames:v", "1", "-vf", "scale='min(640,iw)':-2", "-f", "mjpeg"];
+                self.produce(job.id, &video.path, *at, &options, &thumbnail, None).await?;
+                cache.forget(&thumbnail);
+                Ok(())
+            }
+            JobKind::Preview { key } => {
+                let video = find_video(state, key)?;
+                // Without a known duration, take a frame every ten seconds from the start.
+                let duration = video.duration().filter(|duration| *duration > 0.0);
+                let interval = duration.map_or(10.0, |duration| duration / PREVIEW_FRAMES as f64);
+                let filter = format!("fps=1/{},scale=320:-2,setpts=N/{}/TB", interval, PREVIEW_RATE);
+                let frames = PREVIEW_FRAMES.to_string();
+                let rate = PREVIEW_RATE.to_string();
+                let options = [
+                    "-vf", &filter, "-frames:v", &frames, "-r", &rate, "-an",
+                    "-c:v", "libwebp", "-quality", "60", "-loop", "0", "-f", "webp",
+                ];
+                let preview = self.preview_path(key);
+                let start = duration.map_or(0.0, |_| interval / 2.0);
+                self.produce(job.id, &video.path, start, &options, &preview, duration).await?;
+                cache.forget(&preview);
+                Ok(())
+            }
+            JobKind::Audio { key, format } => {
+                let video = find_video(state, key)?;
+                let mut options = vec!["-vn", "-map", "0:a:0"];
+                options.extend(format.options());
+                let audio = self.audio_path(key, *format);
+                self.produce(job.id, &video.path, 0.0, &options, &audio, video.duration()).await?;
+                let dir = self.data_dir.join(AUDIO_DIR);
+                let prune = move || prune_extracted(&dir, &audio, AUDIO_MAX_AGE, MAX_AUDIO_BYTES);
+                let _ = tokio::task::spawn_blocking(prune).await;
+                Ok(())
+            }
+            JobKind::Clip { key, start, end } => {
+                let video = find_video(state, key)?.path;
+                let length = (end - start).to_string();
+                let options = [
+                    "-t", &length, "-map", "0:v?", "-map", "0:a?", "-c", "copy",
+                    "-avoid_negative_ts", "make_zero", "-movflags", "+faststart", "-f", "mp4",
+                ];
+                let clip = self.clip_path(key, *start, *end);
+                self.produce(job.id, &video, *start, &options, &clip, Some(end - start)).await?;
+                let dir = self.data_dir.join(CLIPS_DIR);
+                let prune = move || prune_extracted(&dir, &clip, CLIP_MAX_AGE, MAX_CLIPS_BYTES);
+                let _ = tokio::task::spawn_blocking(prune).await;
+                Ok(())
+            }
+            #[cfg(feature = "transcoding")]
+            JobKind::Convert { key, container, codec, replace, watermark } => {
+                let video = find_video(state, key)?;
+                let watermark = if *watermark {
+                    Some(self.watermark.as_ref().ok_or("No watermark is configured")?)
+                } else {
+                    None
+                };
+                let converted = convert::target(&video.path, *container, *codec, *replace, watermark.is_some())?;
+                let options = convert::options(*container, *codec, watermark);
+                let options: Vec<&str> = options.iter().map(String::as_str).collect();
+                self.produce(job.id, &video.path, 0.0, &options, &converted, video.duration()).await?;
+                let mut state = state.lock().unwrap();
+                let added = convert::add_conversion(&mut state, store, &video, converted, *replace)
+                    .map_err(|(_, err)| err)?;
+                info!("Converted {} to {}", video.key, added.key);
+                Ok(())
+            }
+        }
+    }
+
+    /// Run ffmpeg on a video from `start` seconds into it, writing `output` with the given options.
+    /// The output only appears once it is complete.
+    async fn produce(
+        &self,
+        id: u64,
+        video: &Path,
+        start: f64,
+        options: &[&str],
+        output: &Path,
+        duration: Option<f64>,
+    ) -> Result<(), String> {
+        if let Some(parent) = output.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|err| format!("Failed to create {}: {}", parent.display(), err))?;
+        }
+        let mut partial = output.as_os_str().to_owned();
+        partial.push(".part");
+        let partial = PathBuf::from(partial);
+        let mut args: Vec<OsString> = vec!["-y".into(), "-ss".into(), start.to_string().into(), "-i".into()];
+        args.push(video.into());
+        args.extend(options.iter().map(OsString::from));
+        args.push(partial.clone().into());
+        if let Err(err) = self.run_ffmpeg(id, args, duration).await {
+            let _ = tokio::fs::remove_file(&partial).await;
+            return Err(format!("{} ({})", err, video.display()));
+        }
+        tokio::fs::rename(&partial, output)
+            .await
+            .map_err(|err| format!("Failed to write {}: {}", output.display(), err))
+    }
+
+    /// Run ffmpeg for a job, publishing every line it logs and its progress through `duration` seconds of output.
+    async fn run_ffmpeg(&self, id: u64, args: Vec<OsString>, duration: Option<f64>) -> Result<(), String> {
+        let mut child = Command::new(&self.ffmpeg)
+            .args(["-hide_banner", "-nostats", "-v", "error", "-progress", "pipe:1"])
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|err| format!("Failed to run {}: {}", self.ffmpeg, err))?;
+        let mut progress = child.stdout.take().map(|stdout| BufReader::new(stdout).lines());
+        let mut log = child.stderr.take().map(|stderr| BufReader::new(stderr).lines());
+        let mut last_line = None;
+        while progress.is_some() || log.is_some() {
+            tokio::select! {
+                line = async { progress.as_mut().unwrap().next_line().await }, if progress.is_some() => match line {
+                    Ok(Some(line)) => self.report_progress(id, &line, duration),
+                    _ => progress = None,
+                },
+                line = async { log.as_mut().unwrap().next_line().await }, if log.is_some() => match line {
+                    Ok(Some(line)) => {
+                        self.publish(JobUpdate::Log { id, line: line.clone() });
+                        last_line = Some(line);
+                    }
+                    _ => log = None,
+                },
+            }
+        }
+        let status = child
+            .wait()
+            .await
+            .map_err(|err| format!("Failed to run {}: {}", self.ffmpeg, err))?;
+        match last_line {
+            _ if status.success() => Ok(()),
+            Some(line) => Err(format!("{} exited with {}: {}", self.ffmpeg, status, line)),
+            None => Err(format!("{} exited with {}", self.ffmpeg, status)),
+        }
+    }
+
+    /// Parse a line of `ffmpeg -progress` output, e.g. `out_time_us=1500000` or `progress=end`.
+    fn report_progress(&self, id: u64, line: &str, duration: Option<f64>) {
+        let progress = match (line.split_once('='), duration) {
+            (Some(("progress", "end")), _) => 100.0,
+            (Some(("out_time_us", micros)), Some(duration)) if duration > 0.0 => match micros.parse::<f64>() {
+                Ok(micros) => (micros / 1e6 / duration * 100.0).clamp(0.0, 100.0),
+                Err(_) => return,
+            },
+            _ => return,
+        };
+        if let Some(job) = self.jobs.lock().unwrap().jobs.get_mut(&id) {
+            job.progress = Some(progress);
+        }
+        self.publish(JobUpdate::Progress { id, progress });
+    }
+
+    /// Queue a thumbnail for a video unless it has a poster or a thumbnail already.
+    pub fn submit_thumbnail(&self, video: &crate::VideoEntry, priority: Priority) -> Option<Job> {
+        let has_poster = cache::poster_candidates(&video.path)
+            .iter()
+            .chain([&self.thumbnail_path(&video.key)])
+            .any(|path| path.is_file());
+        if has_poster {
+            return None;
+        }
+        Some(self.submit(JobKind::Thumbnail { key: video.key.clone(), at: thumbnail_position(video) }, priority))
+    }
+
+    /// Queue an animated preview for a video unless it has one already, or generating it failed before.
+    pub fn submit_preview(&self, video: &crate::VideoEntry, priority: Priority) -> Option<Job> {
+        let kind = JobKind::Preview { key: video.key.clone() };
+        if self.preview_path(&video.key).is_file() || self.has_failed(&kind) {
+            return None;
+        }
+        Some(self.submit(kind, priority))
+    }
+}
+
+/// Round where a clip starts or ends to a tenth of a second, so requests that barely differ share a clip.
+pub fn round_clip_bound(seconds: f64) -> f64 {
+    (seconds * 10.0).round() / 10.0
+}
+
+/// The files extracted to a folder (clips, audio tracks), with when they were extracted and their size.
+/// Those still being written are left out.
+fn list_extracted(dir: &Path, files: &mut Vec<(PathBuf, std::time::SystemTime, u64)>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        if metadata.is_dir() {
+            list_extracted(&path, files);
+        } else if path.extension().is_some_and(|extension| extension != "part") {
+            let modified = metadata.modified().unwrap_or(std::time::UNIX_EPOCH);
+            files.push((path, modified, metadata.len()));
+        }
+    }
+}
+
+/// Remove the files extracted to a folder that are older than `max_age`, then the oldest ones until they fit in
+/// `max_bytes`. The file just extracted is kept, since it is about to be served.
+fn prune_extracted(dir: &Path, keep: &Path, max_age: Duration, max_bytes: u64) {
+    let mut files = Vec::new();
+    list_extracted(dir, &mut files);
+    files.sort_by_key(|(_, modified, _)| *modified);
+    let mut total: u64 = files.iter().map(|(_, _, bytes)| bytes).sum();
+    for (path, modified, bytes) in files {
+        let expired = modified.elapsed().is_ok_and(|age| age > max_age);
+        if path == keep || !(expired || total > max_bytes) {
+            continue;
+        }
+        match std::fs::remove_file(&path) {
+            Ok(()) => {
+                debug!("Removed {}", path.display());
+                total -= bytes;
+                // Only removes the folder once it is empty.
+                if let Some(parent) = path.parent().filter(|parent| *parent != dir) {
+                    let _ = std::fs::remove_dir(parent);
+                }
+            }
+            Err(err) => warn!("Failed to remove {}: {}", path.display(), err),
+        }
+    }
+}
+
+/// Where to take the thumbnail of a video from: a tenth into it, past intros and fades from black.
+pub fn thumbnail_position(video: &crate::VideoEntry) -> f64 {
+    video.duration().map_or(0.0, |duration| (duration / 10.0).floor())
+}
+
+/// The video with the given key.
+fn find_video(state: &SharedState, key: &str) -> Result<crate::VideoEntry, String> {
+    state
+        .lock()
+        .unwrap()
+        .videos()
+        .iter()
+        .find(|video| video.key == key)
+        .cloned()
+        .ok_or_else(|| format!("No video at {}", key))
+}
+
+/// What to generate in the background for every video that lacks it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Generate {
+    pub thumbnails: bool,
+    pub previews: bool,
+}
+
+/// Queue thumbnails and/or previews for every video that lacks them, and for videos as they are added or moved.
+pub async fn generate_missing(queue: SharedJobQueue, state: SharedState, generate: Generate) {
+    let events = state.lock().unwrap().events().map(|events| events.subscribe());
+    let submit = |video: &crate::VideoEntry| {
+        if generate.thumbnails {
+            queue.submit_thumbnail(video, Priority::Low);
+        }
+        if generate.previews {
+            queue.submit_preview(video, Priority::Low);
+        }
+    };
+    let submit_all = || {
+        for video in state.lock().unwrap().videos().iter() {
+            submit(video);
+        }
+    };
+    submit_all();
+    let Some(mut events) = events else {
+        return;
+    };
+    loop {
+        match events.recv().await {
+            Ok(envelope) => match envelope.event {
+                LibraryEvent::VideoAdded { video } | LibraryEvent::VideoMoved { video, .. } => {
+                    if let Some(video) = state.lock().unwrap().get(&video.id) {
+                        submit(video);
+                    }
+                }
+                LibraryEvent::ScanCompleted { .. } => submit_all(),
+                _ => {}
+            },
+            Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => submit_all(),
+            Err(tokio::sync::broadcast::error::RecvError::Closed) => return,
+        }
+    }
+}
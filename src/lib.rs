@@ -1,14 +1,88 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use std::{
-    collections::{HashMap, HashSet},
-    path::PathBuf,
-    sync::{
-        atomic::{AtomicUsize, Ordering},
-        Arc, Mutex,
-    },
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    net::{SocketAddr, ToSocketAddrs},
+    path::{Component, PathBuf},
+    sync::{Arc, Mutex},
 };
-use tracing::log::info;
+use tracing::log::{info, warn};
 use lazy_static::lazy_static;
+use sha2::{Digest, Sha256};
+
+#[cfg(feature = "http3")]
+pub mod http3;
+pub mod access;
+pub mod analytics;
+#[cfg(feature = "ui")]
+pub mod assets;
+pub mod api;
+pub mod auth;
+pub mod bench;
+pub mod cache;
+pub mod config_file;
+#[cfg(feature = "transcoding")]
+pub mod convert;
+pub mod cors;
+pub mod cron;
+pub mod csrf;
+pub mod daemon;
+pub mod dedupe;
+pub mod dir_config;
+pub mod error;
+pub mod events;
+#[cfg(feature = "full-text")]
+pub mod fulltext;
+#[cfg(feature = "ui")]
+pub mod i18n;
+pub mod incoming;
+pub mod ingest;
+pub mod integrity;
+pub mod jobs;
+pub mod library;
+pub mod library_stats;
+pub mod media;
+pub mod metadata;
+pub mod mqtt;
+#[cfg(feature = "ui")]
+pub mod oembed;
+pub mod oidc;
+pub mod private;
+pub mod proxy;
+pub mod reload;
+#[cfg(feature = "sentry")]
+pub mod reporting;
+pub mod robots;
+pub mod scan;
+pub mod search;
+#[cfg(windows)]
+pub mod service;
+pub mod session;
+pub mod share;
+#[cfg(feature = "ui")]
+pub mod sitemap;
+pub mod statsd;
+pub mod storage;
+pub mod store;
+pub mod streams;
+#[cfg(feature = "ui")]
+pub mod templates;
+#[cfg(feature = "otlp")]
+pub mod telemetry;
+#[cfg(feature = "ui")]
+pub mod themes;
+pub mod timeouts;
+pub mod tls;
+pub mod uploads;
+pub mod urls;
+pub mod users;
+pub mod version;
+pub mod vhost;
+pub mod videoignore;
+pub mod viewer;
+pub mod visibility;
+#[cfg(feature = "transcoding")]
+pub mod watermark;
+pub mod webdav;
 
 lazy_static! {
     pub static ref VIDEO_EXTENSIONS: Vec<String> = vec![
@@ -25,31 +99,724 @@ lazy_static! {
         "wmv".into(),
         "3gp".into()
     ];
+
+    /// Indexed along with the videos with `--audio`.
+    pub static ref AUDIO_EXTENSIONS: Vec<String> = vec![
+        "mp3".into(),
+        "flac".into(),
+        "m4a".into(),
+        "ogg".into(),
+        "oga".into(),
+        "opus".into(),
+        "wav".into(),
+        "aac".into()
+    ];
+
+    /// Indexed along with the videos with `--images`.
+    pub static ref IMAGE_EXTENSIONS: Vec<String> = vec![
+        "jpg".into(),
+        "jpeg".into(),
+        "png".into(),
+        "gif".into(),
+        "webp".into(),
+        "avif".into(),
+        "bmp".into()
+    ];
+}
+
+/// What kind of media a file in the index is, which decides how it is shown and played.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaKind {
+    #[default]
+    Video,
+    Audio,
+    Image,
+}
+
+impl MediaKind {
+    /// The kind of a file by its lowercased extension.
+    pub fn of_extension(extension: &str) -> Self {
+        if AUDIO_EXTENSIONS.iter().any(|audio| audio == extension) {
+            Self::Audio
+        } else if IMAGE_EXTENSIONS.iter().any(|image| image == extension) {
+            Self::Image
+        } else {
+            Self::Video
+        }
+    }
+
+    pub fn of_path(path: &std::path::Path) -> Self {
+        let extension = path.extension().unwrap_or_default().to_string_lossy().to_ascii_lowercase();
+        Self::of_extension(&extension)
+    }
+
+    /// The name of the kind, which is also the route its files are served under, e.g. `/audio/:id`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Video => "video",
+            Self::Audio => "audio",
+            Self::Image => "image",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "video" => Some(Self::Video),
+            "audio" => Some(Self::Audio),
+            "image" => Some(Self::Image),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for MediaKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// So templates can compare kinds with their names, as they do in dev mode where they only see the names.
+impl PartialEq<&str> for MediaKind {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
 }
 
 
 
 /// The configuration for the video server.
 #[derive(Parser, Debug, Clone)]
+#[clap(args_override_self = true)]
 pub struct VideoPlayerConfig {
+    /// A file with more options, one per line, e.g. `max-stream-rate = 10MiB/s`. Some of them are applied
+    /// without a restart when the file changes or on SIGHUP.
+    #[clap(long)]
+    pub config: Option<PathBuf>,
+
+    /// Fork into the background, detached from the terminal, e.g. for init scripts.
+    /// The output goes to `--log-file`, and is discarded without one.
+    #[cfg(unix)]
+    #[clap(long)]
+    pub daemonize: bool,
+
+    /// Run as a Windows service. Set by `service install`, not meant to be given by hand.
+    #[cfg(windows)]
+    #[clap(long, hide = true)]
+    pub service: bool,
+
+    /// A file to write the id of the process to. It is locked while the server runs,
+    /// so a second instance with the same file refuses to start.
+    #[clap(long)]
+    pub pid_file: Option<PathBuf>,
+
+    /// Append the logs to this file instead of printing them.
+    #[clap(long)]
+    pub log_file: Option<PathBuf>,
+
     #[clap(short, long, default_value = "assets")]
     pub assets_root: String,
 
+    /// Serve a different assets root for requests to a host, e.g. `family.example.com=/srv/family`.
+    /// May be given multiple times. Each host gets its own index, and its own store under `--data-dir`.
+    /// Requests to other hosts are served from `--assets-root`.
+    #[clap(long, value_parser = vhost::parse_vhost)]
+    pub vhost: Vec<(String, PathBuf)>,
+
     #[clap(short, long, default_value = "9092")]
     pub port: u16,
 
-    #[clap(short, long, default_value = "0.0.0.0")]
+    /// The address (or host name) to listen on.
+    #[clap(short = 'H', long, default_value = "0.0.0.0")]
     pub host: String,
+
+    /// The path prefix the server is mounted at behind a reverse proxy, e.g. `/videos`.
+    #[clap(long, default_value = "")]
+    pub base_path: String,
+
+    /// The url the server is reached at from outside, including the base path, e.g.
+    /// `https://example.com/videos`. Used for links in previews of shared pages; by default they point to the host
+    /// each request was sent to.
+    #[clap(long)]
+    pub public_url: Option<String>,
+
+    /// Paths crawlers may index according to `/robots.txt`, e.g. `/watch/`. May be given multiple times. By
+    /// default they are asked not to index anything.
+    #[clap(long)]
+    pub robots_allow: Vec<String>,
+
+    /// Origins whose pages may call the API and play media from other sites, e.g. `https://app.example.com`, or
+    /// `*` for any. Comma separated or given multiple times. Cross-origin requests are refused by browsers otherwise.
+    #[clap(long, value_delimiter = ',')]
+    pub cors_origin: Vec<String>,
+
+    /// Reverse proxies (addresses or CIDR ranges) whose `Forwarded`
+    /// and `X-Forwarded-For` headers are trusted to identify the client.
+    #[clap(long, value_delimiter = ',', value_parser = proxy::parse_network)]
+    pub trusted_proxies: Vec<ipnet::IpNet>,
+
+    /// Only serve clients from these addresses or CIDR ranges, e.g. `192.168.0.0/16,10.0.0.0/8`.
+    /// Behind a reverse proxy, the client is identified through `--trusted-proxies`.
+    #[clap(long, value_delimiter = ',', value_parser = proxy::parse_network)]
+    pub allow_ip: Vec<ipnet::IpNet>,
+
+    /// Never serve clients from these addresses or CIDR ranges, even if they are allowed.
+    #[clap(long, value_delimiter = ',', value_parser = proxy::parse_network)]
+    pub deny_ip: Vec<ipnet::IpNet>,
+
+    /// A PEM encoded certificate chain to serve HTTPS (and HTTP/2 via ALPN) with.
+    #[clap(long, requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// The PEM encoded private key for the certificate.
+    #[clap(long, requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// A PEM bundle of CAs. When set, clients must present a certificate issued by one of them.
+    #[clap(long)]
+    pub tls_client_ca: Option<PathBuf>,
+
+    /// The size in bytes of the chunks video files are streamed in.
+    #[clap(long, default_value_t = media::DEFAULT_STREAM_BUFFER_SIZE)]
+    pub stream_buffer_size: usize,
+
+    /// The fastest a single response streams a video (or clip, or audio track), e.g. `10MiB/s` or `40Mbit/s`,
+    /// so one client can't saturate the uplink. Unlimited if not set.
+    #[clap(long, value_parser = media::parse_rate)]
+    pub max_stream_rate: Option<u64>,
+
+    /// The most videos (or clip and audio downloads, or WebDAV files) a single client address may stream at once.
+    /// Further requests are refused with 429 Too Many Requests. Unlimited if not set.
+    #[clap(long)]
+    pub max_streams_per_client: Option<usize>,
+
+    /// The most seconds a single response may take to stream a video before it is cut off.
+    #[clap(long)]
+    pub max_stream_duration: Option<u64>,
+
+    /// The seconds a client has to send the headers of a request, also the longest an HTTP/1 connection is kept
+    /// open waiting for the next request.
+    #[clap(long)]
+    pub header_read_timeout: Option<u64>,
+
+    /// The seconds after which connections with no request being handled and nothing read or written are closed.
+    #[clap(long)]
+    pub idle_timeout: Option<u64>,
+
+    /// The number of MiB of posters and subtitles to keep in memory.
+    #[clap(long, default_value_t = 64)]
+    pub cache_size: usize,
+
+    /// Files larger than this many KiB are never kept in memory.
+    #[clap(long, default_value_t = 1024)]
+    pub cache_max_entry_size: usize,
+
+    /// The `Cache-Control` header of videos and subtitles, e.g. `public, max-age=86400`. Not sent by default.
+    #[clap(long, value_parser = cache::parse_cache_control)]
+    pub cache_control_media: Option<axum::http::HeaderValue>,
+
+    /// The `Cache-Control` header of posters, thumbnails and previews.
+    #[clap(long, value_parser = cache::parse_cache_control)]
+    pub cache_control_images: Option<axum::http::HeaderValue>,
+
+    /// The `Cache-Control` header of the HTML pages, e.g. `no-cache`.
+    #[clap(long, value_parser = cache::parse_cache_control)]
+    pub cache_control_pages: Option<axum::http::HeaderValue>,
+
+    /// Extract codec, resolution and duration of every video with ffprobe while scanning.
+    #[clap(long)]
+    pub probe: bool,
+
+    /// Also index audio files (mp3, flac, m4a, ogg, ...), e.g. to serve a music folder. They are listed along with
+    /// the videos and played with an audio player.
+    #[clap(long)]
+    pub audio: bool,
+
+    /// Also index images (jpg, png, gif, webp, ...), shown in the listings as pictures. Posters next to videos
+    /// aren't indexed as images of their own.
+    #[clap(long)]
+    pub images: bool,
+
+    /// The ffprobe executable to use.
+    #[clap(long, default_value = "ffprobe")]
+    pub ffprobe: String,
+
+    /// The ffmpeg executable background jobs (thumbnails, ...) run.
+    #[clap(long, default_value = "ffmpeg")]
+    pub ffmpeg: String,
+
+    /// How many background jobs may run at the same time.
+    #[clap(long, default_value_t = 2)]
+    pub job_workers: usize,
+
+    /// Generate a thumbnail with ffmpeg for every video without a poster, kept in `--data-dir`.
+    #[clap(long)]
+    pub thumbnails: bool,
+
+    /// Generate an animated preview with ffmpeg for every video up front, kept in `--data-dir`.
+    /// Otherwise they are generated when first asked for.
+    #[clap(long)]
+    pub previews: bool,
+
+    /// A PNG image to burn into videos converted with a watermark, e.g. review copies to share.
+    #[cfg(feature = "transcoding")]
+    #[clap(long, conflicts_with = "watermark_text")]
+    pub watermark: Option<PathBuf>,
+
+    /// A line of text to burn into videos converted with a watermark, instead of an image.
+    #[cfg(feature = "transcoding")]
+    #[clap(long)]
+    pub watermark_text: Option<String>,
+
+    /// Where the watermark is placed.
+    #[cfg(feature = "transcoding")]
+    #[clap(long, value_enum, default_value_t = watermark::WatermarkPosition::BottomRight)]
+    pub watermark_position: watermark::WatermarkPosition,
+
+    /// How opaque the watermark is, from 0 to 1.
+    #[cfg(feature = "transcoding")]
+    #[clap(long, default_value_t = 0.5, value_parser = watermark::parse_opacity)]
+    pub watermark_opacity: f64,
+
+    /// An RTSP stream to record into the library with ffmpeg, as `name=url`, e.g. `door=rtsp://192.168.1.20/live`,
+    /// or as `name@22:00-06:00=url` to only record within that window every day (UTC). May be given multiple times.
+    #[clap(long, value_parser = ingest::parse_camera)]
+    pub record: Vec<ingest::Camera>,
+
+    /// The folder under the root recordings are kept in, in a folder per stream.
+    #[clap(long, default_value = "recordings")]
+    pub record_dir: PathBuf,
+
+    /// The length of the segments streams are recorded in, in seconds. Each is indexed once it is done.
+    #[clap(long, default_value_t = 900)]
+    pub record_segment: u64,
+
+    /// Delete recordings that are older than this many hours. They are kept forever otherwise.
+    #[clap(long)]
+    pub record_retention: Option<u64>,
+
+    /// Load templates from `--templates-dir` on every request instead of using the
+    /// ones compiled into the binary, so UI changes don't need a rebuild.
+    #[clap(long)]
+    pub dev: bool,
+
+    /// Serve only the JSON API and the media, without any of the HTML pages, for running your own frontend.
+    #[clap(long)]
+    pub no_ui: bool,
+
+    /// The language of the UI for browsers that don't ask for one there is a translation for: `en`, `de`, `es` or
+    /// `fr`.
+    #[clap(long, default_value = "en")]
+    pub lang: String,
+
+    /// The theme of viewers that didn't pick one: `dark`, `light`, `auto` (following the system) or a custom one.
+    #[clap(long, default_value = "dark")]
+    pub theme: String,
+
+    /// A directory of custom themes, each a CSS file setting the color variables of the UI, e.g. `solarized.css`.
+    #[clap(long)]
+    pub themes_dir: Option<PathBuf>,
+
+    /// The directory templates are loaded from in dev mode.
+    #[clap(long, default_value = "templates")]
+    pub templates_dir: PathBuf,
+
+    /// A secret that allows changing the library (renaming, moving, ...) through the API
+    /// when given as `Authorization: Bearer <token>`. Logged in users don't need it.
+    #[clap(long, env = "SVS_API_TOKEN")]
+    pub api_token: Option<String>,
+
+    /// A secret to sign share links with. Signed links play their video without a login until they expire; without
+    /// a secret, share links are simply watch pages.
+    #[clap(long, env = "SVS_SHARE_SECRET")]
+    pub share_secret: Option<String>,
+
+    /// A secret to sign session cookies with. One is generated and kept in `--data-dir` otherwise.
+    #[clap(long, env = "SVS_SESSION_SECRET")]
+    pub session_secret: Option<String>,
+
+    /// How many days a session lasts without being used, logging its user out.
+    #[clap(long, default_value_t = 30)]
+    pub session_days: u64,
+
+    /// The directory in which watch progress and other per-viewer data is persisted.
+    #[clap(long, default_value = "data")]
+    pub data_dir: PathBuf,
+
+    /// The number of MiB every user may upload. Videos they uploaded count towards it while they're in the library.
+    #[clap(long)]
+    pub upload_quota: Option<u64>,
+
+    /// The folder deleted videos are moved to, relative to the assets root, e.g. `.trash`.
+    /// It is never indexed.
+    #[clap(long, default_value = ".trash")]
+    pub trash_dir: PathBuf,
+
+    /// Only index the root upfront, and every other folder when it is first browsed,
+    /// while a background task works through the rest. For very large libraries.
+    #[clap(long)]
+    pub lazy_index: bool,
+
+    /// Rescan the library on a schedule, given as a cron expression in UTC, e.g. `"0 3 * * *"` for every night at 3.
+    /// For libraries on network mounts (NFS, SMB), whose changes aren't noticed otherwise.
+    #[clap(long, value_parser = cron::Schedule::parse)]
+    pub rescan_schedule: Option<cron::Schedule>,
+
+    /// Only rescan these folders on the schedule, merging what changed under them into the index like
+    /// `POST /api/rescan` does, rather than the whole library. Comma separated or given multiple times.
+    #[clap(long, value_delimiter = ',', requires = "rescan_schedule")]
+    pub rescan_path: Vec<String>,
+
+    /// Leave files modified less than this many seconds ago out of the index until they stop changing, so files still
+    /// being copied don't show up broken. Empty files and files being downloaded (`.part`, `.!qB`, ...) are always
+    /// left out. Their folders are rescanned once they settle.
+    #[clap(long, default_value_t = 30)]
+    pub settle_time: u64,
+
+    /// A folder to import videos from, e.g. where a camera is offloaded to. Once they settle, videos dropped there are
+    /// checked with ffprobe (when installed), moved into the library under `--incoming-template` and indexed. Those
+    /// ffprobe can't read are moved into a `rejected` folder in it instead.
+    #[clap(long)]
+    pub incoming: Option<PathBuf>,
+
+    /// Where imported videos go under the root, without the extension. `{name}` is the name of the file, `{title}` the
+    /// name with spaces for underscores and dots, and `{year}`, `{month}`, `{day}`, `{date}` and `{time}` when it was
+    /// last modified, in UTC.
+    #[clap(long, default_value = "Imported/{date}/{name}", value_parser = incoming::Template::parse)]
+    pub incoming_template: incoming::Template,
+
+    /// A url that library events (videos added or removed, scans completed) are POSTed to as JSON.
+    /// May be given multiple times.
+    #[clap(long)]
+    pub webhook: Vec<String>,
+
+    /// An MQTT broker to publish library events and playback starts to,
+    /// e.g. `mqtt://homeassistant.local:1883?client_id=videos`.
+    #[clap(long)]
+    pub mqtt_url: Option<String>,
+
+    /// The topic events are published under, as `<topic>/<event>`.
+    #[clap(long, default_value = "static-video-server")]
+    pub mqtt_topic: String,
+
+    /// Send metrics (requests, bytes sent, request and scan durations) to a StatsD server at this address,
+    /// e.g. `localhost:8125`.
+    #[clap(long)]
+    pub statsd: Option<String>,
+
+    /// The prefix of the names of the metrics sent to StatsD.
+    #[clap(long, default_value = "static_video_server")]
+    pub statsd_prefix: String,
+
+    /// Send the method and status of requests to StatsD as DogStatsD tags instead of as part of the metric names.
+    #[clap(long, requires = "statsd")]
+    pub dogstatsd: bool,
+
+    /// Search through a tantivy index kept in `--data-dir` (titles, paths, codecs and subtitles)
+    /// instead of scoring every video on every search. For very large libraries.
+    #[cfg(feature = "full-text")]
+    #[clap(long)]
+    pub full_text_index: bool,
+
+    /// Expose the library as a read-only WebDAV share at `/dav`.
+    #[clap(long)]
+    pub webdav: bool,
+
+    /// Obtain and renew a certificate for this domain via ACME (Let's Encrypt).
+    /// May be given multiple times. The server must be reachable on port 443 for the domain.
+    #[clap(long, conflicts_with = "tls_cert")]
+    pub acme_domain: Vec<String>,
+
+    /// The contact email to register the ACME account with.
+    #[clap(long, requires = "acme_domain")]
+    pub acme_contact: Vec<String>,
+
+    /// The directory in which issued certificates and the ACME account are cached.
+    #[clap(long, default_value = "acme-cache")]
+    pub acme_cache: PathBuf,
+
+    /// Use the Let's Encrypt staging environment, e.g. while testing a setup.
+    #[clap(long, requires = "acme_domain")]
+    pub acme_staging: bool,
+
+    /// Additionally listen for HTTP/3 over QUIC on the same (UDP) port. Experimental.
+    #[cfg(feature = "http3")]
+    #[clap(long, requires = "tls_cert")]
+    pub http3: bool,
+
+    /// Report panics and server errors to this Sentry DSN.
+    #[cfg(feature = "sentry")]
+    #[clap(long, env = "SENTRY_DSN")]
+    pub sentry_dsn: Option<String>,
+
+    /// The OpenID Connect issuer to authenticate users against.
+    /// When set, every page requires a login.
+    #[clap(long, requires_all = ["oidc_client_id", "oidc_redirect_url"])]
+    pub oidc_issuer: Option<String>,
+
+    /// The client id registered with the OpenID Connect provider.
+    #[clap(long)]
+    pub oidc_client_id: Option<String>,
+
+    /// The client secret registered with the OpenID Connect provider.
+    #[clap(long, env = "OIDC_CLIENT_SECRET")]
+    pub oidc_client_secret: Option<String>,
+
+    /// The externally reachable url of the `/auth/callback` route.
+    #[clap(long)]
+    pub oidc_redirect_url: Option<String>,
+
+    /// A TOML file with the roles of the logged in users: `admin`, `uploader` or `viewer`. Without it, every logged
+    /// in user is an admin. It is read again when the configuration is reloaded.
+    #[clap(long)]
+    pub users: Option<PathBuf>,
+
+    /// Run a command instead of serving the library.
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Commands run against the library instead of serving it.
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Convert videos to another container and/or codec.
+    #[cfg(feature = "transcoding")]
+    Convert(convert::ConvertArgs),
+    /// Load a running server and report its throughput and latency.
+    Bench(bench::BenchArgs),
+    /// Report the videos that are identical and the space they waste, optionally writing a script to remove them.
+    Dedupe(dedupe::DedupeArgs),
+    /// Re-hash the videos and report those that got corrupted since they were last hashed.
+    Verify(integrity::VerifyArgs),
+    /// Install or uninstall the Windows service.
+    #[cfg(windows)]
+    #[clap(subcommand)]
+    Service(service::ServiceCommand),
+}
+
+impl VideoPlayerConfig {
+    /// Whether the server is reached over HTTPS: it serves it itself, or `--public-url` is an `https://` one.
+    /// Cookies are only sent over HTTPS then.
+    pub fn is_https(&self) -> bool {
+        self.tls_cert.is_some()
+            || !self.acme_domain.is_empty()
+            || self.public_url.as_deref().is_some_and(|url| url.starts_with("https://"))
+    }
+
+    /// The configured base path without a trailing slash,
+    /// so it can be prefixed to any absolute route. Empty when mounted at the root.
+    pub fn base_path(&self) -> String {
+        normalize_base_path(&self.base_path)
+    }
+
+    /// Check that the server can start with this configuration,
+    /// i.e. that every assets root is a readable directory and the address is valid.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        validate_root(std::path::Path::new(&self.assets_root))?;
+        for (_, root) in &self.vhost {
+            validate_root(root)?;
+        }
+        validate_trash_dir(&self.trash_dir)?;
+        self.socket_addr().map(|_| ())
+    }
+
+    /// The address to listen on, resolving the host if it is a name rather than an address.
+    pub fn socket_addr(&self) -> Result<SocketAddr, ConfigError> {
+        if self.port == 0 {
+            return Err(ConfigError::InvalidPort);
+        }
+        (self.host.as_str(), self.port)
+            .to_socket_addrs()
+            .map_err(|err| ConfigError::InvalidHost(self.host.clone(), err.to_string()))?
+            .next()
+            .ok_or_else(|| ConfigError::InvalidHost(self.host.clone(), "it has no addresses".into()))
+    }
+}
+
+fn validate_root(root: &std::path::Path) -> Result<(), ConfigError> {
+    match std::fs::metadata(root) {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Err(ConfigError::MissingRoot(root.to_path_buf())),
+        Err(err) => Err(ConfigError::UnreadableRoot(root.to_path_buf(), err)),
+        Ok(metadata) if !metadata.is_dir() => Err(ConfigError::RootNotADirectory(root.to_path_buf())),
+        Ok(_) => std::fs::read_dir(root)
+            .map(|_| ())
+            .map_err(|err| ConfigError::UnreadableRoot(root.to_path_buf(), err)),
+    }
+}
+
+/// The trash has to be a folder strictly inside the root, or emptying it would delete the library.
+fn validate_trash_dir(trash_dir: &std::path::Path) -> Result<(), ConfigError> {
+    let mut components = trash_dir.components().filter(|component| *component != Component::CurDir).peekable();
+    let valid = components.peek().is_some() && components.all(|component| matches!(component, Component::Normal(_)));
+    if valid {
+        Ok(())
+    } else {
+        Err(ConfigError::InvalidTrashDir(trash_dir.to_path_buf()))
+    }
+}
+
+/// A problem with the configuration that keeps the server from starting.
+#[derive(Debug)]
+pub enum ConfigError {
+    MissingRoot(PathBuf),
+    RootNotADirectory(PathBuf),
+    UnreadableRoot(PathBuf, std::io::Error),
+    InvalidPort,
+    InvalidHost(String, String),
+    InvalidTrashDir(PathBuf),
+}
+
+impl ConfigError {
+    /// The exit code to stop with, following the conventions of `sysexits.h`.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::MissingRoot(_) | Self::RootNotADirectory(_) => 66,
+            Self::UnreadableRoot(..) => 77,
+            Self::InvalidPort | Self::InvalidHost(..) | Self::InvalidTrashDir(_) => 64,
+        }
+    }
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingRoot(root) => {
+                write!(f, "The assets root {} does not exist. Pass the folder your videos are in with --assets-root", root.display())?;
+                if root.starts_with("~") {
+                    write!(f, " (a quoted ~ is not expanded to your home directory)")?;
+                }
+                Ok(())
+            }
+            Self::RootNotADirectory(root) => write!(
+                f,
+                "The assets root {} is a file. Pass the folder your videos are in with --assets-root",
+                root.display()
+            ),
+            Self::UnreadableRoot(root, err) => write!(
+                f,
+                "The assets root {} can't be read ({}). Check that the server's user has permission to list it",
+                root.display(),
+                err
+            ),
+            Self::InvalidPort => write!(f, "Port 0 can't be listened on. Pass a port between 1 and 65535 with --port"),
+            Self::InvalidHost(host, err) => write!(
+                f,
+                "Can't listen on {} ({}). Pass an address like 0.0.0.0, :: or 127.0.0.1 with --host",
+                host, err
+            ),
+            Self::InvalidTrashDir(trash_dir) => write!(
+                f,
+                "The trash folder {:?} is not inside the assets root. Pass a folder relative to it with --trash-dir",
+                trash_dir
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Normalize a base path to either `""` or `"/some/prefix"`, percent-encoded
+/// so it can be used in links and redirects as is.
+pub fn normalize_base_path(base_path: &str) -> String {
+    urls::encode_href("", &urls::decode_path(base_path), false)
+}
+
+/// Check if a path has one of the given (lowercase) video extensions, ignoring case,
+/// so `MOVIE.MP4` counts as well.
+pub fn has_video_extension(extensions: &HashSet<String>, path: &std::path::Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extensions.contains(&extension.to_ascii_lowercase()))
+}
+
+/// A video in the index.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct VideoEntry {
+    /// The id the video is served under, e.g. `/video/:id`.
+    pub id: String,
+    /// The path of the video on disk.
+    #[serde(skip_serializing)]
+    pub path: PathBuf,
+    /// The path relative to the root, with `/` separators. The id is made from it, and it keys the
+    /// [`store::IndexStore`].
+    #[serde(skip_serializing)]
+    pub key: String,
+    /// The file name of the video.
+    pub name: String,
+    /// The title the video is displayed with: a custom one, or else its file name.
+    pub title: String,
+    /// The size of the file in bytes.
+    pub size: u64,
+    /// The lowercased file extension, e.g. `mkv`.
+    pub extension: String,
+    /// Codec, resolution and duration, if the video was probed.
+    pub metadata: Option<metadata::VideoMetadata>,
+    /// Whether this is a video, an audio file (indexed with `--audio`) or an image (with `--images`).
+    pub kind: MediaKind,
+    /// Whether the video is left out of listings, by the `.svs.toml` of its folder or through the API.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub hidden: bool,
+    /// Whether the video is left out of listings and only served with one of its access tokens.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub private: bool,
+}
+
+impl VideoEntry {
+    /// The duration in seconds, if the video was probed.
+    pub fn duration(&self) -> Option<f64> {
+        self.metadata.as_ref().and_then(|metadata| metadata.duration)
+    }
+}
+
+/// The file name of a key without its extension, lowercased with runs of anything but ASCII letters and digits made
+/// dashes, e.g. `holiday-2022` for `Travel/Holiday 2022.mp4`. `video` if nothing is left.
+pub fn video_slug(key: &str) -> String {
+    let name = key.rsplit('/').next().unwrap_or(key);
+    let stem = name.rsplit_once('.').map_or(name, |(stem, _)| stem);
+    let mut slug = String::new();
+    for c in stem.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug: String = slug.trim_end_matches('-').chars().take(48).collect();
+    match slug.trim_end_matches('-') {
+        "" => "video".to_string(),
+        slug => slug.to_string(),
+    }
 }
 
 /// The video index state that is shared between all requests.
 /// Store a list of videos and their paths.
+///
+/// The list is an immutable snapshot that handlers can hold on to (and iterate)
+/// without copying it or keeping the state locked.
 #[derive(Default)]
 pub struct VideoPlayerState {
-    pub videos: HashMap<String, String>,
+    videos: Arc<Vec<VideoEntry>>,
+    positions: HashMap<String, usize>,
     video_extensions: HashSet<String>,
-    next_index: AtomicUsize,
     root: Option<String>,
+    pub base_path: String,
+    pub stream_buffer_size: usize,
+    pub stream_limits: media::StreamLimits,
+    generation: u64,
+    ffprobe: Option<String>,
+    store: Option<store::SharedIndexStore>,
+    trash_dir: Option<PathBuf>,
+    storage: storage::StorageUsage,
+    library_stats: library_stats::LibraryStats,
+    indexed_dirs: HashSet<PathBuf>,
+    lazy: bool,
+    events: Option<events::EventBus>,
+    scan_progress: scan::SharedScanProgress,
+    quarantine: BTreeMap<PathBuf, scan::QuarantinedFile>,
+    /// How long files must not have been modified for to be indexed.
+    settle: std::time::Duration,
+    /// The files left out of the index while they are still being written.
+    unsettled: BTreeSet<PathBuf>,
 }
 
 pub type SharedState = Arc<Mutex<VideoPlayerState>>;
@@ -60,76 +827,652 @@ impl VideoPlayerState {
     pub fn new() -> Self {
         Self {
             video_extensions: HashSet::from_iter(
-                VIDEO_EXTENSIONS.iter().map(|s| s.to_string()),
+                VIDEO_EXTENSIONS.iter().map(|s| s.to_ascii_lowercase()),
             ),
+            stream_buffer_size: media::DEFAULT_STREAM_BUFFER_SIZE,
             ..Default::default()
         }
     }
 
 
-    fn advance_index(&mut self) {
-        self.next_index
-            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-    }
-
     /// Check if a path is a supported video file.
     pub fn is_video_file<P: AsRef<std::path::Path>>(&self, path: P) -> bool {
-        if let Some(extension) = path.as_ref().extension() {
-            if self.video_extensions.contains(extension.to_str().unwrap()) {
-                return true;
+        has_video_extension(&self.video_extensions, path.as_ref())
+    }
+
+    /// The extensions that are interpreted as videos.
+    pub fn video_extensions(&self) -> &HashSet<String> {
+        &self.video_extensions
+    }
+
+    /// The kinds of media that are indexed, videos first.
+    pub fn kinds(&self) -> Vec<MediaKind> {
+        [MediaKind::Video, MediaKind::Audio, MediaKind::Image]
+            .into_iter()
+            .filter(|kind| {
+                *kind == MediaKind::Video
+                    || self.video_extensions.iter().any(|extension| MediaKind::of_extension(extension) == *kind)
+            })
+            .collect()
+    }
+
+    pub fn load_videos<P: AsRef<std::path::Path>>(&mut self, root: P) -> error::Result<()> {
+        self.visit_dirs(root)
+    }
+
+    /// Load a video from a path, hidden from listings or not, unless it was hidden or shown through the API.
+    pub fn load_video(&mut self, path: PathBuf, hidden: bool) -> error::Result<()> {
+        let extension = path
+            .extension()
+            .ok_or_else(|| error::Error::MissingExtension(path.clone()))?
+            .to_string_lossy()
+            .to_lowercase();
+        let scan_error = |source| error::Error::Scan {
+            path: path.clone(),
+            source,
+        };
+        let size = std::fs::metadata(&path).map_err(scan_error)?.len();
+        // Otherwise files that can't be read, e.g. for lack of permission, would only fail once played.
+        std::fs::File::open(&path).map_err(scan_error)?;
+        let metadata = match self.ffprobe.as_deref() {
+            Some(ffprobe) => metadata::probe(ffprobe, &path).map_err(|reason| error::Error::Broken {
+                path: path.clone(),
+                reason,
+            })?,
+            None => None,
+        };
+        let key = self.relative_key(&path);
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let title = self
+            .store
+            .as_ref()
+            .and_then(|store| store.title(&key))
+            .unwrap_or_else(|| name.clone());
+        let hidden = self.store.as_ref().and_then(|store| store.visibility(&key)).unwrap_or(hidden);
+        let private = self.store.as_ref().is_some_and(|store| store.is_private(&key));
+        let kind = MediaKind::of_extension(&extension);
+        self.quarantine.remove(&path);
+        self.push_video(VideoEntry {
+            id: String::new(),
+            key,
+            name,
+            title,
+            size,
+            extension,
+            metadata,
+            kind,
+            hidden,
+            private,
+            path,
+        });
+        Ok(())
+    }
+
+    /// The id of a video with a key: its file name as a slug, followed by the start of a hash of the key, so that files
+    /// with the same name in different folders get different ids, and a file gets the same id whatever else is found
+    /// and in whichever order. The hash only gets longer in the unlikely case that it's not enough to tell them apart.
+    fn id_for(&self, key: &str, extension: &str) -> String {
+        let hash = format!("{:x}", Sha256::digest(key.as_bytes()));
+        let slug = video_slug(key);
+        [8, 16, hash.len()]
+            .into_iter()
+            .map(|length| format!("{}-{}.{}", slug, &hash[..length], extension))
+            .find(|id| self.get(id).is_none_or(|other| other.key == key))
+            .unwrap_or_else(|| format!("{}-{}.{}", slug, hash, extension))
+    }
+
+    /// Add a video to the index under the id of its key.
+    fn push_video(&mut self, mut video: VideoEntry) {
+        video.id = self.id_for(&video.key, &video.extension);
+        info!("Loading video: {} as {}", video.path.display(), video.id);
+        self.storage.add(&video.key, video.size);
+        self.library_stats.add(&video);
+        let videos = Arc::make_mut(&mut self.videos);
+        self.positions.insert(video.id.clone(), videos.len());
+        videos.push(video);
+    }
+
+    /// Put the videos from a position on back in the order of their keys, after they were added or changed, given
+    /// that the ones before it still are in order. Only the positions of the videos that moved are updated.
+    fn sort_from(&mut self, start: usize) {
+        if start >= self.videos.len() {
+            return;
+        }
+        let videos = Arc::make_mut(&mut self.videos);
+        videos[start..].sort_by(|a, b| a.key.cmp(&b.key));
+        let first = videos[..start].partition_point(|video| video.key < videos[start].key);
+        videos[first..].sort_by(|a, b| a.key.cmp(&b.key));
+        for (position, video) in videos.iter().enumerate().skip(first) {
+            if let Some(known) = self.positions.get_mut(&video.id) {
+                *known = position;
             }
         }
-        false
     }
 
-    pub fn load_videos<P: AsRef<std::path::Path>>(&mut self, root: P) -> std::io::Result<()> {
-        self.visit_dirs(root)
+    /// The path of a file relative to the root, with `/` separators.
+    pub fn relative_key(&self, path: &std::path::Path) -> String {
+        let relative = self
+            .root
+            .as_deref()
+            .and_then(|root| path.strip_prefix(root).ok())
+            .unwrap_or(path);
+        relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    /// A snapshot of all indexed videos, in the order of their keys, i.e. of their paths relative to the root.
+    pub fn videos(&self) -> Arc<Vec<VideoEntry>> {
+        self.videos.clone()
     }
 
-    /// Load a video from a path.
-    pub fn load_video(&mut self, path: PathBuf) {
-        let stored_file_name = path.to_str().unwrap().to_string();
-        let extension = path.extension().unwrap();
-        let server_path = format!(
-            "{}.{}",
-            self.next_index.load(Ordering::SeqCst),
-            extension.to_str().unwrap()
-        );
-        info!("Loading video: {} as {}", stored_file_name, server_path);
-        self.advance_index();
-        self.videos.insert(server_path, stored_file_name);
+    /// Look up a video by its id.
+    pub fn get(&self, id: &str) -> Option<&VideoEntry> {
+        self.positions.get(id).map(|&position| &self.videos[position])
+    }
+
+    /// Change a video in place, e.g. after its file was renamed.
+    /// Returns the changed video, or `None` if there is no video with the id.
+    pub fn update_video(&mut self, id: &str, change: impl FnOnce(&mut VideoEntry)) -> Option<&VideoEntry> {
+        let position = *self.positions.get(id)?;
+        let video = &mut Arc::make_mut(&mut self.videos)[position];
+        let key = video.key.clone();
+        self.storage.remove(&video.key, video.size);
+        self.library_stats.remove(video);
+        change(video);
+        self.storage.add(&video.key, video.size);
+        self.library_stats.add(video);
+        let mut id = video.id.clone();
+        if video.key != key {
+            // Moved or renamed, so it goes by another id, and belongs elsewhere in the order.
+            let (new_key, extension) = (video.key.clone(), video.extension.clone());
+            self.positions.remove(&id);
+            id = self.id_for(&new_key, &extension);
+            Arc::make_mut(&mut self.videos)[position].id = id.clone();
+            self.positions.insert(id.clone(), position);
+            self.sort_from(0);
+        }
+        self.generation += 1;
+        self.get(&id)
     }
 
     /// Recursively visit all directories and load videos from them.
-    pub fn visit_dirs<P: AsRef<std::path::Path>>(&mut self, root: P) -> std::io::Result<()> {
-        if root.as_ref().is_dir() {
-            if let Ok(dir) = std::fs::read_dir(root.as_ref()) {
-                for entry in dir {
-                    let entry = entry?;
-                    let path = entry.path();
-                    if path.is_dir() {
-                        self.visit_dirs(path)?;
-                    } else if self.is_video_file(path.as_path()) {
-                        self.load_video(path);
-                    }
-                }
+    /// Fails if the given directory can't be read; subdirectories that can't be read are skipped.
+    pub fn visit_dirs<P: AsRef<std::path::Path>>(&mut self, root: P) -> error::Result<()> {
+        for subdir in self.index_dir(root.as_ref())? {
+            if let Err(err) = self.visit_dirs(subdir) {
+                warn!("{}", err);
             }
         }
         Ok(())
     }
 
-    /// Build a new video index state from a config.
-    pub fn build(config: &VideoPlayerConfig) -> Self {
+    /// Load the videos directly inside a directory, unless that already happened.
+    /// Returns its subdirectories (except the trash and those ignored by a `.videoignore`), sorted by name.
+    pub fn index_dir(&mut self, dir: &std::path::Path) -> error::Result<Vec<PathBuf>> {
+        let scan_error = |source| error::Error::Scan {
+            path: dir.to_path_buf(),
+            source,
+        };
+        let first_visit = !self.indexed_dirs.contains(dir);
+        let mut loaded = 0;
+        let root = self.root.as_deref().map(std::path::Path::new).unwrap_or(dir);
+        let ignore = videoignore::VideoIgnore::load(root, dir);
+        let config = dir_config::DirConfig::load(root, dir);
+        let mut subdirs = vec![];
+        let mut videos = vec![];
+        let mut downloading = HashSet::new();
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                self.quarantine(dir.to_path_buf(), err.to_string());
+                return Err(scan_error(err));
+            }
+        };
+        for entry in entries {
+            let path = match entry {
+                Ok(entry) => entry.path(),
+                Err(err) => {
+                    self.quarantine(dir.to_path_buf(), err.to_string());
+                    continue;
+                }
+            };
+            if path.is_dir() {
+                if !self.is_trash(&path) && !ignore.is_ignored(&path, true) {
+                    subdirs.push(path);
+                }
+            } else if first_visit && !ignore.is_ignored(&path, false) {
+                if let Some(target) = scan::download_target(&path) {
+                    downloading.insert(target);
+                    self.unsettled.insert(path);
+                    continue;
+                }
+                let indexed = match &config.extensions {
+                    Some(extensions) => has_video_extension(extensions, &path),
+                    None => self.is_video_file(&path),
+                };
+                if indexed {
+                    videos.push(path);
+                }
+            }
+        }
+        if first_visit {
+            // Posters are shown with their video, not as images of their own.
+            let posters: HashSet<PathBuf> = videos
+                .iter()
+                .filter(|path| MediaKind::of_path(path) != MediaKind::Image)
+                .flat_map(|path| cache::poster_candidates(path))
+                .collect();
+            videos.retain(|path| !posters.contains(path));
+            // Files still being written would show up broken. They are picked up by a rescan once they settle.
+            let settle = self.settle;
+            let (videos, unsettled): (Vec<PathBuf>, Vec<PathBuf>) = videos
+                .into_iter()
+                .filter(|path| !downloading.contains(path))
+                .partition(|path| scan::is_settled(path, settle));
+            self.unsettled.extend(unsettled);
+            self.indexed_dirs.insert(dir.to_path_buf());
+            let start = self.videos.len();
+            for video in videos {
+                let hidden = config.hides(&video.file_name().unwrap_or_default().to_string_lossy());
+                match self.load_video(video.clone(), hidden) {
+                    Ok(()) => loaded += 1,
+                    Err(error::Error::Scan { source, .. }) => self.quarantine(video, source.to_string()),
+                    Err(error::Error::Broken { reason, .. }) => self.quarantine(video, reason),
+                    Err(err) => warn!("Skipping a video: {}", err),
+                }
+            }
+            self.sort_from(start);
+            self.generation += 1;
+        }
+        self.scan_progress.dir_processed(loaded);
+        subdirs.sort();
+        Ok(subdirs)
+    }
+
+    /// Whether videos are indexed as directories are browsed rather than all upfront.
+    pub fn is_lazy(&self) -> bool {
+        self.lazy
+    }
+
+    /// Index the root, and unless lazy everything under it.
+    /// In lazy mode the rest is left to browsing and [`fill_index`].
+    fn index_root(&mut self) -> error::Result<()> {
+        let root = PathBuf::from(self.root.clone().ok_or(error::Error::NoRoot)?);
+        let span = tracing::info_span!("scan", root = %root.display(), lazy = self.lazy, videos = tracing::field::Empty);
+        let _entered = span.enter();
+        let _scan = self.scan_progress.start(None);
+        let started = std::time::Instant::now();
+        let indexed = if self.lazy {
+            self.index_dir(&root).map(|_| ())
+        } else {
+            self.load_videos(root)
+        };
+        span.record("videos", self.videos.len());
+        statsd::timing("scan.duration", started.elapsed(), &[]);
+        statsd::gauge("videos", self.videos.len() as u64, &[]);
+        indexed
+    }
+
+    /// Build a new video index state from a config,
+    /// taking custom titles of the videos from the store.
+    pub fn build(config: &VideoPlayerConfig, store: store::SharedIndexStore) -> error::Result<Self> {
         let mut state = Self::new();
+        state.store = Some(store);
         state.root = Some(config.assets_root.clone());
-        state.load_videos(state.root.clone().unwrap()).unwrap();
+        state.trash_dir = Some(PathBuf::from(&config.assets_root).join(&config.trash_dir));
+        state.base_path = config.base_path();
+        state.stream_buffer_size = config.stream_buffer_size;
+        state.stream_limits = media::StreamLimits::from_config(config);
+        if config.audio {
+            state.video_extensions.extend(AUDIO_EXTENSIONS.iter().cloned());
+        }
+        if config.images {
+            state.video_extensions.extend(IMAGE_EXTENSIONS.iter().cloned());
+        }
+        if config.probe {
+            state.ffprobe = Some(config.ffprobe.clone());
+        }
+        state.lazy = config.lazy_index;
+        state.settle = std::time::Duration::from_secs(config.settle_time);
+        state.events = Some(events::EventBus::new(&config.assets_root));
+        state.index_root()?;
+        Ok(state)
+    }
+
+    /// The folder deleted videos are moved to.
+    pub fn trash_dir(&self) -> Option<&std::path::Path> {
+        self.trash_dir.as_deref()
+    }
+
+    /// Whether a path is the trash folder or inside it.
+    pub fn is_trash(&self, path: &std::path::Path) -> bool {
+        self.trash_dir
+            .as_deref()
+            .is_some_and(|trash_dir| path.starts_with(trash_dir))
+    }
+
+    /// Whether the `.svs.toml` files of its folder hide a video.
+    pub fn hidden_by_config(&self, path: &std::path::Path) -> bool {
+        match (self.root.as_deref(), path.parent(), path.file_name()) {
+            (Some(root), Some(dir), Some(name)) => {
+                dir_config::DirConfig::load(std::path::Path::new(root), dir).hides(&name.to_string_lossy())
+            }
+            _ => false,
+        }
+    }
+
+    /// Add a single video to the index, e.g. after it was restored from the trash.
+    pub fn add_video(&mut self, path: PathBuf) -> error::Result<&VideoEntry> {
+        let hidden = self.hidden_by_config(&path);
+        let start = self.videos.len();
+        self.load_video(path, hidden)?;
+        let id = self.videos[start].id.clone();
+        self.sort_from(start);
+        self.generation += 1;
+        let video = self.get(&id).expect("added videos are indexed");
+        self.emit(events::LibraryEvent::VideoAdded { video: video.into() });
+        Ok(video)
+    }
+
+    /// Drop a video from the index, e.g. after it was moved to the trash.
+    pub fn remove_video(&mut self, id: &str) -> Option<VideoEntry> {
+        let position = self.positions.remove(id)?;
+        let video = Arc::make_mut(&mut self.videos).remove(position);
+        self.storage.remove(&video.key, video.size);
+        self.library_stats.remove(&video);
+        for later in self.positions.values_mut().filter(|later| **later > position) {
+            *later -= 1;
+        }
+        self.generation += 1;
+        self.emit(events::LibraryEvent::VideoRemoved { video: (&video).into() });
+        Some(video)
+    }
+
+    /// The events of this library.
+    pub fn events(&self) -> Option<&events::EventBus> {
+        self.events.as_ref()
+    }
+
+    fn emit(&self, event: events::LibraryEvent) {
+        if let Some(events) = &self.events {
+            events.emit(event);
+        }
+    }
+
+    /// The space taken up by the indexed videos.
+    pub fn storage(&self) -> &storage::StorageUsage {
+        &self.storage
+    }
+
+    /// How far the running scan got, or how the last one went.
+    pub fn scan_progress(&self) -> &scan::SharedScanProgress {
+        &self.scan_progress
+    }
+
+    /// Keep a file the scan couldn't index out of the index, and list it in the quarantine instead.
+    fn quarantine(&mut self, path: PathBuf, reason: String) {
+        warn!("Quarantining {}: {}", path.display(), reason);
+        let file = scan::QuarantinedFile {
+            path: self.relative_key(&path),
+            reason,
+            found: store::now(),
+        };
+        self.quarantine.insert(path, file);
+    }
+
+    /// The files the scans couldn't index, by path.
+    pub fn quarantined(&self) -> Vec<scan::QuarantinedFile> {
+        self.quarantine.values().cloned().collect()
+    }
+
+    /// The files left out of the index while they are still being written, or downloaded.
+    pub fn unsettled(&self) -> Vec<PathBuf> {
+        self.unsettled.iter().cloned().collect()
+    }
+
+    /// How many videos there are, how long and how large, by extension, resolution and folder.
+    pub fn library_stats(&self) -> &library_stats::LibraryStats {
+        &self.library_stats
+    }
+
+    /// The root directory the videos are indexed from.
+    pub fn root(&self) -> Option<&str> {
+        self.root.as_deref()
+    }
+
+    /// A counter that changes whenever the index does,
+    /// so anything derived from the index can tell when it's stale.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Reload the video index state. On failure the previous index is kept.
+    pub fn reload(&mut self) -> error::Result<()> {
+        let mut fresh = self.empty_like();
+        fresh.index_root()?;
+        self.replace_index(fresh);
+        Ok(())
+    }
+
+    /// An empty index with the same settings, to scan into without holding the lock on this one.
+    pub fn empty_like(&self) -> Self {
+        Self {
+            video_extensions: self.video_extensions.clone(),
+            root: self.root.clone(),
+            base_path: self.base_path.clone(),
+            stream_buffer_size: self.stream_buffer_size,
+            stream_limits: self.stream_limits,
+            ffprobe: self.ffprobe.clone(),
+            store: self.store.clone(),
+            trash_dir: self.trash_dir.clone(),
+            lazy: self.lazy,
+            events: self.events.clone(),
+            scan_progress: self.scan_progress.clone(),
+            settle: self.settle,
+            ..Default::default()
+        }
+    }
+
+    /// Index the root of an index made with [`Self::empty_like`] (and, unless lazy, everything under it).
+    pub fn scan(&mut self) -> error::Result<()> {
+        self.index_root()
+    }
+
+    /// Take over the videos of a freshly scanned index,
+    /// announcing which videos were added and removed since the last scan.
+    pub fn replace_index(&mut self, fresh: Self) {
+        if self.events.is_some() {
+            let old: HashMap<&str, &VideoEntry> =
+                self.videos.iter().map(|video| (video.key.as_str(), video)).collect();
+            let new: HashMap<&str, &VideoEntry> =
+                fresh.videos.iter().map(|video| (video.key.as_str(), video)).collect();
+            for (key, video) in &new {
+                if !old.contains_key(key) {
+                    self.emit(events::LibraryEvent::VideoAdded { video: (*video).into() });
+                }
+            }
+            for (key, video) in &old {
+                if !new.contains_key(key) {
+                    self.emit(events::LibraryEvent::VideoRemoved { video: (*video).into() });
+                }
+            }
+            self.emit(events::LibraryEvent::ScanCompleted { videos: new.len() });
+        }
+        self.videos = fresh.videos;
+        self.positions = fresh.positions;
+        self.storage = fresh.storage;
+        self.library_stats = fresh.library_stats;
+        self.indexed_dirs = fresh.indexed_dirs;
+        self.quarantine = fresh.quarantine;
+        self.unsettled = fresh.unsettled;
+        self.generation += 1;
+    }
+
+    /// Take over a fresh scan of a folder (into an index made with [`Self::empty_like`]) in place of what was indexed
+    /// under it, leaving the rest of the index as is. Videos that are still there keep their ids.
+    pub fn merge_dir(&mut self, dir: &std::path::Path, fresh: Self) -> RescanSummary {
+        let mut summary = RescanSummary::default();
+        let mut found: HashMap<String, VideoEntry> = Arc::try_unwrap(fresh.videos)
+            .unwrap_or_else(|videos| (*videos).clone())
+            .into_iter()
+            .map(|video| (video.key.clone(), video))
+            .collect();
+        let gone: Vec<String> = self
+            .videos
+            .iter()
+            .filter(|video| video.path.starts_with(dir) && !found.contains_key(&video.key))
+            .map(|video| video.id.clone())
+            .collect();
+        for id in gone {
+            self.remove_video(&id);
+            summary.removed += 1;
+        }
+        let kept: Vec<(String, String)> = self
+            .videos
+            .iter()
+            .filter(|video| found.contains_key(&video.key))
+            .map(|video| (video.id.clone(), video.key.clone()))
+            .collect();
+        for (id, key) in kept {
+            let Some(current) = found.remove(&key) else {
+                continue;
+            };
+            let video = self.get(&id).expect("kept videos are indexed");
+            let changed = video.size != current.size
+                || video.metadata != current.metadata
+                || video.hidden != current.hidden
+                || video.private != current.private;
+            if changed {
+                self.update_video(&id, |video| {
+                    video.size = current.size;
+                    video.metadata = current.metadata;
+                    video.hidden = current.hidden;
+                    video.private = current.private;
+                });
+                summary.updated += 1;
+            }
+        }
+        let mut added: Vec<VideoEntry> = found.into_values().collect();
+        added.sort_by(|a, b| a.path.cmp(&b.path));
+        let start = self.videos.len();
+        for video in added {
+            self.push_video(video);
+            let video = &self.videos[self.videos.len() - 1];
+            self.emit(events::LibraryEvent::VideoAdded { video: video.into() });
+            summary.added += 1;
+        }
+        self.sort_from(start);
+        self.indexed_dirs.retain(|indexed| !indexed.starts_with(dir));
+        self.indexed_dirs.extend(fresh.indexed_dirs);
+        self.quarantine.retain(|path, _| !path.starts_with(dir));
+        self.quarantine.extend(fresh.quarantine);
+        self.unsettled.retain(|path| !path.starts_with(dir));
+        self.unsettled.extend(fresh.unsettled);
+        self.generation += 1;
+        self.emit(events::LibraryEvent::ScanCompleted { videos: self.videos.len() });
+        summary
+    }
+}
+
+/// What changed in a folder that was rescanned.
+#[derive(Debug, Clone, Copy, Default, serde::Serialize)]
+pub struct RescanSummary {
+    pub added: usize,
+    pub removed: usize,
+    /// Videos whose file changed, e.g. in size.
+    pub updated: usize,
+}
+
+/// Index every directory that hasn't been browsed yet, breadth first.
+/// The state is only locked for one directory at a time, so requests keep being served meanwhile.
+pub fn fill_index(state: &SharedState) {
+    let Some(root) = state.lock().unwrap().root().map(PathBuf::from) else {
+        return;
+    };
+    let scan_progress = state.lock().unwrap().scan_progress().clone();
+    let _scan = scan_progress.start(None);
+    let mut pending = VecDeque::from([root]);
+    while let Some(dir) = pending.pop_front() {
+        let subdirs = state.lock().unwrap().index_dir(&dir);
+        match subdirs {
+            Ok(subdirs) => pending.extend(subdirs),
+            Err(err) => warn!("Failed to index {}: {}", dir.display(), err),
+        }
+    }
+    info!("Finished indexing in the background.");
+    let state = state.lock().unwrap();
+    state.emit(events::LibraryEvent::ScanCompleted {
+        videos: state.videos.len(),
+    });
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A library of files with some content, in a folder of its own under the temporary directory.
+    fn library(name: &str, files: &[&str]) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("svs-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        for file in files {
+            let path = root.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, b"not really a video").unwrap();
+        }
+        root
+    }
+
+    fn indexed(root: &std::path::Path) -> VideoPlayerState {
+        let mut state = VideoPlayerState::new();
+        state.root = Some(root.to_string_lossy().to_string());
+        state.load_videos(root).unwrap();
         state
     }
 
-    /// Reload the video index state.
-    pub fn reload(&mut self) {
-        self.next_index = AtomicUsize::new(0);
-        self.videos.clear();
-        self.load_videos(self.root.clone().unwrap()).unwrap();
+    fn ids(state: &VideoPlayerState) -> HashMap<String, String> {
+        state.videos().iter().map(|video| (video.key.clone(), video.id.clone())).collect()
+    }
+
+    #[test]
+    fn slugs_file_names() {
+        assert_eq!(video_slug("Travel/Holiday 2022.mp4"), "holiday-2022");
+        assert_eq!(video_slug("--Ep. 1 (final)!.mkv"), "ep-1-final");
+        assert_eq!(video_slug("東京.mp4"), "video");
+        assert_eq!(video_slug(&format!("{}.mp4", "a".repeat(100))).len(), 48);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn tells_same_named_files_apart() {
+        let root = library("same-names", &["a/episode 1.mp4", "b/episode 1.mp4", "episode 1.mp4"]);
+        let state = indexed(&root);
+        let ids = ids(&state);
+        assert_eq!(ids.len(), 3);
+        assert_eq!(ids.values().collect::<HashSet<_>>().len(), 3);
+        for (key, id) in &ids {
+            assert!(id.starts_with("episode-1-") && id.ends_with(".mp4"), "{}", id);
+            assert_eq!(&state.get(id).unwrap().key, key);
+        }
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn keeps_ids_across_reloads() {
+        let root = library("reloads", &["a/clip.mp4", "b/clip.mp4"]);
+        let before = ids(&indexed(&root));
+        // Whatever else turns up, and wherever it lands in the scan, the ids of the others stay the same.
+        std::fs::create_dir_all(root.join("0")).unwrap();
+        std::fs::write(root.join("0/clip.mp4"), b"not really a video").unwrap();
+        let after = ids(&indexed(&root));
+        assert_eq!(after.len(), 3);
+        for (key, id) in &before {
+            assert_eq!(after.get(key), Some(id));
+        }
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+}
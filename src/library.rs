@@ -0,0 +1,396 @@
+//! Changes to the files of the library, keeping the index and the store in sync with the disk.
+
+use axum::http::StatusCode;
+use serde::Serialize;
+use std::path::{Component, Path, PathBuf};
+use tracing::{info, warn};
+
+use crate::{cache, events::LibraryEvent, store::IndexStore, VideoEntry, VideoPlayerState};
+
+pub type LibraryResult<T> = Result<T, (StatusCode, String)>;
+
+/// Check that a new file name is a plain name, without any path in it.
+pub(crate) fn validate_file_name(name: &str) -> LibraryResult<()> {
+    let is_plain = !name.is_empty()
+        && name != "."
+        && name != ".."
+        && !name.contains(['/', '\\', '\0'])
+        && name.trim() == name;
+    if is_plain {
+        Ok(())
+    } else {
+        Err((StatusCode::UNPROCESSABLE_ENTITY, format!("{:?} is not a valid file name", name)))
+    }
+}
+
+/// The sidecar files (posters, subtitles) of a video that exist, paired with their path for a new video path.
+fn sidecars(from: &Path, to: &Path) -> Vec<(PathBuf, PathBuf)> {
+    let old = cache::poster_candidates(from)
+        .into_iter()
+        .chain(cache::subtitle_candidates(from));
+    let new = cache::poster_candidates(to)
+        .into_iter()
+        .chain(cache::subtitle_candidates(to));
+    old.zip(new).filter(|(old, _)| old.is_file()).collect()
+}
+
+/// Move a video file and its sidecars, then update the index entry and carry the store data over.
+/// `new_path` must not exist yet.
+fn relocate(
+    state: &mut VideoPlayerState,
+    store: &IndexStore,
+    id: &str,
+    new_path: PathBuf,
+) -> LibraryResult<VideoEntry> {
+    let video = state
+        .get(id)
+        .cloned()
+        .ok_or((StatusCode::NOT_FOUND, format!("No video with id {}", id)))?;
+    if new_path.exists() {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("{} already exists", state.relative_key(&new_path)),
+        ));
+    }
+
+    let sidecars = sidecars(&video.path, &new_path);
+    std::fs::rename(&video.path, &new_path).map_err(|err| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to move {}: {}", video.key, err),
+        )
+    })?;
+    for (from, to) in sidecars {
+        if to.exists() {
+            continue;
+        }
+        if let Err(err) = std::fs::rename(&from, &to) {
+            warn!("Failed to move {}: {}", from.display(), err);
+        }
+    }
+
+    let new_key = state.relative_key(&new_path);
+    info!("Moved {} to {}", video.key, new_key);
+    store.rename_key(&video.key, &new_key);
+    let name = new_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let title = store.title(&new_key).unwrap_or_else(|| name.clone());
+    let updated = state
+        .update_video(id, |video| {
+            video.path = new_path;
+            video.key = new_key;
+            video.name = name;
+            video.title = title;
+        })
+        .cloned()
+        .unwrap_or(video.clone());
+    if let Some(events) = state.events() {
+        events.emit(LibraryEvent::VideoMoved {
+            from: video.key,
+            video: (&updated).into(),
+        });
+    }
+    Ok(updated)
+}
+
+/// Rename the file of a video (and its sidecars) within its folder. The extension has to stay the same.
+pub fn rename_video(
+    state: &mut VideoPlayerState,
+    store: &IndexStore,
+    id: &str,
+    new_name: &str,
+) -> LibraryResult<VideoEntry> {
+    validate_file_name(new_name)?;
+    let video = state
+        .get(id)
+        .ok_or((StatusCode::NOT_FOUND, format!("No video with id {}", id)))?;
+    let new_path = video.path.with_file_name(new_name);
+    let same_extension = new_path
+        .extension()
+        .map(|extension| extension.to_string_lossy().eq_ignore_ascii_case(&video.extension))
+        .unwrap_or(false);
+    if !same_extension {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("The new name has to keep the .{} extension", video.extension),
+        ));
+    }
+    if new_path == video.path {
+        return Ok(video.clone());
+    }
+    relocate(state, store, id, new_path)
+}
+
+/// Map a path given relative to the root onto the disk, refusing anything that would escape the root.
+pub fn resolve_folder(root: &Path, folder: impl AsRef<Path>) -> LibraryResult<PathBuf> {
+    let folder = folder.as_ref();
+    let mut path = root.to_path_buf();
+    for component in folder.components() {
+        match component {
+            Component::Normal(segment) => path.push(segment),
+            Component::CurDir | Component::RootDir => {}
+            _ => {
+                return Err((
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    format!("{:?} is not a folder under the root", folder),
+                ))
+            }
+        }
+    }
+    Ok(path)
+}
+
+/// Like [`resolve_folder`] under the root of a library, but refusing the trash, which only trashing and restoring
+/// videos may touch.
+pub fn resolve_library_folder(state: &VideoPlayerState, folder: impl AsRef<Path>) -> LibraryResult<PathBuf> {
+    let root = state
+        .root()
+        .map(PathBuf::from)
+        .ok_or((StatusCode::NOT_FOUND, "No library root is configured".to_string()))?;
+    let path = resolve_folder(&root, folder.as_ref())?;
+    if state.is_trash(&path) {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("{:?} is in the trash", folder.as_ref()),
+        ));
+    }
+    Ok(path)
+}
+
+/// The folder under the root to rescan, given relative to the root.
+pub fn resolve_rescan_dir(state: &VideoPlayerState, folder: &str) -> LibraryResult<PathBuf> {
+    resolve_library_folder(state, folder)
+}
+
+/// Move a video (and its sidecars) to another folder under the root, creating it if needed.
+/// An empty folder moves the video to the root itself.
+pub fn move_video(
+    state: &mut VideoPlayerState,
+    store: &IndexStore,
+    id: &str,
+    folder: &str,
+) -> LibraryResult<VideoEntry> {
+    let folder = resolve_library_folder(state, folder)?;
+    let video = state
+        .get(id)
+        .ok_or((StatusCode::NOT_FOUND, format!("No video with id {}", id)))?;
+    let Some(name) = video.path.file_name() else {
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, format!("{} has no file name", video.key)));
+    };
+    let new_path = folder.join(name);
+    if new_path == video.path {
+        return Ok(video.clone());
+    }
+    if folder.exists() && !folder.is_dir() {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("{} is not a folder", state.relative_key(&folder)),
+        ));
+    }
+    std::fs::create_dir_all(&folder).map_err(|err| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to create {}: {}", folder.display(), err),
+        )
+    })?;
+    relocate(state, store, id, new_path)
+}
+
+/// Give a video a custom title, or go back to its file name with `None`.
+pub fn retitle_video(
+    state: &mut VideoPlayerState,
+    store: &IndexStore,
+    id: &str,
+    title: Option<String>,
+) -> LibraryResult<VideoEntry> {
+    let title = title.map(|title| title.trim().to_string()).filter(|title| !title.is_empty());
+    let video = state
+        .update_video(id, |video| {
+            store.set_title(&video.key, title.clone());
+            video.title = title.unwrap_or_else(|| video.name.clone());
+        })
+        .ok_or((StatusCode::NOT_FOUND, format!("No video with id {}", id)))?
+        .clone();
+    if let Some(events) = state.events() {
+        events.emit(LibraryEvent::VideoUpdated { video: (&video).into() });
+    }
+    Ok(video)
+}
+
+/// A video in the trash.
+#[derive(Debug, Clone, Serialize)]
+pub struct TrashedVideo {
+    /// The path the video had (and gets restored to) relative to the root.
+    pub key: String,
+    pub size: u64,
+}
+
+fn trash_dir(state: &VideoPlayerState) -> LibraryResult<PathBuf> {
+    state
+        .trash_dir()
+        .map(PathBuf::from)
+        .ok_or((StatusCode::NOT_FOUND, "No trash folder is configured".to_string()))
+}
+
+/// Move a file and its sidecars, creating the destination folder if needed.
+fn move_with_sidecars(from: &Path, to: &Path) -> LibraryResult<()> {
+    if to.exists() {
+        return Err((StatusCode::CONFLICT, format!("{} already exists", to.display())));
+    }
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent).map_err(|err| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to create {}: {}", parent.display(), err),
+            )
+        })?;
+    }
+    let sidecars = sidecars(from, to);
+    std::fs::rename(from, to).map_err(|err| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to move {}: {}", from.display(), err),
+        )
+    })?;
+    for (from, to) in sidecars.into_iter().filter(|(_, to)| !to.exists()) {
+        if let Err(err) = std::fs::rename(&from, &to) {
+            warn!("Failed to move {}: {}", from.display(), err);
+        }
+    }
+    Ok(())
+}
+
+/// Move a video (and its sidecars) to the trash, keeping its path relative to the root,
+/// and drop it from the index. Everything the store knows about it is kept for a restore.
+pub fn trash_video(state: &mut VideoPlayerState, id: &str) -> LibraryResult<TrashedVideo> {
+    let trash_dir = trash_dir(state)?;
+    let video = state
+        .get(id)
+        .cloned()
+        .ok_or((StatusCode::NOT_FOUND, format!("No video with id {}", id)))?;
+    let size = std::fs::metadata(&video.path).map(|metadata| metadata.len()).unwrap_or(0);
+    move_with_sidecars(&video.path, &trash_dir.join(&video.key))?;
+    state.remove_video(id);
+    info!("Moved {} to the trash", video.key);
+    Ok(TrashedVideo { key: video.key, size })
+}
+
+/// The videos in the trash.
+pub fn list_trash(state: &VideoPlayerState) -> LibraryResult<Vec<TrashedVideo>> {
+    fn visit(
+        state: &VideoPlayerState,
+        trash_dir: &Path,
+        dir: &Path,
+        videos: &mut Vec<TrashedVideo>,
+    ) -> std::io::Result<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                visit(state, trash_dir, &path, videos)?;
+            } else if state.is_video_file(&path) {
+                let key = path
+                    .strip_prefix(trash_dir)
+                    .unwrap_or(&path)
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                let size = std::fs::metadata(&path).map(|metadata| metadata.len()).unwrap_or(0);
+                videos.push(TrashedVideo { key, size });
+            }
+        }
+        Ok(())
+    }
+
+    let trash_dir = trash_dir(state)?;
+    let mut videos = vec![];
+    if trash_dir.is_dir() {
+        visit(state, &trash_dir, &trash_dir, &mut videos).map_err(|err| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to list the trash: {}", err),
+            )
+        })?;
+    }
+    videos.sort_by(|a, b| a.key.cmp(&b.key));
+    Ok(videos)
+}
+
+/// Move a video from the trash back to where it was and add it to the index again.
+pub fn restore_video(state: &mut VideoPlayerState, key: &str) -> LibraryResult<VideoEntry> {
+    let trash_dir = trash_dir(state)?;
+    let trashed = resolve_folder(&trash_dir, key)?;
+    if !trashed.is_file() || !state.is_video_file(&trashed) {
+        return Err((StatusCode::NOT_FOUND, format!("{} is not in the trash", key)));
+    }
+    let restored = resolve_library_folder(state, key)?;
+    move_with_sidecars(&trashed, &restored)?;
+    info!("Restored {} from the trash", key);
+    Ok(state.add_video(restored)?.clone())
+}
+
+/// Delete everything in the trash for good. Returns the number of videos deleted.
+pub fn empty_trash(state: &VideoPlayerState) -> LibraryResult<usize> {
+    let deleted = list_trash(state)?.len();
+    let trash_dir = trash_dir(state)?;
+    if trash_dir.is_dir() {
+        std::fs::remove_dir_all(&trash_dir).map_err(|err| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to empty the trash: {}", err),
+            )
+        })?;
+    }
+    info!("Emptied the trash, deleting {} videos", deleted);
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{store::IndexStore, VideoPlayerConfig};
+    use clap::Parser;
+    use std::sync::Arc;
+
+    #[test]
+    fn resolves_folders_under_the_root() {
+        let root = Path::new("/srv/videos");
+        assert_eq!(resolve_folder(root, "shows/s1").unwrap(), root.join("shows/s1"));
+        assert_eq!(resolve_folder(root, "./shows").unwrap(), root.join("shows"));
+        assert_eq!(resolve_folder(root, "/shows").unwrap(), root.join("shows"));
+        assert_eq!(resolve_folder(root, "").unwrap(), root);
+    }
+
+    #[test]
+    fn rejects_folders_outside_the_root() {
+        let root = Path::new("/srv/videos");
+        for folder in ["..", "../etc", "shows/../../etc", "shows/.."] {
+            let (status, _) = resolve_folder(root, folder).unwrap_err();
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", folder);
+        }
+    }
+
+    #[test]
+    fn rejects_the_trash() {
+        let root = std::env::temp_dir().join(format!("svs-trash-{}", std::process::id()));
+        std::fs::create_dir_all(root.join(".trash")).unwrap();
+        let config = VideoPlayerConfig::parse_from(["static-video-server", "--assets-root", root.to_str().unwrap()]);
+        let state = VideoPlayerState::build(&config, Arc::new(IndexStore::in_memory())).unwrap();
+        assert_eq!(resolve_library_folder(&state, "shows").unwrap(), root.join("shows"));
+        for folder in [".trash", ".trash/shows", "./.trash"] {
+            assert!(resolve_library_folder(&state, folder).is_err(), "{}", folder);
+            assert!(resolve_rescan_dir(&state, folder).is_err(), "{}", folder);
+        }
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn validates_file_names() {
+        assert!(validate_file_name("Holiday 2022.mp4").is_ok());
+        for name in ["", ".", "..", "a/b.mp4", "a\\b.mp4", " padded.mp4", "nul\0.mp4"] {
+            assert!(validate_file_name(name).is_err(), "{:?}", name);
+        }
+    }
+}
@@ -0,0 +1,98 @@
+//! What the library holds: how many videos, how long and how large, in total and by extension, resolution and
+//! top-level folder. Kept up to date while videos are indexed, like [`crate::storage::StorageUsage`], so reporting it
+//! doesn't go through every video.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::VideoEntry;
+
+/// The folder videos right in the root count towards.
+const ROOT_FOLDER: &str = ".";
+
+/// The resolution videos that weren't probed (or have none, like audio) count towards.
+const UNKNOWN_RESOLUTION: &str = "unknown";
+
+/// The videos of a part of the library.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Totals {
+    pub videos: usize,
+    /// The duration in seconds of the videos that were probed.
+    pub duration: f64,
+    pub size: u64,
+}
+
+impl Totals {
+    fn add(&mut self, video: &VideoEntry) {
+        self.videos += 1;
+        self.duration += video.duration().unwrap_or_default();
+        self.size += video.size;
+    }
+
+    /// Stop counting a video. Returns whether none is left.
+    fn remove(&mut self, video: &VideoEntry) -> bool {
+        self.videos = self.videos.saturating_sub(1);
+        self.size = self.size.saturating_sub(video.size);
+        // Rounding errors shouldn't add up to durations of nothing.
+        self.duration = match self.videos {
+            0 => 0.0,
+            _ => (self.duration - video.duration().unwrap_or_default()).max(0.0),
+        };
+        self.videos == 0
+    }
+}
+
+/// The totals of the indexed videos.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LibraryStats {
+    #[serde(flatten)]
+    pub total: Totals,
+    /// By lowercased extension, e.g. `mkv`.
+    pub extensions: BTreeMap<String, Totals>,
+    /// By width and height, e.g. `1920x1080`.
+    pub resolutions: BTreeMap<String, Totals>,
+    /// By the folder right under the root they are in, `.` for the root itself.
+    pub folders: BTreeMap<String, Totals>,
+}
+
+fn resolution_of(video: &VideoEntry) -> String {
+    match video.metadata.as_ref().map(|metadata| (metadata.width, metadata.height)) {
+        Some((Some(width), Some(height))) => format!("{}x{}", width, height),
+        _ => UNKNOWN_RESOLUTION.to_string(),
+    }
+}
+
+fn folder_of(video: &VideoEntry) -> String {
+    match video.key.split_once('/') {
+        Some((folder, _)) => folder.to_string(),
+        None => ROOT_FOLDER.to_string(),
+    }
+}
+
+impl LibraryStats {
+    /// Count a video.
+    pub fn add(&mut self, video: &VideoEntry) {
+        self.total.add(video);
+        for (groups, group) in self.groups_of(video) {
+            groups.entry(group).or_default().add(video);
+        }
+    }
+
+    /// Stop counting a video, e.g. after it was removed or changed.
+    pub fn remove(&mut self, video: &VideoEntry) {
+        self.total.remove(video);
+        for (groups, group) in self.groups_of(video) {
+            if groups.get_mut(&group).is_some_and(|totals| totals.remove(video)) {
+                groups.remove(&group);
+            }
+        }
+    }
+
+    fn groups_of(&mut self, video: &VideoEntry) -> [(&mut BTreeMap<String, Totals>, String); 3] {
+        [
+            (&mut self.extensions, video.extension.clone()),
+            (&mut self.resolutions, resolution_of(video)),
+            (&mut self.folders, folder_of(video)),
+        ]
+    }
+}
@@ -1,152 +1,1146 @@
-use askama::Template;
 use axum::{
-    body::{BoxBody, boxed, Body},
-    extract::{Path, State},
-    http::{StatusCode, Request, Response},
-    response::{Html, IntoResponse, Redirect},
-    routing::{get, post, get_service},
-    Router,
+    body::Body,
+    extract::{Path, Query, State},
+    http::{header, StatusCode, Request},
+    middleware,
+    response::{IntoResponse, Redirect, Response},
+    routing::{get, post},
+    Extension, Router,
 };
-use clap::Parser;
-use tower::ServiceExt;
+#[cfg(feature = "ui")]
+use axum::{
+    body::Bytes,
+    extract::RawQuery,
+    http::{HeaderMap, Uri},
+    response::Html,
+};
+use axum_server::tls_rustls::RustlsConfig;
+#[cfg(feature = "ui")]
+use std::collections::HashMap;
 use std::{
-    collections::HashMap,
     net::SocketAddr,
     path::PathBuf,
     sync::{
         Arc, Mutex,
     },
+    time::Duration,
 };
 use tracing::{info, log::error};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use tracing_subscriber::{fmt::writer::BoxMakeWriter, layer::SubscriberExt, util::SubscriberInitExt};
 use static_video_server::*;
-use tower_http::{services::ServeDir, trace::TraceLayer};
+#[cfg(feature = "http3")]
+use tower_http::set_header::SetResponseHeaderLayer;
+use tower_http::trace::TraceLayer;
 
 
-struct HtmlTemplate<T>(T);
+#[cfg(feature = "ui")]
+#[allow(clippy::too_many_arguments)]
+pub async fn index(
+    State(state): State<SharedState>,
+    Extension(pages): Extension<cache::SharedPageCache>,
+    Extension(renderer): Extension<templates::Renderer>,
+    Extension(store): Extension<store::SharedIndexStore>,
+    Extension(layout): Extension<viewer::Layout>,
+    Extension(search): Extension<search::SharedSearchIndex>,
+    Extension(t): Extension<i18n::SharedMessages>,
+    Extension(theme): Extension<themes::SharedTheme>,
+    RawQuery(query): RawQuery,
+    Query(mut filter): Query<api::VideoFilter>,
+    Query(page): Query<api::Page>,
+    show_hidden: visibility::ShowHidden,
+) -> Response {
+    filter.show_hidden = show_hidden.enabled();
+    render_listing(
+        &state, &pages, &renderer, &store, &search, &t, &theme, layout, query, &filter, page, show_hidden, false,
+    )
+}
 
-#[derive(Template)]
-#[template(path = "index.html")]
-pub struct IndexTemplate {
-    pub videos: HashMap<String, PathBuf>,
+/// The rows of the next chunk of the index, requested by the page as the viewer scrolls down.
+#[cfg(feature = "ui")]
+#[allow(clippy::too_many_arguments)]
+pub async fn video_chunk(
+    State(state): State<SharedState>,
+    Extension(pages): Extension<cache::SharedPageCache>,
+    Extension(renderer): Extension<templates::Renderer>,
+    Extension(store): Extension<store::SharedIndexStore>,
+    Extension(layout): Extension<viewer::Layout>,
+    Extension(search): Extension<search::SharedSearchIndex>,
+    Extension(t): Extension<i18n::SharedMessages>,
+    Extension(theme): Extension<themes::SharedTheme>,
+    RawQuery(query): RawQuery,
+    Query(mut filter): Query<api::VideoFilter>,
+    Query(page): Query<api::Page>,
+    show_hidden: visibility::ShowHidden,
+) -> Response {
+    filter.show_hidden = show_hidden.enabled();
+    render_listing(
+        &state, &pages, &renderer, &store, &search, &t, &theme, layout, query, &filter, page, show_hidden, true,
+    )
 }
 
-impl<T> IntoResponse for HtmlTemplate<T>
-where
-    T: Template,
-{
-    fn into_response(self) -> axum::response::Response {
-        match self.0.render() {
-            Ok(html) => Html(html).into_response(),
-            Err(err) => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Failed to render template. Error: {}", err),
-            )
-                .into_response(),
+/// Render a chunk of the filtered listing, either as the index page or as just its rows.
+#[cfg(feature = "ui")]
+#[allow(clippy::too_many_arguments)]
+fn render_listing(
+    state: &SharedState,
+    pages: &cache::PageCache,
+    renderer: &templates::Renderer,
+    store: &store::IndexStore,
+    search: &search::SearchIndex,
+    t: &i18n::Messages,
+    theme: &themes::Theme,
+    layout: viewer::Layout,
+    query: Option<String>,
+    filter: &api::VideoFilter,
+    page: api::Page,
+    show_hidden: visibility::ShowHidden,
+    fragment: bool,
+) -> Response {
+    let query = query.unwrap_or_default();
+    let template_name = if fragment { "video_chunk.html" } else { "index.html" };
+    // Editors get other pages: with the toggle listing hidden videos, and those videos if they asked for them.
+    let key = format!(
+        "{}?{}#{}#{}#{}#{}{}",
+        template_name,
+        query,
+        layout.as_str(),
+        t.lang,
+        theme.name,
+        show_hidden.admin,
+        show_hidden.enabled()
+    );
+    let (videos, base_path, generation, kinds) = {
+        let state = state.lock().unwrap();
+        (state.videos(), state.base_path.clone(), state.generation(), state.kinds())
+    };
+    // Play counts and ratings change without the index changing, so pages using them aren't cached.
+    let cacheable = !renderer.is_dev() && !filter.uses_stats();
+    if let Some(html) = pages.get(&key, generation).filter(|_| cacheable) {
+        return Html(html).into_response();
+    }
+
+    let (videos, next) = page.slice(filter.apply(&videos, &store.stats(), search));
+    let next = next
+        .map(|offset| format!("{}/chunks/videos?{}", base_path, api::Page::query_at(&query, offset)))
+        .unwrap_or_default();
+    let rendered = if fragment {
+        renderer.render(
+            template_name,
+            &templates::VideoChunkTemplate {
+                t,
+                videos,
+                base_path,
+                next,
+                layout: layout.as_str().to_string(),
+            },
+        )
+    } else {
+        renderer.render(
+            template_name,
+            &templates::IndexTemplate {
+                t,
+                theme,
+                title: t.index_title.clone(),
+                videos,
+                base_path,
+                bucket: filter
+                    .bucket
+                    .map(|bucket| bucket.as_str().to_string())
+                    .unwrap_or_default(),
+                next,
+                layout: layout.as_str().to_string(),
+                query: filter.q.clone().unwrap_or_default(),
+                kinds: templates::KindLink::all(&kinds, filter.kind.as_deref(), t),
+                visibility: templates::KindLink::show_hidden(show_hidden, t),
+            },
+        )
+    };
+    match rendered {
+        Ok(html) => {
+            let html = Bytes::from(html);
+            if cacheable {
+                pages.insert(key, generation, html.clone());
+            }
+            Html(html).into_response()
         }
+        Err(err) => templates::render_failed(err),
     }
 }
 
-pub async fn index(State(state): State<SharedState>) -> impl IntoResponse {
-    let template = IndexTemplate {
-        videos: state.lock().unwrap().videos.clone().into_iter().map(|(k, v)| {
-            (k, PathBuf::from(v))
-        }).collect(),
+/// The videos the viewer starred. Rendered for every request, since it differs between viewers.
+#[cfg(feature = "ui")]
+#[allow(clippy::too_many_arguments)]
+pub async fn favorites(
+    State(state): State<SharedState>,
+    Extension(store): Extension<store::SharedIndexStore>,
+    Extension(viewer): Extension<viewer::Viewer>,
+    Extension(layout): Extension<viewer::Layout>,
+    Extension(search): Extension<search::SharedSearchIndex>,
+    Extension(renderer): Extension<templates::Renderer>,
+    Extension(t): Extension<i18n::SharedMessages>,
+    Extension(theme): Extension<themes::SharedTheme>,
+    Query(mut filter): Query<api::VideoFilter>,
+    show_hidden: visibility::ShowHidden,
+) -> Response {
+    filter.show_hidden = show_hidden.enabled();
+    let (videos, base_path, kinds) = {
+        let state = state.lock().unwrap();
+        (state.videos(), state.base_path.clone(), state.kinds())
+    };
+    let favorites = store.favorites(viewer.as_str());
+    let template = templates::IndexTemplate {
+        t: &t,
+        theme: &theme,
+        title: t.favorites.clone(),
+        videos: filter
+            .apply(&videos, &store.stats(), &search)
+            .into_iter()
+            .filter(|video| favorites.contains(&video.key))
+            .collect(),
+        base_path,
+        bucket: filter
+            .bucket
+            .map(|bucket| bucket.as_str().to_string())
+            .unwrap_or_default(),
+        next: String::new(),
+        layout: layout.as_str().to_string(),
+        query: filter.q.clone().unwrap_or_default(),
+        kinds: templates::KindLink::all(&kinds, filter.kind.as_deref(), &t),
+        visibility: templates::KindLink::show_hidden(show_hidden, &t),
+    };
+    renderer.page("index.html", &template)
+}
+
+/// The videos the viewer started, most recent first.
+#[cfg(feature = "ui")]
+pub async fn history(
+    State(state): State<SharedState>,
+    Extension(store): Extension<store::SharedIndexStore>,
+    Extension(viewer): Extension<viewer::Viewer>,
+    Extension(layout): Extension<viewer::Layout>,
+    Extension(renderer): Extension<templates::Renderer>,
+    Extension(t): Extension<i18n::SharedMessages>,
+    Extension(theme): Extension<themes::SharedTheme>,
+) -> Response {
+    let (videos, base_path) = {
+        let state = state.lock().unwrap();
+        (state.videos(), state.base_path.clone())
     };
-    HtmlTemplate(template)
+    let by_key: HashMap<&str, &VideoEntry> =
+        videos.iter().map(|video| (video.key.as_str(), video)).collect();
+    let template = templates::IndexTemplate {
+        t: &t,
+        theme: &theme,
+        title: t.history.clone(),
+        videos: store
+            .history(viewer.as_str())
+            .iter()
+            .filter_map(|entry| by_key.get(entry.key.as_str()).copied())
+            .collect(),
+        base_path,
+        bucket: String::new(),
+        next: String::new(),
+        layout: layout.as_str().to_string(),
+        query: String::new(),
+        kinds: vec![],
+        visibility: vec![],
+    };
+    renderer.page("index.html", &template)
+}
+
+/// The videos and subfolders of a folder. In lazy mode, this is what indexes the folder.
+#[cfg(feature = "ui")]
+pub async fn browse(
+    State(state): State<SharedState>,
+    Extension(renderer): Extension<templates::Renderer>,
+    Extension(store): Extension<store::SharedIndexStore>,
+    Extension(t): Extension<i18n::SharedMessages>,
+    Extension(theme): Extension<themes::SharedTheme>,
+    show_hidden: visibility::ShowHidden,
+    uri: Uri,
+) -> Response {
+    // Decoded by hand rather than with `Path`, since folder names need not be UTF-8.
+    let relative = urls::decode_path(uri.path().strip_prefix("/browse").unwrap_or_default());
+    let (videos, subdirs, root, dir, base_path) = {
+        let mut state = state.lock().unwrap();
+        let Some(root) = state.root().map(PathBuf::from) else {
+            return error::Error::NoRoot.into_response();
+        };
+        let dir = match library::resolve_folder(&root, &relative) {
+            Ok(dir) if dir.is_dir() && !state.is_trash(&dir) => dir,
+            _ => {
+                let message = format!("There is no folder {} in the library.", relative.display());
+                return (StatusCode::NOT_FOUND, message).into_response();
+            }
+        };
+        let subdirs = match state.index_dir(&dir) {
+            Ok(subdirs) => subdirs,
+            Err(err) => {
+                error!("{}", err);
+                return err.into_response();
+            }
+        };
+        (state.videos(), subdirs, root, dir, state.base_path.clone())
+    };
+
+    let prefix = format!("{}/browse", base_path);
+    let folders = subdirs
+        .iter()
+        .filter_map(|subdir| Some((subdir.file_name()?, dir_config::DirConfig::load(&root, subdir))))
+        .filter(|(_, config)| !config.hidden || show_hidden.enabled())
+        .map(|(name, config)| templates::FolderLink {
+            name: config.title.unwrap_or_else(|| name.to_string_lossy().to_string()),
+            href: urls::encode_href(&prefix, &relative.join(name), true),
+        })
+        .collect();
+    let mut crumb_path = PathBuf::new();
+    let breadcrumbs = std::iter::once(templates::FolderLink {
+        name: t.library.clone(),
+        href: format!("{}/", prefix),
+    })
+    .chain(relative.iter().map(|name| {
+        crumb_path.push(name);
+        templates::FolderLink {
+            name: dir_config::DirConfig::load(&root, &root.join(&crumb_path))
+                .title
+                .unwrap_or_else(|| name.to_string_lossy().to_string()),
+            href: urls::encode_href(&prefix, &crumb_path, true),
+        }
+    }))
+    .collect();
+    // A hidden folder opened by link shows its videos, only those hidden on their own are left out.
+    let folder_hidden = dir_config::DirConfig::load(&root, &dir).hidden;
+    let videos = api::folder_videos(&videos, &root, &dir, &store.stats())
+        .into_iter()
+        .filter(|video| !video.hidden || folder_hidden || show_hidden.enabled())
+        .collect();
+    let template = templates::BrowseTemplate {
+        t: &t,
+        theme: &theme,
+        path: relative.to_string_lossy().to_string(),
+        breadcrumbs,
+        folders,
+        videos,
+        base_path,
+        visibility: templates::KindLink::show_hidden(show_hidden, &t),
+    };
+    renderer.page("browse.html", &template)
+}
+
+/// The page of a single video, which is what links shared to chat apps show a preview of.
+#[cfg(feature = "ui")]
+#[allow(clippy::too_many_arguments)]
+pub async fn watch(
+    Path(video_id): Path<String>,
+    State(state): State<SharedState>,
+    Extension(renderer): Extension<templates::Renderer>,
+    Extension(store): Extension<store::SharedIndexStore>,
+    Extension(public_url): Extension<urls::PublicUrl>,
+    Extension(t): Extension<i18n::SharedMessages>,
+    Extension(theme): Extension<themes::SharedTheme>,
+    Query(token): Query<private::TokenQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let (video, next, base_path) = {
+        let state = state.lock().unwrap();
+        let Some(video) = state.get(&video_id).cloned() else {
+            return error::Error::VideoNotFound(video_id).into_response();
+        };
+        // The video after this one in its folder, e.g. the next episode.
+        let next = state.root().zip(video.path.parent()).and_then(|(root, dir)| {
+            let videos = state.videos();
+            let folder = api::folder_videos(&videos, std::path::Path::new(root), dir, &store.stats());
+            let position = folder.iter().position(|other| other.id == video.id)?;
+            // Private videos need tokens of their own.
+            folder[position + 1..].iter().find(|next| !next.private).map(|next| (*next).clone())
+        });
+        (video, next, state.base_path.clone())
+    };
+    let root = public_url.resolve(&headers, &base_path);
+    let page_url = format!("{}/watch/{}", root, video.id);
+    let metadata = video.metadata.clone().unwrap_or_default();
+    let token_query = token.as_query();
+    let template = templates::WatchTemplate {
+        t: &t,
+        theme: &theme,
+        video: &video,
+        oembed_url: format!(
+            "{}/oembed?url={}",
+            root,
+            // A private video is only described to the holders of one of its tokens.
+            urls::encode_query_value(&format!("{}{}", page_url, if video.private { &token_query } else { "" }))
+        ),
+        page_url,
+        video_url: format!("{}/{}/{}{}", root, video.kind, video.id, token_query),
+        poster_url: format!("{}{}", poster_path(&root, &video), token_query),
+        content_type: media::content_type(&video.path).to_string(),
+        duration: video
+            .duration()
+            .map(|duration| format!("{:.0}", duration))
+            .unwrap_or_default(),
+        width: metadata.width.map(|width| width.to_string()).unwrap_or_default(),
+        height: metadata.height.map(|height| height.to_string()).unwrap_or_default(),
+        next_title: next.as_ref().map(|next| next.title.clone()).unwrap_or_default(),
+        next_page: next.as_ref().map(|next| format!("{}/watch/{}", base_path, next.id)).unwrap_or_default(),
+        next_media: next
+            .as_ref()
+            .map(|next| format!("{}/{}/{}", base_path, next.kind, next.id))
+            .unwrap_or_default(),
+        next_poster: next.as_ref().map(|next| poster_path(&base_path, next)).unwrap_or_default(),
+        base_path,
+        token_query,
+    };
+    renderer.page("watch.html", &template)
+}
+
+/// The url of the poster of a video, given the url of the root. Images are their own poster.
+#[cfg(feature = "ui")]
+fn poster_path(root: &str, video: &VideoEntry) -> String {
+    match video.kind {
+        MediaKind::Image => format!("{}/image/{}", root, video.id),
+        _ => format!("{}/poster/{}", root, video.id),
+    }
+}
+
+/// The player of a single video alone, embedded in other sites through oEmbed.
+#[cfg(feature = "ui")]
+pub async fn embed(
+    Path(video_id): Path<String>,
+    State(state): State<SharedState>,
+    Extension(renderer): Extension<templates::Renderer>,
+    Extension(t): Extension<i18n::SharedMessages>,
+    Query(token): Query<private::TokenQuery>,
+) -> Response {
+    let (video, base_path) = {
+        let state = state.lock().unwrap();
+        let Some(video) = state.get(&video_id).cloned() else {
+            return error::Error::VideoNotFound(video_id).into_response();
+        };
+        (video, state.base_path.clone())
+    };
+    let template = templates::EmbedTemplate {
+        t: &t,
+        video: &video,
+        base_path,
+        content_type: media::content_type(&video.path).to_string(),
+        token_query: token.as_query(),
+    };
+    renderer.page("embed.html", &template)
 }
 
 pub async fn health_check() -> impl IntoResponse {
     (StatusCode::OK, "OK")
 }
 
-pub async fn favicon() -> impl IntoResponse {
-    let mut headers = axum::http::HeaderMap::new();
-    headers.insert(
-        axum::http::header::CONTENT_TYPE,
-        "image/x-icon".parse().unwrap(),
-    );
-    (headers, include_bytes!("../assets/favicon.ico").to_vec())
+/// Anything that isn't a route.
+pub async fn not_found() -> impl IntoResponse {
+    (StatusCode::NOT_FOUND, "There is nothing at this address.")
 }
 
-pub async fn reload(State(state): State<SharedState>) -> impl IntoResponse {
-    state.lock().unwrap().reload();
-    Redirect::to("/")
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct ReloadQuery {
+    /// Only rescan this folder, relative to the root.
+    pub path: Option<String>,
 }
 
-pub async fn get_static_file(path: PathBuf) -> Result<Response<BoxBody>, (StatusCode, String)> {
+/// Rescan the library, or a folder of it, and go back to the index. Needs a login or the API token, like the API.
+pub async fn reload(
+    _editor: auth::Editor,
+    State(state): State<SharedState>,
+    Extension(cache): Extension<cache::SharedFileCache>,
+    Extension(reloader): Extension<reload::SharedReloader>,
+    Query(query): Query<ReloadQuery>,
+) -> Result<Redirect, (StatusCode, String)> {
+    match query.path {
+        Some(path) => {
+            let dir = library::resolve_rescan_dir(&state.lock().unwrap(), &path)?;
+            reloader.rescan_dir(state.clone(), dir).await;
+        }
+        None => {
+            reloader.reload(state.clone()).await;
+        }
+    }
+    cache.clear();
+    let base_path = state.lock().unwrap().base_path.clone();
+    Ok(Redirect::to(&format!("{}/", base_path)))
+}
 
-    let request = Request::builder().body(Body::empty()).unwrap();
+#[axum_macros::debug_handler]
+pub async fn video_handler(
+    Path(video_id): Path<String>,
+    State(state): State<SharedState>,
+    Extension(store): Extension<store::SharedIndexStore>,
+    Extension(viewer): Extension<viewer::Viewer>,
+    slot: streams::StreamSlot,
+    request: Request<Body>,
+) -> Response {
+    serve_media(None, video_id, state, store, viewer, slot, request).await
+}
 
-    match ServeDir::new(path.clone()).oneshot(request).await {
-        Ok(response) => Ok(response.map(boxed)),
-        Err(err) => {
-            error!("Failed to open file: \nError: {}", err);
-            Err((StatusCode::INTERNAL_SERVER_ERROR, "Failed to open file".to_string()))
+/// An audio file, which is served under `/video/:id` as well.
+pub async fn audio_handler(
+    Path(video_id): Path<String>,
+    State(state): State<SharedState>,
+    Extension(store): Extension<store::SharedIndexStore>,
+    Extension(viewer): Extension<viewer::Viewer>,
+    slot: streams::StreamSlot,
+    request: Request<Body>,
+) -> Response {
+    serve_media(Some(MediaKind::Audio), video_id, state, store, viewer, slot, request).await
+}
+
+/// An image, which is served under `/video/:id` as well.
+pub async fn image_handler(
+    Path(video_id): Path<String>,
+    State(state): State<SharedState>,
+    Extension(store): Extension<store::SharedIndexStore>,
+    Extension(viewer): Extension<viewer::Viewer>,
+    slot: streams::StreamSlot,
+    request: Request<Body>,
+) -> Response {
+    serve_media(Some(MediaKind::Image), video_id, state, store, viewer, slot, request).await
+}
+
+/// Serve a file of the index, of any kind unless one is given.
+async fn serve_media(
+    kind: Option<MediaKind>,
+    video_id: String,
+    state: SharedState,
+    store: store::SharedIndexStore,
+    viewer: viewer::Viewer,
+    slot: streams::StreamSlot,
+    request: Request<Body>,
+) -> Response {
+    let (video, buffer_size, limits, events) = {
+        let state = state.lock().unwrap();
+        let video = state.get(&video_id).filter(|video| kind.is_none_or(|kind| video.kind == kind));
+        let Some(video) = video.cloned() else {
+            return error::Error::VideoNotFound(video_id).into_response();
+        };
+        (video, state.stream_buffer_size, state.stream_limits, state.events().cloned())
+    };
+    // Looking at an image isn't playing it.
+    if video.kind != MediaKind::Image
+        && media::starts_playback(request.method(), request.headers())
+        && store.record_play(viewer.as_str(), &video.key)
+    {
+        if let Some(events) = events {
+            events.emit(events::LibraryEvent::PlaybackStarted { video: (&video).into() });
+        }
+    }
+    let mut response = match media::serve_file(&video.path, request, buffer_size).await {
+        Ok(response) => streams::hold(analytics::count(limits.apply(response), store, video.key.clone()), slot),
+        Err(err) => err.into_response(),
+    };
+    if let Some(name) = video.path.file_name() {
+        if let Ok(value) = header::HeaderValue::from_str(&urls::content_disposition("inline", name)) {
+            response.headers_mut().insert(header::CONTENT_DISPOSITION, value);
         }
     }
+    response
 }
 
-pub fn static_file_router() -> Router {
-    let serve_dir = get_service(ServeDir::new("assets")).handle_error(handle_error);
-    Router::new()
-        .route("/", serve_dir.clone())
-        .fallback_service(serve_dir)
+/// Serve the first existing sidecar of a video from the in-memory cache.
+async fn serve_sidecar(
+    video_id: &str,
+    state: &SharedState,
+    cache: &cache::FileCache,
+    candidates: impl Fn(&VideoEntry) -> Vec<PathBuf>,
+) -> Response {
+    let Some(candidates) = state.lock().unwrap().get(video_id).map(candidates) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    for candidate in candidates {
+        match cache.get(&candidate).await {
+            Ok(Some(file)) => {
+                return ([(header::CONTENT_TYPE, file.content_type)], file.bytes).into_response();
+            }
+            Ok(None) => continue,
+            Err(err) => {
+                error!("Failed to read {}: {}", candidate.display(), err);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        }
+    }
+    StatusCode::NOT_FOUND.into_response()
 }
 
-async fn handle_error(_err: std::io::Error) -> impl IntoResponse {
-    (StatusCode::INTERNAL_SERVER_ERROR, "Something went wrong...")
+pub async fn poster_handler(
+    Path(video_id): Path<String>,
+    State(state): State<SharedState>,
+    Extension(cache): Extension<cache::SharedFileCache>,
+    Extension(jobs): Extension<jobs::SharedJobQueue>,
+) -> Response {
+    // A generated thumbnail stands in for videos without a poster of their own.
+    let candidates = |video: &VideoEntry| {
+        let mut candidates = cache::poster_candidates(&video.path);
+        candidates.push(jobs.thumbnail_path(&video.key));
+        candidates
+    };
+    serve_sidecar(&video_id, &state, &cache, candidates).await
 }
 
-#[axum_macros::debug_handler]
-pub async fn video_handler(
+/// The animated preview of a video, generated in the background when first asked for.
+pub async fn preview_handler(
     Path(video_id): Path<String>,
     State(state): State<SharedState>,
-) -> impl IntoResponse {
-    let file_path = state
-        .lock()
-        .unwrap()
-        .videos
-        .get(&video_id)
-        .unwrap_or_else(|| panic!("Failed to find video with given id: {}", video_id.clone()))
-        .clone();
-
-    drop(state);
+    Extension(cache): Extension<cache::SharedFileCache>,
+    Extension(jobs): Extension<jobs::SharedJobQueue>,
+) -> Response {
+    let Some(video) = state.lock().unwrap().get(&video_id).cloned() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let path = jobs.preview_path(&video.key);
+    match cache.get(&path).await {
+        Ok(Some(file)) => ([(header::CONTENT_TYPE, file.content_type)], file.bytes).into_response(),
+        Ok(None) => {
+            jobs.submit_preview(&video, jobs::Priority::Normal);
+            StatusCode::NOT_FOUND.into_response()
+        }
+        Err(err) => {
+            error!("Failed to read {}: {}", path.display(), err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
 
-    get_static_file(PathBuf::from(&file_path)).await
+pub async fn subtitles_handler(
+    Path(video_id): Path<String>,
+    State(state): State<SharedState>,
+    Extension(cache): Extension<cache::SharedFileCache>,
+) -> Response {
+    serve_sidecar(&video_id, &state, &cache, |video| cache::subtitle_candidates(&video.path)).await
 }
 
-pub fn set_up_logging() {
-    tracing_subscriber::registry()
+/// Log to the terminal, or to the end of `log_file` if given.
+pub fn set_up_logging(log_file: Option<&std::path::Path>) {
+    let output = match log_file {
+        Some(path) => match std::fs::OpenOptions::new().create(true).append(true).open(path) {
+            Ok(file) => tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(BoxMakeWriter::new(Mutex::new(file))),
+            Err(err) => {
+                eprintln!("Can't log to {}: {}", path.display(), err);
+                std::process::exit(1);
+            }
+        },
+        None => tracing_subscriber::fmt::layer().with_writer(BoxMakeWriter::new(std::io::stdout)),
+    };
+    let registry = tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "static-video-server=debug,tower_http=debug".into()),
+                .unwrap_or_else(|_| "static_video_server=debug,tower_http=debug".into()),
         )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+        .with(output);
+
+    #[cfg(feature = "sentry")]
+    let registry = registry.with(sentry::integrations::tracing::layer());
+
+    #[cfg(feature = "otlp")]
+    let registry = registry.with(match telemetry::otlp_layer() {
+        Ok(layer) => layer,
+        Err(err) => {
+            eprintln!("Failed to set up the OTLP exporter: {}", err);
+            std::process::exit(1);
+        }
+    });
+
+    registry.init();
 }
 
-#[tokio::main]
-pub async fn main() {
-    set_up_logging();
-    let config = VideoPlayerConfig::parse();
-    let state = Arc::new(Mutex::new(VideoPlayerState::build(&config)));
+/// The routes serving a single library (the one under `config.assets_root`), with their own index and store.
+fn library_app(
+    config: &VideoPlayerConfig,
+    mqtt: Option<&mqtt::MqttPublisher>,
+    live: &config_file::LiveSettings,
+) -> Router {
+    let file_cache = Arc::new(cache::FileCache::new(
+        config.cache_size * 1024 * 1024,
+        config.cache_max_entry_size * 1024,
+    ));
+    let page_cache = Arc::new(cache::PageCache::new(64));
+    let reloader = Arc::new(reload::Reloader::default());
+    let store = match store::IndexStore::open(&config.data_dir) {
+        Ok(store) => Arc::new(store),
+        Err(err) => {
+            error!("Failed to open the store in {}: {}", config.data_dir.display(), err);
+            std::process::exit(1);
+        }
+    };
+    let state = match VideoPlayerState::build(config, store.clone()) {
+        Ok(state) => Arc::new(Mutex::new(state)),
+        Err(err) => {
+            error!("Failed to index {}: {}", config.assets_root, err);
+            std::process::exit(1);
+        }
+    };
+    if let Some(events) = state.lock().unwrap().events() {
+        if !config.webhook.is_empty() {
+            tokio::spawn(events::deliver_webhooks(events.subscribe(), config.webhook.clone()));
+        }
+        if let Some(mqtt) = mqtt {
+            tokio::spawn(mqtt.clone().forward(events.subscribe()));
+        }
+    }
+    let search = Arc::new(search_index(config, &state));
+    let jobs = match jobs::JobQueue::open(&config.data_dir, &config.ffmpeg) {
+        #[cfg(feature = "transcoding")]
+        Ok(jobs) => Arc::new(jobs.with_watermark(watermark::Watermark::from_config(config))),
+        #[cfg(not(feature = "transcoding"))]
+        Ok(jobs) => Arc::new(jobs),
+        Err(err) => {
+            error!("Failed to open the job queue in {}: {}", config.data_dir.display(), err);
+            std::process::exit(1);
+        }
+    };
+    tokio::spawn(store::flush_periodically(store.clone()));
+    jobs.start(config.job_workers, state.clone(), store.clone(), file_cache.clone());
+    if config.thumbnails || config.previews {
+        let generate = jobs::Generate {
+            thumbnails: config.thumbnails,
+            previews: config.previews,
+        };
+        tokio::spawn(jobs::generate_missing(jobs.clone(), state.clone(), generate));
+    }
+    if let Some(importer) = incoming::Importer::new(config) {
+        importer.start(state.clone());
+    }
+    tokio::spawn(reload::rescan_settled(
+        Duration::from_secs(config.settle_time),
+        reloader.clone(),
+        state.clone(),
+        file_cache.clone(),
+    ));
+    if let Some(schedule) = &config.rescan_schedule {
+        tokio::spawn(reload::rescan_on_schedule(
+            schedule.clone(),
+            config.rescan_path.clone(),
+            reloader.clone(),
+            state.clone(),
+            file_cache.clone(),
+        ));
+    }
+    if !config.record.is_empty() {
+        ingest::Recorder::new(config).start(&config.record, state.clone());
+    }
+    if config.lazy_index {
+        info!("Indexing {} in the background.", config.assets_root);
+        let state = state.clone();
+        tokio::task::spawn_blocking(move || fill_index(&state));
+    }
+    let uploads = match uploads::Uploads::open(&config.data_dir, config.upload_quota.map(|quota| quota * 1024 * 1024)) {
+        Ok(uploads) => Arc::new(uploads),
+        Err(err) => {
+            error!("Failed to open the uploads in {}: {}", config.data_dir.display(), err);
+            std::process::exit(1);
+        }
+    };
+    live.add_library(state.clone());
 
-    let app = Router::new()
-        .nest_service("/assets/", static_file_router())
-        .route("/favicon.ico", get(favicon))
+    let media = Router::new()
         .route("/video/:video_id", get(video_handler))
-        .route("/", get(index))
-        .route("/reload", post(reload))
+        .route("/audio/:video_id", get(audio_handler))
+        .route("/image/:video_id", get(image_handler))
+        .route("/subtitles/:video_id", get(subtitles_handler));
+    let images = Router::new()
+        .route("/poster/:video_id", get(poster_handler))
+        .route("/preview/:video_id", get(preview_handler));
+
+    let mut app = Router::new()
+        .route("/robots.txt", get(robots::robots_txt))
+        .merge(cache::with_cache_control(private::guarded(media, &state), config.cache_control_media.as_ref()))
+        .merge(cache::with_cache_control(private::guarded(images, &state), config.cache_control_images.as_ref()))
+        .merge(users::require(users::Role::Admin, Router::new().route("/reload", post(reload))))
         .route("/healthcheck", get(health_check))
-        .layer(TraceLayer::new_for_http())
-        .with_state(state);
+        .merge(private::guarded(api::api_router(), &state))
+        .merge(users::require(users::Role::Admin, analytics::analytics_router()))
+        .merge(users::require(users::Role::Uploader, uploads::uploads_router()))
+        .merge(private::guarded(share::share_router(), &state))
+        .merge(users::require(users::Role::Admin, private::private_router()))
+        .merge(users::require(users::Role::Admin, visibility::visibility_router()))
+        .fallback(not_found);
+    if config.no_ui {
+        info!("Serving only the API and the media, without the UI.");
+    } else {
+        app = with_ui(app, config, &state);
+    }
+    let app = app
+        .layer(Extension(file_cache))
+        .layer(Extension(page_cache))
+        .layer(Extension(urls::PublicUrl::new(
+            config.public_url.as_deref(),
+            config.tls_cert.is_some() || !config.acme_domain.is_empty(),
+        )))
+        .layer(Extension(Arc::new(robots::RobotsPolicy {
+            allow: config.robots_allow.clone(),
+            base_path: config.base_path(),
+        })))
+        .layer(Extension(Arc::new(share::ShareLinks::new(config.share_secret.as_deref()))))
+        .layer(Extension(store))
+        .layer(Extension(search))
+        .layer(Extension(jobs))
+        .layer(Extension(uploads))
+        .layer(Extension(live.api_token.clone()))
+        .layer(Extension(live.users.clone()))
+        .layer(Extension(reloader))
+        .layer(middleware::from_fn(viewer::identify_viewer))
+        .layer(middleware::from_fn(viewer::choose_layout))
+        .with_state(state.clone());
+
+    if config.webdav {
+        info!("Serving a read-only WebDAV share at {}", webdav::DAV_PREFIX);
+        return app.merge(webdav::webdav_router().with_state(state));
+    }
+    app
+}
+
+/// Add the pages to the routes of a library, and what only makes sense with them: their assets, the sitemap of the
+/// pages, embedding the player and rendering errors as pages.
+#[cfg(feature = "ui")]
+fn with_ui(app: Router<SharedState>, config: &VideoPlayerConfig, state: &SharedState) -> Router<SharedState> {
+    let renderer = if config.dev {
+        info!("Dev mode: loading templates from {}", config.templates_dir.display());
+        templates::Renderer::dev(config.templates_dir.clone())
+    } else {
+        templates::Renderer::compiled()
+    };
+    let translations = match i18n::Translations::new(&config.lang) {
+        Ok(translations) => Arc::new(translations),
+        Err(err) => {
+            error!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    let themes = match themes::Themes::load(config.themes_dir.as_deref(), &config.theme) {
+        Ok(themes) => Arc::new(themes),
+        Err(err) => {
+            error!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    let pages = Router::new()
+        .route("/", get(index))
+        .route("/chunks/videos", get(video_chunk))
+        .route("/favorites", get(favorites))
+        .route("/history", get(history))
+        .route("/watch/:video_id", get(watch))
+        .route("/embed/:video_id", get(embed))
+        .route("/browse", get(browse))
+        .route("/browse/", get(browse))
+        .route("/browse/*path", get(browse))
+        .route("/share/:video_id", get(share::share_page))
+        .merge(users::require(users::Role::Admin, Router::new().route("/stats", get(analytics::dashboard))));
+    let pages = private::guarded(pages, state);
+    app.route("/assets/*file", get(assets::ui_asset))
+        .route("/favicon.ico", get(assets::favicon))
+        .merge(cache::with_cache_control(pages, config.cache_control_pages.as_ref()))
+        .route("/oembed", get(oembed::oembed))
+        .merge(sitemap::sitemap_router())
+        .layer(middleware::from_fn_with_state(state.clone(), templates::error_pages))
+        .layer(middleware::from_fn_with_state(themes, themes::choose_theme))
+        .layer(middleware::from_fn_with_state(translations, i18n::choose_language))
+        .layer(Extension(renderer))
+}
+
+#[cfg(not(feature = "ui"))]
+fn with_ui(app: Router<SharedState>, _config: &VideoPlayerConfig, _state: &SharedState) -> Router<SharedState> {
+    info!("Built without the UI: serving only the API and the media.");
+    app
+}
+
+/// The full-text index of a library if enabled, kept up to date as the library changes.
+#[cfg(feature = "full-text")]
+fn search_index(config: &VideoPlayerConfig, state: &SharedState) -> search::SearchIndex {
+    if !config.full_text_index {
+        return search::SearchIndex::default();
+    }
+    let dir = config.data_dir.join("fulltext");
+    let index = match fulltext::FullTextIndex::open(&dir) {
+        Ok(index) => Arc::new(index),
+        Err(err) => {
+            error!("Failed to open the full-text index in {}: {}", dir.display(), err);
+            std::process::exit(1);
+        }
+    };
+    let videos = state.lock().unwrap().videos();
+    if let Err(err) = index.sync(&videos) {
+        tracing::warn!("Failed to update the full-text index: {}", err);
+    }
+    if let Some(events) = state.lock().unwrap().events() {
+        tokio::spawn(fulltext::keep_updated(index.clone(), state.clone(), events.subscribe()));
+    }
+    search::SearchIndex::full_text(index)
+}
+
+#[cfg(not(feature = "full-text"))]
+fn search_index(_config: &VideoPlayerConfig, _state: &SharedState) -> search::SearchIndex {
+    search::SearchIndex::default()
+}
+
+pub fn main() {
+    let (config, matches) = match config_file::load(std::env::args_os()) {
+        Ok(loaded) => loaded,
+        Err(err) => err.exit(),
+    };
+    #[cfg(windows)]
+    if let Some(Command::Service(command)) = &config.command {
+        std::process::exit(service::manage(command));
+    }
+    #[cfg(windows)]
+    if config.service {
+        std::process::exit(service::run(|config, matches| Box::pin(serve(config, matches))));
+    }
+    #[cfg(unix)]
+    if config.daemonize {
+        let log_file = config.log_file.as_deref().unwrap_or(std::path::Path::new("/dev/null"));
+        if let Err(err) = daemon::daemonize(log_file) {
+            eprintln!("Failed to run in the background: {}", err);
+            std::process::exit(1);
+        }
+    }
+    // Kept open (and locked) until the process exits.
+    let _pid_file = config.pid_file.as_deref().map(|path| match daemon::write_pid_file(path) {
+        Ok(file) => file,
+        Err(err) => {
+            eprintln!("Can't write the PID file {}: {}", path.display(), err);
+            std::process::exit(1);
+        }
+    });
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(err) => {
+            eprintln!("Failed to start the runtime: {}", err);
+            std::process::exit(1);
+        }
+    };
+    runtime.block_on(serve(config, matches));
+}
+
+/// Everything but the setup of the process: runs the command, or serves until the server stops.
+async fn serve(config: VideoPlayerConfig, matches: clap::ArgMatches) {
+    set_up_logging(config.log_file.as_deref());
+    #[cfg(feature = "sentry")]
+    let _sentry = reporting::init(&config);
+    // Loads another server, so it doesn't need a library of its own.
+    if let Some(Command::Bench(args)) = &config.command {
+        std::process::exit(bench::run(args).await);
+    }
+    if let Err(err) = config.validate() {
+        error!("{}", err);
+        std::process::exit(err.exit_code());
+    }
+    #[cfg(feature = "transcoding")]
+    if let Some(Command::Convert(args)) = &config.command {
+        std::process::exit(convert::run(&config, args).await);
+    }
+    if let Some(Command::Dedupe(args)) = &config.command {
+        std::process::exit(dedupe::run(&config, args).await);
+    }
+    if let Some(Command::Verify(args)) = &config.command {
+        std::process::exit(integrity::run(&config, args).await);
+    }
+    let oidc = match oidc::OidcState::build(&config).await {
+        Ok(oidc) => oidc.map(Arc::new),
+        Err(err) => {
+            error!("Failed to set up OpenID Connect login: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let build = version::BuildInfo::current();
+    info!("static-video-server {} (commit {}, built {})", build.version, build.commit, build.build_date);
+
+    let statsd = match statsd::init(&config) {
+        Ok(statsd) => statsd,
+        Err(err) => {
+            error!("Failed to set up StatsD: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let mqtt = match config.mqtt_url.as_deref().map(|url| mqtt::MqttPublisher::connect(url, &config.mqtt_topic)) {
+        Some(Ok(mqtt)) => Some(mqtt),
+        Some(Err(err)) => {
+            error!("Failed to set up MQTT: {}", err);
+            std::process::exit(1);
+        }
+        None => None,
+    };
+
+    let sessions = match session::Sessions::open(&config) {
+        Ok(sessions) => Arc::new(sessions),
+        Err(err) => {
+            error!("Failed to open the sessions in {}: {}", config.data_dir.display(), err);
+            std::process::exit(1);
+        }
+    };
+
+    let live = Arc::new(config_file::LiveSettings::new(&config));
+    let mut app = library_app(&config, mqtt.as_ref(), &live);
+    if !config.vhost.is_empty() {
+        let hosts = config
+            .vhost
+            .iter()
+            .map(|(host, root)| {
+                info!("Serving {} for host {}", root.display(), host);
+                let mut tenant = config.clone();
+                tenant.assets_root = root.to_string_lossy().to_string();
+                tenant.data_dir = config.data_dir.join(host);
+                (host.clone(), library_app(&tenant, mqtt.as_ref(), &live))
+            })
+            .collect();
+        app = vhost::dispatch(app, hosts);
+    }
+
+    if let Some(oidc) = oidc {
+        info!("Requiring OpenID Connect login for all pages.");
+        app = app
+            .merge(oidc::oidc_router(oidc.clone()))
+            .layer(middleware::from_fn_with_state(oidc, oidc::require_login));
+    }
+    app = app
+        .merge(session::session_router(sessions.clone(), live.api_token.clone()))
+        .layer(middleware::from_fn_with_state(live.api_token.clone(), csrf::verify_csrf))
+        .layer(middleware::from_fn_with_state(sessions, session::attach_session));
+
+    let base_path = config.base_path();
+    if !base_path.is_empty() {
+        info!("Serving under base path {}", base_path);
+        app = Router::new().nest(&base_path, app);
+    }
+    if !config.cors_origin.is_empty() {
+        info!("Allowing cross-origin requests from {}", config.cors_origin.join(", "));
+    }
+    // Around the whole router rather than each route, to see the `Allow` header it adds to `405` responses.
+    let cors = Arc::new(cors::CorsPolicy::new(&config.cors_origin));
+    app = Router::new()
+        .fallback_service(app)
+        .layer(middleware::from_fn_with_state(cors, cors::handle_cors));
+    // With a configuration file, addresses may be restricted later.
+    if config.config.is_some() || !live.ip_filter.read().unwrap().is_open() {
+        app = app.layer(middleware::from_fn_with_state(live.ip_filter.clone(), access::filter_clients));
+    }
+    let trusted_proxies = Arc::new(proxy::TrustedProxies::new(config.trusted_proxies.clone()));
+    let app = app
+        .layer(TraceLayer::new_for_http().make_span_with(|request: &Request<Body>| {
+            let client = proxy::ClientIp::resolve(request.extensions(), request.headers());
+            tracing::info_span!(
+                "request",
+                method = %request.method(),
+                uri = %request.uri(),
+                client = %client,
+            )
+        }));
+    let app = if statsd {
+        app.layer(middleware::from_fn(statsd::record_requests))
+    } else {
+        app
+    };
+    #[cfg(feature = "sentry")]
+    let app = app.layer(middleware::from_fn(reporting::report_errors));
+    let app = app
+        .layer(Extension(live.stream_limiter.clone()))
+        .layer(Extension(trusted_proxies));
+
+    #[cfg(feature = "http3")]
+    let app = if config.http3 {
+        if let (Some(cert), Some(key)) = (&config.tls_cert, &config.tls_key) {
+            let addr = config.socket_addr().unwrap();
+            let tls_config = match tls::server_config(&config, cert, key) {
+                Ok(tls_config) => tls_config,
+                Err(err) => {
+                    error!("Failed to set up TLS: {}", err);
+                    std::process::exit(1);
+                }
+            };
+            let quic_app = app.clone();
+            tokio::spawn(async move {
+                if let Err(err) = http3::serve(addr, tls_config, quic_app).await {
+                    error!("HTTP/3 listener failed: {}", err);
+                }
+            });
+        }
+        app.layer(SetResponseHeaderLayer::overriding(
+            axum::http::header::ALT_SVC,
+            axum::http::HeaderValue::from_str(&format!("h3=\":{}\"; ma=86400", config.port)).unwrap(),
+        ))
+    } else {
+        app
+    };
+
+    if let Some(path) = &config.config {
+        info!("Reloading {} when it changes or on SIGHUP", path.display());
+        tokio::spawn(config_file::watch(live, path.clone(), matches));
+    }
 
     let host_port = format!("{}:{}", config.host, config.port);
-    let addr = host_port.parse::<SocketAddr>().unwrap();
-    info!("Starting server on {}", host_port);
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
+    let addr = config.socket_addr().unwrap();
+    let service = app.into_make_service_with_connect_info::<SocketAddr>();
+    let http_config = timeouts::http_config(&config);
+    let idle_timeout = config.idle_timeout.map(Duration::from_secs);
+
+    match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => {
+            let tls_config = match tls::server_config(&config, cert, key) {
+                Ok(tls_config) => RustlsConfig::from_config(Arc::new(tls_config)),
+                Err(err) => {
+                    error!("Failed to set up TLS: {}", err);
+                    std::process::exit(1);
+                }
+            };
+            info!("Starting server on https://{}", host_port);
+            let served = axum_server::bind_rustls(addr, tls_config)
+                .http_config(http_config)
+                .map(|tls| timeouts::IdleTimeout::new(tls, idle_timeout))
+                .serve(service)
+                .await;
+            exit_if_failed(served, &host_port);
+        }
+        _ if !config.acme_domain.is_empty() => {
+            info!(
+                "Starting server on https://{} for {}",
+                host_port,
+                config.acme_domain.join(", ")
+            );
+            let acceptor = match tls::acme_acceptor(&config) {
+                Ok(acceptor) => acceptor,
+                Err(err) => {
+                    error!("Failed to set up TLS: {}", err);
+                    std::process::exit(1);
+                }
+            };
+            let served = axum_server::bind(addr)
+                .http_config(http_config)
+                .acceptor(timeouts::IdleTimeout::new(acceptor, idle_timeout))
+                .serve(service)
+                .await;
+            exit_if_failed(served, &host_port);
+        }
+        _ => {
+            info!("Starting server on {}", host_port);
+            let served = axum_server::bind(addr)
+                .http_config(http_config)
+                .map(|plain| timeouts::IdleTimeout::new(plain, idle_timeout))
+                .serve(service)
+                .await;
+            exit_if_failed(served, &host_port);
+        }
+    }
+}
+
+/// Stop with a readable message if the listener couldn't be set up, e.g. because the port is taken.
+fn exit_if_failed(served: std::io::Result<()>, host_port: &str) {
+    if let Err(err) = served {
+        match err.kind() {
+            std::io::ErrorKind::AddrInUse => {
+                error!("Can't listen on {}: the port is already in use. Pick another one with --port", host_port)
+            }
+            std::io::ErrorKind::PermissionDenied => error!(
+                "Can't listen on {}: permission denied. Ports below 1024 usually need elevated privileges",
+                host_port
+            ),
+            _ => error!("Can't listen on {}: {}", host_port, err),
+        }
+        std::process::exit(69);
+    }
 }
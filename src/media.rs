@@ -0,0 +1,262 @@
+//! Streaming of media files from disk.
+
+use axum::{
+    body::{boxed, BoxBody, Bytes, HttpBody},
+    http::{header, HeaderMap, Method, Request, Response, StatusCode},
+};
+use std::{
+    future::Future,
+    path::Path,
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+use tokio::time::{Instant, Sleep};
+use tower::ServiceExt;
+use tower_http::services::ServeFile;
+use tracing::error;
+
+use crate::VideoPlayerConfig;
+
+/// The default size of the chunks a media file is read and sent in.
+///
+/// `tower-http` defaults to 64 KiB, which costs a syscall and a poll of the body per 64 KiB
+/// when streaming multi-GB files. Larger chunks trade a little memory per stream for less CPU.
+pub const DEFAULT_STREAM_BUFFER_SIZE: usize = 256 * 1024;
+
+/// The MIME type a media file is served with.
+pub fn content_type(path: &Path) -> mime_guess::Mime {
+    let extension = path.extension().map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+    match extension.as_deref() {
+        // Guessed as `audio/m4a`, which browsers don't know.
+        Some("m4a") => "audio/mp4".parse().unwrap(),
+        _ => mime_guess::from_path(path).first_or_octet_stream(),
+    }
+}
+
+/// Serve a file in response to a request, honoring `Range`, conditional and `HEAD` requests.
+///
+/// Hyper has no way to hand a file descriptor to the kernel (`sendfile`), so the data is
+/// still copied through userspace; the chunk size is the knob that matters for throughput.
+pub async fn serve_file<B: Send + 'static>(
+    path: &Path,
+    request: Request<B>,
+    buffer_size: usize,
+) -> Result<Response<BoxBody>, (StatusCode, String)> {
+    match ServeFile::new_with_mime(path, &content_type(path))
+        .with_buf_chunk_size(buffer_size)
+        .oneshot(request)
+        .await
+    {
+        Ok(response) => Ok(response.map(boxed)),
+        Err(err) => {
+            error!("Failed to open file: \nError: {}", err);
+            Err((StatusCode::INTERNAL_SERVER_ERROR, "Failed to open file".to_string()))
+        }
+    }
+}
+
+/// Parse a rate such as `10MiB/s`, `500KB/s` or `40Mbit/s` into bytes per second.
+pub fn parse_rate(value: &str) -> Result<u64, String> {
+    let invalid = || format!("{} is not a rate like 10MiB/s or 40Mbit/s", value);
+    let rate = value.trim();
+    let rate = rate.strip_suffix("/s").unwrap_or(rate);
+    let split = rate
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(rate.len());
+    let (number, unit) = rate.split_at(split);
+    let number: f64 = number.parse().map_err(|_| invalid())?;
+    let multiplier = match unit.trim() {
+        "" | "B" => 1.0,
+        "KB" | "kB" => 1e3,
+        "KiB" => 1024.0,
+        "MB" => 1e6,
+        "MiB" => 1024.0 * 1024.0,
+        "GB" => 1e9,
+        "GiB" => 1024.0 * 1024.0 * 1024.0,
+        "kbit" | "Kbit" => 1e3 / 8.0,
+        "Mbit" => 1e6 / 8.0,
+        "Gbit" => 1e9 / 8.0,
+        _ => return Err(invalid()),
+    };
+    match (number * multiplier) as u64 {
+        0 => Err(format!("{} is too slow to stream anything", value)),
+        rate => Ok(rate),
+    }
+}
+
+/// The limits on every response streaming media.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StreamLimits {
+    /// In bytes per second.
+    pub rate: Option<u64>,
+    /// How long a response may take to send before it is cut off.
+    pub duration: Option<Duration>,
+}
+
+impl StreamLimits {
+    pub fn from_config(config: &VideoPlayerConfig) -> Self {
+        Self {
+            rate: config.max_stream_rate,
+            duration: config.max_stream_duration.map(Duration::from_secs),
+        }
+    }
+
+    pub fn apply(&self, response: Response<BoxBody>) -> Response<BoxBody> {
+        cut_off(throttle(response, self.rate), self.duration)
+    }
+}
+
+/// Limit how fast the body of a response is sent to `rate` bytes per second on average, if a rate is given.
+pub fn throttle(response: Response<BoxBody>, rate: Option<u64>) -> Response<BoxBody> {
+    let Some(rate) = rate else {
+        return response;
+    };
+    response.map(|body| {
+        boxed(Throttled {
+            inner: body,
+            rate,
+            started: Instant::now(),
+            sent: 0,
+            delay: None,
+        })
+    })
+}
+
+/// End the body of a response with an error once it took longer than `duration` to send, if a duration is given.
+/// The connection is closed, so clients can tell the response is incomplete.
+pub fn cut_off(response: Response<BoxBody>, duration: Option<Duration>) -> Response<BoxBody> {
+    let Some(duration) = duration else {
+        return response;
+    };
+    response.map(|body| {
+        boxed(CutOff {
+            inner: body,
+            deadline: Box::pin(tokio::time::sleep(duration)),
+        })
+    })
+}
+
+struct CutOff {
+    inner: BoxBody,
+    deadline: Pin<Box<Sleep>>,
+}
+
+impl HttpBody for CutOff {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Bytes, axum::Error>>> {
+        if self.deadline.as_mut().poll(cx).is_ready() {
+            let timed_out = std::io::Error::new(std::io::ErrorKind::TimedOut, "The stream took too long");
+            return Poll::Ready(Some(Err(axum::Error::new(timed_out))));
+        }
+        Pin::new(&mut self.inner).poll_data(cx)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, axum::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+}
+
+/// A body that pauses after every chunk until it is back on schedule for its rate.
+struct Throttled {
+    inner: BoxBody,
+    /// In bytes per second.
+    rate: u64,
+    /// When the schedule started, i.e. when nothing was sent yet.
+    started: Instant,
+    sent: u64,
+    delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl HttpBody for Throttled {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Bytes, axum::Error>>> {
+        if let Some(delay) = self.delay.as_mut() {
+            ready!(delay.as_mut().poll(cx));
+            self.delay = None;
+        }
+        let chunk = ready!(Pin::new(&mut self.inner).poll_data(cx));
+        if let Some(Ok(data)) = &chunk {
+            self.sent += data.len() as u64;
+            let now = Instant::now();
+            let elapsed = Duration::from_secs_f64(self.sent as f64 / self.rate as f64);
+            let due = self.started + elapsed;
+            if due > now {
+                self.delay = Some(Box::pin(tokio::time::sleep_until(due)));
+            } else {
+                // Behind schedule, e.g. because the client paused reading: don't make up for it in a burst.
+                self.started = now.checked_sub(elapsed).unwrap_or(now);
+            }
+        }
+        Poll::Ready(chunk)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, axum::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+}
+
+/// Whether a request fetches a file from its start, i.e. the first range a player requests
+/// when playback begins (as opposed to seeking or resuming a download).
+pub fn starts_playback(method: &Method, headers: &HeaderMap) -> bool {
+    if method != Method::GET {
+        return false;
+    }
+    match headers.get(header::RANGE).and_then(|range| range.to_str().ok()) {
+        None => true,
+        Some(range) => range
+            .trim()
+            .strip_prefix("bytes=")
+            .map(|ranges| ranges.trim_start().starts_with("0-"))
+            .unwrap_or(false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_rates() {
+        assert_eq!(parse_rate("10MiB/s"), Ok(10 * 1024 * 1024));
+        assert_eq!(parse_rate("500KB/s"), Ok(500_000));
+        assert_eq!(parse_rate("40Mbit/s"), Ok(5_000_000));
+        assert_eq!(parse_rate(" 1.5 MB "), Ok(1_500_000));
+        assert_eq!(parse_rate("2048"), Ok(2048));
+        assert_eq!(parse_rate("1GiB/s"), Ok(1 << 30));
+    }
+
+    #[test]
+    fn rejects_unknown_units() {
+        assert!(parse_rate("10 parsecs/s").is_err());
+        assert!(parse_rate("10mib/s").is_err());
+        assert!(parse_rate("fast").is_err());
+        assert!(parse_rate("").is_err());
+        assert!(parse_rate("-5MB/s").is_err());
+    }
+
+    #[test]
+    fn rejects_rates_too_slow_to_stream() {
+        assert!(parse_rate("0MB/s").is_err());
+        assert!(parse_rate("0.5B/s").is_err());
+        assert!(parse_rate("1kbit/s").is_ok());
+    }
+}
@@ -0,0 +1,79 @@
+//! Technical metadata of videos (codec, resolution, duration), extracted with `ffprobe`.
+
+use serde::{Deserialize, Serialize};
+use std::{path::Path, process::Command};
+use tracing::{debug, warn};
+
+/// What `ffprobe` tells us about a video.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct VideoMetadata {
+    /// The codec of the first video stream, e.g. `h264` or `hevc`.
+    pub codec: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// The duration in seconds.
+    pub duration: Option<f64>,
+}
+
+#[derive(Deserialize)]
+struct ProbeOutput {
+    #[serde(default)]
+    streams: Vec<ProbeStream>,
+    format: Option<ProbeFormat>,
+}
+
+#[derive(Deserialize)]
+struct ProbeStream {
+    codec_type: Option<String>,
+    codec_name: Option<String>,
+    width: Option<u32>,
+    height: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct ProbeFormat {
+    duration: Option<String>,
+}
+
+/// Run `ffprobe` on a file. Returns `None` if it isn't installed, and why if it can't read the file.
+pub fn probe(ffprobe: &str, path: &Path) -> Result<Option<VideoMetadata>, String> {
+    let output = Command::new(ffprobe)
+        .args(["-v", "error", "-print_format", "json", "-show_format", "-show_streams"])
+        .arg(path)
+        .output();
+    let output = match output {
+        Ok(output) if output.status.success() => output,
+        Ok(output) => {
+            debug!("ffprobe could not read {}", path.display());
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let prefix = format!("{}: ", path.display());
+            return Err(match stderr.lines().last() {
+                Some(reason) => format!("ffprobe could not read it: {}", reason.trim().trim_start_matches(&prefix)),
+                None => "ffprobe could not read it".to_string(),
+            });
+        }
+        Err(err) => {
+            warn!("Failed to run {}: {}", ffprobe, err);
+            return Ok(None);
+        }
+    };
+    Ok(parse_probe_output(&output.stdout))
+}
+
+/// Parse the JSON written by `ffprobe -print_format json -show_format -show_streams`.
+pub fn parse_probe_output(output: &[u8]) -> Option<VideoMetadata> {
+    let output: ProbeOutput = serde_json::from_slice(output).ok()?;
+    let video = output
+        .streams
+        .iter()
+        .find(|stream| stream.codec_type.as_deref() == Some("video"));
+    Some(VideoMetadata {
+        codec: video.and_then(|stream| stream.codec_name.clone()),
+        width: video.and_then(|stream| stream.width),
+        height: video.and_then(|stream| stream.height),
+        duration: output
+            .format
+            .and_then(|format| format.duration)
+            .and_then(|duration| duration.parse().ok()),
+    })
+}
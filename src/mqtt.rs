@@ -0,0 +1,83 @@
+//! Publishing library events to an MQTT broker, e.g. for Home Assistant.
+
+use rumqttc::{AsyncClient, MqttOptions, QoS};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{debug, warn};
+
+use crate::events::EventEnvelope;
+
+/// The client id used unless the broker url names one.
+const DEFAULT_CLIENT_ID: &str = "static-video-server";
+
+#[derive(Debug)]
+pub struct MqttError(String);
+
+impl std::fmt::Display for MqttError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for MqttError {}
+
+/// A connection to the broker that events are published through.
+#[derive(Clone)]
+pub struct MqttPublisher {
+    client: AsyncClient,
+    topic: String,
+}
+
+impl MqttPublisher {
+    /// Connect to a broker given as `mqtt://host:1883` or `mqtts://host:8883`,
+    /// optionally with `?client_id=...` and other options understood by rumqttc.
+    ///
+    /// Events are published to `<topic>/<event name>`. The connection is kept up (and re-established) in the background.
+    pub fn connect(url: &str, topic: &str) -> Result<Self, MqttError> {
+        let url = if url.contains("client_id=") {
+            url.to_string()
+        } else if url.contains('?') {
+            format!("{}&client_id={}", url, DEFAULT_CLIENT_ID)
+        } else {
+            format!("{}?client_id={}", url, DEFAULT_CLIENT_ID)
+        };
+        let mut options = MqttOptions::parse_url(url)
+            .map_err(|err| MqttError(format!("Invalid MQTT broker url: {}", err)))?;
+        options.set_keep_alive(Duration::from_secs(30));
+        let (client, mut event_loop) = AsyncClient::new(options, 64);
+        tokio::spawn(async move {
+            loop {
+                if let Err(err) = event_loop.poll().await {
+                    warn!("MQTT connection failed: {}. Retrying.", err);
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                }
+            }
+        });
+        Ok(Self {
+            client,
+            topic: topic.trim_end_matches('/').to_string(),
+        })
+    }
+
+    /// Publish every event received until the library goes away.
+    pub async fn forward(self, mut events: broadcast::Receiver<EventEnvelope>) {
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    warn!("MQTT publishing fell behind and missed {} events.", missed);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            };
+            let topic = format!("{}/{}", self.topic, event.event.name());
+            let Ok(payload) = serde_json::to_vec(&event) else {
+                continue;
+            };
+            match self.client.publish(&topic, QoS::AtLeastOnce, false, payload).await {
+                Ok(()) => debug!("Published to {}", topic),
+                Err(err) => warn!("Failed to publish to {}: {}", topic, err),
+            }
+        }
+    }
+}
@@ -0,0 +1,160 @@
+//! [oEmbed](https://oembed.com) for watch pages, so sites that support it embed the player when given a link.
+
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    private::{self, TokenQuery},
+    store::SharedIndexStore,
+    urls::PublicUrl,
+    users::Role,
+    MediaKind, SharedState,
+};
+
+/// The size of the player when the size of the video is unknown.
+const DEFAULT_SIZE: (u32, u32) = (640, 360);
+
+/// The size of the player of audio files.
+const AUDIO_SIZE: (u32, u32) = (640, 80);
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OEmbedQuery {
+    /// The url of a watch page.
+    pub url: String,
+    pub maxwidth: Option<u32>,
+    pub maxheight: Option<u32>,
+    /// Only `json` is supported.
+    pub format: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OEmbed {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub version: &'static str,
+    pub title: String,
+    pub provider_name: &'static str,
+    pub provider_url: String,
+    pub thumbnail_url: String,
+    pub thumbnail_width: u32,
+    pub thumbnail_height: u32,
+    pub html: String,
+    /// The image itself, for photos.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Scale a size down to fit within the maximums, keeping its aspect ratio.
+fn fit(size: (u32, u32), max_width: Option<u32>, max_height: Option<u32>) -> (u32, u32) {
+    let (width, height) = (f64::from(size.0), f64::from(size.1));
+    let scale = [
+        max_width.map(|max| f64::from(max) / width),
+        max_height.map(|max| f64::from(max) / height),
+    ]
+    .into_iter()
+    .flatten()
+    .fold(1.0, f64::min);
+    ((width * scale).round().max(1.0) as u32, (height * scale).round().max(1.0) as u32)
+}
+
+/// The id of the video a watch page url is for, whichever host it names.
+fn watched_id(url: &str, base_path: &str) -> Option<String> {
+    let uri: Uri = url.parse().ok()?;
+    let id = uri.path().strip_prefix(base_path)?.strip_prefix("/watch/")?;
+    (!id.is_empty() && !id.contains('/')).then(|| id.to_string())
+}
+
+/// The access token in the query of a watch page url, e.g. `?token=...`.
+fn url_token(url: &str) -> TokenQuery {
+    let uri: Option<Uri> = url.parse().ok();
+    let token = uri.as_ref().and_then(Uri::query).and_then(|query| {
+        query.split('&').find_map(|pair| {
+            let value = pair.strip_prefix("token=")?;
+            Some(percent_encoding::percent_decode_str(value).decode_utf8_lossy().to_string())
+        })
+    });
+    TokenQuery { token }
+}
+
+/// The oEmbed of a watch page: an iframe with the player of its video. Private videos are only described to admins
+/// and to watch page urls with one of their tokens, and hidden ones only to admins.
+pub async fn oembed(
+    State(state): State<SharedState>,
+    Extension(public_url): Extension<PublicUrl>,
+    Extension(store): Extension<SharedIndexStore>,
+    role: Role,
+    Query(query): Query<OEmbedQuery>,
+    headers: HeaderMap,
+) -> Response {
+    if query.format.as_deref().is_some_and(|format| format != "json") {
+        return (StatusCode::NOT_IMPLEMENTED, "Only the json format is supported").into_response();
+    }
+    let (video, base_path) = {
+        let state = state.lock().unwrap();
+        let video = watched_id(&query.url, &state.base_path).and_then(|id| state.get(&id).cloned());
+        let Some(video) = video else {
+            return (StatusCode::NOT_FOUND, "Not a watch page of this library").into_response();
+        };
+        (video, state.base_path.clone())
+    };
+    let token = url_token(&query.url);
+    let allowed = role == Role::Admin
+        || (!video.hidden
+            && (!video.private
+                || token.token.as_deref().is_some_and(|token| private::is_valid(&store, &video.key, token))));
+    if !allowed {
+        return (StatusCode::NOT_FOUND, "Not a watch page of this library").into_response();
+    }
+    // The player and the thumbnail of a private video need its token as well.
+    let token = if video.private { token.as_query() } else { String::new() };
+    let root = public_url.resolve(&headers, &base_path);
+    let size = video
+        .metadata
+        .as_ref()
+        .and_then(|metadata| Some((metadata.width?, metadata.height?)))
+        .filter(|(width, height)| *width > 0 && *height > 0)
+        .unwrap_or(if video.kind == MediaKind::Audio { AUDIO_SIZE } else { DEFAULT_SIZE });
+    let (width, height) = fit(size, query.maxwidth, query.maxheight);
+    let html = format!(
+        "<iframe src=\"{}/embed/{}{}\" width=\"{}\" height=\"{}\" frameborder=\"0\" allow=\"autoplay; fullscreen\" \
+         allowfullscreen></iframe>",
+        root,
+        video.id,
+        token.replace('&', "&amp;"),
+        width,
+        height
+    );
+    Json(OEmbed {
+        // Audio players are embedded as generic rich content.
+        kind: match video.kind {
+            MediaKind::Video => "video",
+            MediaKind::Audio => "rich",
+            MediaKind::Image => "photo",
+        },
+        version: "1.0",
+        title: video.title.clone(),
+        provider_name: "static-video-server",
+        provider_url: format!("{}/", root),
+        thumbnail_url: format!(
+            "{}/{}/{}{}",
+            root,
+            if video.kind == MediaKind::Image { "image" } else { "poster" },
+            video.id,
+            token
+        ),
+        thumbnail_width: width,
+        thumbnail_height: height,
+        html,
+        url: (video.kind == MediaKind::Image).then(|| format!("{}/image/{}{}", root, video.id, token)),
+        width,
+        height,
+    })
+    .into_response()
+}
@@ -0,0 +1,236 @@
+use axum::{
+    extract::{Query, State},
+    http::{Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Redirect, Response},
+    routing::get,
+    Extension, Router,
+};
+use axum_extra::extract::cookie::{Cookie, CookieJar};
+use openidconnect::{
+    core::{CoreAuthenticationFlow, CoreClient, CoreProviderMetadata},
+    reqwest::async_http_client,
+    AuthorizationCode, ClientId, ClientSecret, CsrfToken, IssuerUrl, Nonce, PkceCodeChallenge,
+    PkceCodeVerifier, RedirectUrl, Scope,
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{auth::secrets_match, session::CurrentSession, VideoPlayerConfig};
+
+/// Routes that stay reachable without a session. Signed share links check their signature instead.
+const PUBLIC_PATHS: [&str; 5] = ["/auth/", "/healthcheck", "/favicon.ico", "/robots.txt", "/shared/"];
+
+/// How long a login may take at the provider before its callback is refused.
+const PENDING_LOGIN_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// The cookie tying a pending login to the browser that started it, so nobody can finish their own login in the
+/// browser of somebody else (login CSRF).
+const LOGIN_STATE_COOKIE: &str = "svs_login_state";
+
+/// How many logins may be pending at once. The oldest are dropped to make room for new ones.
+const MAX_PENDING_LOGINS: usize = 10_000;
+
+/// A user that completed the login flow.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserSession {
+    pub subject: String,
+    pub name: Option<String>,
+    pub email: Option<String>,
+    /// Whether the provider verified the email, the only way it can be trusted to be that of the user.
+    #[serde(default)]
+    pub email_verified: bool,
+}
+
+/// A login that was started but whose callback hasn't arrived yet.
+struct PendingLogin {
+    pkce_verifier: PkceCodeVerifier,
+    nonce: Nonce,
+    started: Instant,
+}
+
+/// The state of the OpenID Connect login flow, shared between all requests.
+pub struct OidcState {
+    client: CoreClient,
+    pending: Mutex<HashMap<String, PendingLogin>>,
+    base_path: String,
+    /// Whether the login state cookie is only sent over HTTPS.
+    secure: bool,
+}
+
+pub type SharedOidcState = Arc<OidcState>;
+
+#[derive(Debug)]
+pub struct OidcError(String);
+
+impl std::fmt::Display for OidcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for OidcError {}
+
+impl OidcState {
+    /// Discover the provider and build the login state from a config.
+    /// Returns `None` if no issuer is configured.
+    pub async fn build(config: &VideoPlayerConfig) -> Result<Option<Self>, OidcError> {
+        let Some(issuer) = config.oidc_issuer.clone() else {
+            return Ok(None);
+        };
+        let issuer = IssuerUrl::new(issuer).map_err(|err| OidcError(err.to_string()))?;
+        let metadata = CoreProviderMetadata::discover_async(issuer, async_http_client)
+            .await
+            .map_err(|err| OidcError(format!("Failed to discover provider: {}", err)))?;
+        let redirect_url = RedirectUrl::new(config.oidc_redirect_url.clone().unwrap_or_default())
+            .map_err(|err| OidcError(err.to_string()))?;
+
+        let client = CoreClient::from_provider_metadata(
+            metadata,
+            ClientId::new(config.oidc_client_id.clone().unwrap_or_default()),
+            config.oidc_client_secret.clone().map(ClientSecret::new),
+        )
+        .set_redirect_uri(redirect_url);
+
+        Ok(Some(Self {
+            client,
+            pending: Default::default(),
+            base_path: config.base_path(),
+            secure: config.is_https(),
+        }))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CallbackParams {
+    code: String,
+    state: String,
+}
+
+impl OidcState {
+    /// The cookie holding the state of a login for as long as it may take.
+    fn state_cookie(&self, state: &str) -> Cookie<'static> {
+        let secure = if self.secure { "; Secure" } else { "" };
+        let cookie = format!(
+            "{}={}; Path=/; HttpOnly; SameSite=Lax; Max-Age={}{}",
+            LOGIN_STATE_COOKIE,
+            state,
+            PENDING_LOGIN_TIMEOUT.as_secs(),
+            secure
+        );
+        Cookie::parse(cookie).expect("the login state cookie is valid")
+    }
+}
+
+/// Start the authorization code flow by redirecting to the provider.
+pub async fn login(State(oidc): State<SharedOidcState>, jar: CookieJar) -> impl IntoResponse {
+    let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+    let (auth_url, csrf_token, nonce) = oidc
+        .client
+        .authorize_url(
+            CoreAuthenticationFlow::AuthorizationCode,
+            CsrfToken::new_random,
+            Nonce::new_random,
+        )
+        .add_scope(Scope::new("email".into()))
+        .add_scope(Scope::new("profile".into()))
+        .set_pkce_challenge(pkce_challenge)
+        .url();
+
+    {
+        let mut pending = oidc.pending.lock().unwrap();
+        pending.retain(|_, login| login.started.elapsed() < PENDING_LOGIN_TIMEOUT);
+        if pending.len() >= MAX_PENDING_LOGINS {
+            let oldest = pending.iter().min_by_key(|(_, login)| login.started).map(|(state, _)| state.clone());
+            if let Some(oldest) = oldest {
+                pending.remove(&oldest);
+            }
+        }
+        pending.insert(
+            csrf_token.secret().clone(),
+            PendingLogin {
+                pkce_verifier,
+                nonce,
+                started: Instant::now(),
+            },
+        );
+    }
+    (jar.add(oidc.state_cookie(csrf_token.secret())), Redirect::to(auth_url.as_str()))
+}
+
+/// Finish the authorization code flow and start a session.
+pub async fn callback(
+    State(oidc): State<SharedOidcState>,
+    Extension(session): Extension<CurrentSession>,
+    jar: CookieJar,
+    Query(params): Query<CallbackParams>,
+) -> Result<(CookieJar, Redirect), (StatusCode, String)> {
+    let started_here = jar
+        .get(LOGIN_STATE_COOKIE)
+        .is_some_and(|cookie| secrets_match(cookie.value().as_bytes(), params.state.as_bytes()));
+    if !started_here {
+        return Err((StatusCode::BAD_REQUEST, "The login was started in another browser".to_string()));
+    }
+    let mut removal = Cookie::named(LOGIN_STATE_COOKIE);
+    removal.set_path("/");
+    let jar = jar.remove(removal);
+    let pending = oidc
+        .pending
+        .lock()
+        .unwrap()
+        .remove(&params.state)
+        .filter(|login| login.started.elapsed() < PENDING_LOGIN_TIMEOUT)
+        .ok_or((StatusCode::BAD_REQUEST, "Unknown or expired login state".to_string()))?;
+
+    let token_response = oidc
+        .client
+        .exchange_code(AuthorizationCode::new(params.code))
+        .set_pkce_verifier(pending.pkce_verifier)
+        .request_async(async_http_client)
+        .await
+        .map_err(|err| {
+            warn!("Failed to exchange authorization code: {}", err);
+            (StatusCode::BAD_GATEWAY, "Failed to exchange authorization code".to_string())
+        })?;
+
+    let id_token = openidconnect::TokenResponse::id_token(&token_response)
+        .ok_or((StatusCode::BAD_GATEWAY, "Provider did not return an id token".to_string()))?;
+    let claims = id_token
+        .claims(&oidc.client.id_token_verifier(), &pending.nonce)
+        .map_err(|err| (StatusCode::UNAUTHORIZED, format!("Invalid id token: {}", err)))?;
+
+    let user = UserSession {
+        subject: claims.subject().to_string(),
+        name: claims.preferred_username().map(|name| name.to_string()),
+        email: claims.email().map(|email| email.to_string()),
+        email_verified: claims.email_verified() == Some(true),
+    };
+    info!("User {} logged in.", user.subject);
+    let (jar, _) = session.log_in(jar, Some(user), None);
+    Ok((jar, Redirect::to(&format!("{}/", oidc.base_path))))
+}
+
+/// Redirect every request without a valid session to the login flow.
+/// The [`UserSession`] of logged in users is attached to the request by [`crate::session::attach_session`].
+pub async fn require_login<B>(State(oidc): State<SharedOidcState>, request: Request<B>, next: Next<B>) -> Response {
+    if request.extensions().get::<UserSession>().is_some() {
+        return next.run(request).await;
+    }
+    let path = request.uri().path();
+    if PUBLIC_PATHS.iter().any(|public| path.starts_with(public)) {
+        return next.run(request).await;
+    }
+    Redirect::to(&format!("{}/auth/login", oidc.base_path)).into_response()
+}
+
+pub fn oidc_router(oidc: SharedOidcState) -> Router {
+    Router::new()
+        .route("/auth/login", get(login))
+        .route("/auth/callback", get(callback))
+        .with_state(oidc)
+}
@@ -0,0 +1,243 @@
+//! Private videos: left out of listings and only served with one of their access tokens, given as `?token=`, or to
+//! admins. This holds even when nothing else requires a login, so a video can be shared with a few people only.
+//!
+//! Tokens are minted per video and kept in the store, hashed, so they can be revoked one by one.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{Request, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{delete, get, put},
+    Extension, Json, Router,
+};
+use openidconnect::CsrfToken;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+use crate::{
+    auth::Editor,
+    store::{self, IndexStore, SharedIndexStore, VideoToken},
+    users::Role,
+    SharedState,
+};
+
+/// The query parameter, and the field, holding an access token.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TokenQuery {
+    pub token: Option<String>,
+}
+
+impl TokenQuery {
+    /// The token as a query string to append to the urls of a video, e.g. `?token=...`, or empty.
+    pub fn as_query(&self) -> String {
+        match self.token.as_deref().filter(|token| !token.is_empty()) {
+            Some(token) => format!("?token={}", crate::urls::encode_query_value(token)),
+            None => String::new(),
+        }
+    }
+}
+
+fn hash(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Mint an access token to a video. The token itself is only known to the caller.
+pub fn mint(store: &IndexStore, key: &str, label: Option<String>, expires_in: Option<u64>) -> (String, VideoToken) {
+    let token = CsrfToken::new_random().secret().clone();
+    let sha256 = hash(&token);
+    let video_token = VideoToken {
+        id: sha256[..12].to_string(),
+        sha256,
+        label,
+        created: store::now(),
+        expires: expires_in.map(|expires_in| store::now() + expires_in),
+    };
+    store.add_video_token(key, video_token.clone());
+    (token, video_token)
+}
+
+/// Whether a token is one of the tokens of a video that haven't expired.
+pub fn is_valid(store: &IndexStore, key: &str, token: &str) -> bool {
+    let sha256 = hash(token);
+    let now = store::now();
+    store
+        .video_tokens(key)
+        .iter()
+        .any(|video_token| video_token.sha256 == sha256 && video_token.expires.is_none_or(|expires| expires > now))
+}
+
+/// Reject requests for private videos that carry none of their tokens and don't come from an admin.
+/// Applies to routes with a `:video_id`; others are let through.
+async fn require_token<B>(
+    State(state): State<SharedState>,
+    Extension(store): Extension<SharedIndexStore>,
+    params: Option<Path<HashMap<String, String>>>,
+    Query(query): Query<TokenQuery>,
+    role: Role,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let video_id = params.and_then(|Path(mut params)| params.remove("video_id"));
+    let private_key = video_id.and_then(|video_id| {
+        let state = state.lock().unwrap();
+        state.get(&video_id).filter(|video| video.private).map(|video| video.key.clone())
+    });
+    let Some(key) = private_key else {
+        return next.run(request).await;
+    };
+    let authorized = role == Role::Admin || query.token.is_some_and(|token| is_valid(&store, &key, &token));
+    if !authorized {
+        return (StatusCode::FORBIDDEN, "This video is private. Open it with a link that has its token.").into_response();
+    }
+    next.run(request).await
+}
+
+/// Guard the routes of a router that serve videos, so private ones need a token.
+pub fn guarded(router: Router<SharedState>, state: &SharedState) -> Router<SharedState> {
+    router.route_layer(middleware::from_fn_with_state(state.clone(), require_token))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PrivacyUpdate {
+    pub private: bool,
+}
+
+/// Make a video private, or public again. Its tokens are kept either way.
+pub async fn set_private(
+    Path(video_id): Path<String>,
+    _editor: Editor,
+    State(state): State<SharedState>,
+    Extension(store): Extension<SharedIndexStore>,
+    Json(update): Json<PrivacyUpdate>,
+) -> Response {
+    let mut state = state.lock().unwrap();
+    let Some(key) = state.get(&video_id).map(|video| video.key.clone()) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    store.set_private(&key, update.private);
+    state.update_video(&video_id, |video| video.private = update.private);
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// The access tokens of a video, without the tokens themselves.
+pub async fn list_tokens(
+    Path(video_id): Path<String>,
+    _editor: Editor,
+    State(state): State<SharedState>,
+    Extension(store): Extension<SharedIndexStore>,
+) -> Response {
+    let Some(key) = state.lock().unwrap().get(&video_id).map(|video| video.key.clone()) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    Json(store.video_tokens(&key)).into_response()
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TokenRequest {
+    /// What the token is for, e.g. who it is given to.
+    pub label: Option<String>,
+    /// How many seconds the token works for. Forever by default.
+    pub expires_in: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MintedToken {
+    /// The token, which can't be looked up again.
+    pub token: String,
+    #[serde(flatten)]
+    pub info: VideoToken,
+}
+
+/// Mint an access token to a video.
+pub async fn mint_token(
+    Path(video_id): Path<String>,
+    _editor: Editor,
+    State(state): State<SharedState>,
+    Extension(store): Extension<SharedIndexStore>,
+    request: Option<Json<TokenRequest>>,
+) -> Response {
+    let Some(key) = state.lock().unwrap().get(&video_id).map(|video| video.key.clone()) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let Json(request) = request.unwrap_or_default();
+    let (token, info) = mint(&store, &key, request.label, request.expires_in);
+    (StatusCode::CREATED, Json(MintedToken { token, info })).into_response()
+}
+
+/// Revoke an access token of a video.
+pub async fn revoke_token(
+    Path((video_id, token_id)): Path<(String, String)>,
+    _editor: Editor,
+    State(state): State<SharedState>,
+    Extension(store): Extension<SharedIndexStore>,
+) -> Response {
+    let Some(key) = state.lock().unwrap().get(&video_id).map(|video| video.key.clone()) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if store.revoke_video_token(&key, &token_id) {
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        StatusCode::NOT_FOUND.into_response()
+    }
+}
+
+pub fn private_router() -> Router<SharedState> {
+    Router::new()
+        .route("/api/videos/:video_id/private", put(set_private))
+        .route("/api/videos/:video_id/tokens", get(list_tokens).post(mint_token))
+        .route("/api/videos/:video_id/tokens/:token_id", delete(revoke_token))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_minted_tokens() {
+        let store = IndexStore::in_memory();
+        let (token, info) = mint(&store, "family/birthday.mp4", Some("grandma".to_string()), None);
+        assert!(is_valid(&store, "family/birthday.mp4", &token));
+        assert_eq!(info.sha256, hash(&token));
+        assert_eq!(store.video_tokens("family/birthday.mp4")[0].label.as_deref(), Some("grandma"));
+    }
+
+    #[test]
+    fn rejects_wrong_tokens() {
+        let store = IndexStore::in_memory();
+        let (token, _) = mint(&store, "family/birthday.mp4", None, None);
+        assert!(!is_valid(&store, "family/birthday.mp4", "guessed"));
+        assert!(!is_valid(&store, "family/birthday.mp4", ""));
+        assert!(!is_valid(&store, "family/birthday.mp4", &hash(&token)));
+    }
+
+    #[test]
+    fn rejects_tokens_of_other_videos() {
+        let store = IndexStore::in_memory();
+        let (token, _) = mint(&store, "family/birthday.mp4", None, None);
+        assert!(!is_valid(&store, "family/wedding.mp4", &token));
+    }
+
+    #[test]
+    fn rejects_expired_and_revoked_tokens() {
+        let store = IndexStore::in_memory();
+        let (expired, mut info) = mint(&store, "family/birthday.mp4", None, Some(60));
+        assert!(is_valid(&store, "family/birthday.mp4", &expired));
+        info.expires = Some(store::now() - 1);
+        store.revoke_video_token("family/birthday.mp4", &info.id);
+        store.add_video_token("family/birthday.mp4", info);
+        assert!(!is_valid(&store, "family/birthday.mp4", &expired));
+
+        let (revoked, info) = mint(&store, "family/birthday.mp4", None, None);
+        assert!(store.revoke_video_token("family/birthday.mp4", &info.id));
+        assert!(!is_valid(&store, "family/birthday.mp4", &revoked));
+    }
+
+    #[test]
+    fn appends_tokens_to_urls() {
+        assert_eq!(TokenQuery { token: None }.as_query(), "");
+        assert_eq!(TokenQuery { token: Some(String::new()) }.as_query(), "");
+        assert_eq!(TokenQuery { token: Some("a+b".to_string()) }.as_query(), "?token=a%2Bb");
+    }
+}
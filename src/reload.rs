@@ -0,0 +1,154 @@
+//! Rescanning the library without blocking requests, and without piling up rescans.
+
+use futures_util::{
+    future::{BoxFuture, Shared},
+    FutureExt,
+};
+use std::{
+    collections::BTreeSet,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tracing::{info, warn};
+
+use crate::{cache::SharedFileCache, cron::Schedule, fill_index, library, scan, store, RescanSummary, SharedState};
+
+/// How long to wait for more reload requests before starting a rescan, so a burst of them is handled by one rescan.
+pub const RELOAD_DEBOUNCE: Duration = Duration::from_millis(250);
+
+type Rescan = Shared<BoxFuture<'static, u64>>;
+
+/// Coalesces reload requests: at most one rescan runs at a time, and requests that arrive
+/// while one is pending or running wait for it instead of starting another.
+#[derive(Default)]
+pub struct Reloader {
+    pending: Mutex<Option<Rescan>>,
+}
+
+pub type SharedReloader = Arc<Reloader>;
+
+impl Reloader {
+    /// Rescan the library (or join the rescan in progress).
+    /// Returns the generation of the index once the rescan is done.
+    pub async fn reload(self: &Arc<Self>, state: SharedState) -> u64 {
+        let rescan = {
+            let mut pending = self.pending.lock().unwrap();
+            match pending.as_ref() {
+                Some(rescan) => rescan.clone(),
+                None => {
+                    let rescan = self.clone().rescan(state).boxed().shared();
+                    *pending = Some(rescan.clone());
+                    rescan
+                }
+            }
+        };
+        rescan.await
+    }
+
+    async fn rescan(self: Arc<Self>, state: SharedState) -> u64 {
+        tokio::time::sleep(RELOAD_DEBOUNCE).await;
+        let scanning = state.clone();
+        let generation = tokio::task::spawn_blocking(move || {
+            // Scan into a fresh index so requests are still served from the old one meanwhile.
+            let mut fresh = scanning.lock().unwrap().empty_like();
+            let scanned = fresh.scan();
+            let mut state = scanning.lock().unwrap();
+            match scanned {
+                Ok(()) => state.replace_index(fresh),
+                Err(err) => warn!("Rescanning failed, keeping the previous index: {}", err),
+            }
+            state.generation()
+        })
+        .await
+        .unwrap_or_default();
+        self.pending.lock().unwrap().take();
+        info!("Rescanned the library.");
+
+        if state.lock().unwrap().is_lazy() {
+            tokio::task::spawn_blocking(move || fill_index(&state));
+        }
+        generation
+    }
+
+    /// Rescan only a folder of the library, merging what changed under it into the index.
+    /// A folder that no longer exists has everything that was under it removed.
+    pub async fn rescan_dir(&self, state: SharedState, dir: PathBuf) -> RescanSummary {
+        let summary = tokio::task::spawn_blocking(move || {
+            let (mut fresh, scan_progress, folder) = {
+                let state = state.lock().unwrap();
+                (state.empty_like(), state.scan_progress().clone(), state.relative_key(&dir))
+            };
+            let _scan = scan_progress.start(Some(folder));
+            if dir.is_dir() {
+                if let Err(err) = fresh.visit_dirs(&dir) {
+                    warn!("Rescanning {} failed: {}", dir.display(), err);
+                }
+            }
+            state.lock().unwrap().merge_dir(&dir, fresh)
+        })
+        .await
+        .unwrap_or_default();
+        info!(
+            "Rescanned a folder: {} added, {} removed, {} updated.",
+            summary.added, summary.removed, summary.updated
+        );
+        summary
+    }
+}
+
+/// Rescan the library, or only some folders of it, whenever the schedule says so, for as long as the server runs.
+pub async fn rescan_on_schedule(
+    schedule: Schedule,
+    folders: Vec<String>,
+    reloader: SharedReloader,
+    state: SharedState,
+    cache: SharedFileCache,
+) {
+    loop {
+        let now = store::now();
+        let Some(next) = schedule.next_after(now) else {
+            warn!("The rescan schedule never comes around, so the library isn't rescanned on a schedule.");
+            return;
+        };
+        tokio::time::sleep(Duration::from_secs(next - now)).await;
+        info!("Rescanning on schedule.");
+        if folders.is_empty() {
+            reloader.reload(state.clone()).await;
+        }
+        for folder in &folders {
+            let dir = library::resolve_rescan_dir(&state.lock().unwrap(), folder);
+            match dir {
+                Ok(dir) => {
+                    reloader.rescan_dir(state.clone(), dir).await;
+                }
+                Err((_, err)) => warn!("Not rescanning {}: {}", folder, err),
+            }
+        }
+        cache.clear();
+    }
+}
+
+/// How often to check whether the files left out of the index while being written have settled.
+const SETTLE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Rescan the folders of the files left out of the index while they were still being written, once they settle,
+/// for as long as the server runs.
+pub async fn rescan_settled(settle: Duration, reloader: SharedReloader, state: SharedState, cache: SharedFileCache) {
+    loop {
+        tokio::time::sleep(SETTLE_CHECK_INTERVAL).await;
+        let unsettled = state.lock().unwrap().unsettled();
+        let dirs: BTreeSet<PathBuf> = unsettled
+            .iter()
+            .filter(|path| scan::is_settled(path, settle))
+            .filter_map(|path| path.parent().map(PathBuf::from))
+            .collect();
+        for dir in &dirs {
+            info!("Rescanning {} as files in it settled.", dir.display());
+            reloader.rescan_dir(state.clone(), dir.clone()).await;
+        }
+        if !dirs.is_empty() {
+            cache.clear();
+        }
+    }
+}
@@ -0,0 +1,69 @@
+//! Reporting panics and server errors to Sentry, with the `sentry` feature.
+//!
+//! Panics and `error!` logs, which include every response with a 5xx status, are reported along with the request
+//! they happened in.
+
+use axum::{
+    http::{header, Request},
+    middleware::Next,
+    response::Response,
+};
+use sentry::{protocol, Hub, SentryFutureExt};
+use std::sync::Arc;
+
+use crate::{proxy::ClientIp, VideoPlayerConfig};
+
+/// Headers that are never sent along, since they carry credentials.
+const SECRET_HEADERS: [header::HeaderName; 3] = [header::AUTHORIZATION, header::COOKIE, header::PROXY_AUTHORIZATION];
+
+/// Start reporting to the configured DSN, if any. Reporting stops once the guard is dropped.
+/// The environment and release can be overridden with `SENTRY_ENVIRONMENT` and `SENTRY_RELEASE`.
+pub fn init(config: &VideoPlayerConfig) -> Option<sentry::ClientInitGuard> {
+    let dsn = config.sentry_dsn.as_deref()?;
+    let guard = sentry::init((
+        dsn,
+        sentry::ClientOptions {
+            release: sentry::release_name!(),
+            ..Default::default()
+        },
+    ));
+    guard.is_enabled().then_some(guard)
+}
+
+/// What Sentry is told about a request.
+fn describe<B>(request: &Request<B>) -> protocol::Request {
+    let headers = request
+        .headers()
+        .iter()
+        .filter(|(name, _)| !SECRET_HEADERS.contains(name))
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect();
+    let host = request
+        .headers()
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .unwrap_or("localhost");
+    let client = ClientIp::resolve(request.extensions(), request.headers());
+    protocol::Request {
+        url: format!("http://{}{}", host, request.uri().path()).parse().ok(),
+        method: Some(request.method().to_string()),
+        query_string: request.uri().query().map(String::from),
+        headers,
+        env: [("REMOTE_ADDR".to_string(), client.to_string())].into(),
+        ..Default::default()
+    }
+}
+
+/// Run every request with its own hub that knows about the request, so anything reported while handling it (panics,
+/// errors logged) carries it along.
+pub async fn report_errors<B>(request: Request<B>, next: Next<B>) -> Response {
+    let hub = Arc::new(Hub::new_from_top(Hub::current()));
+    let described = describe(&request);
+    hub.configure_scope(|scope| {
+        scope.add_event_processor(move |mut event| {
+            event.request.get_or_insert_with(|| described.clone());
+            Some(event)
+        })
+    });
+    next.run(request).bind_hub(hub).await
+}
@@ -0,0 +1,43 @@
+//! `/robots.txt`, telling crawlers what they may index: nothing by default, or the paths given with `--robots-allow`.
+
+use axum::{
+    http::{header, HeaderMap},
+    response::IntoResponse,
+    Extension,
+};
+use std::{fmt::Write, sync::Arc};
+
+use crate::urls::PublicUrl;
+
+#[derive(Debug, Clone, Default)]
+pub struct RobotsPolicy {
+    /// The paths crawlers may index, relative to the base path.
+    pub allow: Vec<String>,
+    pub base_path: String,
+}
+
+pub type SharedRobotsPolicy = Arc<RobotsPolicy>;
+
+impl RobotsPolicy {
+    /// The `robots.txt`, pointing to the sitemap at `root` if anything may be indexed.
+    pub fn render(&self, root: &str) -> String {
+        let mut robots = String::from("User-agent: *\n");
+        for path in &self.allow {
+            let _ = writeln!(robots, "Allow: {}/{}", self.base_path, path.trim_start_matches('/'));
+        }
+        let _ = writeln!(robots, "Disallow: {}/", self.base_path);
+        if !self.allow.is_empty() {
+            let _ = writeln!(robots, "\nSitemap: {}/sitemap.xml", root);
+        }
+        robots
+    }
+}
+
+pub async fn robots_txt(
+    Extension(policy): Extension<SharedRobotsPolicy>,
+    Extension(public_url): Extension<PublicUrl>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let root = public_url.resolve(&headers, &policy.base_path);
+    ([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], policy.render(&root))
+}
@@ -0,0 +1,138 @@
+//! How far the scans of the library got, as shown at `/api/scan/status`, the files they couldn't index, as listed at
+//! `/api/quarantine`, and the files they leave out while they are still being written.
+
+use serde::Serialize;
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::store;
+
+/// What the running scan went through so far, or what the last one went through.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ScanStatus {
+    pub running: bool,
+    /// The folder being rescanned, relative to the root, or `None` for the whole library.
+    pub folder: Option<String>,
+    /// The directories listed so far.
+    pub dirs_processed: u64,
+    /// The videos indexed so far.
+    pub files_indexed: u64,
+    /// When the scan started, in seconds since the Unix epoch, or `None` if there was none yet.
+    pub started: Option<u64>,
+    /// How long the scan has been running, or how long it took.
+    pub elapsed_secs: f64,
+}
+
+/// A file, or a directory, the scans couldn't read, kept out of the index.
+#[derive(Debug, Clone, Serialize)]
+pub struct QuarantinedFile {
+    /// Its path relative to the root.
+    pub path: String,
+    pub reason: String,
+    /// When the scan came across it, in seconds since the Unix epoch.
+    pub found: u64,
+}
+
+#[derive(Debug, Default)]
+struct Progress {
+    status: ScanStatus,
+    /// The scans running at once, which are counted as one.
+    running: usize,
+    started: Option<Instant>,
+}
+
+/// Tracks the scans of the library. Shared between the index and the fresh indexes rescans scan into.
+#[derive(Debug, Default)]
+pub struct ScanProgress {
+    progress: Mutex<Progress>,
+}
+
+pub type SharedScanProgress = Arc<ScanProgress>;
+
+impl ScanProgress {
+    /// Track a scan of a folder, or of the whole library, until the returned guard is dropped.
+    /// Scans starting while another is running are counted as part of it.
+    pub fn start(self: &Arc<Self>, folder: Option<String>) -> ScanGuard {
+        let mut progress = self.progress.lock().unwrap();
+        if progress.running == 0 {
+            progress.status = ScanStatus {
+                running: true,
+                folder,
+                started: Some(store::now()),
+                ..Default::default()
+            };
+            progress.started = Some(Instant::now());
+        } else if folder.is_none() {
+            progress.status.folder = None;
+        }
+        progress.running += 1;
+        ScanGuard {
+            progress: self.clone(),
+        }
+    }
+
+    /// Count a directory listed, and the videos indexed from it, towards the running scan, if any.
+    pub fn dir_processed(&self, files: usize) {
+        let mut progress = self.progress.lock().unwrap();
+        if progress.running > 0 {
+            progress.status.dirs_processed += 1;
+            progress.status.files_indexed += files as u64;
+        }
+    }
+
+    pub fn status(&self) -> ScanStatus {
+        let progress = self.progress.lock().unwrap();
+        let mut status = progress.status.clone();
+        if progress.running > 0 {
+            status.elapsed_secs = progress.started.map(|started| started.elapsed().as_secs_f64()).unwrap_or_default();
+        }
+        status
+    }
+}
+
+/// Ends tracking a scan once dropped.
+pub struct ScanGuard {
+    progress: SharedScanProgress,
+}
+
+impl Drop for ScanGuard {
+    fn drop(&mut self) {
+        let mut progress = self.progress.progress.lock().unwrap();
+        progress.running -= 1;
+        if progress.running == 0 {
+            progress.status.running = false;
+            progress.status.elapsed_secs =
+                progress.started.map(|started| started.elapsed().as_secs_f64()).unwrap_or_default();
+        }
+    }
+}
+
+/// Extensions download clients and browsers give files while downloading them, e.g. `movie.mp4.part`, or give files
+/// they keep next to them meanwhile, e.g. `movie.mp4.aria2`. Lowercased.
+const PARTIAL_EXTENSIONS: &[&str] = &["part", "partial", "!qb", "crdownload", "download", "aria2", "tmp"];
+
+/// The file a file is a part of while it is being downloaded, e.g. `movie.mp4` for `movie.mp4.part`.
+pub fn download_target(path: &Path) -> Option<PathBuf> {
+    let extension = path.extension()?.to_string_lossy().to_lowercase();
+    PARTIAL_EXTENSIONS
+        .contains(&extension.as_str())
+        .then(|| path.with_extension(""))
+}
+
+/// Whether a file is done being written: it isn't a part of a download, isn't empty and wasn't modified for a while.
+/// Files that are gone are settled too, as there is nothing to wait for.
+pub fn is_settled(path: &Path, settle: Duration) -> bool {
+    if download_target(path).is_some() {
+        return !path.exists();
+    }
+    match std::fs::metadata(path) {
+        Ok(metadata) => {
+            let age = metadata.modified().ok().and_then(|modified| modified.elapsed().ok());
+            metadata.len() > 0 && age.is_none_or(|age| age >= settle)
+        }
+        Err(_) => true,
+    }
+}
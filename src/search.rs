@@ -0,0 +1,117 @@
+//! Fuzzy search over titles and paths, tolerant of typos like `vaction` for `vacation-2022.mp4`.
+//!
+//! Candidates are scored by how many of the query's trigrams (three letter sequences, with word
+//! boundaries padded) they contain, and plain substring matches always rank first.
+//! With the `full-text` feature, a tantivy index can be used instead (see [`crate::fulltext`]).
+
+use std::{collections::HashSet, sync::Arc};
+
+use crate::VideoEntry;
+
+/// The least fraction of the query's trigrams a candidate must contain to match.
+pub const MIN_SCORE: f64 = 0.5;
+
+/// The words of a text, lowercased: runs of letters and digits.
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+}
+
+/// The trigrams of every word, padded so the start and end of a word count too.
+fn trigrams(text: &str) -> HashSet<[char; 3]> {
+    let mut trigrams = HashSet::new();
+    for word in words(text) {
+        let padded: Vec<char> = "  ".chars().chain(word.chars()).chain(" ".chars()).collect();
+        for window in padded.windows(3) {
+            trigrams.insert([window[0], window[1], window[2]]);
+        }
+    }
+    trigrams
+}
+
+/// A search query, prepared to be matched against many candidates.
+#[derive(Debug, Clone)]
+pub struct Query {
+    /// The words of the query joined by spaces, for substring matches.
+    normalized: String,
+    trigrams: HashSet<[char; 3]>,
+}
+
+impl Query {
+    /// Prepare a query, or `None` if it has nothing to search for.
+    pub fn new(query: &str) -> Option<Self> {
+        let normalized = words(query).collect::<Vec<_>>().join(" ");
+        if normalized.is_empty() {
+            return None;
+        }
+        Some(Self {
+            trigrams: trigrams(&normalized),
+            normalized,
+        })
+    }
+
+    /// How well a candidate matches, from 0 to 2: above 1 for substring matches
+    /// (and 2 for a candidate that is exactly the query), else the fraction of trigrams it contains.
+    pub fn score(&self, candidate: &str) -> f64 {
+        let candidate_words = words(candidate).collect::<Vec<_>>().join(" ");
+        if candidate_words.contains(&self.normalized) {
+            return 1.0 + self.normalized.len() as f64 / candidate_words.len() as f64;
+        }
+        let found = trigrams(&candidate_words).intersection(&self.trigrams).count();
+        found as f64 / self.trigrams.len() as f64
+    }
+
+    /// The best score among several candidates, e.g. the title and the path of a video.
+    pub fn best_score<'a>(&self, candidates: impl IntoIterator<Item = &'a str>) -> f64 {
+        candidates
+            .into_iter()
+            .map(|candidate| self.score(candidate))
+            .fold(0.0, f64::max)
+    }
+}
+
+/// How listings are searched: by scoring every video, or through a full-text index.
+#[derive(Default)]
+pub struct SearchIndex {
+    #[cfg(feature = "full-text")]
+    full_text: Option<Arc<crate::fulltext::FullTextIndex>>,
+}
+
+pub type SharedSearchIndex = Arc<SearchIndex>;
+
+impl SearchIndex {
+    /// Search through a full-text index rather than scoring every video.
+    #[cfg(feature = "full-text")]
+    pub fn full_text(index: Arc<crate::fulltext::FullTextIndex>) -> Self {
+        Self { full_text: Some(index) }
+    }
+
+    /// The videos matching a search, best match first.
+    pub fn rank<'a>(&self, query: &str, videos: Vec<&'a VideoEntry>) -> Vec<&'a VideoEntry> {
+        #[cfg(feature = "full-text")]
+        if let Some(index) = &self.full_text {
+            match index.search(query, videos.len()) {
+                Ok(keys) => {
+                    let ranks: std::collections::HashMap<String, usize> =
+                        keys.into_iter().enumerate().map(|(rank, (key, _))| (key, rank)).collect();
+                    let mut videos: Vec<&VideoEntry> =
+                        videos.into_iter().filter(|video| ranks.contains_key(&video.key)).collect();
+                    videos.sort_by_key(|video| ranks[&video.key]);
+                    return videos;
+                }
+                Err(err) => tracing::warn!("Full-text search failed, scoring every video instead: {}", err),
+            }
+        }
+        let Some(query) = Query::new(query) else {
+            return videos;
+        };
+        let mut scored: Vec<(f64, &VideoEntry)> = videos
+            .into_iter()
+            .map(|video| (query.best_score([video.title.as_str(), video.key.as_str()]), video))
+            .filter(|(score, _)| *score >= MIN_SCORE)
+            .collect();
+        scored.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+        scored.into_iter().map(|(_, video)| video).collect()
+    }
+}
@@ -0,0 +1,190 @@
+//! Running as a Windows service, e.g. on a media PC, and installing or uninstalling the service.
+//!
+//! `service install` registers the executable with the options it was given, plus `--service`, which makes it report
+//! to the service control manager instead of running in a console.
+
+use clap::{ArgMatches, Subcommand};
+use std::{
+    ffi::OsString,
+    future::Future,
+    pin::Pin,
+    sync::{mpsc, OnceLock},
+    time::{Duration, Instant},
+};
+use windows_service::{
+    define_windows_service,
+    service::{
+        ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode, ServiceInfo,
+        ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+    },
+    service_control_handler::{self, ServiceControlHandlerResult},
+    service_dispatcher,
+    service_manager::{ServiceManager, ServiceManagerAccess},
+};
+
+use crate::{config_file, VideoPlayerConfig};
+
+pub const SERVICE_NAME: &str = "static-video-server";
+const DISPLAY_NAME: &str = "Static Video Server";
+const SERVICE_TYPE: ServiceType = ServiceType::OWN_PROCESS;
+
+/// How long uninstalling waits for the service to stop.
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum ServiceCommand {
+    /// Register the service, started with Windows, with the options given before `service`.
+    Install,
+    /// Stop the service if it's running, and remove it.
+    Uninstall,
+}
+
+/// Serves until the server stops, given the configuration and the arguments it was parsed from.
+pub type Serve = fn(VideoPlayerConfig, ArgMatches) -> Pin<Box<dyn Future<Output = ()>>>;
+
+static SERVE: OnceLock<Serve> = OnceLock::new();
+
+/// The options to start the service with: the ones given to `service install`, without the subcommand.
+fn launch_arguments() -> Vec<OsString> {
+    let mut args: Vec<OsString> = std::env::args_os().skip(1).collect();
+    if let Some(subcommand) = args.iter().rposition(|arg| arg == "service") {
+        args.truncate(subcommand);
+    }
+    args.insert(0, "--service".into());
+    let logs_elsewhere = args
+        .iter()
+        .any(|arg| arg == "--log-file" || arg.to_string_lossy().starts_with("--log-file="));
+    if !logs_elsewhere {
+        // Services have no console to log to.
+        if let Ok(exe) = std::env::current_exe() {
+            args.push("--log-file".into());
+            args.push(exe.with_file_name("static-video-server.log").into());
+        }
+    }
+    args
+}
+
+fn install() -> windows_service::Result<()> {
+    let manager = ServiceManager::local_computer(
+        None::<&str>,
+        ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+    )?;
+    let info = ServiceInfo {
+        name: SERVICE_NAME.into(),
+        display_name: DISPLAY_NAME.into(),
+        service_type: SERVICE_TYPE,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: std::env::current_exe().map_err(windows_service::Error::Winapi)?,
+        launch_arguments: launch_arguments(),
+        dependencies: vec![],
+        account_name: None,
+        account_password: None,
+    };
+    let service = manager.create_service(&info, ServiceAccess::CHANGE_CONFIG)?;
+    service.set_description("Serves a folder of videos over HTTP.")?;
+    Ok(())
+}
+
+fn uninstall() -> windows_service::Result<()> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+    let service = manager.open_service(
+        SERVICE_NAME,
+        ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+    )?;
+    if service.query_status()?.current_state != ServiceState::Stopped {
+        service.stop()?;
+        let started = Instant::now();
+        while service.query_status()?.current_state != ServiceState::Stopped && started.elapsed() < STOP_TIMEOUT {
+            std::thread::sleep(Duration::from_millis(250));
+        }
+    }
+    service.delete()
+}
+
+/// Run a `service` subcommand, returning the exit code.
+pub fn manage(command: &ServiceCommand) -> i32 {
+    let (done, result) = match command {
+        ServiceCommand::Install => ("Installed", install()),
+        ServiceCommand::Uninstall => ("Uninstalled", uninstall()),
+    };
+    match result {
+        Ok(()) => {
+            println!("{} the {} service.", done, SERVICE_NAME);
+            0
+        }
+        Err(err) => {
+            // Most likely missing permissions: managing services requires an elevated prompt.
+            eprintln!("Failed: {}. Managing services requires running as an administrator.", err);
+            1
+        }
+    }
+}
+
+fn status(state: ServiceState, exit_code: u32) -> ServiceStatus {
+    ServiceStatus {
+        service_type: SERVICE_TYPE,
+        current_state: state,
+        controls_accepted: if state == ServiceState::Running {
+            ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN
+        } else {
+            ServiceControlAccept::empty()
+        },
+        exit_code: ServiceExitCode::Win32(exit_code),
+        checkpoint: 0,
+        wait_hint: Duration::default(),
+        process_id: None,
+    }
+}
+
+define_windows_service!(ffi_service_main, service_main);
+
+fn service_main(_arguments: Vec<OsString>) {
+    let (stop, stopped) = mpsc::channel();
+    let handler = move |control| match control {
+        ServiceControl::Stop | ServiceControl::Shutdown => {
+            let _ = stop.send(());
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    };
+    let Ok(status_handle) = service_control_handler::register(SERVICE_NAME, handler) else {
+        return;
+    };
+    // The arguments the service was registered with, rather than the ones for this start only.
+    let (config, matches) = match config_file::load(std::env::args_os()) {
+        Ok(loaded) => loaded,
+        Err(_) => {
+            let _ = status_handle.set_service_status(status(ServiceState::Stopped, 1));
+            return;
+        }
+    };
+    let (Some(serve), Ok(runtime)) = (SERVE.get(), tokio::runtime::Runtime::new()) else {
+        let _ = status_handle.set_service_status(status(ServiceState::Stopped, 1));
+        return;
+    };
+    let _ = status_handle.set_service_status(status(ServiceState::Running, 0));
+    runtime.block_on(async {
+        let stopped = tokio::task::spawn_blocking(move || stopped.recv());
+        tokio::select! {
+            _ = serve(config, matches) => {}
+            _ = stopped => {}
+        }
+    });
+    runtime.shutdown_timeout(Duration::from_secs(1));
+    let _ = status_handle.set_service_status(status(ServiceState::Stopped, 0));
+}
+
+/// Hand the process over to the service control manager, which calls `serve` once the service starts.
+/// Returns the exit code once the service stopped.
+pub fn run(serve: Serve) -> i32 {
+    let _ = SERVE.set(serve);
+    match service_dispatcher::start(SERVICE_NAME, ffi_service_main) {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("Failed to start as a service, --service is for the service control manager only: {}", err);
+            1
+        }
+    }
+}
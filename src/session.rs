@@ -0,0 +1,429 @@
+//! Sessions: a signed `svs_session` cookie remembering a browser, so credentials are given once rather than with every
+//! request. Every visitor gets one, which keys their watch progress, favorites and history. Logging in, with OpenID
+//! Connect or with the `--api-token` at `POST /auth/token`, attaches a login to it until it expires or
+//! `POST /auth/logout` ends it.
+//!
+//! The cookie holds the id of the session and when it expires, signed so it can't be forged or extended. Logins are
+//! also kept in `sessions.json` in the data directory, along with the preferences of logged in users, so logging out
+//! ends them for good and they survive restarts. Sessions are extended while they are used.
+
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Redirect, Response},
+    routing::post,
+    Extension, Form, Json, Router,
+};
+use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use openidconnect::CsrfToken;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+use tracing::{info, warn};
+
+use crate::{
+    auth::SharedApiToken,
+    oidc::UserSession,
+    store::{self, persist},
+    viewer, VideoPlayerConfig,
+};
+
+/// The name of the cookie that carries the session.
+pub const SESSION_COOKIE: &str = "svs_session";
+
+/// The name of the cookie that carries the CSRF token of the session, readable by the scripts of the pages.
+pub const CSRF_COOKIE: &str = "svs_csrf";
+
+const SESSIONS_FILE: &str = "sessions.json";
+
+/// Who logged in to a session.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Login {
+    /// The user that logged in with OpenID Connect.
+    pub user: Option<UserSession>,
+    /// The SHA-256 of the API token logged in with. The login ends when the token changes.
+    pub api_token: Option<String>,
+    pub created: u64,
+    pub expires: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SessionsData {
+    /// The key the cookies are signed with, unless `--session-secret` is given.
+    #[serde(default)]
+    secret: String,
+    /// The SHA-256 of a session id -> who logged in to it.
+    #[serde(default)]
+    logins: HashMap<String, Login>,
+    /// `user:<subject>` -> the name of a preference, e.g. `layout` -> its value.
+    #[serde(default)]
+    preferences: HashMap<String, HashMap<String, String>>,
+}
+
+/// The logins and the key the session cookies are signed with, shared by all libraries.
+pub struct Sessions {
+    path: PathBuf,
+    secret: Vec<u8>,
+    /// How long a session lasts without being used, in seconds.
+    lifetime: u64,
+    base_path: String,
+    /// Whether the cookies are only sent over HTTPS, i.e. the server is reached over it.
+    secure: bool,
+    data: Mutex<SessionsData>,
+}
+
+pub type SharedSessions = Arc<Sessions>;
+
+fn hash(id: &str) -> String {
+    format!("{:x}", Sha256::digest(id.as_bytes()))
+}
+
+impl Sessions {
+    /// Load the logins from the data directory, generating a signing key on first use.
+    pub fn open(config: &VideoPlayerConfig) -> std::io::Result<Self> {
+        let path = config.data_dir.join(SESSIONS_FILE);
+        let mut data: SessionsData = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => SessionsData::default(),
+            Err(err) => return Err(err),
+        };
+        if data.secret.is_empty() {
+            data.secret = format!("{}{}", CsrfToken::new_random().secret(), CsrfToken::new_random().secret());
+        }
+        let now = store::now();
+        data.logins.retain(|_, login| login.expires > now);
+        persist(&path, &data)?;
+        let secret = config
+            .session_secret
+            .clone()
+            .filter(|secret| !secret.is_empty())
+            .unwrap_or_else(|| data.secret.clone());
+        Ok(Self {
+            path,
+            secret: secret.into_bytes(),
+            lifetime: config.session_days * 24 * 60 * 60,
+            base_path: config.base_path(),
+            secure: config.is_https(),
+            data: Mutex::new(data),
+        })
+    }
+
+    fn update<R>(&self, change: impl FnOnce(&mut SessionsData) -> R) -> R {
+        let mut data = self.data.lock().unwrap();
+        let result = change(&mut data);
+        if let Err(err) = persist(&self.path, &*data) {
+            warn!("Failed to save the sessions to {}: {}", self.path.display(), err);
+        }
+        result
+    }
+
+    fn signature(&self, id: &str, expires: u64) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC takes keys of any size");
+        mac.update(format!("{}.{}", id, expires).as_bytes());
+        mac
+    }
+
+    /// The value of the cookie of a session: `<id>.<expires>.<signature>`.
+    fn sign(&self, id: &str, expires: u64) -> String {
+        let signature = URL_SAFE_NO_PAD.encode(self.signature(id, expires).finalize().into_bytes());
+        format!("{}.{}.{}", id, expires, signature)
+    }
+
+    /// The id of the session and when it expires, if the cookie is signed and hasn't expired.
+    fn verify(&self, cookie: &str) -> Option<(String, u64)> {
+        let mut parts = cookie.splitn(3, '.');
+        let (id, expires, signature) = (parts.next()?, parts.next()?, parts.next()?);
+        let expires: u64 = expires.parse().ok()?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+        let valid = expires > store::now() && self.signature(id, expires).verify_slice(&signature).is_ok();
+        valid.then(|| (id.to_string(), expires))
+    }
+
+    /// The cookie itself is kept by browsers, the expiry it is signed with is what counts.
+    fn cookie(&self, id: &str, expires: u64) -> Cookie<'static> {
+        Cookie::build(SESSION_COOKIE, self.sign(id, expires))
+            .path("/")
+            .http_only(true)
+            .secure(self.secure)
+            .same_site(SameSite::Lax)
+            .permanent()
+            .finish()
+    }
+
+    /// The token state-changing requests of a session carry, see [`crate::csrf`].
+    fn csrf_token(&self, id: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC takes keys of any size");
+        mac.update(format!("csrf.{}", id).as_bytes());
+        URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+    }
+
+    fn csrf_cookie(&self, id: &str) -> Cookie<'static> {
+        Cookie::build(CSRF_COOKIE, self.csrf_token(id))
+            .path("/")
+            .secure(self.secure)
+            .same_site(SameSite::Lax)
+            .permanent()
+            .finish()
+    }
+
+    fn login(&self, id: &str) -> Option<Login> {
+        let now = store::now();
+        self.data.lock().unwrap().logins.get(&hash(id)).filter(|login| login.expires > now).cloned()
+    }
+}
+
+/// The session of a request.
+#[derive(Clone)]
+pub struct CurrentSession {
+    pub id: String,
+    pub login: Option<Login>,
+    sessions: SharedSessions,
+}
+
+impl CurrentSession {
+    /// Who is watching, e.g. to key watch progress by: `user:<subject>` once logged in, `session:<id>` otherwise.
+    pub fn viewer(&self) -> String {
+        match self.login.as_ref().and_then(|login| login.user.as_ref()) {
+            Some(user) => format!("user:{}", user.subject),
+            None => format!("session:{}", self.id),
+        }
+    }
+
+    /// A preference of the logged in user, e.g. `layout`, picked on any of their devices.
+    pub fn preference(&self, name: &str) -> Option<String> {
+        self.login.as_ref()?.user.as_ref()?;
+        let data = self.sessions.data.lock().unwrap();
+        data.preferences.get(&self.viewer())?.get(name).cloned()
+    }
+
+    /// Remember a preference for the logged in user. Anonymous visitors only have it in a cookie of its own.
+    pub fn remember(&self, name: &str, value: &str) {
+        if self.login.as_ref().and_then(|login| login.user.as_ref()).is_none() {
+            return;
+        }
+        let viewer = self.viewer();
+        self.sessions.update(|data| {
+            data.preferences.entry(viewer).or_default().insert(name.to_string(), value.to_string())
+        });
+    }
+
+    /// Log in, in a new session so an id handed out before the login can't be used to take it over.
+    /// Returns the cookies of the new session and its CSRF token.
+    pub fn log_in(&self, jar: CookieJar, user: Option<UserSession>, api_token: Option<&str>) -> (CookieJar, String) {
+        let id = CsrfToken::new_random().secret().clone();
+        let now = store::now();
+        let login = Login {
+            user,
+            api_token: api_token.map(hash),
+            created: now,
+            expires: now + self.sessions.lifetime,
+        };
+        let cookie = self.sessions.cookie(&id, login.expires);
+        self.sessions.update(|data| {
+            data.logins.remove(&hash(&self.id));
+            data.logins.insert(hash(&id), login)
+        });
+        (jar.add(cookie).add(self.sessions.csrf_cookie(&id)), self.sessions.csrf_token(&id))
+    }
+
+    /// End the session, logging out.
+    pub fn log_out(&self, jar: CookieJar) -> CookieJar {
+        self.sessions.update(|data| data.logins.remove(&hash(&self.id)));
+        [SESSION_COOKIE, CSRF_COOKIE].into_iter().fold(jar, |jar, name| {
+            let mut removal = Cookie::named(name);
+            removal.set_path("/");
+            removal.set_secure(self.sessions.secure);
+            jar.remove(removal)
+        })
+    }
+
+    /// The token state-changing requests of the session carry.
+    pub fn csrf_token(&self) -> String {
+        self.sessions.csrf_token(&self.id)
+    }
+
+    /// Whether the session logged in with the current API token.
+    pub fn has_api_token(&self, token: &str) -> bool {
+        self.login
+            .as_ref()
+            .and_then(|login| login.api_token.as_deref())
+            .is_some_and(|logged_in_with| logged_in_with == hash(token))
+    }
+}
+
+/// Attach the [`CurrentSession`] to every request, and the [`UserSession`] of logged in users, handing out a session
+/// cookie to new visitors and extending it when half of its lifetime is over. The CSRF cookie goes along with it.
+pub async fn attach_session<B>(
+    State(sessions): State<SharedSessions>,
+    jar: CookieJar,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let now = store::now();
+    let (id, expires) = match jar.get(SESSION_COOKIE).and_then(|cookie| sessions.verify(cookie.value())) {
+        Some(session) => session,
+        None => {
+            // Visitors from before sessions keep their watch progress and history.
+            let id = jar
+                .get(viewer::VIEWER_COOKIE)
+                .map(|cookie| cookie.value().to_string())
+                .filter(|id| !id.is_empty() && id.len() <= 64 && !id.contains('.'))
+                .unwrap_or_else(|| CsrfToken::new_random().secret().clone());
+            (id, 0)
+        }
+    };
+    let login = sessions.login(&id);
+    if let Some(user) = login.as_ref().and_then(|login| login.user.clone()) {
+        request.extensions_mut().insert(user);
+    }
+    request.extensions_mut().insert(CurrentSession {
+        id: id.clone(),
+        login: login.clone(),
+        sessions: sessions.clone(),
+    });
+    let response = next.run(request).await;
+
+    // Logging in or out sets the cookie itself.
+    let replaced = response
+        .headers()
+        .get_all(header::SET_COOKIE)
+        .iter()
+        .any(|cookie| cookie.as_bytes().starts_with(format!("{}=", SESSION_COOKIE).as_bytes()));
+    if replaced {
+        return response;
+    }
+    let csrf_token = sessions.csrf_token(&id);
+    let stale_csrf = jar.get(CSRF_COOKIE).map(|cookie| cookie.value()) != Some(csrf_token.as_str());
+    let mut cookies = jar;
+    if stale_csrf {
+        cookies = cookies.add(sessions.csrf_cookie(&id));
+    }
+    if expires.saturating_sub(now) <= sessions.lifetime / 2 {
+        let expires = now + sessions.lifetime;
+        if login.is_some() {
+            let key = hash(&id);
+            sessions.update(|data| data.logins.get_mut(&key).map(|login| login.expires = expires));
+        }
+        cookies = cookies.add(sessions.cookie(&id, expires));
+    }
+    (cookies, response).into_response()
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TokenLogin {
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenSession {
+    /// The token to send as `X-CSRF-Token` with the state-changing requests of the session.
+    pub csrf_token: String,
+}
+
+/// Log in with the API token, given as `Authorization: Bearer <token>` or as the `token` field of a form, so the
+/// requests that follow don't need it. Answers with the CSRF token of the new session.
+pub async fn token_login(
+    Extension(session): Extension<CurrentSession>,
+    Extension(api_token): Extension<SharedApiToken>,
+    jar: CookieJar,
+    headers: HeaderMap,
+    form: Option<Form<TokenLogin>>,
+) -> Response {
+    let given = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string())
+        .or_else(|| form.and_then(|Form(form)| form.token));
+    let Some(token) = given.filter(|token| api_token.matches(token)) else {
+        return (StatusCode::UNAUTHORIZED, "Missing or invalid API token").into_response();
+    };
+    info!("Logged in with the API token.");
+    let (jar, csrf_token) = session.log_in(jar, None, Some(&token));
+    (jar, Json(TokenSession { csrf_token })).into_response()
+}
+
+/// End the current session. Only posted, with the CSRF token, so other sites can't log visitors out.
+pub async fn logout(
+    State(sessions): State<SharedSessions>,
+    Extension(session): Extension<CurrentSession>,
+    jar: CookieJar,
+) -> impl IntoResponse {
+    (session.log_out(jar), Redirect::to(&format!("{}/", sessions.base_path)))
+}
+
+pub fn session_router(sessions: SharedSessions, api_token: SharedApiToken) -> Router {
+    Router::new()
+        .route("/auth/token", post(token_login))
+        .route("/auth/logout", post(logout))
+        .layer(Extension(api_token))
+        .with_state(sessions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sessions(secret: &str) -> Sessions {
+        Sessions {
+            path: std::env::temp_dir().join(format!("svs-sessions-{}.json", std::process::id())),
+            secret: secret.as_bytes().to_vec(),
+            lifetime: 60 * 60,
+            base_path: String::new(),
+            secure: false,
+            data: Mutex::new(SessionsData::default()),
+        }
+    }
+
+    #[test]
+    fn verifies_what_it_signs() {
+        let sessions = sessions("secret");
+        let expires = store::now() + 60;
+        assert_eq!(sessions.verify(&sessions.sign("abc", expires)), Some(("abc".to_string(), expires)));
+    }
+
+    #[test]
+    fn rejects_tampered_cookies() {
+        let sessions = sessions("secret");
+        let expires = store::now() + 60;
+        let cookie = sessions.sign("abc", expires);
+        let signature = cookie.rsplit('.').next().unwrap();
+        assert_eq!(sessions.verify(&format!("abd.{}.{}", expires, signature)), None);
+        assert_eq!(sessions.verify(&format!("abc.{}.{}", expires + 3600, signature)), None);
+        assert_eq!(sessions.verify(&format!("abc.{}.AAAA", expires)), None);
+        assert_eq!(sessions.verify(&format!("abc.{}", expires)), None);
+        assert_eq!(sessions.verify("abc.soon.AAAA"), None);
+        assert_eq!(sessions.verify(""), None);
+    }
+
+    #[test]
+    fn rejects_cookies_signed_with_another_key() {
+        let expires = store::now() + 60;
+        let cookie = sessions("one secret").sign("abc", expires);
+        assert_eq!(sessions("another secret").verify(&cookie), None);
+    }
+
+    #[test]
+    fn rejects_expired_cookies() {
+        let sessions = sessions("secret");
+        assert_eq!(sessions.verify(&sessions.sign("abc", store::now() - 1)), None);
+    }
+
+    #[test]
+    fn marks_cookies_secure_over_https() {
+        let mut sessions = sessions("secret");
+        assert_eq!(sessions.cookie("abc", store::now() + 60).secure(), Some(false));
+        sessions.secure = true;
+        assert_eq!(sessions.cookie("abc", store::now() + 60).secure(), Some(true));
+        assert_eq!(sessions.csrf_cookie("abc").secure(), Some(true));
+        assert_eq!(sessions.cookie("abc", store::now() + 60).http_only(), Some(true));
+    }
+}
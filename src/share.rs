@@ -0,0 +1,211 @@
+//! Share links to videos, shown with a QR code to open them on a phone.
+//!
+//! Without a `--share-secret`, a share link is simply the watch page of the video. With one, it is a link that plays
+//! the video until it expires, signed so that it works without a login and can't be altered to reach another video
+//! or last longer. The signature covers the path of the video rather than its id, so a link stops working instead of
+//! playing another video should a rescan hand its id to a different file.
+
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::{Request, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Extension, Router,
+};
+#[cfg(feature = "ui")]
+use axum::http::HeaderMap;
+use hmac::{Hmac, Mac};
+#[cfg(feature = "ui")]
+use qrcode::{render::svg, QrCode};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::sync::Arc;
+
+use crate::{analytics, error, media, store, streams, SharedState, VideoEntry};
+#[cfg(feature = "ui")]
+use crate::{
+    i18n::{Messages, SharedMessages},
+    templates::{Renderer, ShareTemplate},
+    themes::SharedTheme,
+    urls::PublicUrl,
+};
+
+/// How long signed links are valid for unless asked otherwise: a day.
+#[cfg(feature = "ui")]
+const DEFAULT_EXPIRES_IN: u64 = 24 * 60 * 60;
+
+/// The longest signed links may be valid for: 30 days.
+#[cfg(feature = "ui")]
+const MAX_EXPIRES_IN: u64 = 30 * 24 * 60 * 60;
+
+#[derive(Debug, Clone, Default)]
+pub struct ShareLinks {
+    secret: Option<Vec<u8>>,
+}
+
+pub type SharedShareLinks = Arc<ShareLinks>;
+
+impl ShareLinks {
+    pub fn new(secret: Option<&str>) -> Self {
+        Self {
+            secret: secret.filter(|secret| !secret.is_empty()).map(|secret| secret.as_bytes().to_vec()),
+        }
+    }
+
+    /// Whether links are signed and expire.
+    pub fn signs(&self) -> bool {
+        self.secret.is_some()
+    }
+
+    fn signature(secret: &[u8], video: &VideoEntry, expires: u64) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes keys of any size");
+        mac.update(video.key.as_bytes());
+        mac.update(b"\n");
+        mac.update(expires.to_string().as_bytes());
+        mac
+    }
+
+    /// The link to share a video with, `root` being the absolute url of the library.
+    pub fn link(&self, root: &str, video: &VideoEntry, expires: u64) -> String {
+        let Some(secret) = &self.secret else {
+            return format!("{}/watch/{}", root, video.id);
+        };
+        let signature: String = Self::signature(secret, video, expires)
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        format!("{}/shared/{}?expires={}&sig={}", root, video.id, expires, signature)
+    }
+
+    /// Whether a signed link to the video is genuine and hasn't expired yet.
+    pub fn verify(&self, video: &VideoEntry, expires: u64, signature: &str) -> bool {
+        let Some(secret) = &self.secret else {
+            return false;
+        };
+        let Some(signature) = decode_hex(signature) else {
+            return false;
+        };
+        expires > store::now() && Self::signature(secret, video, expires).verify_slice(&signature).is_ok()
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|at| u8::from_str_radix(hex.get(at..at + 2)?, 16).ok())
+        .collect()
+}
+
+/// The QR code of a link as an SVG element, to inline in a page.
+#[cfg(feature = "ui")]
+fn qr_code(link: &str) -> Option<String> {
+    let svg = QrCode::new(link.as_bytes())
+        .ok()?
+        .render::<svg::Color>()
+        .min_dimensions(240, 240)
+        .quiet_zone(true)
+        .build();
+    // Without the XML declaration, which has no place in HTML.
+    svg.find("<svg").map(|start| svg[start..].to_string())
+}
+
+/// A duration in seconds, the way the share page offers them.
+#[cfg(feature = "ui")]
+fn describe(seconds: u64, t: &Messages) -> String {
+    let (message, n) = match seconds {
+        seconds if seconds.is_multiple_of(86_400) => (&t.days, seconds / 86_400),
+        seconds if seconds.is_multiple_of(3600) => (&t.hours, seconds / 3600),
+        seconds => (&t.minutes, seconds.div_ceil(60)),
+    };
+    Messages::fill(message, &[("n", &n.to_string())])
+}
+
+#[cfg(feature = "ui")]
+#[derive(Debug, Clone, Deserialize)]
+pub struct ShareQuery {
+    /// How many seconds a signed link stays valid.
+    pub expires_in: Option<u64>,
+}
+
+/// The share link of a video, with its QR code.
+#[cfg(feature = "ui")]
+#[allow(clippy::too_many_arguments)]
+pub async fn share_page(
+    Path(video_id): Path<String>,
+    State(state): State<SharedState>,
+    Extension(renderer): Extension<Renderer>,
+    Extension(public_url): Extension<PublicUrl>,
+    Extension(links): Extension<SharedShareLinks>,
+    Extension(t): Extension<SharedMessages>,
+    Extension(theme): Extension<SharedTheme>,
+    Query(query): Query<ShareQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let (video, base_path) = {
+        let state = state.lock().unwrap();
+        let Some(video) = state.get(&video_id).cloned() else {
+            return error::Error::VideoNotFound(video_id).into_response();
+        };
+        (video, state.base_path.clone())
+    };
+    let expires_in = query.expires_in.unwrap_or(DEFAULT_EXPIRES_IN).clamp(60, MAX_EXPIRES_IN);
+    let link = links.link(&public_url.resolve(&headers, &base_path), &video, store::now() + expires_in);
+    let Some(qr_code) = qr_code(&link) else {
+        return (StatusCode::INTERNAL_SERVER_ERROR, "The link is too long for a QR code").into_response();
+    };
+    let template = ShareTemplate {
+        t: &t,
+        theme: &theme,
+        video: &video,
+        title: Messages::fill(&t.share_title, &[("title", &video.title)]),
+        base_path,
+        link,
+        qr_code,
+        signed: links.signs(),
+        notice: Messages::fill(&t.share_signed, &[("duration", &describe(expires_in, &t))]),
+    };
+    renderer.page("share.html", &template)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SignedQuery {
+    pub expires: u64,
+    pub sig: String,
+}
+
+/// Play the video of a signed share link, which needs no login.
+pub async fn shared_video(
+    Path(video_id): Path<String>,
+    State(state): State<SharedState>,
+    Extension(links): Extension<SharedShareLinks>,
+    Extension(store): Extension<store::SharedIndexStore>,
+    Query(query): Query<SignedQuery>,
+    slot: streams::StreamSlot,
+    request: Request<Body>,
+) -> Response {
+    let (video, buffer_size, limits) = {
+        let state = state.lock().unwrap();
+        let Some(video) = state.get(&video_id).cloned() else {
+            return error::Error::VideoNotFound(video_id).into_response();
+        };
+        (video, state.stream_buffer_size, state.stream_limits)
+    };
+    if !links.verify(&video, query.expires, &query.sig) {
+        return (StatusCode::FORBIDDEN, "This share link is invalid or has expired.").into_response();
+    }
+    match media::serve_file(&video.path, request, buffer_size).await {
+        Ok(response) => streams::hold(analytics::count(limits.apply(response), store, video.key), slot),
+        Err(err) => err.into_response(),
+    }
+}
+
+pub fn share_router() -> Router<SharedState> {
+    Router::new()
+        .route("/shared/:video_id", get(shared_video))
+}
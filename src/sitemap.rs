@@ -0,0 +1,136 @@
+//! A sitemap of the watch pages, so search engines can index a public library.
+//!
+//! Sitemaps are rendered once per version of the index and kept in the page cache until it changes. Libraries with
+//! more videos than fit in one sitemap get a sitemap index pointing to several.
+
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Extension, Router,
+};
+use std::fmt::Write;
+
+use crate::{cache::SharedPageCache, urls::PublicUrl, webdav::escape_xml, MediaKind, SharedState, VideoEntry};
+
+/// The most urls a sitemap may list.
+const URLS_PER_SITEMAP: usize = 50_000;
+
+fn xml_response(xml: Bytes) -> Response {
+    ([(header::CONTENT_TYPE, "application/xml; charset=utf-8")], xml).into_response()
+}
+
+fn urlset(root: &str, videos: &[&VideoEntry]) -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\" \
+         xmlns:video=\"http://www.google.com/schemas/sitemap-video/1.1\" \
+         xmlns:image=\"http://www.google.com/schemas/sitemap-image/1.1\">\n",
+    );
+    for video in videos {
+        match video.kind {
+            MediaKind::Video => {}
+            MediaKind::Audio => {
+                let _ = writeln!(xml, "<url><loc>{}/watch/{}</loc></url>", root, video.id);
+                continue;
+            }
+            MediaKind::Image => {
+                let _ = writeln!(
+                    xml,
+                    "<url><loc>{root}/watch/{id}</loc><image:image><image:loc>{root}/image/{id}</image:loc></image:image></url>",
+                    root = root,
+                    id = video.id,
+                );
+                continue;
+            }
+        }
+        let title = escape_xml(&video.title);
+        let _ = write!(
+            xml,
+            "<url><loc>{root}/watch/{id}</loc><video:video><video:thumbnail_loc>{root}/poster/{id}</video:thumbnail_loc>\
+             <video:title>{title}</video:title><video:description>{title}</video:description>\
+             <video:content_loc>{root}/video/{id}</video:content_loc>",
+            root = root,
+            id = video.id,
+            title = title,
+        );
+        if let Some(duration) = video.duration() {
+            let _ = write!(xml, "<video:duration>{:.0}</video:duration>", duration.max(1.0));
+        }
+        xml.push_str("</video:video></url>\n");
+    }
+    xml.push_str("</urlset>\n");
+    xml
+}
+
+fn sitemap_index(root: &str, parts: usize) -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <sitemapindex xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    );
+    for part in 0..parts {
+        let _ = writeln!(xml, "<sitemap><loc>{}/sitemaps/{}</loc></sitemap>", root, part);
+    }
+    xml.push_str("</sitemapindex>\n");
+    xml
+}
+
+/// Render a sitemap (`part` is `None` for `/sitemap.xml`), or take it from the cache if the index didn't change.
+fn render(
+    state: &SharedState,
+    pages: &SharedPageCache,
+    public_url: &PublicUrl,
+    headers: &HeaderMap,
+    part: Option<usize>,
+) -> Response {
+    let (videos, base_path, generation) = {
+        let state = state.lock().unwrap();
+        (state.videos(), state.base_path.clone(), state.generation())
+    };
+    let root = public_url.resolve(headers, &base_path);
+    let key = format!("sitemap/{:?}#{}", part, root);
+    if let Some(xml) = pages.get(&key, generation) {
+        return xml_response(xml);
+    }
+    let listed: Vec<&VideoEntry> = videos.iter().filter(|video| !video.hidden && !video.private).collect();
+    let chunks: Vec<&[&VideoEntry]> = listed.chunks(URLS_PER_SITEMAP).collect();
+    let xml = match part {
+        None if chunks.len() > 1 => sitemap_index(&root, chunks.len()),
+        None => urlset(&root, chunks.first().copied().unwrap_or_default()),
+        Some(part) => match chunks.get(part) {
+            Some(chunk) if chunks.len() > 1 => urlset(&root, chunk),
+            _ => return StatusCode::NOT_FOUND.into_response(),
+        },
+    };
+    let xml = Bytes::from(xml);
+    pages.insert(key, generation, xml.clone());
+    xml_response(xml)
+}
+
+/// The sitemap of every watch page, or the index of the sitemaps that list them in large libraries.
+pub async fn sitemap(
+    State(state): State<SharedState>,
+    Extension(pages): Extension<SharedPageCache>,
+    Extension(public_url): Extension<PublicUrl>,
+    headers: HeaderMap,
+) -> Response {
+    render(&state, &pages, &public_url, &headers, None)
+}
+
+/// One of the sitemaps listed by the sitemap index.
+pub async fn sitemap_part(
+    Path(part): Path<usize>,
+    State(state): State<SharedState>,
+    Extension(pages): Extension<SharedPageCache>,
+    Extension(public_url): Extension<PublicUrl>,
+    headers: HeaderMap,
+) -> Response {
+    render(&state, &pages, &public_url, &headers, Some(part))
+}
+
+pub fn sitemap_router() -> Router<SharedState> {
+    Router::new()
+        .route("/sitemap.xml", get(sitemap))
+        .route("/sitemaps/:part", get(sitemap_part))
+}
@@ -0,0 +1,152 @@
+//! Sending metrics to a StatsD (or DogStatsD) server: requests by status, bytes sent, and how long requests and
+//! scans take.
+//!
+//! Metrics are sent over UDP and never wait for the server; they are dropped if it can't keep up.
+
+use axum::{
+    body::{boxed, BoxBody, Bytes, HttpBody},
+    http::{HeaderMap, Request},
+    middleware::Next,
+    response::Response,
+};
+use std::{
+    fmt::Write,
+    net::{ToSocketAddrs, UdpSocket},
+    pin::Pin,
+    sync::OnceLock,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tracing::debug;
+
+use crate::VideoPlayerConfig;
+
+static SINK: OnceLock<StatsD> = OnceLock::new();
+
+#[derive(Debug)]
+pub struct StatsD {
+    socket: UdpSocket,
+    /// Prepended to every metric, followed by a dot.
+    prefix: String,
+    /// Whether to send tags the DogStatsD way. Plain StatsD has no tags, so their values go into the name instead.
+    tags: bool,
+}
+
+impl StatsD {
+    pub fn connect(addr: &str, prefix: &str, tags: bool) -> std::io::Result<Self> {
+        let target = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, format!("{} has no address", addr)))?;
+        let socket = UdpSocket::bind(if target.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" })?;
+        socket.connect(target)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            prefix: prefix.trim_end_matches('.').to_string(),
+            tags,
+        })
+    }
+
+    fn send(&self, name: &str, value: &str, kind: &str, tags: &[(&str, &str)]) {
+        let mut line = String::new();
+        if !self.prefix.is_empty() {
+            let _ = write!(line, "{}.", self.prefix);
+        }
+        line.push_str(name);
+        if !self.tags {
+            tags.iter().for_each(|(_, value)| {
+                let _ = write!(line, ".{}", value);
+            });
+        }
+        let _ = write!(line, ":{}|{}", value, kind);
+        if self.tags && !tags.is_empty() {
+            let tags: Vec<String> = tags.iter().map(|(name, value)| format!("{}:{}", name, value)).collect();
+            let _ = write!(line, "|#{}", tags.join(","));
+        }
+        if let Err(err) = self.socket.send(line.as_bytes()) {
+            debug!("Failed to send metric {}: {}", line, err);
+        }
+    }
+}
+
+/// Start sending metrics to the server configured with `--statsd`, if any.
+pub fn init(config: &VideoPlayerConfig) -> std::io::Result<bool> {
+    let Some(addr) = &config.statsd else {
+        return Ok(false);
+    };
+    let sink = StatsD::connect(addr, &config.statsd_prefix, config.dogstatsd)?;
+    Ok(SINK.set(sink).is_ok())
+}
+
+/// Add to a counter.
+pub fn count(name: &str, value: u64, tags: &[(&str, &str)]) {
+    if let Some(sink) = SINK.get() {
+        sink.send(name, &value.to_string(), "c", tags);
+    }
+}
+
+/// Record how long something took, in milliseconds.
+pub fn timing(name: &str, duration: Duration, tags: &[(&str, &str)]) {
+    if let Some(sink) = SINK.get() {
+        sink.send(name, &duration.as_millis().to_string(), "ms", tags);
+    }
+}
+
+/// Set a gauge to its current value.
+pub fn gauge(name: &str, value: u64, tags: &[(&str, &str)]) {
+    if let Some(sink) = SINK.get() {
+        sink.send(name, &value.to_string(), "g", tags);
+    }
+}
+
+/// Count every request and time it until its response is ready, and count the bytes of the response once it is sent
+/// (or the client goes away).
+pub async fn record_requests<B>(request: Request<B>, next: Next<B>) -> Response {
+    let started = Instant::now();
+    let method = request.method().clone();
+    let response = next.run(request).await;
+    let status = format!("{}xx", response.status().as_u16() / 100);
+    let tags = [("method", method.as_str()), ("status", status.as_str())];
+    count("requests", 1, &tags);
+    timing("request.duration", started.elapsed(), &tags);
+    response.map(|body| boxed(Counted { inner: body, sent: 0 }))
+}
+
+/// A body counting the bytes sent, to report them when it is dropped.
+struct Counted {
+    inner: BoxBody,
+    sent: u64,
+}
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        if self.sent > 0 {
+            count("bytes_sent", self.sent, &[]);
+        }
+    }
+}
+
+impl HttpBody for Counted {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Bytes, axum::Error>>> {
+        let chunk = Pin::new(&mut self.inner).poll_data(cx);
+        if let Poll::Ready(Some(Ok(data))) = &chunk {
+            self.sent += data.len() as u64;
+        }
+        chunk
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, axum::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+}
@@ -0,0 +1,87 @@
+//! How much space the library takes up, kept up to date while videos are indexed
+//! so reporting it doesn't walk the disk.
+
+use serde::Serialize;
+use std::{collections::BTreeMap, path::Path};
+
+/// The space taken up by the videos in a folder, including its subfolders.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct FolderUsage {
+    pub size: u64,
+    pub videos: usize,
+}
+
+/// The space taken up by the indexed videos.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StorageUsage {
+    pub total_size: u64,
+    pub videos: usize,
+    /// Usage by folder relative to the root, e.g. `Travel` and `Travel/2022`.
+    pub folders: BTreeMap<String, FolderUsage>,
+}
+
+/// The folders a video counts towards, from its key: all the folders it is nested in.
+fn folders_of(key: &str) -> impl Iterator<Item = &str> {
+    key.match_indices('/').map(move |(end, _)| &key[..end])
+}
+
+impl StorageUsage {
+    /// Count a video of the given size.
+    pub fn add(&mut self, key: &str, size: u64) {
+        self.total_size += size;
+        self.videos += 1;
+        for folder in folders_of(key) {
+            let usage = self.folders.entry(folder.to_string()).or_default();
+            usage.size += size;
+            usage.videos += 1;
+        }
+    }
+
+    /// Stop counting a video, e.g. after it was removed or moved.
+    pub fn remove(&mut self, key: &str, size: u64) {
+        self.total_size = self.total_size.saturating_sub(size);
+        self.videos = self.videos.saturating_sub(1);
+        for folder in folders_of(key) {
+            if let Some(usage) = self.folders.get_mut(folder) {
+                usage.size = usage.size.saturating_sub(size);
+                usage.videos = usage.videos.saturating_sub(1);
+                if usage.videos == 0 {
+                    self.folders.remove(folder);
+                }
+            }
+        }
+    }
+}
+
+/// A number of bytes the way people read them, e.g. `1.5 GiB`.
+pub fn describe_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// The free and total space of the filesystem a path is on.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct FilesystemSpace {
+    /// The space available to the server process.
+    pub available: u64,
+    pub total: u64,
+}
+
+impl FilesystemSpace {
+    pub fn of(path: &Path) -> std::io::Result<Self> {
+        Ok(Self {
+            available: fs2::available_space(path)?,
+            total: fs2::total_space(path)?,
+        })
+    }
+}
@@ -0,0 +1,559 @@
+//! The index store: what viewers did with the videos (watch progress, favorites, play counts, history, ratings,
+//! traffic), persisted as JSON in the data directory so it survives restarts and rescans.
+//!
+//! Videos are keyed by their path relative to the root rather than by their id,
+//! since ids are reassigned whenever the library is rescanned.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{hash_map::Entry, BTreeMap, BTreeSet, HashMap},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tracing::error;
+
+/// The name of the file the store is persisted to, inside the data directory.
+pub const STORE_FILE: &str = "store.json";
+
+/// Plays of the same video by the same viewer within this window count once.
+pub const PLAY_WINDOW: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// The number of videos kept in the watch history of every viewer.
+pub const HISTORY_LENGTH: usize = 500;
+
+/// The number of plays kept for the stats, of all viewers.
+pub const RECENT_PLAYS: usize = 100;
+
+/// How long traffic is kept for, by the hour.
+pub const TRAFFIC_RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// How often bytes served are persisted, which is not worth doing for every response.
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How far a viewer got into a video.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Progress {
+    /// The playback position in seconds.
+    pub position: f64,
+    /// The duration of the video in seconds, as reported by the player.
+    pub duration: Option<f64>,
+    /// When the progress was recorded, in seconds since the Unix epoch.
+    pub updated: u64,
+}
+
+/// A video in the watch history of a viewer.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub key: String,
+    /// When the video was last started, in seconds since the Unix epoch.
+    pub watched: u64,
+}
+
+/// What was served in an hour.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Traffic {
+    pub plays: u64,
+    pub bytes: u64,
+}
+
+/// The checksum of a video, to tell when its file got corrupted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checksum {
+    /// The SHA-256 of the file, in hex.
+    pub sha256: String,
+    /// The size of the file when it was hashed.
+    pub size: u64,
+    /// When the file was last modified when it was hashed, in seconds since the Unix epoch. Along with the size, this
+    /// tells files that were changed on purpose apart from corrupted ones.
+    pub modified: u64,
+    /// When the file was last hashed, in seconds since the Unix epoch.
+    pub verified: u64,
+}
+
+/// An access token to a private video. Only a hash of the token is kept.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VideoToken {
+    /// Tells the tokens of a video apart, e.g. to revoke one, without giving it away.
+    pub id: String,
+    /// The SHA-256 of the token, in hex.
+    pub sha256: String,
+    /// What the token is for, e.g. who it was given to.
+    pub label: Option<String>,
+    /// When it was minted, in seconds since the Unix epoch.
+    pub created: u64,
+    /// When it stops working, in seconds since the Unix epoch, or `None` if it doesn't.
+    pub expires: Option<u64>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct StoreData {
+    /// Viewer -> video key -> progress.
+    #[serde(default)]
+    progress: HashMap<String, HashMap<String, Progress>>,
+    /// Viewer -> keys of the videos they starred.
+    #[serde(default)]
+    favorites: HashMap<String, BTreeSet<String>>,
+    /// Video key -> number of plays.
+    #[serde(default)]
+    views: HashMap<String, u64>,
+    /// Viewer -> the videos they started, most recent first.
+    #[serde(default)]
+    history: HashMap<String, Vec<HistoryEntry>>,
+    /// Video key -> viewer -> their rating from 1 to 5 stars.
+    #[serde(default)]
+    ratings: HashMap<String, HashMap<String, u8>>,
+    /// Video key -> the title it is displayed with instead of its file name.
+    #[serde(default)]
+    titles: HashMap<String, String>,
+    /// Video key -> bytes of it served.
+    #[serde(default)]
+    bytes_served: HashMap<String, u64>,
+    /// The videos started by any viewer, most recent first.
+    #[serde(default)]
+    recent_plays: Vec<HistoryEntry>,
+    /// The start of every hour, in seconds since the Unix epoch -> what was served in it.
+    #[serde(default)]
+    traffic: BTreeMap<u64, Traffic>,
+    /// Video key -> the checksum of its file.
+    #[serde(default)]
+    checksums: HashMap<String, Checksum>,
+    /// The keys of the videos that are only served with one of their tokens.
+    #[serde(default)]
+    private: BTreeSet<String>,
+    /// Video key -> the tokens it is served with while private.
+    #[serde(default)]
+    video_tokens: HashMap<String, Vec<VideoToken>>,
+    /// Video key -> whether it is hidden, overriding the `.svs.toml` of its folder.
+    #[serde(default)]
+    visibility: HashMap<String, bool>,
+}
+
+/// Play counts, ratings and bytes served of all videos, by video key.
+#[derive(Debug, Clone, Default)]
+pub struct VideoStats {
+    pub views: HashMap<String, u64>,
+    /// The average rating of every rated video.
+    pub ratings: HashMap<String, f64>,
+    pub bytes_served: HashMap<String, u64>,
+}
+
+impl VideoStats {
+    pub fn views(&self, key: &str) -> u64 {
+        self.views.get(key).copied().unwrap_or(0)
+    }
+
+    pub fn bytes_served(&self, key: &str) -> u64 {
+        self.bytes_served.get(key).copied().unwrap_or(0)
+    }
+
+    pub fn rating(&self, key: &str) -> Option<f64> {
+        self.ratings.get(key).copied()
+    }
+}
+
+pub struct IndexStore {
+    /// Where the store is persisted, or `None` to only keep it in memory.
+    path: Option<PathBuf>,
+    data: Mutex<StoreData>,
+    /// When each viewer last started each video. Not persisted.
+    recent_plays: Mutex<HashMap<(String, String), Instant>>,
+    /// Whether there are changes that weren't persisted yet.
+    dirty: AtomicBool,
+    /// Counts the changes persisted right away, see [`IndexStore::revision`].
+    revision: AtomicU64,
+}
+
+pub type SharedIndexStore = Arc<IndexStore>;
+
+/// The current time in seconds since the Unix epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+impl IndexStore {
+    /// Open the store in a data directory, starting empty if nothing was persisted yet.
+    pub fn open(data_dir: &Path) -> std::io::Result<Self> {
+        let path = data_dir.join(STORE_FILE);
+        let data = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => StoreData::default(),
+            Err(err) => return Err(err),
+        };
+        Ok(Self {
+            path: Some(path),
+            data: Mutex::new(data),
+            recent_plays: Default::default(),
+            dirty: AtomicBool::new(false),
+            revision: AtomicU64::new(0),
+        })
+    }
+
+    /// A store that is never persisted.
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            data: Default::default(),
+            recent_plays: Default::default(),
+            dirty: AtomicBool::new(false),
+            revision: AtomicU64::new(0),
+        }
+    }
+
+    /// Change the store and persist it.
+    fn update<R>(&self, change: impl FnOnce(&mut StoreData) -> R) -> R {
+        let mut data = self.data.lock().unwrap();
+        let result = change(&mut data);
+        self.revision.fetch_add(1, Ordering::Relaxed);
+        self.persist(&data);
+        result
+    }
+
+    /// A counter that changes whenever play counts, ratings, titles or anything else but bytes served do,
+    /// so listings that include them can tell when they're stale.
+    pub fn revision(&self) -> u64 {
+        self.revision.load(Ordering::Relaxed)
+    }
+
+    fn persist(&self, data: &StoreData) {
+        self.dirty.store(false, Ordering::Relaxed);
+        if let Some(path) = &self.path {
+            if let Err(err) = persist(path, data) {
+                error!("Failed to persist {}: {}", path.display(), err);
+            }
+        }
+    }
+
+    /// Persist changes left for later, like bytes served.
+    pub fn flush(&self) {
+        if self.dirty.load(Ordering::Relaxed) {
+            self.persist(&self.data.lock().unwrap());
+        }
+    }
+
+    /// The progress of a viewer in a video.
+    pub fn progress(&self, viewer: &str, key: &str) -> Option<Progress> {
+        let data = self.data.lock().unwrap();
+        data.progress.get(viewer)?.get(key).cloned()
+    }
+
+    /// The progress of a viewer in every video they started, by video key.
+    pub fn all_progress(&self, viewer: &str) -> HashMap<String, Progress> {
+        let data = self.data.lock().unwrap();
+        data.progress.get(viewer).cloned().unwrap_or_default()
+    }
+
+    pub fn set_progress(&self, viewer: &str, key: &str, progress: Progress) {
+        self.update(|data| {
+            data.progress
+                .entry(viewer.to_string())
+                .or_default()
+                .insert(key.to_string(), progress);
+        })
+    }
+
+    /// The keys of the videos a viewer starred.
+    pub fn favorites(&self, viewer: &str) -> BTreeSet<String> {
+        let data = self.data.lock().unwrap();
+        data.favorites.get(viewer).cloned().unwrap_or_default()
+    }
+
+    /// Star a video for a viewer, or unstar it if it already was.
+    /// Returns whether the video is starred now.
+    pub fn toggle_favorite(&self, viewer: &str, key: &str) -> bool {
+        self.update(|data| {
+            let favorites = data.favorites.entry(viewer.to_string()).or_default();
+            if favorites.remove(key) {
+                false
+            } else {
+                favorites.insert(key.to_string());
+                true
+            }
+        })
+    }
+
+    /// The play counts, average ratings and bytes served of all videos.
+    pub fn stats(&self) -> VideoStats {
+        let data = self.data.lock().unwrap();
+        VideoStats {
+            views: data.views.clone(),
+            bytes_served: data.bytes_served.clone(),
+            ratings: data
+                .ratings
+                .iter()
+                .filter(|(_, ratings)| !ratings.is_empty())
+                .map(|(key, ratings)| {
+                    let total: u32 = ratings.values().map(|&rating| rating as u32).sum();
+                    (key.clone(), total as f64 / ratings.len() as f64)
+                })
+                .collect(),
+        }
+    }
+
+    /// Move a video to the top of the viewer's history and count a play of it,
+    /// unless the viewer already started it within the [`PLAY_WINDOW`].
+    /// Returns whether the play was counted.
+    pub fn record_play(&self, viewer: &str, key: &str) -> bool {
+        let now = Instant::now();
+        let counted = {
+            let mut recent = self.recent_plays.lock().unwrap();
+            recent.retain(|_, started| now.duration_since(*started) < PLAY_WINDOW);
+            match recent.entry((viewer.to_string(), key.to_string())) {
+                Entry::Occupied(_) => false,
+                Entry::Vacant(entry) => {
+                    entry.insert(now);
+                    true
+                }
+            }
+        };
+        self.update(|data| {
+            let entry = HistoryEntry {
+                key: key.to_string(),
+                watched: self::now(),
+            };
+            if counted {
+                *data.views.entry(key.to_string()).or_default() += 1;
+                data.traffic_at(entry.watched).plays += 1;
+                data.recent_plays.insert(0, entry.clone());
+                data.recent_plays.truncate(RECENT_PLAYS);
+            }
+            let history = data.history.entry(viewer.to_string()).or_default();
+            history.retain(|entry| entry.key != key);
+            history.insert(0, entry);
+            history.truncate(HISTORY_LENGTH);
+        });
+        counted
+    }
+
+    /// Count bytes of a video that were served. Persisted within the [`FLUSH_INTERVAL`].
+    pub fn record_bytes(&self, key: &str, bytes: u64) {
+        if bytes == 0 {
+            return;
+        }
+        let mut data = self.data.lock().unwrap();
+        *data.bytes_served.entry(key.to_string()).or_default() += bytes;
+        data.traffic_at(self::now()).bytes += bytes;
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// The videos started by any viewer, most recent first.
+    pub fn recent_plays(&self) -> Vec<HistoryEntry> {
+        self.data.lock().unwrap().recent_plays.clone()
+    }
+
+    /// What was served in every hour since `since` (in seconds since the Unix epoch), by the start of the hour.
+    /// Hours without traffic are included.
+    pub fn traffic(&self, since: u64) -> Vec<(u64, Traffic)> {
+        let data = self.data.lock().unwrap();
+        let first = since - since % 3600;
+        (first..=self::now())
+            .step_by(3600)
+            .map(|hour| (hour, data.traffic.get(&hour).copied().unwrap_or_default()))
+            .collect()
+    }
+
+    /// The ratings a viewer gave, by video key.
+    pub fn ratings_by(&self, viewer: &str) -> HashMap<String, u8> {
+        let data = self.data.lock().unwrap();
+        data.ratings
+            .iter()
+            .filter_map(|(key, ratings)| Some((key.clone(), *ratings.get(viewer)?)))
+            .collect()
+    }
+
+    /// Rate a video from 1 to 5 stars for a viewer, or take their rating back with `None`.
+    pub fn set_rating(&self, viewer: &str, key: &str, rating: Option<u8>) {
+        self.update(|data| {
+            let ratings = data.ratings.entry(key.to_string()).or_default();
+            match rating {
+                Some(rating) => {
+                    ratings.insert(viewer.to_string(), rating);
+                }
+                None => {
+                    ratings.remove(viewer);
+                }
+            }
+        })
+    }
+
+    /// The custom title of a video.
+    pub fn title(&self, key: &str) -> Option<String> {
+        self.data.lock().unwrap().titles.get(key).cloned()
+    }
+
+    /// Give a video a custom title, or go back to its file name with `None`.
+    pub fn set_title(&self, key: &str, title: Option<String>) {
+        self.update(|data| match title {
+            Some(title) => {
+                data.titles.insert(key.to_string(), title);
+            }
+            None => {
+                data.titles.remove(key);
+            }
+        })
+    }
+
+    /// The checksum of a video, if it was hashed.
+    pub fn checksum(&self, key: &str) -> Option<Checksum> {
+        self.data.lock().unwrap().checksums.get(key).cloned()
+    }
+
+    pub fn set_checksum(&self, key: &str, checksum: Checksum) {
+        self.update(|data| {
+            data.checksums.insert(key.to_string(), checksum);
+        })
+    }
+
+    /// Whether a video is only served with one of its tokens.
+    pub fn is_private(&self, key: &str) -> bool {
+        self.data.lock().unwrap().private.contains(key)
+    }
+
+    pub fn set_private(&self, key: &str, private: bool) {
+        self.update(|data| {
+            if private {
+                data.private.insert(key.to_string());
+            } else {
+                data.private.remove(key);
+            }
+        })
+    }
+
+    /// Whether a video was hidden, or shown, through the API regardless of the `.svs.toml` of its folder.
+    pub fn visibility(&self, key: &str) -> Option<bool> {
+        self.data.lock().unwrap().visibility.get(key).copied()
+    }
+
+    /// Hide or show a video, or with `None`, leave it to the `.svs.toml` of its folder again.
+    pub fn set_visibility(&self, key: &str, hidden: Option<bool>) {
+        self.update(|data| match hidden {
+            Some(hidden) => {
+                data.visibility.insert(key.to_string(), hidden);
+            }
+            None => {
+                data.visibility.remove(key);
+            }
+        })
+    }
+
+    /// The access tokens of a video, oldest first.
+    pub fn video_tokens(&self, key: &str) -> Vec<VideoToken> {
+        self.data.lock().unwrap().video_tokens.get(key).cloned().unwrap_or_default()
+    }
+
+    pub fn add_video_token(&self, key: &str, token: VideoToken) {
+        self.update(|data| data.video_tokens.entry(key.to_string()).or_default().push(token))
+    }
+
+    /// Revoke an access token of a video. Returns whether it had one with that id.
+    pub fn revoke_video_token(&self, key: &str, id: &str) -> bool {
+        self.update(|data| {
+            let Some(tokens) = data.video_tokens.get_mut(key) else {
+                return false;
+            };
+            let count = tokens.len();
+            tokens.retain(|token| token.id != id);
+            let revoked = tokens.len() < count;
+            if tokens.is_empty() {
+                data.video_tokens.remove(key);
+            }
+            revoked
+        })
+    }
+
+    /// Carry everything known about a video over to its new key after the file was renamed or moved.
+    pub fn rename_key(&self, from: &str, to: &str) {
+        if from == to {
+            return;
+        }
+        self.update(|data| {
+            for progress in data.progress.values_mut() {
+                if let Some(progress_in_video) = progress.remove(from) {
+                    progress.insert(to.to_string(), progress_in_video);
+                }
+            }
+            for favorites in data.favorites.values_mut() {
+                if favorites.remove(from) {
+                    favorites.insert(to.to_string());
+                }
+            }
+            if let Some(views) = data.views.remove(from) {
+                data.views.insert(to.to_string(), views);
+            }
+            for history in data.history.values_mut() {
+                for entry in history.iter_mut().filter(|entry| entry.key == from) {
+                    entry.key = to.to_string();
+                }
+            }
+            if let Some(ratings) = data.ratings.remove(from) {
+                data.ratings.insert(to.to_string(), ratings);
+            }
+            if let Some(title) = data.titles.remove(from) {
+                data.titles.insert(to.to_string(), title);
+            }
+            if let Some(bytes) = data.bytes_served.remove(from) {
+                data.bytes_served.insert(to.to_string(), bytes);
+            }
+            if let Some(checksum) = data.checksums.remove(from) {
+                data.checksums.insert(to.to_string(), checksum);
+            }
+            if data.private.remove(from) {
+                data.private.insert(to.to_string());
+            }
+            if let Some(tokens) = data.video_tokens.remove(from) {
+                data.video_tokens.insert(to.to_string(), tokens);
+            }
+            if let Some(hidden) = data.visibility.remove(from) {
+                data.visibility.insert(to.to_string(), hidden);
+            }
+            for entry in data.recent_plays.iter_mut().filter(|entry| entry.key == from) {
+                entry.key = to.to_string();
+            }
+        })
+    }
+
+    /// The videos a viewer started, most recent first.
+    pub fn history(&self, viewer: &str) -> Vec<HistoryEntry> {
+        let data = self.data.lock().unwrap();
+        data.history.get(viewer).cloned().unwrap_or_default()
+    }
+}
+
+impl StoreData {
+    /// The traffic of the hour a time falls in, forgetting hours older than the [`TRAFFIC_RETENTION`].
+    fn traffic_at(&mut self, time: u64) -> &mut Traffic {
+        let hour = time - time % 3600;
+        let oldest = hour.saturating_sub(TRAFFIC_RETENTION.as_secs());
+        self.traffic.retain(|&start, _| start >= oldest);
+        self.traffic.entry(hour).or_default()
+    }
+}
+
+/// Persist the bytes served of a store every [`FLUSH_INTERVAL`].
+pub async fn flush_periodically(store: SharedIndexStore) {
+    let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+    loop {
+        interval.tick().await;
+        store.flush();
+    }
+}
+
+/// Write JSON next to its destination and move it in place,
+/// so a crash never leaves a half written file behind.
+pub fn persist(path: &Path, data: &impl Serialize) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let json = serde_json::to_vec(data)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+    let temporary = path.with_extension("json.tmp");
+    std::fs::write(&temporary, json)?;
+    std::fs::rename(&temporary, path)
+}
@@ -0,0 +1,159 @@
+//! Limiting how many media streams a client has open at once, e.g. download managers opening a dozen connections
+//! per file.
+
+use axum::{
+    async_trait,
+    body::{boxed, BoxBody, Bytes, HttpBody},
+    extract::FromRequestParts,
+    http::{header, request::Parts, Extensions, HeaderMap, Method, Response, StatusCode},
+    response::{IntoResponse, Response as AxumResponse},
+};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    pin::Pin,
+    sync::{Arc, Mutex, RwLock},
+    task::{Context, Poll},
+};
+use tracing::debug;
+
+use crate::proxy::ClientIp;
+
+/// How long clients refused a stream are asked to wait before trying again, in seconds.
+const RETRY_AFTER: &str = "5";
+
+/// The streams open per client address.
+#[derive(Debug, Default)]
+pub struct StreamLimiter {
+    /// Unlimited if not set.
+    max: RwLock<Option<usize>>,
+    active: Mutex<HashMap<IpAddr, usize>>,
+}
+
+pub type SharedStreamLimiter = Arc<StreamLimiter>;
+
+impl StreamLimiter {
+    pub fn new(max: Option<usize>) -> Self {
+        Self {
+            max: RwLock::new(max),
+            ..Default::default()
+        }
+    }
+
+    /// Change the limit. Clients with more streams open keep them, but can't open another until below it.
+    pub fn set_max(&self, max: Option<usize>) {
+        *self.max.write().unwrap() = max;
+    }
+
+    /// Take a slot for a new stream of the client, or `None` if it has as many open as it may.
+    /// Streams are counted even without a limit, so one set later applies to the streams already open.
+    pub fn acquire(self: &Arc<Self>, ip: IpAddr) -> Option<StreamSlot> {
+        let max = *self.max.read().unwrap();
+        let ip = ip.to_canonical();
+        let mut active = self.active.lock().unwrap();
+        let count = active.entry(ip).or_default();
+        if max.is_some_and(|max| *count >= max) {
+            return None;
+        }
+        *count += 1;
+        Some(StreamSlot { limiter: Some(self.clone()), ip })
+    }
+
+    fn release(&self, ip: IpAddr) {
+        let mut active = self.active.lock().unwrap();
+        if let Some(count) = active.get_mut(&ip) {
+            *count -= 1;
+            if *count == 0 {
+                active.remove(&ip);
+            }
+        }
+    }
+}
+
+/// One of the streams a client has open, released when dropped.
+///
+/// As an extractor, handlers serving media refuse the request with `429 Too Many Requests` when the client has no
+/// slot left. Keep the slot open for as long as the response body with [`hold`]. `HEAD` requests, which only probe
+/// the size of a file and whether it supports ranges, don't take a slot.
+#[derive(Debug)]
+pub struct StreamSlot {
+    limiter: Option<SharedStreamLimiter>,
+    ip: IpAddr,
+}
+
+impl StreamSlot {
+    /// Take a slot for the client making a request, from the limiter in its extensions.
+    pub fn for_request(method: &Method, extensions: &Extensions, headers: &HeaderMap) -> Result<Self, TooManyStreams> {
+        let ClientIp(ip) = ClientIp::resolve(extensions, headers);
+        match extensions.get::<SharedStreamLimiter>() {
+            Some(limiter) if method != Method::HEAD => limiter.acquire(ip).ok_or(TooManyStreams(ip)),
+            _ => Ok(Self { limiter: None, ip }),
+        }
+    }
+}
+
+impl Drop for StreamSlot {
+    fn drop(&mut self) {
+        if let Some(limiter) = &self.limiter {
+            limiter.release(self.ip);
+        }
+    }
+}
+
+/// Why a client was refused a stream: it has too many open.
+#[derive(Debug)]
+pub struct TooManyStreams(pub IpAddr);
+
+impl IntoResponse for TooManyStreams {
+    fn into_response(self) -> AxumResponse {
+        debug!("Refused another stream to {}", self.0);
+        (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, RETRY_AFTER)],
+            "Too many streams are open from your address, close one first.",
+        )
+            .into_response()
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for StreamSlot
+where
+    S: Send + Sync,
+{
+    type Rejection = TooManyStreams;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Self::for_request(&parts.method, &parts.extensions, &parts.headers)
+    }
+}
+
+/// Keep a slot open until the body of the response is sent (or the client goes away).
+pub fn hold(response: Response<BoxBody>, slot: StreamSlot) -> Response<BoxBody> {
+    response.map(|body| boxed(Held { inner: body, _slot: slot }))
+}
+
+struct Held {
+    inner: BoxBody,
+    _slot: StreamSlot,
+}
+
+impl HttpBody for Held {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Bytes, axum::Error>>> {
+        Pin::new(&mut self.inner).poll_data(cx)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, axum::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+}
@@ -0,0 +1,50 @@
+//! Exporting traces over OTLP, e.g. to Jaeger or Tempo, with the `otlp` feature.
+//!
+//! The exporter is configured with the standard `OTEL_*` environment variables, and only set up when an endpoint is
+//! given with `OTEL_EXPORTER_OTLP_ENDPOINT` or `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT`.
+
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::SpanExporter;
+use opentelemetry_sdk::{trace::SdkTracerProvider, Resource};
+use tracing::Subscriber;
+use tracing_subscriber::{registry::LookupSpan, Layer};
+
+/// The service name reported unless `OTEL_SERVICE_NAME` says otherwise.
+const SERVICE_NAME: &str = "static-video-server";
+
+/// The first of the environment variables that is set and not empty.
+fn env(names: &[&str]) -> Option<String> {
+    names
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|value| !value.trim().is_empty())
+}
+
+/// A layer exporting the spans of `tracing` over OTLP, or `None` if no endpoint is configured.
+/// The protocol is `http/protobuf` unless `OTEL_EXPORTER_OTLP_PROTOCOL` is `grpc`.
+pub fn otlp_layer<S>() -> Result<Option<impl Layer<S>>, String>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    if env(&["OTEL_EXPORTER_OTLP_TRACES_ENDPOINT", "OTEL_EXPORTER_OTLP_ENDPOINT"]).is_none() {
+        return Ok(None);
+    }
+    let protocol = env(&["OTEL_EXPORTER_OTLP_TRACES_PROTOCOL", "OTEL_EXPORTER_OTLP_PROTOCOL"]);
+    let exporter = match protocol.as_deref() {
+        Some("grpc") => SpanExporter::builder().with_tonic().build(),
+        _ => SpanExporter::builder().with_http().build(),
+    }
+    .map_err(|err| err.to_string())?;
+
+    let mut resource = Resource::builder();
+    if env(&["OTEL_SERVICE_NAME"]).is_none() {
+        resource = resource.with_service_name(SERVICE_NAME);
+    }
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource.build())
+        .build();
+    let tracer = provider.tracer(SERVICE_NAME);
+    opentelemetry::global::set_tracer_provider(provider);
+    Ok(Some(tracing_opentelemetry::layer().with_tracer(tracer)))
+}
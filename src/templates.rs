@@ -0,0 +1,366 @@
+//! The HTML templates of the UI.
+//!
+//! Templates are compiled into the binary with askama. In dev mode the same files are instead
+//! loaded from disk and rendered with minijinja on every request, so UI changes show up on reload.
+//! Templates therefore stick to the syntax both engines share: plain fields, loops and conditions.
+
+use askama::Template;
+use axum::{
+    body::{boxed, Full, HttpBody},
+    extract::State,
+    http::{header, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::{Html, IntoResponse, Response},
+    Extension,
+};
+use serde::Serialize;
+use std::path::PathBuf;
+use tracing::error;
+
+use crate::{
+    i18n::{Messages, SharedMessages},
+    themes::{SharedTheme, Theme},
+    visibility::ShowHidden,
+    MediaKind, SharedState, VideoEntry,
+};
+
+/// Error messages longer than this are cut off on the error page.
+const MAX_ERROR_MESSAGE: usize = 4096;
+
+#[derive(Template, Serialize)]
+#[template(path = "index.html")]
+pub struct IndexTemplate<'a> {
+    pub t: &'a Messages,
+    pub theme: &'a Theme,
+    /// The heading of the page.
+    pub title: String,
+    pub videos: Vec<&'a VideoEntry>,
+    pub base_path: String,
+    /// The duration bucket the listing is narrowed down to, or empty for all videos.
+    pub bucket: String,
+    /// The url of the next chunk of videos, or empty if all of them are rendered.
+    pub next: String,
+    /// `list` or `grid`.
+    pub layout: String,
+    /// What was searched for, or empty.
+    pub query: String,
+    /// Links to list a single kind of media, empty if only videos are indexed.
+    pub kinds: Vec<KindLink>,
+    /// The link listing hidden videos too, or not anymore, for admins only.
+    pub visibility: Vec<KindLink>,
+}
+
+/// A link narrowing a listing down to one kind of media, or to all of them.
+#[derive(Debug, Clone, Serialize)]
+pub struct KindLink {
+    pub name: String,
+    pub href: String,
+    pub active: bool,
+}
+
+impl KindLink {
+    /// The links for the indexed kinds, given the kinds the listing is narrowed down to.
+    pub fn all(kinds: &[MediaKind], selected: Option<&str>, t: &Messages) -> Vec<Self> {
+        if kinds.len() < 2 {
+            return vec![];
+        }
+        let selected = selected.and_then(MediaKind::parse);
+        let all = Self {
+            name: t.everything.clone(),
+            href: "?".into(),
+            active: selected.is_none(),
+        };
+        std::iter::once(all)
+            .chain(kinds.iter().map(|kind| Self {
+                name: match kind {
+                    MediaKind::Video => t.videos.clone(),
+                    MediaKind::Audio => t.music.clone(),
+                    MediaKind::Image => t.images.clone(),
+                },
+                href: format!("?kind={}", kind),
+                active: selected == Some(*kind),
+            }))
+            .collect()
+    }
+
+    /// The toggle listing hidden videos too, empty unless the viewer is an admin.
+    pub fn show_hidden(show_hidden: ShowHidden, t: &Messages) -> Vec<Self> {
+        if !show_hidden.admin {
+            return vec![];
+        }
+        vec![Self {
+            name: t.show_hidden.clone(),
+            href: if show_hidden.requested { "?".into() } else { "?show_hidden=true".into() },
+            active: show_hidden.requested,
+        }]
+    }
+}
+
+/// The rows of one chunk of the index, loaded as the viewer scrolls.
+#[derive(Template, Serialize)]
+#[template(path = "video_chunk.html")]
+pub struct VideoChunkTemplate<'a> {
+    pub t: &'a Messages,
+    pub videos: Vec<&'a VideoEntry>,
+    pub base_path: String,
+    /// The url of the chunk after this one, or empty if this is the last.
+    pub next: String,
+    /// `list` or `grid`.
+    pub layout: String,
+}
+
+/// A folder linked from the browse page: a subfolder, or one of the breadcrumbs.
+#[derive(Debug, Clone, Serialize)]
+pub struct FolderLink {
+    pub name: String,
+    pub href: String,
+}
+
+#[derive(Template, Serialize)]
+#[template(path = "browse.html")]
+pub struct BrowseTemplate<'a> {
+    pub t: &'a Messages,
+    pub theme: &'a Theme,
+    /// The folder relative to the root, empty for the root itself.
+    pub path: String,
+    /// The root and every folder down to this one, this one last.
+    pub breadcrumbs: Vec<FolderLink>,
+    pub folders: Vec<FolderLink>,
+    pub videos: Vec<&'a VideoEntry>,
+    pub base_path: String,
+    /// The link listing hidden folders and videos too, or not anymore, for admins only.
+    pub visibility: Vec<KindLink>,
+}
+
+/// The page of a single video, with the metadata for previews of links to it in chat apps and social networks.
+#[derive(Template, Serialize)]
+#[template(path = "watch.html")]
+pub struct WatchTemplate<'a> {
+    pub t: &'a Messages,
+    pub theme: &'a Theme,
+    pub video: &'a VideoEntry,
+    pub base_path: String,
+    /// The absolute urls of this page, the video and its poster.
+    pub page_url: String,
+    pub video_url: String,
+    pub poster_url: String,
+    /// Where to discover the oEmbed of this page.
+    pub oembed_url: String,
+    /// The MIME type of the video, e.g. `video/mp4`.
+    pub content_type: String,
+    /// The duration in whole seconds, width and height of the video, each empty if unknown.
+    pub duration: String,
+    pub width: String,
+    pub height: String,
+    /// The title, page, media and poster urls of the video after this one in its folder, played once this one ends.
+    /// All empty if this is the last one.
+    pub next_title: String,
+    pub next_page: String,
+    pub next_media: String,
+    pub next_poster: String,
+    /// The access token the page was opened with, as a query string for the urls of the video, or empty.
+    pub token_query: String,
+}
+
+/// Just the player of a video, to be embedded in other sites.
+#[derive(Template, Serialize)]
+#[template(path = "embed.html")]
+pub struct EmbedTemplate<'a> {
+    pub t: &'a Messages,
+    pub video: &'a VideoEntry,
+    pub base_path: String,
+    pub content_type: String,
+    /// The access token the player was opened with, as a query string for the urls of the video, or empty.
+    pub token_query: String,
+}
+
+/// The share link of a video, with its QR code.
+#[derive(Template, Serialize)]
+#[template(path = "share.html")]
+pub struct ShareTemplate<'a> {
+    pub t: &'a Messages,
+    pub theme: &'a Theme,
+    pub video: &'a VideoEntry,
+    /// The heading, naming the video.
+    pub title: String,
+    pub base_path: String,
+    pub link: String,
+    /// The QR code of the link, as an SVG element.
+    pub qr_code: String,
+    /// Whether the link is signed and expires, rather than the watch page.
+    pub signed: bool,
+    /// Who can play the video with a signed link, and for how long.
+    pub notice: String,
+}
+
+/// The dashboard of what is watched the most.
+#[derive(Template, Serialize)]
+#[template(path = "stats.html")]
+pub struct StatsTemplate<'a> {
+    pub t: &'a Messages,
+    pub theme: &'a Theme,
+    pub base_path: String,
+    pub top: Vec<TopRow>,
+    pub recent: Vec<RecentPlay>,
+    /// By the hour, oldest first.
+    pub traffic: Vec<TrafficBar>,
+    /// The bytes served of all videos, e.g. `1.5 GiB served in total.`
+    pub total_served: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TopRow {
+    pub id: String,
+    pub title: String,
+    pub views: u64,
+    /// The bytes served, e.g. `1.5 GiB`.
+    pub served: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RecentPlay {
+    pub id: String,
+    pub title: String,
+    /// How long ago the play started, e.g. `5 min ago`.
+    pub when: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TrafficBar {
+    /// The hour of the day (in UTC), the plays and the bytes served.
+    pub label: String,
+    /// The bytes served relative to the busiest hour shown.
+    pub percent: u64,
+}
+
+/// The page shown to browsers instead of a bare error status and message.
+#[derive(Template, Serialize)]
+#[template(path = "error.html")]
+pub struct ErrorTemplate<'a> {
+    pub t: &'a Messages,
+    pub theme: &'a Theme,
+    pub status: u16,
+    /// The canonical reason of the status, e.g. `Not Found`.
+    pub reason: String,
+    pub message: String,
+    pub base_path: String,
+}
+
+/// Renders templates either from the compiled-in askama templates
+/// or, in dev mode, from the template files on disk.
+#[derive(Debug, Clone, Default)]
+pub struct Renderer {
+    templates_dir: Option<PathBuf>,
+}
+
+impl Renderer {
+    /// A renderer using the templates compiled into the binary.
+    pub fn compiled() -> Self {
+        Self::default()
+    }
+
+    /// A renderer that reads the templates from a directory on every render.
+    pub fn dev(templates_dir: PathBuf) -> Self {
+        Self {
+            templates_dir: Some(templates_dir),
+        }
+    }
+
+    /// Whether templates are loaded from disk, in which case rendered pages shouldn't be cached.
+    pub fn is_dev(&self) -> bool {
+        self.templates_dir.is_some()
+    }
+
+    /// Render a template, looking it up by `name` in dev mode.
+    pub fn render<T: Template + Serialize>(&self, name: &str, template: &T) -> Result<String, String> {
+        match &self.templates_dir {
+            None => template.render().map_err(|err| err.to_string()),
+            Some(templates_dir) => {
+                let mut environment = minijinja::Environment::new();
+                environment.set_loader(minijinja::path_loader(templates_dir));
+                environment
+                    .get_template(name)
+                    .and_then(|dev_template| dev_template.render(template))
+                    .map_err(|err| format!("{:#}", err))
+            }
+        }
+    }
+
+    /// Render a template into a page, or into an internal server error if that fails.
+    pub fn page<T: Template + Serialize>(&self, name: &str, template: &T) -> Response {
+        match self.render(name, template) {
+            Ok(html) => Html(html).into_response(),
+            Err(err) => render_failed(err),
+        }
+    }
+}
+
+/// The response for a template that failed to render.
+pub fn render_failed(err: String) -> Response {
+    error!("Failed to render template: {}", err);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        format!("Failed to render template. Error: {}", err),
+    )
+        .into_response()
+}
+
+/// Turn error responses into the error page for browsers, keeping their status, headers and message.
+/// Other clients (the API, players, ...) keep getting the plain message.
+pub async fn error_pages<B>(
+    State(state): State<SharedState>,
+    Extension(renderer): Extension<Renderer>,
+    Extension(t): Extension<SharedMessages>,
+    Extension(theme): Extension<SharedTheme>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let wants_html = request
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"));
+    let response = next.run(request).await;
+    let status = response.status();
+    let is_html = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("text/html"));
+    if !wants_html || is_html || !(status.is_client_error() || status.is_server_error()) {
+        return response;
+    }
+
+    let (mut parts, mut body) = response.into_parts();
+    let mut message = Vec::new();
+    while let Some(Ok(chunk)) = body.data().await {
+        message.extend_from_slice(&chunk);
+        if message.len() > MAX_ERROR_MESSAGE {
+            message.truncate(MAX_ERROR_MESSAGE);
+            break;
+        }
+    }
+    let message = String::from_utf8_lossy(&message).trim().to_string();
+    let template = ErrorTemplate {
+        t: &t,
+        theme: &theme,
+        status: status.as_u16(),
+        reason: status.canonical_reason().unwrap_or_default().to_string(),
+        message: message.clone(),
+        base_path: state.lock().unwrap().base_path.clone(),
+    };
+    let body = match renderer.render("error.html", &template) {
+        Ok(html) => {
+            parts
+                .headers
+                .insert(header::CONTENT_TYPE, HeaderValue::from_static("text/html; charset=utf-8"));
+            html
+        }
+        Err(err) => {
+            error!("Failed to render the error page: {}", err);
+            message
+        }
+    };
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, boxed(Full::from(body)))
+}
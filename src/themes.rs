@@ -0,0 +1,173 @@
+//! The look of the pages: the built-in dark and light themes, `auto` following the system's color scheme, and custom
+//! themes from `--themes-dir`.
+//!
+//! A custom theme is a CSS file setting the variables of `assets/index.css`, named after the theme, e.g.
+//! `solarized.css`:
+//!
+//! ```css
+//! --background: #002b36;
+//! --text: #eee8d5;
+//! ```
+//!
+//! Variables it leaves out keep their dark values. The theme is picked with `?theme=light`, which is remembered in a
+//! cookie, and is rendered into the pages, so they don't flash in another theme first.
+
+use axum::{
+    extract::State,
+    http::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
+use serde::Serialize;
+use std::{collections::BTreeMap, path::Path, sync::Arc};
+
+use crate::{i18n::SharedMessages, session::CurrentSession};
+
+/// The name of the cookie that remembers the theme a viewer picked.
+pub const THEME_COOKIE: &str = "svs_theme";
+
+/// The themes defined in `assets/index.css`.
+const BUILT_IN: [&str; 3] = ["dark", "light", "auto"];
+
+/// The built-in themes and the custom ones.
+#[derive(Debug, Clone)]
+pub struct Themes {
+    /// The theme of viewers that didn't pick one.
+    default: String,
+    /// The CSS declarations of each custom theme, by name.
+    custom: BTreeMap<String, String>,
+}
+
+pub type SharedThemes = Arc<Themes>;
+
+impl Themes {
+    /// The built-in themes and those in a directory, defaulting to the given one.
+    pub fn load(dir: Option<&Path>, default: &str) -> Result<Self, String> {
+        let mut custom = BTreeMap::new();
+        if let Some(dir) = dir {
+            let entries = std::fs::read_dir(dir).map_err(|err| format!("Can't read {}: {}", dir.display(), err))?;
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.extension().and_then(|extension| extension.to_str()) != Some("css") {
+                    continue;
+                }
+                let Some(name) = path.file_stem().and_then(|name| name.to_str()).filter(|name| is_valid_name(name))
+                else {
+                    return Err(format!("{} isn't a valid theme name: use letters, digits and dashes", path.display()));
+                };
+                let css =
+                    std::fs::read_to_string(&path).map_err(|err| format!("Can't read {}: {}", path.display(), err))?;
+                // Rendered into a <style> element, which it mustn't be able to close.
+                if css.contains('<') {
+                    return Err(format!("{} can't contain '<'", path.display()));
+                }
+                custom.insert(name.to_string(), css.trim().to_string());
+            }
+        }
+        let themes = Self {
+            default: default.to_string(),
+            custom,
+        };
+        if !themes.knows(default) {
+            return Err(format!("There is no theme {}, only {}", default, themes.names().join(", ")));
+        }
+        Ok(themes)
+    }
+
+    fn knows(&self, name: &str) -> bool {
+        BUILT_IN.contains(&name) || self.custom.contains_key(name)
+    }
+
+    fn names(&self) -> Vec<&str> {
+        BUILT_IN.into_iter().chain(self.custom.keys().map(String::as_str)).collect()
+    }
+
+    /// The theme of a page, with the links to switch to the others.
+    fn theme(&self, name: &str, t: Option<&SharedMessages>) -> Theme {
+        Theme {
+            name: name.to_string(),
+            css: self.custom.get(name).cloned().unwrap_or_default(),
+            links: self
+                .names()
+                .into_iter()
+                .map(|other| ThemeLink {
+                    label: match (other, t) {
+                        ("dark", Some(t)) => t.dark.clone(),
+                        ("light", Some(t)) => t.light.clone(),
+                        ("auto", Some(t)) => t.auto.clone(),
+                        _ => other.to_string(),
+                    },
+                    href: format!("?theme={}", other),
+                    active: other == name,
+                })
+                .collect(),
+        }
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+/// The theme a page is rendered in.
+#[derive(Debug, Clone, Serialize)]
+pub struct Theme {
+    pub name: String,
+    /// The declarations of a custom theme, empty for the built-in ones.
+    pub css: String,
+    pub links: Vec<ThemeLink>,
+}
+
+/// A link switching to a theme.
+#[derive(Debug, Clone, Serialize)]
+pub struct ThemeLink {
+    pub label: String,
+    pub href: String,
+    pub active: bool,
+}
+
+pub type SharedTheme = Arc<Theme>;
+
+/// Attach the [`Theme`] to every request: the one picked with `?theme=light` (which is then remembered in a cookie,
+/// and for logged in users in their session), or else the one remembered, or else the default one. Comes after
+/// [`crate::i18n::choose_language`], to name the themes in the language of the page.
+pub async fn choose_theme<B>(
+    State(themes): State<SharedThemes>,
+    jar: CookieJar,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let picked = request
+        .uri()
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("theme="))
+        .filter(|name| themes.knows(name))
+        .map(str::to_string);
+    let session = request.extensions().get::<CurrentSession>().cloned();
+    let remembered = session
+        .as_ref()
+        .and_then(|session| session.preference("theme"))
+        .or_else(|| jar.get(THEME_COOKIE).map(|cookie| cookie.value().to_string()))
+        .filter(|name| themes.knows(name));
+    let name = picked.clone().or(remembered.clone()).unwrap_or_else(|| themes.default.clone());
+    let theme = themes.theme(&name, request.extensions().get::<SharedMessages>());
+    request.extensions_mut().insert(Arc::new(theme));
+    let response = next.run(request).await;
+    match picked {
+        Some(picked) if Some(&picked) != remembered.as_ref() => {
+            if let Some(session) = session {
+                session.remember("theme", &picked);
+            }
+            let cookie = Cookie::build(THEME_COOKIE, picked)
+                .path("/")
+                .same_site(SameSite::Lax)
+                .permanent()
+                .finish();
+            (jar.add(cookie), response).into_response()
+        }
+        _ => response,
+    }
+}
@@ -0,0 +1,188 @@
+//! Timeouts for connections, so stalled or idle clients don't hold on to sockets, file handles and workers forever.
+//!
+//! Hyper only knows how long to wait for request headers. Connections are closed once idle with [`IdleTimeout`]:
+//! nothing was read or written for a while and no request is being handled.
+
+use axum_server::{accept::Accept, HttpConfig};
+use futures_util::future::BoxFuture;
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::{Instant, Sleep},
+};
+use tower::Service;
+
+use crate::VideoPlayerConfig;
+
+/// The HTTP settings of the listeners.
+pub fn http_config(config: &VideoPlayerConfig) -> HttpConfig {
+    let mut http = HttpConfig::new();
+    if let Some(timeout) = config.header_read_timeout {
+        http.http1_header_read_timeout(Duration::from_secs(timeout));
+    }
+    http.build()
+}
+
+/// An acceptor closing the connections of another once they are idle for longer than the timeout, if any.
+#[derive(Debug, Clone)]
+pub struct IdleTimeout<A> {
+    inner: A,
+    timeout: Option<Duration>,
+}
+
+impl<A> IdleTimeout<A> {
+    pub fn new(inner: A, timeout: Option<Duration>) -> Self {
+        Self { inner, timeout }
+    }
+}
+
+impl<A, I, S> Accept<I, S> for IdleTimeout<A>
+where
+    A: Accept<IdleStream<I>, Tracked<S>>,
+{
+    type Stream = A::Stream;
+    type Service = A::Service;
+    type Future = A::Future;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let stream = IdleStream {
+            inner: stream,
+            timeout: self.timeout,
+            in_flight: in_flight.clone(),
+            deadline: self
+                .timeout
+                .map(|timeout| Box::pin(tokio::time::sleep(timeout))),
+        };
+        self.inner.accept(stream, Tracked { inner: service, in_flight })
+    }
+}
+
+/// A connection that fails with `TimedOut` once it is idle for too long.
+pub struct IdleStream<I> {
+    inner: I,
+    timeout: Option<Duration>,
+    /// The requests of the connection that are being handled, i.e. that have no response yet.
+    in_flight: Arc<AtomicUsize>,
+    deadline: Option<Pin<Box<Sleep>>>,
+}
+
+impl<I> IdleStream<I> {
+    /// Push the deadline back after data was read or written.
+    fn touch(&mut self) {
+        if let (Some(timeout), Some(deadline)) = (self.timeout, self.deadline.as_mut()) {
+            deadline.as_mut().reset(Instant::now() + timeout);
+        }
+    }
+
+    /// Whether the connection is idle for too long, while it is waiting for the client.
+    fn check(&mut self, cx: &mut Context<'_>) -> io::Result<()> {
+        let (Some(timeout), Some(deadline)) = (self.timeout, self.deadline.as_mut()) else {
+            return Ok(());
+        };
+        if deadline.as_mut().poll(cx).is_pending() {
+            return Ok(());
+        }
+        if self.in_flight.load(Ordering::Acquire) == 0 {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "The connection was idle for too long"));
+        }
+        // Handling a request, e.g. waiting for a job: check again later.
+        deadline.as_mut().reset(Instant::now() + timeout);
+        let _ = deadline.as_mut().poll(cx);
+        Ok(())
+    }
+}
+
+impl<I: AsyncRead + Unpin> AsyncRead for IdleStream<I> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        match Pin::new(&mut self.inner).poll_read(cx, buf) {
+            Poll::Ready(result) => {
+                self.touch();
+                Poll::Ready(result)
+            }
+            Poll::Pending => match self.check(cx) {
+                Ok(()) => Poll::Pending,
+                Err(err) => Poll::Ready(Err(err)),
+            },
+        }
+    }
+}
+
+impl<I: AsyncWrite + Unpin> AsyncWrite for IdleStream<I> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match Pin::new(&mut self.inner).poll_write(cx, buf) {
+            Poll::Ready(result) => {
+                self.touch();
+                Poll::Ready(result)
+            }
+            Poll::Pending => match self.check(cx) {
+                Ok(()) => Poll::Pending,
+                Err(err) => Poll::Ready(Err(err)),
+            },
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// The service of a connection, counting the requests it is handling.
+#[derive(Debug, Clone)]
+pub struct Tracked<S> {
+    inner: S,
+    in_flight: Arc<AtomicUsize>,
+}
+
+/// Counts a request as handled while it is alive.
+struct InFlight(Arc<AtomicUsize>);
+
+impl InFlight {
+    fn new(in_flight: Arc<AtomicUsize>) -> Self {
+        in_flight.fetch_add(1, Ordering::AcqRel);
+        Self(in_flight)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl<S, R> Service<R> for Tracked<S>
+where
+    S: Service<R>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<S::Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: R) -> Self::Future {
+        let in_flight = InFlight::new(self.in_flight.clone());
+        let response = self.inner.call(request);
+        Box::pin(async move {
+            let _in_flight = in_flight;
+            response.await
+        })
+    }
+}
@@ -0,0 +1,118 @@
+use futures_util::StreamExt;
+use rustls::{
+    server::{AllowAnyAuthenticatedClient, ClientCertVerifier},
+    Certificate, PrivateKey, RootCertStore, ServerConfig,
+};
+use rustls_acme::{
+    acme::ACME_TLS_ALPN_NAME, axum::AxumAcceptor, caches::DirCache, AcmeConfig,
+};
+use std::{io::BufReader, path::Path, sync::Arc};
+use tracing::{error, info};
+
+use crate::VideoPlayerConfig;
+
+#[derive(Debug)]
+pub struct TlsError(String);
+
+impl std::fmt::Display for TlsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for TlsError {}
+
+fn open_pem(path: &Path) -> Result<BufReader<std::fs::File>, TlsError> {
+    std::fs::File::open(path)
+        .map(BufReader::new)
+        .map_err(|err| TlsError(format!("Failed to open {}: {}", path.display(), err)))
+}
+
+/// Load all certificates from a PEM file.
+pub fn load_certs(path: &Path) -> Result<Vec<Certificate>, TlsError> {
+    let certs = rustls_pemfile::certs(&mut open_pem(path)?)
+        .map_err(|err| TlsError(format!("Failed to parse {}: {}", path.display(), err)))?;
+    if certs.is_empty() {
+        return Err(TlsError(format!("No certificates found in {}", path.display())));
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+/// Load the first PKCS#8, RSA or EC private key from a PEM file.
+pub fn load_key(path: &Path) -> Result<PrivateKey, TlsError> {
+    let mut reader = open_pem(path)?;
+    loop {
+        match rustls_pemfile::read_one(&mut reader)
+            .map_err(|err| TlsError(format!("Failed to parse {}: {}", path.display(), err)))?
+        {
+            Some(rustls_pemfile::Item::PKCS8Key(key))
+            | Some(rustls_pemfile::Item::RSAKey(key))
+            | Some(rustls_pemfile::Item::ECKey(key)) => return Ok(PrivateKey(key)),
+            Some(_) => continue,
+            None => return Err(TlsError(format!("No private key found in {}", path.display()))),
+        }
+    }
+}
+
+/// Build a verifier that only admits clients presenting a certificate issued by one of the CAs in the bundle.
+pub fn client_verifier(ca_bundle: &Path) -> Result<Arc<dyn ClientCertVerifier>, TlsError> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(ca_bundle)? {
+        roots
+            .add(&cert)
+            .map_err(|err| TlsError(format!("Invalid CA certificate in {}: {}", ca_bundle.display(), err)))?;
+    }
+    Ok(AllowAnyAuthenticatedClient::new(roots).boxed())
+}
+
+/// Build the server config for the TLS listener from the configured certificate and key,
+/// requiring client certificates if a client CA bundle is configured.
+pub fn server_config(config: &VideoPlayerConfig, cert: &Path, key: &Path) -> Result<ServerConfig, TlsError> {
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let builder = match &config.tls_client_ca {
+        Some(ca_bundle) => builder.with_client_cert_verifier(client_verifier(ca_bundle)?),
+        None => builder.with_no_client_auth(),
+    };
+    let mut server_config = builder
+        .with_single_cert(load_certs(cert)?, load_key(key)?)
+        .map_err(|err| TlsError(format!("Invalid certificate or key: {}", err)))?;
+    server_config.alpn_protocols = alpn_protocols();
+    Ok(server_config)
+}
+
+/// The protocols we negotiate via ALPN on regular TLS connections.
+pub fn alpn_protocols() -> Vec<Vec<u8>> {
+    vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+}
+
+/// Build an acceptor that obtains and renews certificates for the configured
+/// domains from Let's Encrypt, answering TLS-ALPN-01 challenges on the listener itself.
+///
+/// A background task drives the issuance and renewal for the lifetime of the process.
+pub fn acme_acceptor(config: &VideoPlayerConfig) -> Result<AxumAcceptor, TlsError> {
+    let mut state = AcmeConfig::new(&config.acme_domain)
+        .contact(config.acme_contact.iter().map(|email| format!("mailto:{}", email)))
+        .cache(DirCache::new(config.acme_cache.clone()))
+        .directory_lets_encrypt(!config.acme_staging)
+        .state();
+
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let builder = match &config.tls_client_ca {
+        Some(ca_bundle) => builder.with_client_cert_verifier(client_verifier(ca_bundle)?),
+        None => builder.with_no_client_auth(),
+    };
+    let mut rustls_config = builder.with_cert_resolver(state.resolver());
+    rustls_config.alpn_protocols = alpn_protocols();
+    rustls_config.alpn_protocols.push(ACME_TLS_ALPN_NAME.to_vec());
+    let acceptor = state.axum_acceptor(Arc::new(rustls_config));
+
+    tokio::spawn(async move {
+        while let Some(event) = state.next().await {
+            match event {
+                Ok(event) => info!("ACME: {:?}", event),
+                Err(err) => error!("ACME: {:?}", err),
+            }
+        }
+    });
+    Ok(acceptor)
+}
@@ -0,0 +1,657 @@
+//! Resumable uploads over the [tus protocol](https://tus.io/protocols/resumable-upload), so multi-GB videos
+//! survive flaky connections, and per-user quotas so one user can't fill the shared disk.
+//!
+//! Clients that can't speak tus upload in chunks instead, and have the SHA-256 of the whole file verified at the end.
+//!
+//! Uploads are written to the data directory and moved into the library once complete.
+
+use axum::{
+    extract::{BodyStream, Path, Query, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post, put},
+    Extension, Json, Router,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures_util::StreamExt;
+use openidconnect::CsrfToken;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+use tokio::io::AsyncWriteExt;
+use tower_http::set_header::SetResponseHeaderLayer;
+use tracing::{error, info, warn};
+
+use crate::{
+    auth::Editor,
+    library::{self, LibraryResult},
+    storage::FilesystemSpace,
+    store, SharedState, VideoEntry, VideoPlayerState,
+};
+
+/// The version of the tus protocol spoken.
+pub const TUS_VERSION: &str = "1.0.0";
+
+/// The tus extensions supported: creating uploads with `POST` and cancelling them with `DELETE`.
+pub const TUS_EXTENSIONS: &str = "creation,termination";
+
+/// The name of the folder uploads in progress are kept in, inside the data directory.
+pub const UPLOADS_DIR: &str = "uploads";
+
+/// The name of the file recording who uploaded which videos, inside the uploads folder.
+const USAGE_FILE: &str = "usage.json";
+
+/// An upload in progress.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Upload {
+    pub id: String,
+    /// Who started the upload, as in [`Editor::name`].
+    pub owner: String,
+    /// The folder relative to the root the video is saved to.
+    pub folder: String,
+    /// The file name the video is saved as.
+    pub name: String,
+    /// The size of the whole video in bytes.
+    pub length: u64,
+    /// How many bytes arrived so far.
+    pub offset: u64,
+    /// When the upload was started, in seconds since the Unix epoch.
+    pub created: u64,
+    /// Chunked uploads wait for their checksum to be verified once all data arrived, tus uploads are added right away.
+    #[serde(default)]
+    pub chunked: bool,
+}
+
+/// How much of their quota a user takes up.
+#[derive(Debug, Clone, Serialize)]
+pub struct QuotaUsage {
+    /// Bytes taken up by the videos the user uploaded that are still in the library.
+    pub used: u64,
+    /// Bytes set aside for the user's uploads in progress.
+    pub reserved: u64,
+    /// `None` if uploads aren't limited.
+    pub quota: Option<u64>,
+}
+
+/// The uploads in progress of a library, and the videos uploaded so far.
+pub struct Uploads {
+    dir: PathBuf,
+    /// The bytes every user may upload, if limited.
+    quota: Option<u64>,
+    uploads: Mutex<HashMap<String, Upload>>,
+    /// The uploads a request is currently writing to.
+    busy: Mutex<HashSet<String>>,
+    /// User -> key of every video they uploaded -> its size.
+    uploaded: Mutex<HashMap<String, HashMap<String, u64>>>,
+}
+
+pub type SharedUploads = Arc<Uploads>;
+
+/// Marks an upload as being written to until dropped.
+struct Busy<'a> {
+    uploads: &'a Uploads,
+    id: String,
+}
+
+impl Drop for Busy<'_> {
+    fn drop(&mut self) {
+        self.uploads.busy.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl Uploads {
+    /// Open the uploads folder of a data directory, picking up the uploads that were in progress.
+    pub fn open(data_dir: &std::path::Path, quota: Option<u64>) -> std::io::Result<Self> {
+        let dir = data_dir.join(UPLOADS_DIR);
+        std::fs::create_dir_all(&dir)?;
+        let mut uploads = HashMap::new();
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension() != Some("json".as_ref()) || path.ends_with(USAGE_FILE) {
+                continue;
+            }
+            let upload = std::fs::read(&path)
+                .ok()
+                .and_then(|bytes| serde_json::from_slice::<Upload>(&bytes).ok());
+            let Some(mut upload) = upload else {
+                warn!("Ignoring the unreadable upload {}", path.display());
+                continue;
+            };
+            // What made it to disk is what counts, whatever was recorded before.
+            upload.offset = std::fs::metadata(dir.join(format!("{}.part", upload.id)))
+                .map(|metadata| metadata.len().min(upload.length))
+                .unwrap_or(0);
+            uploads.insert(upload.id.clone(), upload);
+        }
+        let uploaded = match std::fs::read(dir.join(USAGE_FILE)) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(err),
+        };
+        Ok(Self {
+            dir,
+            quota,
+            uploads: Mutex::new(uploads),
+            busy: Default::default(),
+            uploaded: Mutex::new(uploaded),
+        })
+    }
+
+    fn part_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.part", id))
+    }
+
+    fn info_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+
+    /// Where an upload ends up in the library.
+    fn target(state: &VideoPlayerState, folder: &str, name: &str) -> LibraryResult<PathBuf> {
+        Ok(library::resolve_library_folder(state, folder)?.join(name))
+    }
+
+    /// How much of their quota a user takes up.
+    pub fn usage(&self, owner: &str, state: &VideoPlayerState) -> QuotaUsage {
+        self.usage_with(owner, state, &self.uploads.lock().unwrap())
+    }
+
+    /// How much of their quota a user takes up, given the uploads in progress.
+    fn usage_with(&self, owner: &str, state: &VideoPlayerState, uploads: &HashMap<String, Upload>) -> QuotaUsage {
+        let videos = state.videos();
+        let sizes: HashMap<&str, u64> = videos.iter().map(|video| (video.key.as_str(), video.size)).collect();
+        let used = self
+            .uploaded
+            .lock()
+            .unwrap()
+            .get(owner)
+            .map(|keys| keys.keys().filter_map(|key| sizes.get(key.as_str())).sum())
+            .unwrap_or(0);
+        let reserved = uploads
+            .values()
+            .filter(|upload| upload.owner == owner)
+            .map(|upload| upload.length)
+            .sum();
+        QuotaUsage {
+            used,
+            reserved,
+            quota: self.quota,
+        }
+    }
+
+    /// Start an upload of `length` bytes, to be saved as `name` in `folder`.
+    pub fn create(
+        &self,
+        owner: &str,
+        folder: &str,
+        name: &str,
+        length: u64,
+        chunked: bool,
+        state: &SharedState,
+    ) -> LibraryResult<Upload> {
+        library::validate_file_name(name)?;
+        let upload = {
+            let state = state.lock().unwrap();
+            if !state.is_video_file(name) {
+                return Err((StatusCode::UNSUPPORTED_MEDIA_TYPE, format!("{} is not a video", name)));
+            }
+            // The checks and the reservation happen under one lock, so concurrent uploads can't both fit the quota.
+            let mut uploads = self.uploads.lock().unwrap();
+            let target = Self::target(&state, folder, name)?;
+            let taken = target.exists()
+                || uploads.values().any(|upload| {
+                    Self::target(&state, &upload.folder, &upload.name).is_ok_and(|other| other == target)
+                });
+            if taken {
+                return Err((StatusCode::CONFLICT, format!("{} exists already", target.display())));
+            }
+            if let Some(quota) = self.quota {
+                let usage = self.usage_with(owner, &state, &uploads);
+                if usage.used + usage.reserved + length > quota {
+                    return Err((
+                        StatusCode::PAYLOAD_TOO_LARGE,
+                        format!(
+                            "Uploading {} bytes would exceed your quota of {} bytes, of which {} are used and {} reserved",
+                            length, quota, usage.used, usage.reserved
+                        ),
+                    ));
+                }
+            }
+            let available = FilesystemSpace::of(&self.dir).map(|space| space.available).unwrap_or(u64::MAX);
+            if length > available {
+                return Err((
+                    StatusCode::INSUFFICIENT_STORAGE,
+                    format!("Only {} bytes of disk space are left for an upload of {} bytes", available, length),
+                ));
+            }
+            let upload = Upload {
+                id: CsrfToken::new_random().secret().clone(),
+                owner: owner.to_string(),
+                folder: folder.to_string(),
+                name: name.to_string(),
+                length,
+                offset: 0,
+                created: store::now(),
+                chunked,
+            };
+            uploads.insert(upload.id.clone(), upload.clone());
+            upload
+        };
+
+        let internal = |err: std::io::Error| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to start the upload: {}", err));
+        let started = std::fs::File::create(self.part_path(&upload.id))
+            .and_then(|_| store::persist(&self.info_path(&upload.id), &upload));
+        if let Err(err) = started {
+            self.uploads.lock().unwrap().remove(&upload.id);
+            let _ = std::fs::remove_file(self.part_path(&upload.id));
+            return Err(internal(err));
+        }
+        info!("{} started uploading {} ({} bytes) as {}", owner, name, length, upload.id);
+        Ok(upload)
+    }
+
+    /// An upload of the given user.
+    pub fn get(&self, id: &str, owner: &str) -> LibraryResult<Upload> {
+        self.uploads
+            .lock()
+            .unwrap()
+            .get(id)
+            .filter(|upload| upload.owner == owner)
+            .cloned()
+            .ok_or((StatusCode::NOT_FOUND, format!("No upload with id {}", id)))
+    }
+
+    /// Claim an upload for writing, so concurrent requests can't interleave their data.
+    fn claim(&self, id: &str) -> LibraryResult<Busy<'_>> {
+        if !self.busy.lock().unwrap().insert(id.to_string()) {
+            return Err((StatusCode::LOCKED, format!("Upload {} is being written to already", id)));
+        }
+        Ok(Busy {
+            uploads: self,
+            id: id.to_string(),
+        })
+    }
+
+    /// Append the data of a request to an upload that is at `offset`.
+    /// Once all of it arrived, the video is moved into the library and returned, unless the upload is chunked.
+    pub async fn append(
+        &self,
+        id: &str,
+        owner: &str,
+        offset: u64,
+        mut body: BodyStream,
+        state: &SharedState,
+    ) -> LibraryResult<(Upload, Option<VideoEntry>)> {
+        let _busy = self.claim(id)?;
+        let mut upload = self.get(id, owner)?;
+        if offset != upload.offset {
+            return Err((
+                StatusCode::CONFLICT,
+                format!("Upload {} is at offset {}, not {}", id, upload.offset, offset),
+            ));
+        }
+        let internal = |err: std::io::Error| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write the upload: {}", err));
+        let mut file = tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(self.part_path(id))
+            .await
+            .map_err(internal)?;
+        // Everything written before an error still counts, so the client can resume from there.
+        let mut failure = None;
+        while let Some(chunk) = body.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(err) => {
+                    failure = Some((StatusCode::BAD_REQUEST, format!("The upload was interrupted: {}", err)));
+                    break;
+                }
+            };
+            if upload.offset + chunk.len() as u64 > upload.length {
+                failure = Some((StatusCode::PAYLOAD_TOO_LARGE, format!("Upload {} is only {} bytes long", id, upload.length)));
+                break;
+            }
+            if let Err(err) = file.write_all(&chunk).await {
+                failure = Some(internal(err));
+                break;
+            }
+            upload.offset += chunk.len() as u64;
+        }
+        if let Err(err) = file.flush().await {
+            failure.get_or_insert(internal(err));
+        }
+        if let Some(current) = self.uploads.lock().unwrap().get_mut(id) {
+            current.offset = upload.offset;
+        }
+        if let Some(failure) = failure {
+            return Err(failure);
+        }
+        if upload.offset < upload.length || upload.chunked {
+            return Ok((upload, None));
+        }
+        let video = self.complete(&upload, state).await?;
+        Ok((upload, Some(video)))
+    }
+
+    /// Check that a chunked upload arrived in full and unharmed, and add it to the library.
+    /// A corrupted upload is dropped, since there is no telling which part of it is broken.
+    pub async fn verify(&self, id: &str, owner: &str, sha256: &str, state: &SharedState) -> LibraryResult<VideoEntry> {
+        let busy = self.claim(id)?;
+        let upload = self.get(id, owner)?;
+        if upload.offset < upload.length {
+            return Err((
+                StatusCode::CONFLICT,
+                format!("Only {} of the {} bytes of upload {} arrived", upload.offset, upload.length, id),
+            ));
+        }
+        let part = self.part_path(id);
+        let checksum = tokio::task::spawn_blocking(move || -> std::io::Result<String> {
+            let mut hasher = Sha256::new();
+            std::io::copy(&mut std::fs::File::open(part)?, &mut hasher)?;
+            Ok(format!("{:x}", hasher.finalize()))
+        })
+        .await
+        .map_err(|err| err.into())
+        .and_then(|checksum| checksum)
+        .map_err(|err: std::io::Error| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read the upload: {}", err)))?;
+        if !checksum.eq_ignore_ascii_case(sha256.trim()) {
+            drop(busy);
+            self.terminate(id, owner).await?;
+            return Err((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("Upload {} is corrupted: its SHA-256 is {}, not {}. Upload it again.", id, checksum, sha256),
+            ));
+        }
+        self.complete(&upload, state).await
+    }
+
+    /// Move a finished upload into the library and index it. Its reservation is only released once the video counts
+    /// towards the quota of the user, or once the upload is dropped because the video can't be indexed.
+    async fn complete(&self, upload: &Upload, state: &SharedState) -> LibraryResult<VideoEntry> {
+        let target = Self::target(&state.lock().unwrap(), &upload.folder, &upload.name)?;
+        if target.exists() {
+            return Err((StatusCode::CONFLICT, format!("{} exists already", target.display())));
+        }
+        let internal = |err: std::io::Error| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to save the upload: {}", err));
+        if let Some(parent) = target.parent() {
+            tokio::fs::create_dir_all(parent).await.map_err(internal)?;
+        }
+        let part = self.part_path(&upload.id);
+        // The data directory may be on another filesystem than the library.
+        if tokio::fs::rename(&part, &target).await.is_err() {
+            tokio::fs::copy(&part, &target).await.map_err(internal)?;
+            let _ = tokio::fs::remove_file(&part).await;
+        }
+        let added = state.lock().unwrap().add_video(target.clone()).cloned();
+        let video = match added {
+            Ok(video) => video,
+            Err(err) => {
+                // Nothing is left in the library that isn't indexed nor counted towards the quota.
+                warn!("Dropping upload {}, {} can't be indexed", upload.id, target.display());
+                let _ = tokio::fs::remove_file(&target).await;
+                self.forget(&upload.id).await;
+                return Err(err.into());
+            }
+        };
+        info!("{} uploaded {}", upload.owner, video.key);
+        {
+            let mut uploaded = self.uploaded.lock().unwrap();
+            uploaded
+                .entry(upload.owner.clone())
+                .or_default()
+                .insert(video.key.clone(), video.size);
+            if let Err(err) = store::persist(&self.dir.join(USAGE_FILE), &*uploaded) {
+                error!("Failed to persist the upload usage: {}", err);
+            }
+        }
+        self.forget(&upload.id).await;
+        Ok(video)
+    }
+
+    /// Drop an upload and whatever arrived of it.
+    pub async fn terminate(&self, id: &str, owner: &str) -> LibraryResult<()> {
+        let _busy = self.claim(id)?;
+        self.get(id, owner)?;
+        let _ = tokio::fs::remove_file(self.part_path(id)).await;
+        self.forget(id).await;
+        info!("{} cancelled upload {}", owner, id);
+        Ok(())
+    }
+
+    async fn forget(&self, id: &str) {
+        self.uploads.lock().unwrap().remove(id);
+        let _ = tokio::fs::remove_file(self.info_path(id)).await;
+    }
+}
+
+/// Refuse requests for another version of the protocol.
+fn check_version(headers: &HeaderMap) -> LibraryResult<()> {
+    match headers.get("tus-resumable") {
+        Some(version) if version == TUS_VERSION => Ok(()),
+        _ => Err((
+            StatusCode::PRECONDITION_FAILED,
+            format!("Only version {} of the tus protocol is supported", TUS_VERSION),
+        )),
+    }
+}
+
+fn number_header(headers: &HeaderMap, name: &str) -> LibraryResult<u64> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok())
+        .ok_or((StatusCode::BAD_REQUEST, format!("Missing or invalid {} header", name)))
+}
+
+/// Parse `Upload-Metadata`: comma separated keys, each followed by a space and its base64 encoded value.
+fn parse_metadata(value: &str) -> LibraryResult<HashMap<String, String>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, encoded) = pair.split_once(' ').unwrap_or((pair, ""));
+            let decoded = STANDARD
+                .decode(encoded.trim())
+                .ok()
+                .and_then(|bytes| String::from_utf8(bytes).ok())
+                .ok_or((StatusCode::BAD_REQUEST, format!("The {} metadata is not base64 encoded UTF-8", key)))?;
+            Ok((key.to_string(), decoded))
+        })
+        .collect()
+}
+
+/// What the server supports, for clients to discover before uploading.
+pub async fn upload_options(Extension(uploads): Extension<SharedUploads>) -> Response {
+    let mut response = (StatusCode::NO_CONTENT, [("tus-extension", TUS_EXTENSIONS)]).into_response();
+    if let Some(quota) = uploads.quota {
+        response.headers_mut().insert("tus-max-size", HeaderValue::from(quota));
+    }
+    response
+}
+
+/// Start an upload. Takes `Upload-Length` and `Upload-Metadata` with a `filename` and optionally a `folder`.
+pub async fn create_upload(
+    editor: Editor,
+    State(state): State<SharedState>,
+    Extension(uploads): Extension<SharedUploads>,
+    headers: HeaderMap,
+) -> LibraryResult<Response> {
+    check_version(&headers)?;
+    if headers.contains_key("upload-defer-length") {
+        return Err((StatusCode::BAD_REQUEST, "Uploads of unknown length are not supported".to_string()));
+    }
+    let length = number_header(&headers, "upload-length")?;
+    let metadata = headers
+        .get("upload-metadata")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let metadata = parse_metadata(metadata)?;
+    let name = metadata
+        .get("filename")
+        .ok_or((StatusCode::BAD_REQUEST, "Upload-Metadata needs a filename".to_string()))?;
+    let folder = metadata.get("folder").map(String::as_str).unwrap_or_default();
+    let upload = uploads.create(&editor.name(), folder, name, length, false, &state)?;
+    let location = format!("{}/api/uploads/{}", state.lock().unwrap().base_path, upload.id);
+    Ok((StatusCode::CREATED, [(header::LOCATION, location)]).into_response())
+}
+
+/// How much of an upload arrived, to resume it from there.
+pub async fn upload_status(
+    Path(upload_id): Path<String>,
+    editor: Editor,
+    Extension(uploads): Extension<SharedUploads>,
+    headers: HeaderMap,
+) -> LibraryResult<Response> {
+    check_version(&headers)?;
+    let upload = uploads.get(&upload_id, &editor.name())?;
+    Ok([
+        (HeaderName::from_static("upload-offset"), upload.offset.to_string()),
+        (HeaderName::from_static("upload-length"), upload.length.to_string()),
+        (header::CACHE_CONTROL, "no-store".to_string()),
+    ]
+    .into_response())
+}
+
+/// Append to an upload from the offset given in `Upload-Offset`.
+pub async fn append_upload(
+    Path(upload_id): Path<String>,
+    editor: Editor,
+    State(state): State<SharedState>,
+    Extension(uploads): Extension<SharedUploads>,
+    headers: HeaderMap,
+    body: BodyStream,
+) -> LibraryResult<Response> {
+    check_version(&headers)?;
+    if headers.get(header::CONTENT_TYPE).is_none_or(|value| value != "application/offset+octet-stream") {
+        return Err((
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Upload data must be sent as application/offset+octet-stream".to_string(),
+        ));
+    }
+    let offset = number_header(&headers, "upload-offset")?;
+    let (upload, _video) = uploads.append(&upload_id, &editor.name(), offset, body, &state).await?;
+    Ok((StatusCode::NO_CONTENT, [("upload-offset", upload.offset.to_string())]).into_response())
+}
+
+/// Cancel an upload.
+pub async fn terminate_upload(
+    Path(upload_id): Path<String>,
+    editor: Editor,
+    Extension(uploads): Extension<SharedUploads>,
+    headers: HeaderMap,
+) -> LibraryResult<StatusCode> {
+    // Chunked uploads are cancelled the same way, without speaking tus.
+    if headers.contains_key("tus-resumable") {
+        check_version(&headers)?;
+    }
+    uploads.terminate(&upload_id, &editor.name()).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// An upload of the requesting user, e.g. to find out where to continue a chunked upload.
+pub async fn get_upload(
+    Path(upload_id): Path<String>,
+    editor: Editor,
+    Extension(uploads): Extension<SharedUploads>,
+) -> LibraryResult<Json<Upload>> {
+    Ok(Json(uploads.get(&upload_id, &editor.name())?))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChunkedUploadRequest {
+    pub filename: String,
+    /// The folder relative to the root, the root itself by default.
+    #[serde(default)]
+    pub folder: String,
+    /// The size of the whole video in bytes.
+    pub length: u64,
+}
+
+/// Start a chunked upload.
+pub async fn create_chunked_upload(
+    editor: Editor,
+    State(state): State<SharedState>,
+    Extension(uploads): Extension<SharedUploads>,
+    Json(request): Json<ChunkedUploadRequest>,
+) -> LibraryResult<(StatusCode, Json<Upload>)> {
+    let upload = uploads.create(
+        &editor.name(),
+        &request.folder,
+        &request.filename,
+        request.length,
+        true,
+        &state,
+    )?;
+    Ok((StatusCode::CREATED, Json(upload)))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChunkQuery {
+    /// Where the chunk starts, which must be where the previous one ended.
+    pub offset: u64,
+}
+
+/// Append a chunk to a chunked upload.
+pub async fn upload_chunk(
+    Path(upload_id): Path<String>,
+    editor: Editor,
+    State(state): State<SharedState>,
+    Extension(uploads): Extension<SharedUploads>,
+    Query(chunk): Query<ChunkQuery>,
+    body: BodyStream,
+) -> LibraryResult<Json<Upload>> {
+    let (upload, _) = uploads.append(&upload_id, &editor.name(), chunk.offset, body, &state).await?;
+    Ok(Json(upload))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CompleteRequest {
+    /// The hex encoded SHA-256 of the whole video.
+    pub sha256: String,
+}
+
+/// Verify a chunked upload against its checksum and add it to the library.
+pub async fn complete_chunked_upload(
+    Path(upload_id): Path<String>,
+    editor: Editor,
+    State(state): State<SharedState>,
+    Extension(uploads): Extension<SharedUploads>,
+    Json(request): Json<CompleteRequest>,
+) -> LibraryResult<(StatusCode, Json<VideoEntry>)> {
+    let video = uploads.verify(&upload_id, &editor.name(), &request.sha256, &state).await?;
+    Ok((StatusCode::CREATED, Json(video)))
+}
+
+/// How much of their quota the requesting user takes up.
+pub async fn upload_usage(
+    editor: Editor,
+    State(state): State<SharedState>,
+    Extension(uploads): Extension<SharedUploads>,
+) -> Json<QuotaUsage> {
+    Json(uploads.usage(&editor.name(), &state.lock().unwrap()))
+}
+
+pub fn uploads_router() -> Router<SharedState> {
+    Router::new()
+        .route("/api/uploads", post(create_upload).options(upload_options))
+        .route("/api/uploads/usage", get(upload_usage))
+        .route("/api/uploads/chunked", post(create_chunked_upload))
+        .route(
+            "/api/uploads/:upload_id",
+            get(get_upload).head(upload_status).patch(append_upload).delete(terminate_upload),
+        )
+        .route("/api/uploads/:upload_id/chunks", put(upload_chunk))
+        .route("/api/uploads/:upload_id/complete", post(complete_chunked_upload))
+        .layer(SetResponseHeaderLayer::overriding(
+            HeaderName::from_static("tus-resumable"),
+            HeaderValue::from_static(TUS_VERSION),
+        ))
+        .layer(SetResponseHeaderLayer::overriding(
+            HeaderName::from_static("tus-version"),
+            HeaderValue::from_static(TUS_VERSION),
+        ))
+}
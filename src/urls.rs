@@ -0,0 +1,213 @@
+//! Encoding of file and folder names into urls and headers, and decoding them back.
+//!
+//! Names may contain spaces, `#`, `?`, `%`, any unicode, or (on old network shares) bytes that aren't UTF-8
+//! at all, so they are always percent-encoded byte for byte, and decoded without assuming UTF-8.
+
+use axum::http::{header, HeaderMap};
+use percent_encoding::{percent_decode_str, percent_encode, AsciiSet, CONTROLS, NON_ALPHANUMERIC};
+use std::{
+    borrow::Cow,
+    ffi::{OsStr, OsString},
+    path::{Component, Path, PathBuf},
+};
+
+/// Characters that must be escaped in a path segment of an href.
+const SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}')
+    .add(b'/')
+    .add(b'&')
+    .add(b'\'');
+
+/// Characters that must be escaped in an extended header parameter (RFC 8187), e.g. `filename*`.
+const HEADER_PARAMETER: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'!')
+    .remove(b'#')
+    .remove(b'$')
+    .remove(b'&')
+    .remove(b'+')
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'^')
+    .remove(b'_')
+    .remove(b'`')
+    .remove(b'|')
+    .remove(b'~');
+
+/// Characters that must be escaped in a query parameter value.
+const QUERY_VALUE: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_').remove(b'~');
+
+/// The bytes of a file name. Names are not necessarily UTF-8, e.g. on old network shares.
+fn name_bytes(name: &OsStr) -> Cow<'_, [u8]> {
+    #[cfg(unix)]
+    {
+        Cow::Borrowed(std::os::unix::ffi::OsStrExt::as_bytes(name))
+    }
+    #[cfg(not(unix))]
+    {
+        match name.to_string_lossy() {
+            Cow::Borrowed(name) => Cow::Borrowed(name.as_bytes()),
+            Cow::Owned(name) => Cow::Owned(name.into_bytes()),
+        }
+    }
+}
+
+/// Percent-encode a single path segment, e.g. a file name.
+pub fn encode_segment(segment: &OsStr) -> String {
+    percent_encode(&name_bytes(segment), SEGMENT).to_string()
+}
+
+/// Percent-encode a value to pass as a query parameter, e.g. a url.
+pub fn encode_query_value(value: &str) -> String {
+    percent_encode(value.as_bytes(), QUERY_VALUE).to_string()
+}
+
+/// Decode a percent-encoded url path into a relative path, keeping names that aren't UTF-8 intact.
+pub fn decode_path(encoded: &str) -> PathBuf {
+    let bytes: Vec<u8> = percent_decode_str(encoded.trim_matches('/')).collect();
+    #[cfg(unix)]
+    let path: OsString = std::os::unix::ffi::OsStringExt::from_vec(bytes);
+    #[cfg(not(unix))]
+    let path = OsString::from(String::from_utf8_lossy(&bytes).into_owned());
+    PathBuf::from(path)
+}
+
+/// Percent-encode every segment of a relative path and append it to a prefix.
+pub fn encode_href(prefix: &str, relative: &Path, is_dir: bool) -> String {
+    let mut href = prefix.to_string();
+    for component in relative.components() {
+        if let Component::Normal(segment) = component {
+            href.push('/');
+            href.push_str(&encode_segment(segment));
+        }
+    }
+    if is_dir {
+        href.push('/');
+    }
+    href
+}
+
+/// A `Content-Disposition` header value that names a file, e.g. for `inline` playback or an `attachment`.
+/// Clients that don't understand the encoded `filename*` fall back to an ASCII approximation.
+pub fn content_disposition(disposition: &str, name: &OsStr) -> String {
+    let fallback: String = name
+        .to_string_lossy()
+        .chars()
+        .map(|c| match c {
+            ' '..='~' if c != '"' && c != '\\' => c,
+            _ => '_',
+        })
+        .collect();
+    format!(
+        "{}; filename=\"{}\"; filename*=UTF-8''{}",
+        disposition,
+        fallback,
+        percent_encode(name.to_string_lossy().as_bytes(), HEADER_PARAMETER)
+    )
+}
+
+/// How to build absolute urls to the server, for links that are used outside of its pages, e.g. in link previews.
+#[derive(Debug, Clone, Default)]
+pub struct PublicUrl {
+    /// The configured `--public-url`, without a trailing slash.
+    configured: Option<String>,
+    /// Whether the server itself serves HTTPS.
+    https: bool,
+}
+
+impl PublicUrl {
+    pub fn new(configured: Option<&str>, https: bool) -> Self {
+        Self {
+            configured: configured.map(|url| url.trim_end_matches('/').to_string()),
+            https,
+        }
+    }
+
+    /// The absolute url of the root of the library, without a trailing slash: the configured one, or else the one
+    /// the request was sent to.
+    pub fn resolve(&self, headers: &HeaderMap, base_path: &str) -> String {
+        if let Some(url) = &self.configured {
+            return url.clone();
+        }
+        let host = headers
+            .get(header::HOST)
+            .and_then(|host| host.to_str().ok())
+            .unwrap_or("localhost");
+        let scheme = headers
+            .get("x-forwarded-proto")
+            .and_then(|proto| proto.to_str().ok())
+            .filter(|proto| *proto == "http" || *proto == "https")
+            .unwrap_or(if self.https { "https" } else { "http" });
+        format!("{}://{}{}", scheme, host, base_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::response::{IntoResponse, Redirect};
+
+    const TRICKY: [&str; 7] = [
+        "a video.mp4",
+        "episode #1.mkv",
+        "what?.webm",
+        "100% real.mp4",
+        "one+one=two.mp4",
+        "東京 の 夜.mp4",
+        "shows/シーズン 2/#3 finale?.mkv",
+    ];
+
+    fn round_trip(relative: &Path) -> PathBuf {
+        let href = encode_href("/browse", relative, false);
+        decode_path(href.strip_prefix("/browse").unwrap())
+    }
+
+    #[test]
+    fn encodes_tricky_names() {
+        assert_eq!(encode_href("", Path::new("a video.mp4"), false), "/a%20video.mp4");
+        assert_eq!(encode_href("", Path::new("episode #1.mkv"), false), "/episode%20%231.mkv");
+        assert_eq!(encode_href("", Path::new("what?.webm"), false), "/what%3F.webm");
+        assert_eq!(encode_href("", Path::new("100% real.mp4"), false), "/100%25%20real.mp4");
+        assert_eq!(encode_href("/base", Path::new("shows/東京"), true), "/base/shows/%E6%9D%B1%E4%BA%AC/");
+    }
+
+    #[test]
+    fn decodes_what_it_encodes() {
+        for name in TRICKY {
+            assert_eq!(round_trip(Path::new(name)), PathBuf::from(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn keeps_plus_signs() {
+        assert_eq!(decode_path("/one+one%3Dtwo.mp4"), PathBuf::from("one+one=two.mp4"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_names_that_are_not_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let name = OsStr::from_bytes(b"caf\xe9 #1.mp4");
+        let href = encode_href("", Path::new(name), false);
+        assert_eq!(href, "/caf%E9%20%231.mp4");
+        assert_eq!(decode_path(&href), PathBuf::from(name));
+    }
+
+    #[test]
+    fn redirects_to_tricky_names() {
+        for name in TRICKY {
+            let response = Redirect::to(&encode_href("/browse", Path::new(name), true)).into_response();
+            let location = response.headers()[header::LOCATION].to_str().unwrap();
+            assert_eq!(decode_path(location.strip_prefix("/browse").unwrap()), PathBuf::from(name), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,240 @@
+//! Roles of the logged in users, kept in the file given with `--users`, e.g.
+//!
+//! ```toml
+//! # The role of logged in users that aren't listed.
+//! default_role = "viewer"
+//!
+//! [roles]
+//! "alice@example.com" = "admin"
+//! "248289761001" = "uploader"
+//! ```
+//!
+//! Users are looked up by subject, then by email if the provider verified it. Usernames are never used, since users
+//! can often pick them. Admins may change the library (reload, rename,
+//! move, delete, ...), uploaders may add files to it, and viewers may only browse and stream. The `--api-token` is an
+//! admin. Without `--users`, every logged in user is an admin and nothing is enforced beyond being logged in.
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, State},
+    http::{request::Parts, Method, Request, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    Extension, Router,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+use tracing::{error, warn};
+
+use crate::{auth::Editor, oidc::UserSession, SharedState};
+
+/// What a user may do, each role allowing what the ones before it do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Viewer,
+    Uploader,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Viewer => "viewer",
+            Self::Uploader => "uploader",
+            Self::Admin => "admin",
+        }
+    }
+}
+
+/// The contents of the `--users` file.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct UsersFile {
+    default_role: Option<Role>,
+    roles: HashMap<String, Role>,
+}
+
+impl UsersFile {
+    fn read(path: &Path) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path).map_err(|err| format!("Can't read {}: {}", path.display(), err))?;
+        toml::from_str(&contents).map_err(|err| format!("{} is invalid: {}", path.display(), err))
+    }
+}
+
+/// The roles of the users, if `--users` is given. It is read again when the configuration is reloaded.
+#[derive(Debug, Default)]
+pub struct UserStore {
+    path: Option<PathBuf>,
+    users: RwLock<UsersFile>,
+}
+
+pub type SharedUserStore = Arc<UserStore>;
+
+impl UserStore {
+    /// A users file that can't be read leaves every logged in user a viewer until it is fixed.
+    pub fn new(path: Option<PathBuf>) -> Self {
+        let users = match path.as_deref().map(UsersFile::read) {
+            Some(Err(err)) => {
+                error!("{}. Every logged in user is a viewer until it is fixed.", err);
+                UsersFile::default()
+            }
+            users => users.and_then(Result::ok).unwrap_or_default(),
+        };
+        Self {
+            path,
+            users: RwLock::new(users),
+        }
+    }
+
+    /// Read the file again, keeping the current roles if it is invalid.
+    pub fn reload(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        match UsersFile::read(path) {
+            Ok(users) => *self.users.write().unwrap() = users,
+            Err(err) => warn!("Keeping the current roles, {}", err),
+        }
+    }
+
+    /// Whether roles are enforced, i.e. `--users` is given.
+    pub fn is_enforced(&self) -> bool {
+        self.path.is_some()
+    }
+
+    /// The role of a logged in user.
+    pub fn role_of(&self, user: &UserSession) -> Role {
+        if !self.is_enforced() {
+            return Role::Admin;
+        }
+        let users = self.users.read().unwrap();
+        let email = user.email.as_deref().filter(|_| user.email_verified);
+        [Some(user.subject.as_str()), email]
+            .into_iter()
+            .flatten()
+            .find_map(|id| users.roles.get(id).copied())
+            .or(users.default_role)
+            .unwrap_or(Role::Viewer)
+    }
+}
+
+/// The role of the request: that of the logged in user, admin for the API token, viewer for anybody else.
+#[async_trait]
+impl<S> FromRequestParts<S> for Role
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let role = match Editor::from_request_parts(parts, state).await {
+            Ok(Editor::Token) => Role::Admin,
+            Ok(Editor::User(_)) => {
+                let users = parts.extensions.get::<SharedUserStore>();
+                match (users, parts.extensions.get::<UserSession>()) {
+                    (Some(users), Some(user)) => users.role_of(user),
+                    (None, Some(_)) => Role::Admin,
+                    _ => Role::Viewer,
+                }
+            }
+            Err(_) => Role::Viewer,
+        };
+        Ok(role)
+    }
+}
+
+async fn require_role<B>(
+    State(required): State<Role>,
+    Extension(users): Extension<SharedUserStore>,
+    role: Role,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    // Without roles, the handlers still check for an editor themselves. Preflight requests carry no credentials.
+    if !users.is_enforced() || request.method() == Method::OPTIONS || role >= required {
+        return next.run(request).await;
+    }
+    let message = format!("This needs the {} role, you are a {}.", required.as_str(), role.as_str());
+    (StatusCode::FORBIDDEN, message).into_response()
+}
+
+/// Restrict the routes of a router to users with a role, or a role that allows more.
+pub fn require(role: Role, router: Router<SharedState>) -> Router<SharedState> {
+    router.route_layer(middleware::from_fn_with_state(role, require_role))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn users(toml: &str) -> UserStore {
+        UserStore {
+            path: Some(PathBuf::from("users.toml")),
+            users: RwLock::new(toml::from_str(toml).unwrap()),
+        }
+    }
+
+    fn user(subject: &str, email: Option<&str>, email_verified: bool) -> UserSession {
+        UserSession {
+            subject: subject.to_string(),
+            name: None,
+            email: email.map(str::to_string),
+            email_verified,
+        }
+    }
+
+    const USERS: &str = r#"
+        default_role = "uploader"
+
+        [roles]
+        "1234" = "viewer"
+        "admin@example.com" = "admin"
+    "#;
+
+    #[test]
+    fn looks_up_subjects_then_verified_emails() {
+        let users = users(USERS);
+        assert_eq!(users.role_of(&user("5678", Some("admin@example.com"), true)), Role::Admin);
+        assert_eq!(users.role_of(&user("1234", Some("admin@example.com"), true)), Role::Viewer);
+        assert_eq!(users.role_of(&user("5678", None, false)), Role::Uploader);
+    }
+
+    #[test]
+    fn ignores_unverified_emails() {
+        let users = users(USERS);
+        assert_eq!(users.role_of(&user("5678", Some("admin@example.com"), false)), Role::Uploader);
+    }
+
+    #[test]
+    fn makes_unknown_users_viewers_without_a_default() {
+        let users = users(r#"roles = { "admin@example.com" = "admin" }"#);
+        assert_eq!(users.role_of(&user("5678", Some("someone@example.com"), true)), Role::Viewer);
+    }
+
+    #[test]
+    fn makes_everybody_admin_without_a_users_file() {
+        assert_eq!(UserStore::new(None).role_of(&user("5678", None, false)), Role::Admin);
+    }
+
+    #[test]
+    fn rejects_invalid_users_files() {
+        assert!(toml::from_str::<UsersFile>(r#"roles = { "1234" = "owner" }"#).is_err());
+        assert!(toml::from_str::<UsersFile>(r#"admins = ["1234"]"#).is_err());
+        let path = std::env::temp_dir().join(format!("svs-users-{}.toml", std::process::id()));
+        std::fs::write(&path, "default_role = \"owner\"").unwrap();
+        let users = UserStore::new(Some(path.clone()));
+        assert_eq!(users.role_of(&user("5678", None, false)), Role::Viewer);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn orders_roles() {
+        assert!(Role::Viewer < Role::Uploader && Role::Uploader < Role::Admin);
+    }
+}
@@ -0,0 +1,40 @@
+//! What is running: the version, commit and date of the build, and the optional features it was built with.
+
+use axum::Json;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    /// The abbreviated hash of the commit built, or `unknown` if it wasn't built from a git checkout.
+    pub commit: &'static str,
+    /// As `YYYY-MM-DD`, honoring `SOURCE_DATE_EPOCH`.
+    pub build_date: &'static str,
+    pub features: Vec<&'static str>,
+}
+
+impl BuildInfo {
+    pub fn current() -> Self {
+        let features = [
+            ("full-text", cfg!(feature = "full-text")),
+            ("http3", cfg!(feature = "http3")),
+            ("otlp", cfg!(feature = "otlp")),
+            ("sentry", cfg!(feature = "sentry")),
+            ("transcoding", cfg!(feature = "transcoding")),
+            ("ui", cfg!(feature = "ui")),
+        ];
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            commit: env!("SVS_GIT_COMMIT"),
+            build_date: env!("SVS_BUILD_DATE"),
+            features: features
+                .into_iter()
+                .filter_map(|(name, enabled)| enabled.then_some(name))
+                .collect(),
+        }
+    }
+}
+
+pub async fn version() -> Json<BuildInfo> {
+    Json(BuildInfo::current())
+}
@@ -0,0 +1,52 @@
+//! Serving several libraries from one process, picked by the `Host` of a request.
+
+use axum::{
+    body::Body,
+    http::{header, Request},
+    Router,
+};
+use std::{collections::HashMap, path::PathBuf};
+use tower::ServiceExt;
+
+/// Parse a virtual host mapping such as `family.example.com=/srv/family`.
+pub fn parse_vhost(value: &str) -> Result<(String, PathBuf), String> {
+    let (host, root) = value
+        .split_once('=')
+        .ok_or_else(|| format!("{} is not of the form <host>=<assets root>", value))?;
+    let host = host.trim().to_ascii_lowercase();
+    if host.is_empty() || root.trim().is_empty() {
+        return Err(format!("{} is not of the form <host>=<assets root>", value));
+    }
+    Ok((host, PathBuf::from(root.trim())))
+}
+
+/// The host a request is addressed to, without the port.
+/// HTTP/2 and HTTP/3 carry it in the uri rather than the `Host` header.
+fn request_host<B>(request: &Request<B>) -> Option<String> {
+    let host = match request.uri().host() {
+        Some(host) => host.to_string(),
+        None => {
+            let host = request.headers().get(header::HOST)?.to_str().ok()?;
+            match host.rsplit_once(':') {
+                Some((name, port)) if port.bytes().all(|byte| byte.is_ascii_digit()) => name.to_string(),
+                _ => host.to_string(),
+            }
+        }
+    };
+    Some(host.trim_end_matches('.').to_ascii_lowercase())
+}
+
+/// Route every request to the app of its host, or to the default app for unknown hosts.
+pub fn dispatch(default: Router, hosts: HashMap<String, Router>) -> Router {
+    Router::new().fallback(move |request: Request<Body>| {
+        let app = request_host(&request)
+            .and_then(|host| hosts.get(&host).cloned())
+            .unwrap_or_else(|| default.clone());
+        async move {
+            match app.oneshot(request).await {
+                Ok(response) => response,
+                Err(err) => match err {},
+            }
+        }
+    })
+}
@@ -0,0 +1,51 @@
+//! `.videoignore` files: gitignore-style patterns keeping files and folders out of the index.
+//!
+//! A `.videoignore` applies to the folder it is in and everything under it. Like `.gitignore` files, patterns of
+//! deeper files take precedence, so a subfolder can bring back (`!pattern`) what a parent folder ignores.
+
+use ignore::gitignore::Gitignore;
+use std::path::Path;
+use tracing::warn;
+
+pub const FILE_NAME: &str = ".videoignore";
+
+/// The `.videoignore` files that apply to a directory.
+#[derive(Debug, Clone, Default)]
+pub struct VideoIgnore {
+    /// From the root down to the directory.
+    matchers: Vec<Gitignore>,
+}
+
+impl VideoIgnore {
+    /// Read the `.videoignore` files of a directory and of its parents up to the root.
+    pub fn load(root: &Path, dir: &Path) -> Self {
+        let relative = dir.strip_prefix(root).unwrap_or(Path::new(""));
+        let mut folder = root.to_path_buf();
+        let mut matchers = vec![];
+        for component in std::iter::once(None).chain(relative.components().map(Some)) {
+            if let Some(component) = component {
+                folder.push(component);
+            }
+            let file = folder.join(FILE_NAME);
+            if !file.is_file() {
+                continue;
+            }
+            let (matcher, err) = Gitignore::new(&file);
+            if let Some(err) = err {
+                warn!("Problem with {}: {}", file.display(), err);
+            }
+            matchers.push(matcher);
+        }
+        Self { matchers }
+    }
+
+    /// Whether a file or folder inside the directory is excluded from the index.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        self.matchers
+            .iter()
+            .rev()
+            .map(|matcher| matcher.matched(path, is_dir))
+            .find(|matched| !matched.is_none())
+            .is_some_and(|matched| matched.is_ignore())
+    }
+}
@@ -0,0 +1,100 @@
+//! Who is watching: the logged in user, or else the anonymous session of the browser, see [`crate::session`].
+//! Per-viewer data such as watch progress is keyed by the viewer.
+
+use axum::{
+    http::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
+use openidconnect::CsrfToken;
+
+use crate::session::CurrentSession;
+
+/// The name of the cookie that identified anonymous viewers before there were sessions.
+pub const VIEWER_COOKIE: &str = "svs_viewer";
+
+/// The viewer behind a request, e.g. `user:<subject>` or `session:<id>`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Viewer(pub String);
+
+impl Viewer {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Attach the [`Viewer`] to every request, the one of its [`CurrentSession`].
+pub async fn identify_viewer<B>(mut request: Request<B>, next: Next<B>) -> Response {
+    let viewer = match request.extensions().get::<CurrentSession>() {
+        Some(session) => session.viewer(),
+        // Sessions are attached around every library, this is only for routers served without them.
+        None => format!("session:{}", CsrfToken::new_random().secret()),
+    };
+    request.extensions_mut().insert(Viewer(viewer));
+    next.run(request).await
+}
+
+/// The name of the cookie that remembers the layout a viewer picked.
+pub const LAYOUT_COOKIE: &str = "svs_layout";
+
+/// How listings are laid out: a detailed list, or a grid of thumbnails.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Layout {
+    #[default]
+    List,
+    Grid,
+}
+
+impl Layout {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::List => "list",
+            Self::Grid => "grid",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "list" => Some(Self::List),
+            "grid" => Some(Self::Grid),
+            _ => None,
+        }
+    }
+}
+
+/// Attach the [`Layout`] to every request: the one picked with `?view=grid` (which is then remembered
+/// in a cookie, and for logged in users in their session), or else the one remembered, or else the list.
+pub async fn choose_layout<B>(jar: CookieJar, mut request: Request<B>, next: Next<B>) -> Response {
+    let picked = request
+        .uri()
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("view="))
+        .and_then(Layout::parse);
+    let session = request.extensions().get::<CurrentSession>().cloned();
+    let remembered = session
+        .as_ref()
+        .and_then(|session| session.preference("layout"))
+        .or_else(|| jar.get(LAYOUT_COOKIE).map(|cookie| cookie.value().to_string()))
+        .and_then(|layout| Layout::parse(&layout));
+    request
+        .extensions_mut()
+        .insert(picked.or(remembered).unwrap_or_default());
+    let response = next.run(request).await;
+    match picked {
+        Some(layout) if picked != remembered => {
+            if let Some(session) = session {
+                session.remember("layout", layout.as_str());
+            }
+            let cookie = Cookie::build(LAYOUT_COOKIE, layout.as_str())
+                .path("/")
+                .same_site(SameSite::Lax)
+                .permanent()
+                .finish();
+            (jar.add(cookie), response).into_response()
+        }
+        _ => response,
+    }
+}
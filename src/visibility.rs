@@ -0,0 +1,93 @@
+//! Hidden videos: left out of the listings, the API and `/browse/`, by the `.svs.toml` of their folder or through
+//! the API, but still played by link. Admins can list them anyway with `?show_hidden=true`.
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Path, Query, State},
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+    routing::put,
+    Extension, Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+
+use crate::{auth::Editor, store::SharedIndexStore, users::Role, SharedState};
+
+#[derive(Debug, Clone, Default, Deserialize)]
+struct ShowHiddenQuery {
+    #[serde(default)]
+    show_hidden: bool,
+}
+
+/// Whether a listing includes hidden videos: only if an admin asks for it with `?show_hidden=true`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ShowHidden {
+    /// Whether the request comes from an admin, who may list hidden videos.
+    pub admin: bool,
+    pub requested: bool,
+}
+
+impl ShowHidden {
+    pub fn enabled(&self) -> bool {
+        self.admin && self.requested
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ShowHidden
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(query) = Query::<ShowHiddenQuery>::from_request_parts(parts, state)
+            .await
+            .unwrap_or_default();
+        Ok(Self {
+            admin: Role::from_request_parts(parts, state).await == Ok(Role::Admin),
+            requested: query.show_hidden,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct VisibilityUpdate {
+    /// Whether the video is hidden, or `null` to leave it to the `.svs.toml` of its folder.
+    pub hidden: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Visibility {
+    /// Whether the video is hidden now.
+    pub hidden: bool,
+    /// Whether that was set through the API rather than by the `.svs.toml` of its folder.
+    pub overridden: bool,
+}
+
+/// Hide a video or show it again, regardless of the `.svs.toml` of its folder.
+pub async fn set_visibility(
+    Path(video_id): Path<String>,
+    _editor: Editor,
+    State(state): State<SharedState>,
+    Extension(store): Extension<SharedIndexStore>,
+    Json(update): Json<VisibilityUpdate>,
+) -> Response {
+    let mut state = state.lock().unwrap();
+    let Some((key, path)) = state.get(&video_id).map(|video| (video.key.clone(), video.path.clone())) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    store.set_visibility(&key, update.hidden);
+    let hidden = update.hidden.unwrap_or_else(|| state.hidden_by_config(&path));
+    state.update_video(&video_id, |video| video.hidden = hidden);
+    Json(Visibility {
+        hidden,
+        overridden: update.hidden.is_some(),
+    })
+    .into_response()
+}
+
+pub fn visibility_router() -> Router<SharedState> {
+    Router::new().route("/api/videos/:video_id/visibility", put(set_visibility))
+}
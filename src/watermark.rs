@@ -0,0 +1,131 @@
+//! A watermark burned into converted videos, e.g. review copies shared outside without giving away the clean masters.
+
+use clap::ValueEnum;
+use std::path::PathBuf;
+
+use crate::VideoPlayerConfig;
+
+/// How far a watermark is kept from the edges of the video, in pixels.
+const MARGIN: u32 = 20;
+
+/// Where a watermark is placed on the video.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum WatermarkPosition {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    #[default]
+    BottomRight,
+    Center,
+}
+
+impl WatermarkPosition {
+    /// The ffmpeg expressions for the top left corner of the watermark, given the names of the width and height
+    /// of the video and of the watermark, which differ between the `overlay` and `drawtext` filters.
+    fn coordinates(self, video: (&str, &str), mark: (&str, &str)) -> (String, String) {
+        let ((width, height), (mark_width, mark_height)) = (video, mark);
+        let left = MARGIN.to_string();
+        let right = format!("{}-{}-{}", width, mark_width, MARGIN);
+        let top = MARGIN.to_string();
+        let bottom = format!("{}-{}-{}", height, mark_height, MARGIN);
+        match self {
+            Self::TopLeft => (left, top),
+            Self::TopRight => (right, top),
+            Self::BottomLeft => (left, bottom),
+            Self::BottomRight => (right, bottom),
+            Self::Center => (
+                format!("({}-{})/2", width, mark_width),
+                format!("({}-{})/2", height, mark_height),
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WatermarkSource {
+    /// A (transparent) PNG overlaid at its own size.
+    Image(PathBuf),
+    /// A line of white text with a shadow, sized relative to the video.
+    Text(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watermark {
+    pub source: WatermarkSource,
+    pub position: WatermarkPosition,
+    /// From 0 (invisible) to 1 (opaque).
+    pub opacity: f64,
+}
+
+/// Parse an opacity between 0 and 1.
+pub fn parse_opacity(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(opacity) if (0.0..=1.0).contains(&opacity) => Ok(opacity),
+        _ => Err(format!("{} is not a number between 0 and 1", value)),
+    }
+}
+
+/// Escape text for the `text` option of `drawtext` inside a filter graph: once for the text expansion and the option
+/// value, then once more for the filter graph.
+fn escape_text(text: &str) -> String {
+    let escape = |text: &str, special: &[char]| {
+        text.chars().fold(String::new(), |mut escaped, c| {
+            if special.contains(&c) {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+            escaped
+        })
+    };
+    let option = escape(text, &['\\', '\'', ':', '%']);
+    escape(&option, &['\\', '\'', '[', ']', ',', ';'])
+}
+
+impl Watermark {
+    /// The watermark configured with `--watermark` or `--watermark-text`, if any.
+    pub fn from_config(config: &VideoPlayerConfig) -> Option<Self> {
+        let source = match (&config.watermark, &config.watermark_text) {
+            (Some(image), _) => WatermarkSource::Image(image.clone()),
+            (None, Some(text)) => WatermarkSource::Text(text.clone()),
+            (None, None) => return None,
+        };
+        Some(Self {
+            source,
+            position: config.watermark_position,
+            opacity: config.watermark_opacity,
+        })
+    }
+
+    /// The ffmpeg options burning the watermark into the first video stream of the first input.
+    /// They add an input for an image, so they go right after the input of the video.
+    /// The result is labelled `[v]` to be mapped into the output.
+    pub fn options(&self) -> Vec<String> {
+        match &self.source {
+            WatermarkSource::Image(image) => {
+                let (x, y) = self.position.coordinates(("W", "H"), ("w", "h"));
+                let filter = format!(
+                    "[1:v]format=rgba,colorchannelmixer=aa={}[mark];[0:v:0][mark]overlay={}:{}[v]",
+                    self.opacity, x, y
+                );
+                vec![
+                    "-i".into(),
+                    image.to_string_lossy().to_string(),
+                    "-filter_complex".into(),
+                    filter,
+                ]
+            }
+            WatermarkSource::Text(text) => {
+                let (x, y) = self.position.coordinates(("w", "h"), ("tw", "th"));
+                let filter = format!(
+                    "[0:v:0]drawtext=text={}:fontsize=h/20:fontcolor=white@{}:shadowcolor=black@{}:shadowx=2:shadowy=2:x={}:y={}[v]",
+                    escape_text(text),
+                    self.opacity,
+                    self.opacity,
+                    x,
+                    y
+                );
+                vec!["-filter_complex".into(), filter]
+            }
+        }
+    }
+}
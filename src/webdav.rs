@@ -0,0 +1,234 @@
+//! A read-only WebDAV view of the indexed folder tree,
+//! so file managers and media players can mount the library directly.
+//!
+//! Only what the listings show is shared: the indexed videos, without private and hidden ones, and the folders
+//! holding them. Files the index leaves out (ignored, excluded by a `.svs.toml`, unsettled or quarantined) aren't.
+
+use axum::{
+    body::{boxed, Body, BoxBody},
+    extract::State,
+    http::{header, HeaderMap, HeaderValue, Method, Request, Response, StatusCode},
+    response::IntoResponse,
+    routing::any,
+    Router,
+};
+use std::{
+    collections::HashSet,
+    fmt::Write,
+    path::{Component, Path, PathBuf},
+};
+use tower::ServiceExt;
+use tower_http::services::ServeFile;
+
+use crate::{
+    streams::{hold, StreamSlot},
+    urls::{decode_path, encode_href},
+    SharedState,
+};
+
+/// The prefix the share is mounted at.
+pub const DAV_PREFIX: &str = "/dav";
+
+const ALLOW: &str = "OPTIONS, GET, HEAD, PROPFIND";
+
+/// A file or directory listed in a PROPFIND response.
+struct Resource {
+    href: String,
+    name: String,
+    metadata: std::fs::Metadata,
+}
+
+pub fn webdav_router() -> Router<SharedState> {
+    Router::new()
+        .route(DAV_PREFIX, any(webdav))
+        .route(&format!("{}/", DAV_PREFIX), any(webdav))
+        .route(&format!("{}/*path", DAV_PREFIX), any(webdav))
+}
+
+/// What the share serves: the videos and the folders holding them.
+struct Shared {
+    videos: HashSet<PathBuf>,
+    folders: HashSet<PathBuf>,
+}
+
+impl Shared {
+    /// Private videos are only served with a token, which WebDAV clients can't give, and hidden ones stay hidden.
+    fn new(videos: &[crate::VideoEntry], root: &Path) -> Self {
+        let videos: HashSet<PathBuf> = videos
+            .iter()
+            .filter(|video| !video.private && !video.hidden)
+            .map(|video| video.path.clone())
+            .collect();
+        let folders = videos
+            .iter()
+            .flat_map(|video| video.ancestors().skip(1).take_while(|folder| folder.starts_with(root)))
+            .map(Path::to_path_buf)
+            .collect();
+        Self { videos, folders }
+    }
+
+    fn has_video(&self, path: &Path) -> bool {
+        self.videos.contains(path)
+    }
+
+    fn has_folder(&self, path: &Path) -> bool {
+        self.folders.contains(path)
+    }
+}
+
+/// Dispatch a WebDAV request on its method.
+pub async fn webdav(State(state): State<SharedState>, request: Request<Body>) -> Response<BoxBody> {
+    let (root, base_path, limits, shared) = {
+        let state = state.lock().unwrap();
+        let Some(root) = state.root().map(PathBuf::from) else {
+            return StatusCode::NOT_FOUND.into_response();
+        };
+        let shared = Shared::new(&state.videos(), &root);
+        (root, state.base_path.clone(), state.stream_limits, shared)
+    };
+
+    let relative = decode_path(request.uri().path().strip_prefix(DAV_PREFIX).unwrap_or_default());
+    let Some(path) = resolve(&root, &relative) else {
+        return StatusCode::FORBIDDEN.into_response();
+    };
+    if path != root && !shared.has_video(&path) && !shared.has_folder(&path) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let href_prefix = format!("{}{}", base_path, DAV_PREFIX);
+
+    match *request.method() {
+        Method::OPTIONS => Response::builder()
+            .header("DAV", "1")
+            .header(header::ALLOW, ALLOW)
+            .body(boxed(Body::empty()))
+            .unwrap(),
+        Method::GET | Method::HEAD => {
+            if !shared.has_video(&path) || !path.is_file() {
+                return StatusCode::NOT_FOUND.into_response();
+            }
+            let slot = match StreamSlot::for_request(request.method(), request.extensions(), request.headers()) {
+                Ok(slot) => slot,
+                Err(refused) => return refused.into_response(),
+            };
+            match ServeFile::new(&path).oneshot(request).await {
+                Ok(response) => hold(limits.apply(response.map(boxed)), slot),
+                Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+            }
+        }
+        ref method if method.as_str() == "PROPFIND" => {
+            propfind(&path, &relative, &href_prefix, request.headers(), &shared)
+        }
+        _ => (
+            StatusCode::METHOD_NOT_ALLOWED,
+            [(header::ALLOW, HeaderValue::from_static(ALLOW))],
+        )
+            .into_response(),
+    }
+}
+
+/// Map a decoded request path onto the root, refusing anything that would escape it.
+fn resolve(root: &Path, relative: &Path) -> Option<PathBuf> {
+    let mut path = root.to_path_buf();
+    for component in relative.components() {
+        match component {
+            Component::Normal(segment) => path.push(segment),
+            Component::CurDir | Component::RootDir => {}
+            _ => return None,
+        }
+    }
+    Some(path)
+}
+
+fn propfind(
+    path: &Path,
+    relative: &Path,
+    href_prefix: &str,
+    headers: &HeaderMap,
+    shared: &Shared,
+) -> Response<BoxBody> {
+    let Ok(metadata) = std::fs::metadata(path) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let mut resources = vec![Resource {
+        href: encode_href(href_prefix, relative, metadata.is_dir()),
+        name,
+        metadata: metadata.clone(),
+    }];
+
+    // Depth: infinity is treated as 1, which clients handle by descending themselves.
+    let depth_zero = headers
+        .get("depth")
+        .map(|depth| depth.as_bytes() == b"0")
+        .unwrap_or(false);
+    if metadata.is_dir() && !depth_zero {
+        if let Ok(entries) = std::fs::read_dir(path) {
+            for entry in entries.flatten() {
+                let Ok(metadata) = entry.metadata() else {
+                    continue;
+                };
+                let child = entry.path();
+                let listed = if metadata.is_dir() { shared.has_folder(&child) } else { shared.has_video(&child) };
+                if !listed {
+                    continue;
+                }
+                resources.push(Resource {
+                    href: encode_href(href_prefix, &relative.join(entry.file_name()), metadata.is_dir()),
+                    name: entry.file_name().to_string_lossy().to_string(),
+                    metadata,
+                });
+            }
+        }
+    }
+
+    Response::builder()
+        .status(StatusCode::MULTI_STATUS)
+        .header(header::CONTENT_TYPE, "application/xml; charset=utf-8")
+        .body(boxed(Body::from(multistatus(&resources))))
+        .unwrap()
+}
+
+pub(crate) fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn multistatus(resources: &[Resource]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n");
+    for resource in resources {
+        let _ = write!(
+            xml,
+            "<D:response><D:href>{}</D:href><D:propstat><D:prop><D:displayname>{}</D:displayname>",
+            escape_xml(&resource.href),
+            escape_xml(&resource.name)
+        );
+        if resource.metadata.is_dir() {
+            xml.push_str("<D:resourcetype><D:collection/></D:resourcetype>");
+        } else {
+            let mime = mime_guess::from_path(&resource.name).first_or_octet_stream();
+            let _ = write!(
+                xml,
+                "<D:resourcetype/><D:getcontentlength>{}</D:getcontentlength><D:getcontenttype>{}</D:getcontenttype>",
+                resource.metadata.len(),
+                mime
+            );
+        }
+        if let Ok(modified) = resource.metadata.modified() {
+            let _ = write!(
+                xml,
+                "<D:getlastmodified>{}</D:getlastmodified>",
+                httpdate::fmt_http_date(modified)
+            );
+        }
+        xml.push_str("</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n");
+    }
+    xml.push_str("</D:multistatus>\n");
+    xml
+}